@@ -0,0 +1,432 @@
+//! Typed request/response protocol for worker subprocesses over stdio.
+//!
+//! The parent spawns a worker with [`StdioClient`] and exchanges one JSON
+//! object per line over the child's stdin/stdout; the worker runs a
+//! [`StdioServer`] loop. Each request carries an id so responses can be
+//! matched up:
+//!
+//! ```text
+//! → {"id":1,"request":{...}}
+//! ← {"id":1,"response":{...}}        or   {"id":1,"error":"message"}
+//! ```
+//!
+//! The client may be shared between threads; concurrent requests are
+//! correlated by id and each waits with its own timeout. Output lines
+//! without a known id are ignored, and the worker's stderr is inherited so
+//! its logs reach the parent's stderr. When the worker's stdin closes, the
+//! server returns cleanly.
+
+use crate::process::CommandSpec;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::{Child, ChildStdin, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Default time to wait for a response.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const EXIT_POLL: Duration = Duration::from_millis(10);
+
+/// One line from the worker, before the payload is decoded.
+#[derive(Deserialize)]
+struct Reply {
+    id: Option<u64>,
+    #[serde(default)]
+    response: Option<Value>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Error from a [`StdioClient`] request.
+#[derive(Debug)]
+pub enum AgentError {
+    /// Writing the request or decoding the response failed.
+    Io(io::Error),
+    /// No response arrived within the timeout.
+    Timeout,
+    /// The worker exited or closed its stdout.
+    Closed,
+    /// The worker answered with an error.
+    Remote(String),
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "agent transport error: {e}"),
+            Self::Timeout => f.write_str("agent did not respond in time"),
+            Self::Closed => f.write_str("agent exited"),
+            Self::Remote(message) => write!(f, "agent error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for AgentError {}
+
+impl From<io::Error> for AgentError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<AgentError> for io::Error {
+    fn from(e: AgentError) -> Self {
+        match e {
+            AgentError::Io(e) => e,
+            AgentError::Timeout => io::Error::new(io::ErrorKind::TimedOut, e.to_string()),
+            AgentError::Closed => io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()),
+            AgentError::Remote(_) => io::Error::other(e.to_string()),
+        }
+    }
+}
+
+/// Serves typed requests read from a stream (normally stdin), writing
+/// responses to another (normally stdout).
+#[derive(Debug)]
+pub struct StdioServer<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl StdioServer<BufReader<io::Stdin>, io::Stdout> {
+    /// Serve on this process's stdin and stdout.
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+impl<R: BufRead, W: Write> StdioServer<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+
+    /// Handle requests one at a time until the input ends.
+    ///
+    /// A request that can't be decoded, or whose handler panics, gets an
+    /// error response; lines without an id are skipped. Returns `Ok` at end
+    /// of input or if the client has gone away (broken pipe).
+    pub fn serve<Req, Resp, F>(mut self, mut handler: F) -> io::Result<()>
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: FnMut(Req) -> Result<Resp, String>,
+    {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let Ok(mut message) = serde_json::from_str::<Value>(line.trim()) else {
+                continue;
+            };
+            let Some(id) = message.get("id").and_then(Value::as_u64) else {
+                continue;
+            };
+            let outcome = match serde_json::from_value::<Req>(message["request"].take()) {
+                Ok(request) => panic::catch_unwind(AssertUnwindSafe(|| handler(request)))
+                    .unwrap_or_else(|_| Err("request handler panicked".to_string())),
+                Err(e) => Err(format!("invalid request: {e}")),
+            };
+            let reply =
+                match outcome.and_then(|r| serde_json::to_value(r).map_err(|e| e.to_string())) {
+                    Ok(response) => json!({ "id": id, "response": response }),
+                    Err(error) => json!({ "id": id, "error": error }),
+                };
+            let written = writeln!(self.writer, "{reply}").and_then(|_| self.writer.flush());
+            match written {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                other => other?,
+            }
+        }
+    }
+}
+
+type Pending = HashMap<u64, Sender<Result<Value, String>>>;
+
+/// Correlation state shared with the stdout reader thread.
+#[derive(Default)]
+struct Shared {
+    pending: Pending,
+    /// Set once the worker's stdout has closed.
+    closed: bool,
+}
+
+/// A worker subprocess spoken to over stdin/stdout.
+///
+/// Dropping the client closes the worker's stdin and kills it if it is
+/// still running; use [`shutdown`](Self::shutdown) to let it exit on its
+/// own first.
+pub struct StdioClient<Req, Resp> {
+    child: Child,
+    stdin: Mutex<Option<ChildStdin>>,
+    shared: Arc<Mutex<Shared>>,
+    reader: Option<JoinHandle<()>>,
+    next_id: AtomicU64,
+    timeout: Duration,
+    _marker: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req: Serialize, Resp: DeserializeOwned> StdioClient<Req, Resp> {
+    /// Spawn `spec` with piped stdin/stdout.
+    pub fn spawn(spec: &CommandSpec) -> io::Result<Self> {
+        let mut child = spec
+            .to_command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let reader = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || read_replies(BufReader::new(stdout), &shared))
+        };
+        Ok(Self {
+            child,
+            stdin: Mutex::new(stdin),
+            shared,
+            reader: Some(reader),
+            next_id: AtomicU64::new(1),
+            timeout: DEFAULT_TIMEOUT,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Set the default response timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send `request` and wait for its response.
+    pub fn request(&self, request: &Req) -> Result<Resp, AgentError> {
+        self.request_timeout(request, self.timeout)
+    }
+
+    /// Send `request` and wait up to `timeout` for its response.
+    pub fn request_timeout(&self, request: &Req, timeout: Duration) -> Result<Resp, AgentError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut shared = lock(&self.shared);
+            if shared.closed {
+                return Err(AgentError::Closed);
+            }
+            shared.pending.insert(id, tx);
+        }
+        let line = json!({ "id": id, "request": request }).to_string();
+        if let Err(e) = self.send(&line) {
+            lock(&self.shared).pending.remove(&id);
+            return Err(if e.kind() == io::ErrorKind::BrokenPipe {
+                AgentError::Closed
+            } else {
+                AgentError::Io(e)
+            });
+        }
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(value)) => serde_json::from_value(value)
+                .map_err(|e| AgentError::Io(io::Error::new(io::ErrorKind::InvalidData, e))),
+            Ok(Err(message)) => Err(AgentError::Remote(message)),
+            Err(RecvTimeoutError::Timeout) => {
+                lock(&self.shared).pending.remove(&id);
+                Err(AgentError::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(AgentError::Closed),
+        }
+    }
+
+    fn send(&self, line: &str) -> io::Result<()> {
+        let mut stdin = self.stdin.lock().unwrap_or_else(|e| e.into_inner());
+        let stdin = stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "stdin closed"))?;
+        writeln!(stdin, "{line}")?;
+        stdin.flush()
+    }
+
+    /// The worker's process id.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Close the worker's stdin and wait up to `grace` for it to exit,
+    /// killing it afterwards.
+    pub fn shutdown(mut self, grace: Duration) -> io::Result<ExitStatus> {
+        self.close_stdin();
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(EXIT_POLL);
+        }
+        self.child.kill()?;
+        self.child.wait()
+    }
+}
+
+impl<Req, Resp> StdioClient<Req, Resp> {
+    fn close_stdin(&self) {
+        self.stdin.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+}
+
+impl<Req, Resp> Drop for StdioClient<Req, Resp> {
+    fn drop(&mut self) {
+        self.close_stdin();
+        if !matches!(self.child.try_wait(), Ok(Some(_))) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl<Req, Resp> fmt::Debug for StdioClient<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StdioClient")
+            .field("pid", &self.child.id())
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Route each reply line to the request waiting for its id.
+fn read_replies(reader: impl BufRead, shared: &Mutex<Shared>) {
+    for line in reader.lines() {
+        let Ok(line) = line else { break };
+        let Ok(reply) = serde_json::from_str::<Reply>(&line) else {
+            continue;
+        };
+        let Some(id) = reply.id else { continue };
+        let Some(tx) = lock(shared).pending.remove(&id) else {
+            continue;
+        };
+        let _ = tx.send(match reply.error {
+            Some(error) => Err(error),
+            None => Ok(reply.response.unwrap_or(Value::Null)),
+        });
+    }
+    let mut shared = lock(shared);
+    shared.closed = true;
+    // Dropping the senders wakes every waiter with `Closed`.
+    shared.pending.clear();
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Add {
+        a: i64,
+        b: i64,
+    }
+
+    #[test]
+    fn test_server_handles_requests_until_eof() {
+        let input = concat!(
+            "{\"id\":1,\"request\":{\"a\":2,\"b\":3}}\n",
+            "not json\n",
+            "{\"request\":{\"a\":0,\"b\":0}}\n",
+            "{\"id\":2,\"request\":{\"a\":\"x\"}}\n",
+            "{\"id\":3,\"request\":{\"a\":-1,\"b\":0}}\r\n",
+            "{\"id\":4,\"request\":{\"a\":1,\"b\":1}}\n",
+        );
+        let mut output = Vec::new();
+        StdioServer::new(Cursor::new(input), &mut output)
+            .serve(|req: Add| match req.a {
+                -1 => Err("negative".to_string()),
+                0 => panic!("unreachable"),
+                _ => Ok(req.a + req.b),
+            })
+            .unwrap();
+        let replies: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0], json!({"id": 1, "response": 5}));
+        assert!(
+            replies[1]["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("invalid request")
+        );
+        assert_eq!(replies[2], json!({"id": 3, "error": "negative"}));
+        assert_eq!(replies[3], json!({"id": 4, "response": 2}));
+    }
+
+    #[test]
+    fn test_server_reports_panics() {
+        let mut output = Vec::new();
+        StdioServer::new(Cursor::new("{\"id\":9,\"request\":null}\n"), &mut output)
+            .serve(|_: Value| -> Result<(), String> { panic!("boom") })
+            .unwrap();
+        let reply: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(reply["error"], "request handler panicked");
+    }
+
+    #[cfg(unix)]
+    fn echo_worker() -> CommandSpec {
+        // Echoes each request back as its response.
+        CommandSpec::new("sh").args([
+            "-c",
+            "while IFS= read -r line; do printf '%s\\n' \"$line\" | sed 's/\"request\"/\"response\"/'; done",
+        ])
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_client_correlates_concurrent_requests() {
+        let client: Arc<StdioClient<Value, Value>> =
+            Arc::new(StdioClient::spawn(&echo_worker()).unwrap());
+        let workers: Vec<_> = (0..8)
+            .map(|i| {
+                let client = Arc::clone(&client);
+                thread::spawn(move || client.request(&json!({ "n": i })).unwrap())
+            })
+            .collect();
+        for (i, worker) in workers.into_iter().enumerate() {
+            assert_eq!(worker.join().unwrap(), json!({ "n": i }));
+        }
+        let client = Arc::into_inner(client).unwrap();
+        assert!(client.shutdown(Duration::from_secs(5)).unwrap().success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_client_timeout_and_exit() {
+        let silent: StdioClient<Value, Value> =
+            StdioClient::spawn(&CommandSpec::new("sh").args(["-c", "cat > /dev/null"])).unwrap();
+        let err = silent
+            .request_timeout(&json!(1), Duration::from_millis(50))
+            .unwrap_err();
+        assert!(matches!(err, AgentError::Timeout));
+
+        let exits: StdioClient<Value, Value> =
+            StdioClient::spawn(&CommandSpec::new("sh").args(["-c", "read line; exit 0"])).unwrap();
+        let err = exits.request(&json!(1)).unwrap_err();
+        assert!(matches!(err, AgentError::Closed), "{err:?}");
+        assert!(matches!(exits.request(&json!(2)), Err(AgentError::Closed)));
+    }
+}
@@ -0,0 +1,458 @@
+//! Single-file `.tar.gz` export of session artifacts.
+//!
+//! [`pack`] bundles a directory into one archive ("export this session for
+//! a bug report"); [`unpack`] extracts it. Archives are deterministic: files
+//! are stored in sorted order with zeroed timestamps and ownership, so packing
+//! the same tree twice produces identical bytes.
+//!
+//! [`Packer`] adds size limits — oversized files, and files beyond the total
+//! budget, are skipped and listed in the [`PackReport`] — and JSON hooks that
+//! rewrite `.json` documents and each line of `.jsonl` files before they are
+//! stored, typically to [`redact`] secrets. Lines that are not
+//! JSON are stored unchanged.
+//!
+//! Only regular files are packed. Extraction refuses absolute paths and `..`
+//! components, and [`unpack_limited`] caps the unpacked size.
+
+use crate::globs::Matcher;
+use crate::guard;
+use crate::gzip;
+use crate::redact::{self, RedactionRules};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Default cap on the unpacked size for [`unpack`] (1 GiB).
+pub const DEFAULT_MAX_UNPACKED: u64 = 1 << 30;
+
+const BLOCK: usize = 512;
+
+type JsonHook = Box<dyn Fn(&str, &mut Value) + Send + Sync>;
+
+/// What [`Packer::pack`] stored and skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackReport {
+    /// `/`-separated paths stored, in archive order.
+    pub files: Vec<String>,
+    /// Files left out because of a size limit.
+    pub skipped: Vec<String>,
+    /// Total stored file bytes (after hooks).
+    pub bytes: u64,
+}
+
+/// Configures how a directory is packed.
+#[derive(Default)]
+pub struct Packer {
+    ignore: Matcher,
+    max_file_size: Option<u64>,
+    max_total_size: Option<u64>,
+    hooks: Vec<JsonHook>,
+}
+
+impl Packer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skip paths matched by `ignore`.
+    pub fn ignore(mut self, ignore: Matcher) -> Self {
+        self.ignore = ignore;
+        self
+    }
+
+    /// Skip files larger than `bytes`.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Stop adding files once the stored total would exceed `bytes`.
+    pub fn max_total_size(mut self, bytes: u64) -> Self {
+        self.max_total_size = Some(bytes);
+        self
+    }
+
+    /// Rewrite every JSON document and JSONL line before it is stored. The
+    /// hook receives the member path and the parsed value.
+    pub fn json_hook(mut self, hook: impl Fn(&str, &mut Value) + Send + Sync + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Redact JSON and JSONL members with `rules`.
+    pub fn redact(self, rules: RedactionRules) -> Self {
+        self.json_hook(move |_, value| {
+            redact::value(value, &rules);
+        })
+    }
+
+    /// Pack `dir` into the `.tar.gz` file `dest`, replacing it atomically.
+    pub fn pack(&self, dir: &Path, dest: &Path) -> io::Result<PackReport> {
+        let mut report = PackReport::default();
+        let mut tar = Vec::new();
+        for rel in walk(dir, &self.ignore)? {
+            let key = rel_key(&rel);
+            let path = dir.join(&rel);
+            let len = fs::metadata(&path)?.len();
+            if self.max_file_size.is_some_and(|max| len > max) {
+                report.skipped.push(key);
+                continue;
+            }
+            let data = self.apply_hooks(&key, fs::read(&path)?);
+            let size = data.len() as u64;
+            if self
+                .max_total_size
+                .is_some_and(|max| report.bytes + size > max)
+            {
+                report.skipped.push(key);
+                continue;
+            }
+            tar.extend_from_slice(&header(&key, size)?);
+            tar.extend_from_slice(&data);
+            tar.resize(tar.len().next_multiple_of(BLOCK), 0);
+            report.bytes += size;
+            report.files.push(key);
+        }
+        tar.resize(tar.len() + 2 * BLOCK, 0);
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp = dest.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        let tmp = guard::remove_file_on_drop(tmp);
+        fs::write(&*tmp, gzip::compress(&tar))?;
+        fs::rename(&*tmp, dest)?;
+        tmp.disarm();
+        Ok(report)
+    }
+
+    fn apply_hooks(&self, key: &str, data: Vec<u8>) -> Vec<u8> {
+        if self.hooks.is_empty() {
+            return data;
+        }
+        let rewrite = |bytes: &[u8]| -> Option<Vec<u8>> {
+            let mut value: Value = serde_json::from_slice(bytes).ok()?;
+            for hook in &self.hooks {
+                hook(key, &mut value);
+            }
+            serde_json::to_vec(&value).ok()
+        };
+        if key.ends_with(".jsonl") {
+            let mut out = Vec::with_capacity(data.len());
+            for line in data.split_inclusive(|b| *b == b'\n') {
+                let body = line.strip_suffix(b"\n").unwrap_or(line);
+                match rewrite(body) {
+                    Some(new) => out.extend_from_slice(&new),
+                    None => out.extend_from_slice(body),
+                }
+                if line.ends_with(b"\n") {
+                    out.push(b'\n');
+                }
+            }
+            out
+        } else if key.ends_with(".json") {
+            rewrite(&data).unwrap_or(data)
+        } else {
+            data
+        }
+    }
+}
+
+/// Pack `dir` into `dest` (a `.tar.gz`), skipping paths matched by `ignore`.
+pub fn pack(dir: &Path, dest: &Path, ignore: &Matcher) -> io::Result<PackReport> {
+    Packer::new().ignore(ignore.clone()).pack(dir, dest)
+}
+
+/// Extract `src` into `dir`, returning the extracted paths. The unpacked
+/// size is capped at [`DEFAULT_MAX_UNPACKED`].
+pub fn unpack(src: &Path, dir: &Path) -> io::Result<Vec<String>> {
+    unpack_limited(src, dir, DEFAULT_MAX_UNPACKED)
+}
+
+/// Extract `src` into `dir`, failing with `InvalidData` if the archive
+/// expands beyond `max_bytes` or holds an unsafe path.
+///
+/// Entries other than regular files and directories are skipped. The
+/// archive is validated completely before anything is written.
+pub fn unpack_limited(src: &Path, dir: &Path, max_bytes: u64) -> io::Result<Vec<String>> {
+    let limit = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+    let tar = gzip::decompress_limited(&fs::read(src)?, limit)?;
+    let entries = parse(&tar)?;
+    let mut out = Vec::new();
+    for entry in entries {
+        let target = dir.join(&entry.path);
+        if entry.is_dir {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, &tar[entry.data.0..entry.data.1])?;
+        out.push(entry.name);
+    }
+    Ok(out)
+}
+
+struct Entry {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+    /// Byte range of the contents within the tar stream.
+    data: (usize, usize),
+}
+
+fn parse(tar: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos + BLOCK <= tar.len() {
+        let block = &tar[pos..pos + BLOCK];
+        if block.iter().all(|b| *b == 0) {
+            break;
+        }
+        let stored: u32 = octal(&block[148..156])? as u32;
+        let sum: u32 = block
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' } else { *b } as u32)
+            .sum();
+        if stored != sum {
+            return Err(invalid("tar header checksum mismatch"));
+        }
+        let size = octal(&block[124..136])? as usize;
+        let start = pos + BLOCK;
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= tar.len())
+            .ok_or_else(|| invalid("truncated tar entry"))?;
+        let name = match cstr(&block[345..500]) {
+            "" => cstr(&block[..100]).to_string(),
+            prefix => format!("{prefix}/{}", cstr(&block[..100])),
+        };
+        let kind = block[156];
+        if matches!(kind, b'0' | 0 | b'5') {
+            let path = safe_path(&name)?;
+            entries.push(Entry {
+                name: name.trim_end_matches('/').to_string(),
+                path,
+                is_dir: kind == b'5',
+                data: (start, end),
+            });
+        }
+        pos = start + size.next_multiple_of(BLOCK);
+    }
+    Ok(entries)
+}
+
+/// A relative path with no `..`, root, or drive components.
+fn safe_path(name: &str) -> io::Result<PathBuf> {
+    let path = PathBuf::from(name);
+    let ok = !name.is_empty()
+        && path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if ok {
+        Ok(path)
+    } else {
+        Err(invalid(&format!("unsafe path in archive: {name}")))
+    }
+}
+
+/// A ustar header for a regular file with zeroed metadata.
+fn header(key: &str, size: u64) -> io::Result<[u8; BLOCK]> {
+    let mut h = [0u8; BLOCK];
+    let (prefix, name) = split_name(key)?;
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut h[100..108], 0o644);
+    put_octal(&mut h[108..116], 0);
+    put_octal(&mut h[116..124], 0);
+    put_octal(&mut h[124..136], size);
+    put_octal(&mut h[136..148], 0);
+    h[156] = b'0';
+    h[257..263].copy_from_slice(b"ustar\0");
+    h[263..265].copy_from_slice(b"00");
+    h[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    h[148..156].fill(b' ');
+    let sum: u32 = h.iter().map(|b| *b as u32).sum();
+    h[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+    Ok(h)
+}
+
+/// Split a long path into ustar's 155-byte prefix and 100-byte name.
+fn split_name(key: &str) -> io::Result<(&str, &str)> {
+    if key.len() <= 100 {
+        return Ok(("", key));
+    }
+    key.match_indices('/')
+        .map(|(i, _)| (&key[..i], &key[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("path too long for archive: {key}"),
+            )
+        })
+}
+
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[field.len() - 1] = 0;
+}
+
+fn octal(field: &[u8]) -> io::Result<u64> {
+    let text = cstr(field).trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid("bad number in tar header"))
+}
+
+fn cstr(field: &[u8]) -> &str {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).unwrap_or("")
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Regular files under `root` (relative, sorted), skipping ignored paths.
+fn walk(root: &Path, ignore: &Matcher) -> io::Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    let mut stack = vec![PathBuf::new()];
+    while let Some(rel_dir) = stack.pop() {
+        for entry in fs::read_dir(root.join(&rel_dir))? {
+            let entry = entry?;
+            let rel = rel_dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if !ignore.matches_dir(&rel) {
+                    stack.push(rel);
+                }
+            } else if file_type.is_file() && !ignore.matches(&rel) {
+                out.push(rel);
+            }
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+fn rel_key(rel: &Path) -> String {
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn session(tmp: &TempDir) -> PathBuf {
+        let dir = tmp.join("session");
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        fs::create_dir_all(dir.join("cache")).unwrap();
+        fs::write(
+            dir.join("state.json"),
+            r#"{"agent":"a","api_key":"sk-123"}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("logs/events.jsonl"),
+            "{\"password\":\"hunter2\"}\nnot json\n{\"ok\":true}\n",
+        )
+        .unwrap();
+        fs::write(dir.join("cache/blob.bin"), [0u8; 64]).unwrap();
+        fs::write(dir.join("notes.txt"), "hello").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_round_trip_is_deterministic() {
+        let tmp = TempDir::new("apiari-archive-test-roundtrip").unwrap();
+        let dir = session(&tmp);
+        let report = pack(&dir, &tmp.join("a.tar.gz"), &Matcher::new(["cache/"])).unwrap();
+        assert_eq!(
+            report.files,
+            ["logs/events.jsonl", "notes.txt", "state.json"]
+        );
+        pack(&dir, &tmp.join("b.tar.gz"), &Matcher::new(["cache/"])).unwrap();
+        assert_eq!(
+            fs::read(tmp.join("a.tar.gz")).unwrap(),
+            fs::read(tmp.join("b.tar.gz")).unwrap()
+        );
+
+        let out = tmp.join("out");
+        let files = unpack(&tmp.join("a.tar.gz"), &out).unwrap();
+        assert_eq!(files, report.files);
+        assert_eq!(fs::read_to_string(out.join("notes.txt")).unwrap(), "hello");
+        assert!(!out.join("cache").exists());
+    }
+
+    #[test]
+    fn test_redaction_hooks() {
+        let tmp = TempDir::new("apiari-archive-test-redact").unwrap();
+        let dir = session(&tmp);
+        let dest = tmp.join("export.tar.gz");
+        Packer::new()
+            .redact(RedactionRules::default())
+            .json_hook(|path, value| {
+                if path == "state.json" {
+                    value["exported"] = true.into();
+                }
+            })
+            .pack(&dir, &dest)
+            .unwrap();
+
+        let out = tmp.join("out");
+        unpack(&dest, &out).unwrap();
+        let state = fs::read_to_string(out.join("state.json")).unwrap();
+        assert!(!state.contains("sk-123"));
+        assert!(state.contains("\"exported\":true"));
+        let events = fs::read_to_string(out.join("logs/events.jsonl")).unwrap();
+        assert!(!events.contains("hunter2"));
+        assert!(events.contains("\nnot json\n{\"ok\":true}\n"));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let tmp = TempDir::new("apiari-archive-test-limits").unwrap();
+        let dir = session(&tmp);
+        let report = Packer::new()
+            .max_file_size(40)
+            .max_total_size(50)
+            .pack(&dir, &tmp.join("small.tar.gz"))
+            .unwrap();
+        assert_eq!(report.files, ["notes.txt", "state.json"]);
+        assert_eq!(report.skipped, ["cache/blob.bin", "logs/events.jsonl"]);
+
+        let err = unpack_limited(&tmp.join("small.tar.gz"), &tmp.join("out"), 1024).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_long_names_and_unsafe_paths() {
+        let tmp = TempDir::new("apiari-archive-test-paths").unwrap();
+        let dir = tmp.join("deep");
+        let nested = dir.join("a".repeat(80)).join("b".repeat(80));
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("file.txt"), "x").unwrap();
+        pack(&dir, &tmp.join("deep.tar.gz"), &Matcher::default()).unwrap();
+        let files = unpack(&tmp.join("deep.tar.gz"), &tmp.join("out")).unwrap();
+        assert_eq!(files[0].len(), 80 + 1 + 80 + 1 + 8);
+
+        let mut tar = header("../escape.txt", 1).unwrap().to_vec();
+        tar.extend_from_slice(&[b'x'; BLOCK]);
+        tar.extend_from_slice(&[0; 2 * BLOCK]);
+        fs::write(tmp.join("evil.tar.gz"), gzip::compress(&tar)).unwrap();
+        let err = unpack(&tmp.join("evil.tar.gz"), &tmp.join("evil")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!tmp.join("escape.txt").exists());
+    }
+}
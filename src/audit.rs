@@ -0,0 +1,406 @@
+//! Hash-chained, append-only audit log.
+//!
+//! [`AuditLog`] appends records through a [`JsonlWriter`], and each record
+//! carries the SHA-256 of the previous one. Editing, deleting, or reordering
+//! any line breaks the chain, which [`verify`] detects and reports.
+//!
+//! The hash covers the canonical JSON (sorted keys, no whitespace) of the
+//! record's `seq`, `ts`, `prev_hash`, and `entry` fields, so verification
+//! works on the raw JSON without knowing the entry type.
+//!
+//! An `AuditLog` assumes it is the only writer to its file; guard it with a
+//! lock if several processes may append.
+
+use crate::clock::now_ms;
+use crate::hash::sha256_hex;
+use crate::ipc::JsonlWriter;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// The `prev_hash` of the first record in a log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One line of an audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord<T> {
+    /// Position in the chain, starting at 0.
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch when the record was appended.
+    pub ts: u64,
+    /// `hash` of the previous record ([`GENESIS_HASH`] for the first).
+    pub prev_hash: String,
+    /// SHA-256 over this record's other fields.
+    pub hash: String,
+    /// The audited action.
+    pub entry: T,
+}
+
+/// Appends hash-chained records to a JSONL file.
+#[derive(Debug)]
+pub struct AuditLog<T> {
+    writer: JsonlWriter<AuditRecord<T>>,
+    next_seq: u64,
+    last_hash: String,
+}
+
+impl<T: Serialize> AuditLog<T> {
+    /// Open (or create) an audit log, resuming the chain from its last
+    /// record.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::InvalidData` if the file exists and its last
+    /// line is not a valid audit record — appending to a damaged chain would
+    /// hide the damage.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut next_seq = 0;
+        let mut last_hash = GENESIS_HASH.to_string();
+
+        if let Some(last) = last_line(path)? {
+            let value: Value = serde_json::from_str(&last)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let (seq, hash) = match (value["seq"].as_u64(), value["hash"].as_str()) {
+                (Some(seq), Some(hash)) => (seq, hash.to_string()),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "last line is not an audit record",
+                    ));
+                }
+            };
+            next_seq = seq + 1;
+            last_hash = hash;
+        }
+
+        Ok(Self {
+            writer: JsonlWriter::new(path),
+            next_seq,
+            last_hash,
+        })
+    }
+
+    /// Append an entry, returning the record as written.
+    pub fn append(&mut self, entry: T) -> io::Result<AuditRecord<T>> {
+        let ts = now_ms();
+        let entry_value = serde_json::to_value(&entry).map_err(io::Error::other)?;
+        let hash = record_hash(self.next_seq, ts, &self.last_hash, &entry_value);
+
+        let record = AuditRecord {
+            seq: self.next_seq,
+            ts,
+            prev_hash: self.last_hash.clone(),
+            hash,
+            entry,
+        };
+        self.writer.append(&record)?;
+        self.next_seq += 1;
+        self.last_hash.clone_from(&record.hash);
+        Ok(record)
+    }
+
+    /// Return the file path.
+    pub fn path(&self) -> &Path {
+        self.writer.path()
+    }
+}
+
+/// Why the chain is broken at a given line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakReason {
+    /// The line is not valid JSON or lacks the chain fields.
+    Malformed,
+    /// `seq` does not follow the previous record.
+    SequenceGap { expected: u64, found: u64 },
+    /// `prev_hash` does not match the previous record's `hash`.
+    PrevHashMismatch,
+    /// `hash` does not match the record's contents.
+    HashMismatch,
+}
+
+/// The first point at which a chain fails verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainBreak {
+    /// 1-based line number in the file.
+    pub line: u64,
+    pub reason: BreakReason,
+}
+
+/// Result of [`verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of records that verified before the first break (or in total).
+    pub valid_records: u64,
+    /// The first break, or `None` if the whole chain is intact.
+    pub first_break: Option<ChainBreak>,
+}
+
+impl VerifyReport {
+    /// Return `true` if the chain is intact.
+    pub fn is_intact(&self) -> bool {
+        self.first_break.is_none()
+    }
+}
+
+/// Walk an audit log from the start and report the first break in the chain.
+///
+/// A missing file is an intact, empty chain. Blank lines are ignored.
+pub fn verify(path: impl AsRef<Path>) -> io::Result<VerifyReport> {
+    let mut report = VerifyReport {
+        valid_records: 0,
+        first_break: None,
+    };
+    let file = match fs::File::open(path.as_ref()) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(report),
+        Err(e) => return Err(e),
+    };
+
+    let mut expected_seq = 0;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fail = |reason| ChainBreak {
+            line: i as u64 + 1,
+            reason,
+        };
+
+        let Some((seq, ts, record_prev, hash, entry)) = parse_record(&line) else {
+            report.first_break = Some(fail(BreakReason::Malformed));
+            break;
+        };
+        if seq != expected_seq {
+            report.first_break = Some(fail(BreakReason::SequenceGap {
+                expected: expected_seq,
+                found: seq,
+            }));
+            break;
+        }
+        if record_prev != prev_hash {
+            report.first_break = Some(fail(BreakReason::PrevHashMismatch));
+            break;
+        }
+        if record_hash(seq, ts, &record_prev, &entry) != hash {
+            report.first_break = Some(fail(BreakReason::HashMismatch));
+            break;
+        }
+
+        report.valid_records += 1;
+        expected_seq += 1;
+        prev_hash = hash;
+    }
+    Ok(report)
+}
+
+fn parse_record(line: &str) -> Option<(u64, u64, String, String, Value)> {
+    let mut value: Value = serde_json::from_str(line).ok()?;
+    let obj = value.as_object_mut()?;
+    Some((
+        obj.get("seq")?.as_u64()?,
+        obj.get("ts")?.as_u64()?,
+        obj.get("prev_hash")?.as_str()?.to_string(),
+        obj.get("hash")?.as_str()?.to_string(),
+        obj.remove("entry")?,
+    ))
+}
+
+fn record_hash(seq: u64, ts: u64, prev_hash: &str, entry: &Value) -> String {
+    let record = json!({
+        "seq": seq,
+        "ts": ts,
+        "prev_hash": prev_hash,
+        "entry": entry,
+    });
+    let mut canonical = String::new();
+    write_canonical(&record, &mut canonical);
+    sha256_hex(canonical.as_bytes())
+}
+
+/// Compact JSON with object keys sorted at every level, whatever order the
+/// map type keeps them in (serde_json's `preserve_order` feature changes it).
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::from(key.as_str()).to_string());
+                out.push(':');
+                write_canonical(&map[key], out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        leaf => out.push_str(&leaf.to_string()),
+    }
+}
+
+fn last_line(path: &Path) -> io::Result<Option<String>> {
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Action {
+        agent: String,
+        command: String,
+    }
+
+    fn action(command: &str) -> Action {
+        Action {
+            agent: "worker-1".into(),
+            command: command.into(),
+        }
+    }
+
+    #[test]
+    fn test_hash_input_has_sorted_keys() {
+        let entry = json!({"b": 1, "a": {"d": [1, {"z": 0, "y": null}], "c": "é\""}});
+        let mut canonical = String::new();
+        write_canonical(&entry, &mut canonical);
+        assert_eq!(
+            canonical,
+            r#"{"a":{"c":"é\"","d":[1,{"y":null,"z":0}]},"b":1}"#
+        );
+        let expected = format!(r#"{{"entry":{canonical},"prev_hash":"p","seq":1,"ts":2}}"#);
+        assert_eq!(
+            record_hash(1, 2, "p", &entry),
+            sha256_hex(expected.as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_chain_links_records() {
+        let tmp = TempDir::new("apiari-audit-test-chain").unwrap();
+        let mut log = AuditLog::open(tmp.join("audit.jsonl")).unwrap();
+        let first = log.append(action("git status")).unwrap();
+        let second = log.append(action("cargo test")).unwrap();
+
+        assert_eq!(first.seq, 0);
+        assert_eq!(first.prev_hash, GENESIS_HASH);
+        assert_eq!(second.seq, 1);
+        assert_eq!(second.prev_hash, first.hash);
+
+        let report = verify(log.path()).unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.valid_records, 2);
+    }
+
+    #[test]
+    fn test_reopen_continues_chain() {
+        let tmp = TempDir::new("apiari-audit-test-reopen").unwrap();
+        let path = tmp.join("audit.jsonl");
+        let first = AuditLog::open(&path)
+            .unwrap()
+            .append(action("one"))
+            .unwrap();
+
+        let mut log = AuditLog::<Action>::open(&path).unwrap();
+        let second = log.append(action("two")).unwrap();
+        assert_eq!(second.seq, 1);
+        assert_eq!(second.prev_hash, first.hash);
+        assert_eq!(verify(&path).unwrap().valid_records, 2);
+    }
+
+    #[test]
+    fn test_tampered_entry_detected() {
+        let tmp = TempDir::new("apiari-audit-test-tamper").unwrap();
+        let path = tmp.join("audit.jsonl");
+        let mut log = AuditLog::open(&path).unwrap();
+        for cmd in ["a", "b", "c"] {
+            log.append(action(cmd)).unwrap();
+        }
+
+        let data = fs::read_to_string(&path).unwrap();
+        fs::write(
+            &path,
+            data.replace("\"command\":\"b\"", "\"command\":\"rm -rf /\""),
+        )
+        .unwrap();
+
+        let report = verify(&path).unwrap();
+        assert_eq!(report.valid_records, 1);
+        assert_eq!(
+            report.first_break,
+            Some(ChainBreak {
+                line: 2,
+                reason: BreakReason::HashMismatch
+            })
+        );
+    }
+
+    #[test]
+    fn test_deleted_line_detected() {
+        let tmp = TempDir::new("apiari-audit-test-delete").unwrap();
+        let path = tmp.join("audit.jsonl");
+        let mut log = AuditLog::open(&path).unwrap();
+        for cmd in ["a", "b", "c"] {
+            log.append(action(cmd)).unwrap();
+        }
+
+        let data = fs::read_to_string(&path).unwrap();
+        let kept: Vec<&str> = data
+            .lines()
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(_, l)| l)
+            .collect();
+        fs::write(&path, kept.join("\n") + "\n").unwrap();
+
+        let report = verify(&path).unwrap();
+        assert_eq!(
+            report.first_break.unwrap().reason,
+            BreakReason::SequenceGap {
+                expected: 1,
+                found: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_malformed_line_detected_and_blocks_open() {
+        let tmp = TempDir::new("apiari-audit-test-malformed").unwrap();
+        let path = tmp.join("audit.jsonl");
+        AuditLog::open(&path).unwrap().append(action("a")).unwrap();
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "garbage\n").unwrap();
+
+        let report = verify(&path).unwrap();
+        assert_eq!(report.first_break.unwrap().reason, BreakReason::Malformed);
+        assert!(AuditLog::<Action>::open(&path).is_err());
+    }
+}
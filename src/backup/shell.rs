@@ -0,0 +1,317 @@
+//! Backends that shell out to `aws` and `rsync`.
+
+use super::Backend;
+use crate::guard;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A [`Backend`] for S3 and S3-compatible stores (MinIO, R2, ...), using the
+/// `aws` CLI and its usual credential configuration.
+#[derive(Debug, Clone)]
+pub struct S3Backend {
+    program: String,
+    bucket: String,
+    prefix: String,
+    endpoint: Option<String>,
+}
+
+impl S3Backend {
+    /// Store objects in `bucket` under `prefix` (may be empty).
+    pub fn new(bucket: impl Into<String>, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into().trim_matches('/').to_string();
+        Self {
+            program: "aws".to_string(),
+            bucket: bucket.into(),
+            prefix,
+            endpoint: None,
+        }
+    }
+
+    /// Talk to an S3-compatible service at `url` instead of AWS.
+    pub fn endpoint(mut self, url: impl Into<String>) -> Self {
+        self.endpoint = Some(url.into());
+        self
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{key}", self.prefix)
+        }
+    }
+
+    fn aws(&self, args: &[&str], stdin: Option<&[u8]>) -> io::Result<Output> {
+        let mut command = Command::new(&self.program);
+        command.arg("s3").args(args);
+        if let Some(endpoint) = &self.endpoint {
+            command.arg("--endpoint-url").arg(endpoint);
+        }
+        spawn(command, &self.program, stdin)
+    }
+}
+
+impl Backend for S3Backend {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let url = format!("s3://{}/{}", self.bucket, self.object_key(key));
+        let output = self.aws(&["cp", "--only-show-errors", "-", &url], Some(data))?;
+        check(output, &self.program)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let url = format!("s3://{}/{}", self.bucket, self.object_key(key));
+        let output = self.aws(&["cp", "--only-show-errors", &url, "-"], None)?;
+        check(output, &self.program)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let full = self.object_key(prefix);
+        let url = format!("s3://{}/{full}", self.bucket);
+        let output = self.aws(&["ls", "--recursive", &url], None)?;
+        // `aws s3 ls` exits 1 with nothing on stderr when nothing matches;
+        // real failures explain themselves on stderr.
+        if output.status.code() == Some(1) && output.stderr.trim_ascii().is_empty() {
+            return Ok(Vec::new());
+        }
+        let output = check(output, &self.program)?;
+        // Lines look like `2024-01-01 00:00:00       123 path/to/key`.
+        let strip = if self.prefix.is_empty() {
+            0
+        } else {
+            self.prefix.len() + 1
+        };
+        Ok(String::from_utf8_lossy(&output)
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(3))
+            .filter(|key| key.starts_with(&full))
+            .map(|key| key[strip..].to_string())
+            .collect())
+    }
+}
+
+/// A [`Backend`] for a directory on another machine, using `rsync` over ssh.
+#[derive(Debug, Clone)]
+pub struct RsyncBackend {
+    program: String,
+    dest: String,
+}
+
+impl RsyncBackend {
+    /// Store objects under `dest`, an rsync destination like
+    /// `backup@nas:/srv/apiari`.
+    pub fn new(dest: impl Into<String>) -> Self {
+        let dest = dest.into().trim_end_matches('/').to_string();
+        Self {
+            program: "rsync".to_string(),
+            dest,
+        }
+    }
+
+    fn rsync(&self, args: &[&str]) -> io::Result<Vec<u8>> {
+        let mut command = Command::new(&self.program);
+        command.args(args);
+        check(spawn(command, &self.program, None)?, &self.program)
+    }
+}
+
+impl Backend for RsyncBackend {
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let tmp = guard::remove_file_on_drop(local_tmp());
+        std::fs::write(&*tmp, data)?;
+        let local = tmp.to_string_lossy().into_owned();
+        self.rsync(&["--mkpath", &local, &format!("{}/{key}", self.dest)])?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let tmp = guard::remove_file_on_drop(local_tmp());
+        let local = tmp.to_string_lossy().into_owned();
+        self.rsync(&[&format!("{}/{key}", self.dest), &local])?;
+        std::fs::read(&*tmp)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let output = self.rsync(&["-r", "--list-only", &format!("{}/", self.dest)])?;
+        // Lines look like `-rw-r--r--  123 2024/01/01 00:00:00 path/to/key`;
+        // keys never contain whitespace.
+        Ok(String::from_utf8_lossy(&output)
+            .lines()
+            .filter(|line| line.starts_with('-'))
+            .filter_map(|line| line.split_whitespace().nth(4))
+            .filter(|key| key.starts_with(prefix))
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// A fresh path in the system temp directory for staging transfers.
+fn local_tmp() -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("apiari-backup-{}-{n}.tmp", std::process::id()))
+}
+
+/// Run `command` to completion, feeding it `stdin`.
+fn spawn(mut command: Command, program: &str, stdin: Option<&[u8]>) -> io::Result<Output> {
+    let mut child = command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                io::Error::new(e.kind(), format!("{program} was not found on PATH"))
+            }
+            _ => e,
+        })?;
+    let write = match (stdin, child.stdin.take()) {
+        (Some(data), Some(mut pipe)) => pipe.write_all(data),
+        _ => Ok(()),
+    };
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        write?;
+    }
+    Ok(output)
+}
+
+/// The stdout of a successful run, or its stderr as an error.
+fn check(output: Output, program: &str) -> io::Result<Vec<u8>> {
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "{program} failed: {}",
+            stderr.trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    /// Stands in for `aws s3`, keeping objects under `$root/store/<bucket>`.
+    /// Like the real CLI, `ls` with no matches exits 1 and prints nothing;
+    /// with `$root/deny` present every call fails with a message.
+    const FAKE_AWS: &str = r#"
+[ -e "$ROOT/deny" ] && { echo "An error occurred (AccessDenied)" >&2; exit 1; }
+[ "$1" = s3 ] || exit 2
+cmd=$2; shift 2
+src= dst= skip=
+for a in "$@"; do
+  if [ -n "$skip" ]; then skip=; echo "$a" >> "$ROOT/endpoints"; continue; fi
+  case "$a" in
+    --endpoint-url) skip=1 ;;
+    --*) ;;
+    *) if [ -z "$src" ]; then src=$a; else dst=$a; fi ;;
+  esac
+done
+case $cmd in
+  cp)
+    if [ "$src" = - ]; then
+      p="$ROOT/store/${dst#s3://}"; mkdir -p "$(dirname "$p")"; cat > "$p"
+    else
+      cat "$ROOT/store/${src#s3://}" 2>/dev/null || { echo "404 Not Found" >&2; exit 1; }
+    fi ;;
+  ls)
+    want=${src#s3://}
+    found=$(cd "$ROOT/store" && find . -type f | sed 's|^\./||' | while read -r k; do
+      case "$k" in "$want"*) echo "2024-01-01 00:00:00          1 ${k#*/}" ;; esac
+    done)
+    [ -n "$found" ] || exit 1
+    echo "$found" ;;
+esac
+"#;
+
+    /// Stands in for `rsync`, treating `host:path` as the local `path`.
+    const FAKE_RSYNC: &str = r#"
+list= args=
+for a in "$@"; do
+  case "$a" in
+    --list-only) list=1 ;;
+    -*) ;;
+    *) args="$args ${a#*:}" ;;
+  esac
+done
+set -- $args
+if [ -n "$list" ]; then
+  cd "$1" || { echo "rsync: change_dir failed" >&2; exit 23; }
+  find . | sed 's|^\./||' | while read -r k; do
+    [ "$k" = . ] && continue
+    if [ -d "$k" ]; then echo "drwxr-xr-x          4,096 2024/01/01 00:00:00 $k"
+    else echo "-rw-r--r--              1 2024/01/01 00:00:00 $k"; fi
+  done
+else
+  mkdir -p "$(dirname "$2")" && cp "$1" "$2" 2>/dev/null || { echo "rsync: link_stat failed" >&2; exit 23; }
+fi
+"#;
+
+    fn install(dir: &Path, name: &str, body: &str) -> String {
+        let path = dir.join(name);
+        let script = format!("#!/bin/sh\nROOT='{}'\n{body}", dir.display());
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn exercise(backend: &impl Backend) {
+        assert_eq!(backend.list("logs/").unwrap(), Vec::<String>::new());
+        backend.put("logs/a/1.jsonl", b"one\n").unwrap();
+        backend.put("state/s/2.json", b"{}").unwrap();
+        assert_eq!(backend.get("logs/a/1.jsonl").unwrap(), b"one\n");
+        assert_eq!(backend.list("logs/").unwrap(), ["logs/a/1.jsonl"]);
+        assert!(backend.get("logs/missing").is_err());
+    }
+
+    #[test]
+    fn test_s3_backend_with_fake_cli() {
+        let tmp = TempDir::new("apiari-backup-test-s3").unwrap();
+        fs::create_dir(tmp.join("store")).unwrap();
+        let backend = S3Backend {
+            program: install(tmp.path(), "aws", FAKE_AWS),
+            ..S3Backend::new("bucket", "/host-a/").endpoint("http://minio:9000")
+        };
+        exercise(&backend);
+        assert!(tmp.join("store/bucket/host-a/logs/a/1.jsonl").exists());
+        assert!(
+            fs::read_to_string(tmp.join("endpoints"))
+                .unwrap()
+                .contains("http://minio:9000")
+        );
+
+        // A failing `ls` with a message is an error, not an empty listing.
+        fs::write(tmp.join("deny"), "").unwrap();
+        let err = backend.list("nothing/").unwrap_err();
+        assert!(err.to_string().contains("AccessDenied"), "{err}");
+    }
+
+    #[test]
+    fn test_rsync_backend_with_fake_cli() {
+        let tmp = TempDir::new("apiari-backup-test-rsync").unwrap();
+        fs::create_dir(tmp.join("remote")).unwrap();
+        let backend = RsyncBackend {
+            program: install(tmp.path(), "rsync", FAKE_RSYNC),
+            ..RsyncBackend::new(format!("nas:{}/", tmp.join("remote").display()))
+        };
+        exercise(&backend);
+        assert_eq!(fs::read(tmp.join("remote/state/s/2.json")).unwrap(), b"{}");
+
+        let missing = RsyncBackend {
+            program: backend.program.clone(),
+            ..RsyncBackend::new(format!("nas:{}", tmp.join("gone").display()))
+        };
+        assert!(missing.list("").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! Persistent key-value cache with expiry.
+//!
+//! A [`Cache`] keeps one JSON file per entry in a directory under
+//! [`cache_dir`](crate::dirs::cache_dir), named by a hash of the key. Each
+//! entry records when it expires; expired entries read as misses and are
+//! deleted.
+//!
+//! The cache is best-effort by design. An unreadable or corrupt entry file
+//! (a crash mid-write on an old version, a truncated disk) is treated as a
+//! miss and removed, and a failure to write an entry never fails the
+//! computation that produced it. When the directory grows past
+//! [`max_bytes`](Cache::max_bytes), the least recently written entries are
+//! evicted.
+
+use crate::clock::unix_ms;
+use crate::clock::{Clock, SystemClock};
+use crate::dirs;
+use crate::hash::sha256_hex;
+use crate::state::save_state;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default size limit for a cache directory.
+pub const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+const EXTENSION: &str = "json";
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    expires_ms: u64,
+    value: Value,
+}
+
+/// A directory of cached values.
+///
+/// ```ignore
+/// let cache = Cache::open("git")?;
+/// let remote: RemoteInfo = cache.get_or_insert_with(&repo_url, Duration::from_secs(3600), || {
+///     fetch_remote_info(&repo_url)
+/// })?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cache<C = SystemClock> {
+    dir: PathBuf,
+    max_bytes: u64,
+    clock: C,
+}
+
+impl Cache {
+    /// Open the cache named `name` under the Apiari cache directory.
+    pub fn open(name: &str) -> io::Result<Self> {
+        Ok(Self::at(dirs::cache_dir()?.join(name)))
+    }
+
+    /// Use `dir` as the cache directory. It is created on first write.
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes: DEFAULT_MAX_BYTES,
+            clock: SystemClock,
+        }
+    }
+}
+
+impl<C: Clock> Cache<C> {
+    /// Evict old entries once the directory exceeds `max_bytes`.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Decide expiry with `clock` instead of the system clock.
+    pub fn with_clock<D: Clock>(self, clock: D) -> Cache<D> {
+        Cache {
+            dir: self.dir,
+            max_bytes: self.max_bytes,
+            clock,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The cached value for `key`, if present, unexpired, and decodable
+    /// as `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let path = self.path(key);
+        let data = fs::read(&path).ok()?;
+        let entry = match serde_json::from_slice::<Entry>(&data) {
+            Ok(entry) if entry.key == key => entry,
+            // Another key with the same hash prefix; leave it alone.
+            Ok(_) => return None,
+            Err(_) => {
+                let _ = fs::remove_file(&path);
+                return None;
+            }
+        };
+        if entry.expires_ms <= self.now_ms() {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        serde_json::from_value(entry.value).ok()
+    }
+
+    /// Store `value` under `key` for `ttl`.
+    pub fn insert<T: Serialize>(&self, key: &str, ttl: Duration, value: &T) -> io::Result<()> {
+        let entry = Entry {
+            key: key.to_string(),
+            expires_ms: self.now_ms().saturating_add(ttl.as_millis() as u64),
+            value: serde_json::to_value(value)?,
+        };
+        save_state(&self.path(key), &entry)?;
+        self.evict()
+    }
+
+    /// Return the cached value for `key`, or compute, store, and return it.
+    ///
+    /// Only an error from `compute` is returned; failing to store the
+    /// result is ignored.
+    pub fn get_or_insert_with<T, F>(&self, key: &str, ttl: Duration, compute: F) -> io::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> io::Result<T>,
+    {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+        let value = compute()?;
+        let _ = self.insert(key, ttl, &value);
+        Ok(value)
+    }
+
+    /// Remove the entry for `key`, if any.
+    pub fn remove(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove every entry.
+    pub fn clear(&self) -> io::Result<()> {
+        for (path, _, _) in self.entries()? {
+            let _ = fs::remove_file(path);
+        }
+        Ok(())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let digest = sha256_hex(key.as_bytes());
+        self.dir.join(format!("{}.{EXTENSION}", &digest[..32]))
+    }
+
+    fn now_ms(&self) -> u64 {
+        unix_ms(self.clock.now())
+    }
+
+    /// Entry files with their size and modification time.
+    fn entries(&self) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let read = match fs::read_dir(&self.dir) {
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for item in read {
+            let item = item?;
+            let path = item.path();
+            if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                continue;
+            }
+            let Ok(meta) = item.metadata() else { continue };
+            if meta.is_file() {
+                entries.push((path, meta.len(), meta.modified().unwrap_or(UNIX_EPOCH)));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Delete the oldest entries until the directory fits in `max_bytes`.
+    fn evict(&self) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+        entries.sort_by_key(|(path, _, modified)| (*modified, path.clone()));
+        for (path, len, _) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{MockClock, TempDir};
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_get_or_insert_with_and_expiry() {
+        let tmp = TempDir::new("apiari-cache-test-ttl").unwrap();
+        let clock = MockClock::at_unix_secs(1_000_000);
+        let cache = Cache::at(tmp.join("cache")).with_clock(clock.clone());
+        let mut computed = 0;
+        let mut fetch = || {
+            cache.get_or_insert_with("remote", HOUR, || {
+                computed += 1;
+                Ok(vec!["origin".to_string()])
+            })
+        };
+        assert_eq!(fetch().unwrap(), ["origin"]);
+        assert_eq!(fetch().unwrap(), ["origin"]);
+        assert_eq!(computed, 1);
+
+        clock.advance(HOUR);
+        assert_eq!(cache.get::<Vec<String>>("remote"), None);
+        assert!(cache.entries().unwrap().is_empty());
+
+        let err = cache
+            .get_or_insert_with::<u32, _>("broken", HOUR, || Err(io::Error::other("offline")))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "offline");
+        assert_eq!(cache.get::<u32>("broken"), None);
+    }
+
+    #[test]
+    fn test_corrupt_entries_are_misses() {
+        let tmp = TempDir::new("apiari-cache-test-corrupt").unwrap();
+        let cache = Cache::at(tmp.path());
+        cache.insert("k", HOUR, &42).unwrap();
+        fs::write(cache.path("k"), "{\"key\": \"k\", \"expi").unwrap();
+        assert_eq!(cache.get::<u32>("k"), None);
+        assert!(!cache.path("k").exists());
+
+        cache.insert("k", HOUR, &"text").unwrap();
+        assert_eq!(cache.get::<u32>("k"), None);
+        assert_eq!(cache.get::<String>("k").as_deref(), Some("text"));
+        cache.remove("k").unwrap();
+        cache.remove("k").unwrap();
+        assert_eq!(cache.get::<String>("k"), None);
+    }
+
+    #[test]
+    fn test_size_eviction_removes_oldest() {
+        let tmp = TempDir::new("apiari-cache-test-evict").unwrap();
+        let value = "x".repeat(1000);
+        let cache = Cache::at(tmp.path()).max_bytes(2500);
+        for key in ["a", "b", "c"] {
+            cache.insert(key, HOUR, &value).unwrap();
+            // Distinct modification times.
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(cache.get::<String>("a"), None);
+        assert!(cache.get::<String>("b").is_some());
+        assert!(cache.get::<String>("c").is_some());
+
+        cache.clear().unwrap();
+        assert!(cache.entries().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+//! Time source abstraction.
+//!
+//! Code that makes time-dependent decisions (expiry, staleness, scheduling)
+//! should take a [`Clock`] instead of calling [`SystemTime::now`] directly so
+//! tests can substitute a controllable clock (see `testutil::MockClock`).
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A source of wall-clock time.
+pub trait Clock: Send + Sync {
+    /// Return the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The real system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for std::sync::Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Box<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+/// Milliseconds since the Unix epoch at `time` (0 before the epoch).
+pub(crate) fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Nanoseconds since the Unix epoch at `time` (0 before the epoch).
+pub(crate) fn unix_ns(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// [`unix_ms`] of the system clock, for timestamps on records. Decisions
+/// based on time should take a [`Clock`] instead.
+pub(crate) fn now_ms() -> u64 {
+    unix_ms(SystemTime::now())
+}
+
+/// [`unix_ns`] of the system clock, for timestamps on records.
+pub(crate) fn now_ns() -> u64 {
+    unix_ns(SystemTime::now())
+}
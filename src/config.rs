@@ -0,0 +1,735 @@
+//! Layered JSON configuration with hot reload.
+//!
+//! A [`Config<T>`] starts from `T::default()` and deep-merges each JSON file
+//! layer over it in order (see [`crate::merge`]; a `null` deletes a key), then
+//! decodes the result into `T` and runs the registered validators. A missing
+//! file is an empty layer.
+//!
+//! # Profiles
+//!
+//! The same tool can run with different settings in dev, staging, and prod.
+//! The active profile comes from [`ConfigBuilder::profile`], or else from
+//! the `APIARI_PROFILE` environment variable. For each layer file, in order:
+//!
+//! 1. the file itself (minus its `"profile"` key),
+//! 2. its `"profile": {"<name>": {...}}` section for the active profile,
+//! 3. a sibling overlay file `<stem>.<name>.<ext>` (`config.prod.json`),
+//!
+//! are merged, each over the previous, before the next layer is applied. So
+//! a later layer beats an earlier one even where the earlier one has profile
+//! settings. A profile no layer defines is an error, to catch typos.
+//!
+//! # Hot reload
+//!
+//! [`Config::watch`] keeps a live copy. When any layer changes on disk the
+//! whole stack is re-read and validated off to the side; only a valid
+//! result replaces the current config, in one atomic swap, and subscribers
+//! receive the list of changed keys. An invalid edit is rejected and
+//! reported, and the previous config stays in effect.
+
+use crate::merge::{self, ArrayStrategy};
+use crate::watch::{self, FileWatcher};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// How often [`Config::watch`] checks the files by default.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Environment variable selecting the profile.
+pub const PROFILE_ENV: &str = "APIARI_PROFILE";
+
+/// Key of the per-profile sections inside a layer.
+pub const PROFILE_KEY: &str = "profile";
+
+type Validator<T> = Arc<dyn Fn(&T) -> Result<(), String> + Send + Sync>;
+type Subscriber<T> = Box<dyn FnMut(&ConfigUpdate<T>) + Send>;
+type RejectHandler = Box<dyn FnMut(&io::Error) + Send>;
+
+/// A loaded configuration: the merged JSON and the decoded value.
+pub struct Config<T> {
+    raw: Value,
+    value: T,
+    sources: Vec<PathBuf>,
+    profile: Option<String>,
+}
+
+impl<T> Config<T>
+where
+    T: Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+{
+    /// Load `T::default()` overlaid with the file at `path`.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::builder(path).load()
+    }
+
+    /// Load `path` and keep it current; see [`ConfigBuilder::watch`].
+    pub fn watch(path: impl Into<PathBuf>, interval: Duration) -> io::Result<LiveConfig<T>> {
+        Self::builder(path).interval(interval).watch()
+    }
+
+    /// Start configuring with `path` as the base layer.
+    pub fn builder(path: impl Into<PathBuf>) -> ConfigBuilder<T> {
+        ConfigBuilder {
+            layers: vec![path.into()],
+            validators: Vec::new(),
+            interval: DEFAULT_INTERVAL,
+            profile: None,
+            profile_env: Some(PROFILE_ENV.to_string()),
+        }
+    }
+}
+
+impl<T> Config<T> {
+    /// The decoded configuration.
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// The merged JSON the value was decoded from.
+    pub fn raw(&self) -> &Value {
+        &self.raw
+    }
+
+    /// The layer and overlay files that existed when this was loaded, in
+    /// merge order.
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// The active profile, if any.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+}
+
+impl<T> Deref for Config<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Config<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("value", &self.value)
+            .field("sources", &self.sources)
+            .field("profile", &self.profile)
+            .finish()
+    }
+}
+
+/// Configures how a [`Config`] is loaded.
+pub struct ConfigBuilder<T> {
+    layers: Vec<PathBuf>,
+    validators: Vec<Validator<T>>,
+    interval: Duration,
+    profile: Option<String>,
+    profile_env: Option<String>,
+}
+
+impl<T> Clone for ConfigBuilder<T> {
+    fn clone(&self) -> Self {
+        Self {
+            layers: self.layers.clone(),
+            validators: self.validators.clone(),
+            interval: self.interval,
+            profile: self.profile.clone(),
+            profile_env: self.profile_env.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for ConfigBuilder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigBuilder")
+            .field("layers", &self.layers)
+            .field("validators", &self.validators.len())
+            .field("interval", &self.interval)
+            .field("profile", &self.profile)
+            .finish()
+    }
+}
+
+impl<T> ConfigBuilder<T>
+where
+    T: Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+{
+    /// Merge the file at `path` over the previous layers.
+    pub fn layer(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(path.into());
+        self
+    }
+
+    /// Reject configs for which `check` returns an error message.
+    pub fn validate(
+        mut self,
+        check: impl Fn(&T) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.validators.push(Arc::new(check));
+        self
+    }
+
+    /// Activate profile `name`, overriding `APIARI_PROFILE`.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// Read the profile from `var` instead of `APIARI_PROFILE`.
+    pub fn profile_env(mut self, var: impl Into<String>) -> Self {
+        self.profile_env = Some(var.into());
+        self
+    }
+
+    /// Ignore the profile environment variable.
+    pub fn without_profile_env(mut self) -> Self {
+        self.profile_env = None;
+        self
+    }
+
+    /// How often [`watch`](Self::watch) checks the files.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Read, merge, decode, and validate every layer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if a layer isn't a JSON object, the merged
+    /// result doesn't decode into `T`, or a validator rejects it, and
+    /// `InvalidInput` if the profile name is malformed or no layer defines
+    /// it.
+    pub fn load(&self) -> io::Result<Config<T>> {
+        let profile = self.active_profile()?;
+        let mut raw = serde_json::to_value(T::default()).map_err(io::Error::other)?;
+        let mut sources = Vec::new();
+        let mut profile_found = false;
+        for path in &self.layers {
+            if let Some(mut layer) = read_layer(path)? {
+                let sections = layer.as_object_mut().and_then(|m| m.remove(PROFILE_KEY));
+                merge::deep_in_place(&mut raw, layer, &ArrayStrategy::Replace);
+                sources.push(path.clone());
+                if let Some(name) = &profile
+                    && let Some(section) = sections.as_ref().and_then(|s| s.get(name))
+                {
+                    if !section.is_object() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{}: profile `{name}` must be a JSON object", path.display()),
+                        ));
+                    }
+                    merge::deep_in_place(&mut raw, section.clone(), &ArrayStrategy::Replace);
+                    profile_found = true;
+                }
+            }
+            if let Some(name) = &profile {
+                let overlay = overlay_path(path, name);
+                if let Some(mut layer) = read_layer(&overlay)? {
+                    if let Some(map) = layer.as_object_mut() {
+                        map.remove(PROFILE_KEY);
+                    }
+                    merge::deep_in_place(&mut raw, layer, &ArrayStrategy::Replace);
+                    sources.push(overlay);
+                    profile_found = true;
+                }
+            }
+        }
+        if let Some(name) = &profile
+            && !profile_found
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("config profile `{name}` is not defined by any layer"),
+            ));
+        }
+        let value: T = serde_json::from_value(raw.clone()).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("invalid config: {e}"))
+        })?;
+        for check in &self.validators {
+            check(&value).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("invalid config: {e}"))
+            })?;
+        }
+        Ok(Config {
+            raw,
+            value,
+            sources,
+            profile,
+        })
+    }
+
+    /// The explicit profile, else the environment's (if enabled and set).
+    fn active_profile(&self) -> io::Result<Option<String>> {
+        let name = match (&self.profile, &self.profile_env) {
+            (Some(name), _) => Some(name.clone()),
+            (None, Some(var)) => crate::env::get::<String>(var)?,
+            (None, None) => None,
+        };
+        let Some(name) = name.map(|n| n.trim().to_string()) else {
+            return Ok(None);
+        };
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid config profile name `{name}`"),
+            ));
+        }
+        Ok(Some(name))
+    }
+
+    /// Files to watch: every layer, plus its overlay for the profile.
+    fn watched_paths(&self) -> io::Result<Vec<PathBuf>> {
+        let profile = self.active_profile()?;
+        Ok(self
+            .layers
+            .iter()
+            .flat_map(|path| {
+                let overlay = profile.as_deref().map(|name| overlay_path(path, name));
+                std::iter::once(path.clone()).chain(overlay)
+            })
+            .collect())
+    }
+
+    /// Load the config and reload it whenever a layer changes.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`load`](Self::load) if the initial config is invalid.
+    pub fn watch(self) -> io::Result<LiveConfig<T>> {
+        let shared = Arc::new(Shared {
+            reloading: Mutex::new(()),
+            current: RwLock::new(Arc::new(self.load()?)),
+            subscribers: Mutex::new(Vec::new()),
+            on_rejected: Mutex::new(Vec::new()),
+            last_error: Mutex::new(None),
+        });
+        let paths = self.watched_paths()?;
+        let builder = Arc::new(self);
+        let watchers = paths
+            .iter()
+            .map(|path| {
+                let shared = Arc::clone(&shared);
+                let builder = Arc::clone(&builder);
+                watch::watch(path, builder.interval, move |_| shared.reload(&builder))
+            })
+            .collect();
+        Ok(LiveConfig {
+            shared,
+            _watchers: watchers,
+        })
+    }
+}
+
+/// `config.json` -> `config.<profile>.json`.
+fn overlay_path(path: &Path, profile: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.{profile}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{profile}"),
+    };
+    path.with_file_name(name)
+}
+
+fn read_layer(path: &Path) -> io::Result<Option<Value>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match serde_json::from_slice(&data) {
+        Ok(Value::Object(map)) => Ok(Some(Value::Object(map))),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: config must be a JSON object", path.display()),
+        )),
+        Err(e) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {e}", path.display()),
+        )),
+    }
+}
+
+/// One changed key, as a dotted path (`server.port`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyChange {
+    pub key: String,
+    /// `None` if the key was added.
+    pub old: Option<Value>,
+    /// `None` if the key was removed.
+    pub new: Option<Value>,
+}
+
+/// Keys that differ between `old` and `new`, sorted. Objects are compared
+/// key by key; anything else (including arrays) is compared whole.
+pub fn diff(old: &Value, new: &Value) -> Vec<KeyChange> {
+    let mut changes = Vec::new();
+    diff_into(&mut changes, String::new(), Some(old), Some(new));
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+    changes
+}
+
+fn diff_into(out: &mut Vec<KeyChange>, key: String, old: Option<&Value>, new: Option<&Value>) {
+    match (old, new) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            for (k, v) in a {
+                diff_into(out, join(&key, k), Some(v), b.get(k));
+            }
+            for (k, v) in b.iter().filter(|(k, _)| !a.contains_key(*k)) {
+                diff_into(out, join(&key, k), None, Some(v));
+            }
+        }
+        (a, b) if a != b => out.push(KeyChange {
+            key,
+            old: a.cloned(),
+            new: b.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{prefix}.{key}")
+    }
+}
+
+/// Delivered to [`LiveConfig::subscribe`] callbacks after a reload.
+#[derive(Debug)]
+pub struct ConfigUpdate<T> {
+    pub changes: Vec<KeyChange>,
+    pub config: Arc<Config<T>>,
+}
+
+impl<T> ConfigUpdate<T> {
+    /// Return `true` if `key` or anything under it changed.
+    pub fn changed(&self, key: &str) -> bool {
+        self.changes.iter().any(|c| {
+            c.key == key
+                || c.key
+                    .strip_prefix(key)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    }
+}
+
+struct Shared<T> {
+    /// Held for a whole reload, so one watcher thread can't swap in an
+    /// older load after another's newer one.
+    reloading: Mutex<()>,
+    current: RwLock<Arc<Config<T>>>,
+    subscribers: Mutex<Vec<Subscriber<T>>>,
+    on_rejected: Mutex<Vec<RejectHandler>>,
+    last_error: Mutex<Option<String>>,
+}
+
+impl<T> Shared<T>
+where
+    T: Serialize + DeserializeOwned + Default + Send + Sync + 'static,
+{
+    fn reload(&self, builder: &ConfigBuilder<T>) {
+        let _reloading = lock(&self.reloading);
+        let next = match builder.load() {
+            Ok(next) => next,
+            Err(e) => {
+                *lock(&self.last_error) = Some(e.to_string());
+                for handler in lock(&self.on_rejected).iter_mut() {
+                    handler(&e);
+                }
+                return;
+            }
+        };
+        *lock(&self.last_error) = None;
+        let next = Arc::new(next);
+        let changes = {
+            let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+            let changes = diff(current.raw(), next.raw());
+            if changes.is_empty() {
+                return;
+            }
+            *current = Arc::clone(&next);
+            changes
+        };
+        let update = ConfigUpdate {
+            changes,
+            config: next,
+        };
+        for subscriber in lock(&self.subscribers).iter_mut() {
+            subscriber(&update);
+        }
+    }
+}
+
+/// A config that reloads when its files change; see [`Config::watch`].
+pub struct LiveConfig<T> {
+    shared: Arc<Shared<T>>,
+    _watchers: Vec<FileWatcher>,
+}
+
+impl<T> LiveConfig<T> {
+    /// The most recently applied config.
+    pub fn snapshot(&self) -> Arc<Config<T>> {
+        Arc::clone(
+            &self
+                .shared
+                .current
+                .read()
+                .unwrap_or_else(|e| e.into_inner()),
+        )
+    }
+
+    /// Call `callback` (on the watcher thread) after each applied change.
+    pub fn subscribe(&self, callback: impl FnMut(&ConfigUpdate<T>) + Send + 'static) {
+        lock(&self.shared.subscribers).push(Box::new(callback));
+    }
+
+    /// Call `callback` (on the watcher thread) when an edit is rejected.
+    pub fn on_rejected(&self, callback: impl FnMut(&io::Error) + Send + 'static) {
+        lock(&self.shared.on_rejected).push(Box::new(callback));
+    }
+
+    /// Why the latest reload was rejected, if it was.
+    pub fn last_error(&self) -> Option<String> {
+        lock(&self.shared.last_error).clone()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LiveConfig<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LiveConfig")
+            .field("current", &self.snapshot())
+            .finish_non_exhaustive()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::sync::mpsc;
+
+    const TICK: Duration = Duration::from_millis(10);
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct Settings {
+        workers: u32,
+        server: Server,
+        tags: Vec<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                workers: 2,
+                server: Server::default(),
+                tags: Vec::new(),
+            }
+        }
+    }
+
+    impl Default for Server {
+        fn default() -> Self {
+            Self {
+                host: "localhost".into(),
+                port: 8080,
+            }
+        }
+    }
+
+    #[test]
+    fn test_layers_merge_over_defaults() {
+        let tmp = TempDir::new("apiari-config-test-layers").unwrap();
+        let base = tmp.join("config.json");
+        let local = tmp.join("config.local.json");
+        fs::write(&base, r#"{"workers": 4, "server": {"port": 9000}}"#).unwrap();
+        fs::write(&local, r#"{"server": {"host": "0.0.0.0"}, "tags": ["a"]}"#).unwrap();
+
+        let config = Config::<Settings>::builder(&base)
+            .layer(&local)
+            .layer(tmp.join("missing.json"))
+            .load()
+            .unwrap();
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.sources(), [base.clone(), local]);
+
+        assert_eq!(
+            Config::<Settings>::load(tmp.join("none.json"))
+                .unwrap()
+                .get(),
+            &Settings::default()
+        );
+        fs::write(&base, r#"{"workers": "many"}"#).unwrap();
+        let err = Config::<Settings>::load(&base).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::write(&base, "[1]").unwrap();
+        assert!(Config::<Settings>::load(&base).is_err());
+    }
+
+    #[test]
+    fn test_profile_precedence() {
+        let tmp = TempDir::new("apiari-config-test-profile").unwrap();
+        let base = tmp.join("config.json");
+        let local = tmp.join("local.json");
+        fs::write(
+            &base,
+            r#"{"workers": 1, "server": {"port": 1},
+                "profile": {"prod": {"workers": 8, "server": {"host": "prod"}}}}"#,
+        )
+        .unwrap();
+        fs::write(tmp.join("config.prod.json"), r#"{"workers": 16}"#).unwrap();
+        fs::write(&local, r#"{"server": {"port": 2}}"#).unwrap();
+        let builder = || {
+            Config::<Settings>::builder(&base)
+                .layer(&local)
+                .without_profile_env()
+        };
+
+        let plain = builder().load().unwrap();
+        assert_eq!((plain.workers, plain.server.port), (1, 2));
+        assert_eq!(plain.profile(), None);
+        assert!(plain.raw().get(PROFILE_KEY).is_none());
+
+        let prod = builder().profile("prod").load().unwrap();
+        assert_eq!(prod.profile(), Some("prod"));
+        assert_eq!(prod.workers, 16, "overlay file beats the section");
+        assert_eq!(prod.server.host, "prod");
+        assert_eq!(prod.server.port, 2, "later layer beats earlier profile");
+        assert_eq!(prod.sources().len(), 3);
+
+        let err = builder().profile("prdo").load().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(builder().profile("../etc").load().is_err());
+
+        unsafe { std::env::set_var("APIARI_TEST_CONFIG_PROFILE", "prod") };
+        let from_env = Config::<Settings>::builder(&base)
+            .profile_env("APIARI_TEST_CONFIG_PROFILE")
+            .load()
+            .unwrap();
+        assert_eq!(from_env.workers, 16);
+    }
+
+    #[test]
+    fn test_diff_reports_dotted_keys() {
+        let old = json!({ "a": 1, "server": { "port": 80, "host": "x" }, "tags": [1] });
+        let new = json!({ "server": { "port": 81, "host": "x", "tls": true }, "tags": [1, 2] });
+        let keys: Vec<_> = diff(&old, &new).into_iter().map(|c| c.key).collect();
+        assert_eq!(keys, ["a", "server.port", "server.tls", "tags"]);
+        let changes = diff(&old, &new);
+        assert_eq!(changes[0].new, None);
+        assert_eq!(changes[2].old, None);
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_watch_applies_valid_and_rejects_invalid() {
+        let tmp = TempDir::new("apiari-config-test-watch").unwrap();
+        let path = tmp.join("config.json");
+        fs::write(&path, r#"{"workers": 4}"#).unwrap();
+        let live = Config::<Settings>::builder(&path)
+            .interval(TICK)
+            .validate(|s| {
+                if s.workers == 0 {
+                    Err("workers must be at least 1".into())
+                } else {
+                    Ok(())
+                }
+            })
+            .watch()
+            .unwrap();
+        let (tx, rx) = mpsc::channel();
+        live.subscribe(move |update| {
+            let keys: Vec<_> = update.changes.iter().map(|c| c.key.clone()).collect();
+            tx.send((keys, update.changed("server"), update.config.workers))
+                .unwrap();
+        });
+        let (rejected_tx, rejected_rx) = mpsc::channel();
+        live.on_rejected(move |e| rejected_tx.send(e.to_string()).unwrap());
+
+        fs::write(&path, r#"{"workers": 6, "server": {"port": 1}}"#).unwrap();
+        let (keys, server_changed, workers) = rx.recv_timeout(WAIT).unwrap();
+        assert_eq!(keys, ["server.port", "workers"]);
+        assert!(server_changed);
+        assert_eq!(workers, 6);
+
+        fs::write(&path, r#"{"workers": 0, "server": {"port": 12}}"#).unwrap();
+        let err = rejected_rx.recv_timeout(WAIT).unwrap();
+        assert!(err.contains("workers must be at least 1"), "{err}");
+        assert_eq!(live.snapshot().workers, 6);
+        assert!(live.last_error().is_some());
+
+        fs::write(&path, r#"{"server": {"port": 1},   "workers": 6}"#).unwrap();
+        fs::write(&path, r#"{"workers": 7, "server": {"port": 1}}"#).unwrap();
+        let (keys, _, workers) = rx.recv_timeout(WAIT).unwrap();
+        assert_eq!((keys, workers), (vec!["workers".to_string()], 7));
+        assert_eq!(live.last_error(), None);
+    }
+
+    #[test]
+    fn test_watch_layer_and_overlay_changed_together() {
+        let tmp = TempDir::new("apiari-config-test-watch-overlay").unwrap();
+        let base = tmp.join("config.json");
+        let overlay = tmp.join("config.prod.json");
+        fs::write(&base, r#"{"workers": 1}"#).unwrap();
+        fs::write(&overlay, r#"{"server": {"port": 1}}"#).unwrap();
+        let live = Config::<Settings>::builder(&base)
+            .without_profile_env()
+            .profile("prod")
+            .interval(TICK)
+            .watch()
+            .unwrap();
+        let (tx, rx) = mpsc::channel();
+        live.subscribe(move |update| {
+            tx.send((update.config.workers, update.config.server.port))
+                .unwrap();
+        });
+
+        fs::write(&base, r#"{"workers": 5}"#).unwrap();
+        fs::write(&overlay, r#"{"server": {"port": 9}}"#).unwrap();
+        let mut seen = vec![(1, 1)];
+        while *seen.last().unwrap() != (5, 9) {
+            seen.push(rx.recv_timeout(WAIT).unwrap());
+        }
+        // Every update moved forward; none reverted to an older load.
+        assert!(
+            seen.windows(2)
+                .all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1)
+        );
+        assert!(rx.recv_timeout(TICK * 10).is_err());
+        let snapshot = live.snapshot();
+        assert_eq!((snapshot.workers, snapshot.server.port), (5, 9));
+    }
+}
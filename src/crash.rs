@@ -0,0 +1,210 @@
+//! Panic capture to a JSONL crash log.
+//!
+//! [`install_hook`] adds a panic hook that appends a [`CrashRecord`] —
+//! message, source location, thread, backtrace, app version, and the most
+//! recent breadcrumbs — to a JSONL file through [`JsonlWriter`], then runs
+//! the previously installed hook so the usual stderr output is kept.
+//!
+//! Breadcrumbs are short notes the application leaves along the way
+//! ([`breadcrumb`]); the last [`MAX_BREADCRUMBS`] are kept in memory and
+//! attached to the next crash record.
+
+use crate::clock::now_ms;
+use crate::ipc::JsonlWriter;
+use serde::{Deserialize, Serialize};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::panic::{self, PanicHookInfo};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+
+/// Breadcrumbs retained for the next crash record.
+pub const MAX_BREADCRUMBS: usize = 50;
+
+/// A note left by application code before a crash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Breadcrumb {
+    pub ts_ms: u64,
+    pub message: String,
+}
+
+/// One panic, as written to the crash log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashRecord {
+    pub ts_ms: u64,
+    pub message: String,
+    /// `file:line:column` of the panic, if known.
+    pub location: Option<String>,
+    /// Thread name, or its id if unnamed.
+    pub thread: String,
+    pub version: Option<String>,
+    pub pid: u32,
+    pub backtrace: String,
+    /// Oldest first.
+    pub breadcrumbs: Vec<Breadcrumb>,
+}
+
+static BREADCRUMBS: Mutex<Breadcrumbs> = Mutex::new(Breadcrumbs::new(MAX_BREADCRUMBS));
+
+/// Bounded ring of breadcrumbs.
+#[derive(Debug)]
+struct Breadcrumbs {
+    items: VecDeque<Breadcrumb>,
+    cap: usize,
+}
+
+impl Breadcrumbs {
+    const fn new(cap: usize) -> Self {
+        Self {
+            items: VecDeque::new(),
+            cap,
+        }
+    }
+
+    fn push(&mut self, message: String) {
+        if self.items.len() == self.cap {
+            self.items.pop_front();
+        }
+        self.items.push_back(Breadcrumb {
+            ts_ms: now_ms(),
+            message,
+        });
+    }
+
+    fn snapshot(&self) -> Vec<Breadcrumb> {
+        self.items.iter().cloned().collect()
+    }
+}
+
+/// Record a breadcrumb to include in the next crash record.
+pub fn breadcrumb(message: impl Into<String>) {
+    BREADCRUMBS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(message.into());
+}
+
+/// Configures and installs the crash hook.
+#[derive(Debug, Clone)]
+pub struct CrashReporter {
+    path: PathBuf,
+    version: Option<String>,
+}
+
+impl CrashReporter {
+    /// Report crashes to the JSONL file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            version: None,
+        }
+    }
+
+    /// Include the application version in every record (typically
+    /// `env!("CARGO_PKG_VERSION")`).
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Install the panic hook, chaining to the current one.
+    pub fn install(self) {
+        let writer = JsonlWriter::new(self.path);
+        let version = self.version;
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // Writing is best-effort: a failing crash log must not turn one
+            // panic into a double panic (which aborts).
+            let _ = writer.append(&record(info, version.clone()));
+            previous(info);
+        }));
+    }
+}
+
+/// Install a panic hook that appends crash records to `path`.
+pub fn install_hook(path: impl Into<PathBuf>) {
+    CrashReporter::new(path).install();
+}
+
+fn record(info: &PanicHookInfo<'_>, version: Option<String>) -> CrashRecord {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    let current = thread::current();
+    let breadcrumbs = match BREADCRUMBS.try_lock() {
+        Ok(crumbs) => crumbs.snapshot(),
+        // Don't deadlock if the panic happened while recording a breadcrumb.
+        Err(_) => Vec::new(),
+    };
+    CrashRecord {
+        ts_ms: now_ms(),
+        message,
+        location: info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+        thread: current
+            .name()
+            .map_or_else(|| format!("{:?}", current.id()), str::to_string),
+        version,
+        pid: std::process::id(),
+        backtrace: Backtrace::force_capture().to_string(),
+        breadcrumbs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::JsonlReader;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_breadcrumbs_are_bounded() {
+        let mut crumbs = Breadcrumbs::new(3);
+        for i in 0..5 {
+            crumbs.push(format!("step {i}"));
+        }
+        let messages: Vec<String> = crumbs.snapshot().into_iter().map(|b| b.message).collect();
+        assert_eq!(messages, ["step 2", "step 3", "step 4"]);
+    }
+
+    #[test]
+    fn test_panic_writes_record() {
+        let tmp = TempDir::new("apiari-crash-test-hook").unwrap();
+        let path = tmp.join("crashes.jsonl");
+        CrashReporter::new(&path).version("1.2.3").install();
+
+        breadcrumb("loading config");
+        breadcrumb("crash-test: about to fail");
+        let result = thread::Builder::new()
+            .name("crash-test-worker".into())
+            .spawn(|| panic!("crash-test: boom {}", 42))
+            .unwrap()
+            .join();
+        // Restore the default hook before asserting, so other tests'
+        // panics don't land in this file.
+        let _ = panic::take_hook();
+        assert!(result.is_err());
+
+        let records: Vec<CrashRecord> = JsonlReader::new(&path).poll().unwrap();
+        let record = records
+            .iter()
+            .find(|r| r.message == "crash-test: boom 42")
+            .expect("crash record written");
+        assert_eq!(record.thread, "crash-test-worker");
+        assert_eq!(record.version.as_deref(), Some("1.2.3"));
+        assert_eq!(record.pid, std::process::id());
+        assert!(record.location.as_ref().unwrap().contains("crash.rs"));
+        assert!(!record.backtrace.is_empty());
+        assert!(
+            record
+                .breadcrumbs
+                .iter()
+                .any(|b| b.message == "crash-test: about to fail")
+        );
+    }
+}
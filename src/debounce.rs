@@ -0,0 +1,428 @@
+//! Debouncing and coalescing of repeated triggers.
+//!
+//! Both types run a callback on a background thread and fold any number of
+//! [`trigger`](Debouncer::trigger) calls into a single run:
+//!
+//! - [`Debouncer`] runs once the triggers have gone quiet for `delay`
+//!   (autosave after the user stops typing). An optional `max_wait` bounds
+//!   how long a steady stream of triggers can postpone the run.
+//! - [`Coalescer`] runs at most once per `interval` (metrics flush, watcher
+//!   rescans). An idle coalescer runs immediately on the first trigger.
+//!
+//! Dropping either one runs any pending callback before the thread exits,
+//! so a final autosave is never lost. [`AsyncDebouncer`] exposes the same
+//! timing as a runtime-agnostic future instead of a callback.
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Runs a callback after triggers have been quiet for a delay.
+#[derive(Debug)]
+pub struct Debouncer {
+    worker: Worker,
+}
+
+impl Debouncer {
+    /// Run `callback` once triggers have been quiet for `delay`.
+    pub fn new(delay: Duration, callback: impl FnMut() + Send + 'static) -> Self {
+        Self::with_max_wait(delay, None, callback)
+    }
+
+    /// Like [`new`](Self::new), but never postpone a run more than
+    /// `max_wait` after the first unhandled trigger.
+    pub fn with_max_wait(
+        delay: Duration,
+        max_wait: Option<Duration>,
+        callback: impl FnMut() + Send + 'static,
+    ) -> Self {
+        Self {
+            worker: Worker::start(Timing::Debounce { delay, max_wait }, callback),
+        }
+    }
+
+    /// Record a trigger, restarting the quiet period.
+    pub fn trigger(&self) {
+        self.worker.trigger();
+    }
+
+    /// Run the callback now if a trigger is pending, and wait for it to
+    /// finish. Must not be called from inside the callback.
+    pub fn flush(&self) {
+        self.worker.flush();
+    }
+
+    /// Return `true` if a trigger is waiting to be handled.
+    pub fn is_pending(&self) -> bool {
+        self.worker.is_pending()
+    }
+}
+
+/// Runs a callback at most once per interval, coalescing triggers.
+#[derive(Debug)]
+pub struct Coalescer {
+    worker: Worker,
+}
+
+impl Coalescer {
+    /// Run `callback` at most once per `interval`.
+    pub fn new(interval: Duration, callback: impl FnMut() + Send + 'static) -> Self {
+        Self {
+            worker: Worker::start(Timing::Coalesce { interval }, callback),
+        }
+    }
+
+    /// Record a trigger. Runs immediately if the last run was at least
+    /// `interval` ago, otherwise once the interval has elapsed.
+    pub fn trigger(&self) {
+        self.worker.trigger();
+    }
+
+    /// Run the callback now if a trigger is pending, and wait for it to
+    /// finish. Must not be called from inside the callback.
+    pub fn flush(&self) {
+        self.worker.flush();
+    }
+
+    /// Return `true` if a trigger is waiting to be handled.
+    pub fn is_pending(&self) -> bool {
+        self.worker.is_pending()
+    }
+}
+
+/// Debouncer whose runs are observed by awaiting [`fired`](Self::fired).
+///
+/// The timing runs on a background thread, so the future works with any
+/// executor.
+#[derive(Debug)]
+pub struct AsyncDebouncer {
+    worker: Worker,
+    signal: Arc<Signal>,
+}
+
+#[derive(Debug, Default)]
+struct Signal {
+    state: Mutex<SignalState>,
+}
+
+#[derive(Debug, Default)]
+struct SignalState {
+    fired: u64,
+    wakers: Vec<Waker>,
+}
+
+impl AsyncDebouncer {
+    /// Fire once triggers have been quiet for `delay`.
+    pub fn new(delay: Duration) -> Self {
+        Self::with_timing(Timing::Debounce {
+            delay,
+            max_wait: None,
+        })
+    }
+
+    /// Fire at most once per `interval`.
+    pub fn coalescing(interval: Duration) -> Self {
+        Self::with_timing(Timing::Coalesce { interval })
+    }
+
+    fn with_timing(timing: Timing) -> Self {
+        let signal = Arc::new(Signal::default());
+        let notify = Arc::clone(&signal);
+        let worker = Worker::start(timing, move || {
+            let mut state = lock(&notify.state);
+            state.fired += 1;
+            for waker in state.wakers.drain(..) {
+                waker.wake();
+            }
+        });
+        Self { worker, signal }
+    }
+
+    /// Record a trigger.
+    pub fn trigger(&self) {
+        self.worker.trigger();
+    }
+
+    /// Wait for the next time the debouncer fires.
+    ///
+    /// Only fires that happen after this call complete the future.
+    pub fn fired(&self) -> Fired {
+        let seen = lock(&self.signal.state).fired;
+        Fired {
+            signal: Arc::clone(&self.signal),
+            seen,
+        }
+    }
+}
+
+/// Future returned by [`AsyncDebouncer::fired`].
+#[derive(Debug)]
+pub struct Fired {
+    signal: Arc<Signal>,
+    seen: u64,
+}
+
+impl Future for Fired {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = lock(&self.signal.state);
+        if state.fired > self.seen {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Timing {
+    Debounce {
+        delay: Duration,
+        max_wait: Option<Duration>,
+    },
+    Coalesce {
+        interval: Duration,
+    },
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// First and latest trigger since the last run, if one is pending.
+    first: Option<Instant>,
+    last: Option<Instant>,
+    last_run: Option<Instant>,
+    runs: u64,
+    flush: bool,
+    shutdown: bool,
+}
+
+impl State {
+    fn deadline(&self, timing: Timing) -> Option<Instant> {
+        let (first, last) = (self.first?, self.last?);
+        Some(match timing {
+            Timing::Debounce { delay, max_wait } => {
+                let quiet = last + delay;
+                max_wait.map_or(quiet, |max| quiet.min(first + max))
+            }
+            Timing::Coalesce { interval } => {
+                self.last_run.map_or(first, |r| (r + interval).max(first))
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+#[derive(Debug)]
+struct Worker {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn start(timing: Timing, mut callback: impl FnMut() + Send + 'static) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            cond: Condvar::new(),
+        });
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || {
+            let shared = thread_shared;
+            let mut state = lock(&shared.state);
+            loop {
+                let deadline = state.deadline(timing);
+                let due =
+                    deadline.is_some_and(|d| state.flush || state.shutdown || Instant::now() >= d);
+                if due {
+                    state.first = None;
+                    state.last = None;
+                    state.flush = false;
+                    state.last_run = Some(Instant::now());
+                    drop(state);
+                    // A panicking callback must not wedge flush() or later triggers.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(&mut callback));
+                    state = lock(&shared.state);
+                    state.runs += 1;
+                    shared.cond.notify_all();
+                    continue;
+                }
+                if state.shutdown {
+                    return;
+                }
+                state.flush = false;
+                state = match deadline {
+                    Some(d) => {
+                        let timeout = d.saturating_duration_since(Instant::now());
+                        shared
+                            .cond
+                            .wait_timeout(state, timeout)
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0
+                    }
+                    None => shared.cond.wait(state).unwrap_or_else(|e| e.into_inner()),
+                };
+            }
+        });
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    fn trigger(&self) {
+        let mut state = lock(&self.shared.state);
+        let now = Instant::now();
+        state.first.get_or_insert(now);
+        state.last = Some(now);
+        self.shared.cond.notify_all();
+    }
+
+    fn flush(&self) {
+        let mut state = lock(&self.shared.state);
+        if state.first.is_none() {
+            return;
+        }
+        let target = state.runs + 1;
+        state.flush = true;
+        self.shared.cond.notify_all();
+        while state.runs < target {
+            state = self
+                .shared
+                .cond
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        lock(&self.shared.state).first.is_some()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        lock(&self.shared.state).shutdown = true;
+        self.shared.cond.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counter() -> (Arc<AtomicUsize>, impl FnMut() + Send + 'static) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let inner = Arc::clone(&count);
+        (count, move || {
+            inner.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    #[test]
+    fn test_debouncer_coalesces_burst() {
+        let (count, callback) = counter();
+        let debouncer = Debouncer::new(Duration::from_millis(50), callback);
+        for _ in 0..10 {
+            debouncer.trigger();
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(!debouncer.is_pending());
+    }
+
+    #[test]
+    fn test_debouncer_max_wait_bounds_delay() {
+        let (count, callback) = counter();
+        let debouncer = Debouncer::with_max_wait(
+            Duration::from_millis(100),
+            Some(Duration::from_millis(60)),
+            callback,
+        );
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(250) {
+            debouncer.trigger();
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(count.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn test_flush_and_drop_run_pending() {
+        let (count, callback) = counter();
+        let debouncer = Debouncer::new(Duration::from_secs(60), callback);
+        debouncer.flush();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        debouncer.trigger();
+        debouncer.flush();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        debouncer.trigger();
+        drop(debouncer);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_coalescer_limits_rate() {
+        let (count, callback) = counter();
+        let coalescer = Coalescer::new(Duration::from_millis(100), callback);
+        coalescer.trigger();
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(count.load(Ordering::SeqCst), 1, "idle trigger runs at once");
+
+        for _ in 0..5 {
+            coalescer.trigger();
+        }
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        thread::sleep(Duration::from_millis(120));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_panicking_callback_keeps_worker_alive() {
+        let (count, mut inner) = counter();
+        let debouncer = Debouncer::new(Duration::from_millis(1), move || {
+            inner();
+            panic!("callback failure");
+        });
+        debouncer.trigger();
+        debouncer.flush();
+        debouncer.trigger();
+        debouncer.flush();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_async_debouncer_fires() {
+        let debouncer = AsyncDebouncer::new(Duration::from_millis(20));
+        let fired = debouncer.fired();
+        let start = Instant::now();
+        debouncer.trigger();
+        debouncer.trigger();
+        block_on(fired);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,314 @@
+//! Line-based three-way merge.
+//!
+//! [`merge3`] combines two edited versions of a text with their common
+//! ancestor, as `git merge-file` does. Changes made on only one side are
+//! taken, identical changes on both sides are taken once, and overlapping
+//! different changes become a [`Conflict`] holding all three versions of
+//! the region. A tool can then prompt for or compute a resolution with
+//! [`Merge::resolve`], or write git-style markers with
+//! [`Merge::with_markers`].
+//!
+//! Lines are compared with their line endings, as in [`crate::patch`].
+//! Matching lines are found with Myers' O(ND) diff.
+
+/// A region of a three-way merge where both sides changed the same lines
+/// differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// First line of the region in the base (1-based). For an insertion
+    /// this is the line the insertion comes before.
+    pub base_line: usize,
+    pub base: String,
+    pub ours: String,
+    pub theirs: String,
+}
+
+/// Part of a merge result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Region {
+    /// Text that merged cleanly.
+    Clean(String),
+    Conflict(Conflict),
+}
+
+/// The result of [`merge3`]: the merged text as clean and conflicting
+/// regions, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Merge {
+    pub regions: Vec<Region>,
+}
+
+/// How to resolve one [`Conflict`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Base,
+    /// Ours followed by theirs.
+    Both,
+    Custom(String),
+}
+
+impl Merge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts().next().is_none()
+    }
+
+    pub fn conflicts(&self) -> impl Iterator<Item = &Conflict> {
+        self.regions.iter().filter_map(|r| match r {
+            Region::Conflict(c) => Some(c),
+            Region::Clean(_) => None,
+        })
+    }
+
+    /// The merged text, if there were no conflicts.
+    pub fn text(&self) -> Option<String> {
+        self.is_clean().then(|| self.resolve(|_| Resolution::Ours))
+    }
+
+    /// The merged text with each conflict replaced by `resolve`'s choice.
+    pub fn resolve(&self, mut resolve: impl FnMut(&Conflict) -> Resolution) -> String {
+        let mut out = String::new();
+        for region in &self.regions {
+            match region {
+                Region::Clean(text) => out.push_str(text),
+                Region::Conflict(c) => match resolve(c) {
+                    Resolution::Ours => out.push_str(&c.ours),
+                    Resolution::Theirs => out.push_str(&c.theirs),
+                    Resolution::Base => out.push_str(&c.base),
+                    Resolution::Both => {
+                        push_lines(&mut out, &c.ours);
+                        out.push_str(&c.theirs);
+                    }
+                    Resolution::Custom(text) => out.push_str(&text),
+                },
+            }
+        }
+        out
+    }
+
+    /// The merged text with `git merge-file --diff3` style markers around
+    /// each conflict.
+    pub fn with_markers(&self, ours_label: &str, theirs_label: &str) -> String {
+        let mut out = String::new();
+        for region in &self.regions {
+            match region {
+                Region::Clean(text) => out.push_str(text),
+                Region::Conflict(c) => {
+                    out.push_str(&format!("<<<<<<< {ours_label}\n"));
+                    push_lines(&mut out, &c.ours);
+                    out.push_str("||||||| base\n");
+                    push_lines(&mut out, &c.base);
+                    out.push_str("=======\n");
+                    push_lines(&mut out, &c.theirs);
+                    out.push_str(&format!(">>>>>>> {theirs_label}\n"));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Append `text`, adding a newline if it doesn't end with one (so a marker
+/// or the next side starts on its own line).
+fn push_lines(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Merge the changes from `base` to `ours` and from `base` to `theirs`.
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> Merge {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let to_ours = matches(&base, &ours);
+    let to_theirs = matches(&base, &theirs);
+
+    let mut merge = Merge::default();
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    while o < base.len() || a < ours.len() || b < theirs.len() {
+        // A run of base lines that both sides kept in place.
+        let stable = (o..base.len())
+            .take_while(|&i| to_ours[i] == Some(a + i - o) && to_theirs[i] == Some(b + i - o))
+            .count();
+        if stable > 0 {
+            push_clean(&mut merge, &base[o..o + stable].concat());
+            o += stable;
+            a += stable;
+            b += stable;
+            continue;
+        }
+        // The changed chunk runs to the next base line both sides kept.
+        let next = (o..base.len()).find(|&i| to_ours[i].is_some() && to_theirs[i].is_some());
+        let (o_end, a_end, b_end) = match next {
+            Some(i) => (i, to_ours[i].unwrap_or(a), to_theirs[i].unwrap_or(b)),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        let base_chunk = &base[o..o_end];
+        let ours_chunk = &ours[a..a_end];
+        let theirs_chunk = &theirs[b..b_end];
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            push_clean(&mut merge, &theirs_chunk.concat());
+        } else if theirs_chunk == base_chunk {
+            push_clean(&mut merge, &ours_chunk.concat());
+        } else {
+            merge.regions.push(Region::Conflict(Conflict {
+                base_line: o + 1,
+                base: base_chunk.concat(),
+                ours: ours_chunk.concat(),
+                theirs: theirs_chunk.concat(),
+            }));
+        }
+        (o, a, b) = (o_end, a_end, b_end);
+    }
+    merge
+}
+
+fn push_clean(merge: &mut Merge, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match merge.regions.last_mut() {
+        Some(Region::Clean(clean)) => clean.push_str(text),
+        _ => merge.regions.push(Region::Clean(text.to_string())),
+    }
+}
+
+/// For each line of `a`, the index of the line of `b` it is matched with in
+/// a longest common subsequence.
+fn matches(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    let mut result = vec![None; a.len()];
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    for (i, m) in result.iter_mut().enumerate().take(prefix) {
+        *m = Some(i);
+    }
+    for k in 1..=suffix {
+        result[a.len() - k] = Some(b.len() - k);
+    }
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    for (x, y) in myers(a_mid, b_mid) {
+        result[prefix + x] = Some(prefix + y);
+    }
+    result
+}
+
+/// Matched `(a, b)` index pairs of a shortest edit script.
+fn myers(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let idx = |k: isize| (k + max + 1) as usize;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk back through the saved frontiers, collecting diagonal moves.
+    let mut pairs = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[idx(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    pairs.reverse();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_non_overlapping_and_identical_changes() {
+        let base = "a\nb\nc\nd\ne\n";
+        let ours = "a\nB\nc\nd\ne\nf\n";
+        let theirs = "a\nb\nc\nD\ne\nf\n";
+        let merge = merge3(base, ours, theirs);
+        assert!(merge.is_clean());
+        assert_eq!(merge.text().unwrap(), "a\nB\nc\nD\ne\nf\n");
+        assert_eq!(merge.regions.len(), 1);
+
+        // Deleting on one side while the other is unchanged.
+        assert_eq!(merge3(base, "a\ne\n", base).text().unwrap(), "a\ne\n");
+        assert_eq!(merge3("", "x\n", "").text().unwrap(), "x\n");
+        assert_eq!(merge3(base, base, base).text().unwrap(), base);
+    }
+
+    #[test]
+    fn test_conflicts_are_structured() {
+        let base = "fn main() {\n    run(1);\n}\n";
+        let ours = "fn main() {\n    run(2);\n}\n";
+        let theirs = "fn main() {\n    run(3);\n    log();\n}\n";
+        let merge = merge3(base, ours, theirs);
+        assert!(!merge.is_clean());
+        assert_eq!(merge.text(), None);
+        let conflict = merge.conflicts().next().unwrap();
+        assert_eq!(conflict.base_line, 2);
+        assert_eq!(conflict.base, "    run(1);\n");
+        assert_eq!(conflict.ours, "    run(2);\n");
+        assert_eq!(conflict.theirs, "    run(3);\n    log();\n");
+
+        assert_eq!(
+            merge.resolve(|_| Resolution::Theirs),
+            "fn main() {\n    run(3);\n    log();\n}\n"
+        );
+        assert_eq!(
+            merge.resolve(|_| Resolution::Custom("    run(4);\n".into())),
+            "fn main() {\n    run(4);\n}\n"
+        );
+        assert_eq!(
+            merge.with_markers("agent", "user"),
+            "fn main() {\n<<<<<<< agent\n    run(2);\n||||||| base\n    run(1);\n\
+             =======\n    run(3);\n    log();\n>>>>>>> user\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_myers_finds_lcs() {
+        let a = ["a", "b", "c", "a", "b", "b", "a"];
+        let b = ["c", "b", "a", "b", "a", "c"];
+        let pairs = myers(&a, &b);
+        assert_eq!(pairs.len(), 4);
+        assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        assert!(pairs.iter().all(|&(x, y)| a[x] == b[y]));
+    }
+}
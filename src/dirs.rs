@@ -0,0 +1,172 @@
+//! Standard per-user directories for Apiari tools.
+//!
+//! Each function returns the platform's conventional base directory with an
+//! `apiari` subdirectory appended, or the value of an `APIARI_*_DIR`
+//! override when set (useful for tests and sandboxes):
+//!
+//! - [`data_dir`] (`APIARI_DATA_DIR`): `$XDG_DATA_HOME` or `~/.local/share`;
+//!   `~/Library/Application Support` on macOS; `%APPDATA%` on Windows.
+//! - [`config_dir`] (`APIARI_CONFIG_DIR`): `$XDG_CONFIG_HOME` or `~/.config`;
+//!   `~/Library/Application Support` on macOS; `%APPDATA%` on Windows.
+//! - [`cache_dir`] (`APIARI_CACHE_DIR`): `$XDG_CACHE_HOME` or `~/.cache`;
+//!   `~/Library/Caches` on macOS; `%LOCALAPPDATA%` on Windows.
+//!
+//! The directories are not created.
+
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::path::PathBuf;
+
+const APP: &str = "apiari";
+
+/// Where one kind of directory comes from on each platform.
+struct Layout {
+    override_var: &'static str,
+    xdg_var: &'static str,
+    xdg_default: &'static [&'static str],
+    macos_library: &'static str,
+    windows_var: &'static str,
+}
+
+const DATA: Layout = Layout {
+    override_var: "APIARI_DATA_DIR",
+    xdg_var: "XDG_DATA_HOME",
+    xdg_default: &[".local", "share"],
+    macos_library: "Application Support",
+    windows_var: "APPDATA",
+};
+
+const CONFIG: Layout = Layout {
+    override_var: "APIARI_CONFIG_DIR",
+    xdg_var: "XDG_CONFIG_HOME",
+    xdg_default: &[".config"],
+    macos_library: "Application Support",
+    windows_var: "APPDATA",
+};
+
+const CACHE: Layout = Layout {
+    override_var: "APIARI_CACHE_DIR",
+    xdg_var: "XDG_CACHE_HOME",
+    xdg_default: &[".cache"],
+    macos_library: "Caches",
+    windows_var: "LOCALAPPDATA",
+};
+
+/// The user's home directory.
+pub fn home_dir() -> Option<PathBuf> {
+    home(&process_env)
+}
+
+/// Directory for persistent application data.
+pub fn data_dir() -> io::Result<PathBuf> {
+    resolve(&DATA, &process_env)
+}
+
+/// Directory for user configuration.
+pub fn config_dir() -> io::Result<PathBuf> {
+    resolve(&CONFIG, &process_env)
+}
+
+/// Directory for caches that may be deleted at any time.
+pub fn cache_dir() -> io::Result<PathBuf> {
+    resolve(&CACHE, &process_env)
+}
+
+/// Resolve `layout` with environment variables read through `env`.
+fn resolve(layout: &Layout, env: &dyn Fn(&str) -> Option<OsString>) -> io::Result<PathBuf> {
+    if let Some(dir) = env_path(env, layout.override_var) {
+        return Ok(dir);
+    }
+    let base = if cfg!(windows) {
+        env_path(env, layout.windows_var)
+    } else if cfg!(target_os = "macos") {
+        home(env).map(|h| h.join("Library").join(layout.macos_library))
+    } else {
+        env_path(env, layout.xdg_var)
+            .or_else(|| home(env).map(|h| layout.xdg_default.iter().fold(h, |p, c| p.join(c))))
+    };
+    base.map(|b| b.join(APP)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("cannot determine a directory; set {}", layout.override_var),
+        )
+    })
+}
+
+/// The real environment.
+fn process_env(var: &str) -> Option<OsString> {
+    env::var_os(var)
+}
+
+fn home(env: &dyn Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env_path(env, var)
+}
+
+/// An absolute path from an environment variable. Relative values are
+/// ignored, as the XDG spec requires.
+fn env_path(env: &dyn Fn(&str) -> Option<OsString>, var: &str) -> Option<PathBuf> {
+    env(var).map(PathBuf::from).filter(|p| p.is_absolute())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An environment holding only `vars`.
+    fn vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + use<> {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |var| {
+            vars.iter()
+                .find(|(k, _)| k == var)
+                .map(|(_, v)| OsString::from(v))
+        }
+    }
+
+    #[test]
+    fn test_override_wins() {
+        let dir = std::env::temp_dir().join("apiari-dirs-test-override");
+        let env = vars(&[("APIARI_CACHE_DIR", dir.to_str().unwrap())]);
+        assert_eq!(resolve(&CACHE, &env).unwrap(), dir);
+    }
+
+    #[test]
+    fn test_nothing_set_is_an_error() {
+        let err = resolve(&CONFIG, &vars(&[])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("APIARI_CONFIG_DIR"));
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_relative_values_ignored() {
+        let env = vars(&[
+            ("APIARI_CONFIG_DIR", "relative/dir"),
+            ("XDG_CONFIG_HOME", "also/relative"),
+            ("HOME", "/home/bee"),
+        ]);
+        assert_eq!(
+            resolve(&CONFIG, &env).unwrap(),
+            PathBuf::from("/home/bee/.config/apiari")
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_xdg_base() {
+        let env = vars(&[("XDG_DATA_HOME", "/xdg/data"), ("HOME", "/home/bee")]);
+        assert_eq!(
+            resolve(&DATA, &env).unwrap(),
+            PathBuf::from("/xdg/data/apiari")
+        );
+        let env = vars(&[("HOME", "/home/bee")]);
+        assert_eq!(
+            resolve(&DATA, &env).unwrap(),
+            PathBuf::from("/home/bee/.local/share/apiari")
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Leader election among identical processes.
+//!
+//! When several copies of a daemon run, exactly one should do maintenance
+//! (GC, compaction, uploads). [`campaign`] tries to take a
+//! [`lease`] on a shared path: the winner becomes the
+//! [`Role::Leader`] for as long as it keeps renewing, everyone else becomes
+//! a [`Role::Follower`].
+//!
+//! A leader that crashes or hangs stops renewing, and its lease expires
+//! after the TTL. A follower can then take over, either by blocking in
+//! [`Follower::wait`] or by starting a background [`Follower::watch`] that
+//! hands over the [`Leadership`] when it wins. A leader whose lease is taken
+//! over (for example after being suspended past its TTL) learns through
+//! [`Leadership::is_leader`] and the [`Election::on_lost`] callback, and
+//! must stop leader-only work.
+
+use crate::lease::{self, Lease, LeaseRecord};
+use crate::shutdown::ShutdownToken;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type Callback = Arc<Mutex<Option<Box<dyn FnOnce() + Send>>>>;
+
+/// Outcome of a campaign.
+#[derive(Debug)]
+pub enum Role {
+    Leader(Leadership),
+    Follower(Follower),
+}
+
+impl Role {
+    pub fn is_leader(&self) -> bool {
+        matches!(self, Self::Leader(_))
+    }
+}
+
+/// Campaign on `path` with default settings.
+pub fn campaign(path: impl Into<PathBuf>) -> io::Result<Role> {
+    Election::new(path).campaign()
+}
+
+/// Configures an election on a lease file.
+pub struct Election {
+    path: PathBuf,
+    owner: Option<String>,
+    ttl: Option<Duration>,
+    on_lost: Callback,
+}
+
+impl Election {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            owner: None,
+            ttl: None,
+            on_lost: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Name this candidate (shown to followers via [`Follower::leader`]).
+    pub fn owner(mut self, owner: impl Into<String>) -> Self {
+        self.owner = Some(owner.into());
+        self
+    }
+
+    /// How long leadership survives without renewal (the lease TTL).
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Called once, from the renewal thread, if leadership is lost after
+    /// being won (now or after a later takeover).
+    pub fn on_lost(self, callback: impl FnOnce() + Send + 'static) -> Self {
+        *self.on_lost.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(callback));
+        self
+    }
+
+    /// Try once to become leader.
+    pub fn campaign(self) -> io::Result<Role> {
+        Ok(match self.lease().try_acquire()? {
+            Some(lease) => Role::Leader(Leadership { lease }),
+            None => Role::Follower(Follower { election: self }),
+        })
+    }
+
+    fn lease(&self) -> lease::LeaseBuilder {
+        let mut builder = Lease::builder(&self.path);
+        if let Some(owner) = &self.owner {
+            builder = builder.owner(owner.clone());
+        }
+        if let Some(ttl) = self.ttl {
+            builder = builder.ttl(ttl);
+        }
+        let on_lost = Arc::clone(&self.on_lost);
+        builder.on_lost(move || {
+            let callback = on_lost.lock().unwrap_or_else(|e| e.into_inner()).take();
+            if let Some(callback) = callback {
+                callback();
+            }
+        })
+    }
+}
+
+impl fmt::Debug for Election {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Election")
+            .field("path", &self.path)
+            .field("owner", &self.owner)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Held leadership. Dropping it (or [`resign`](Self::resign)) steps down.
+#[derive(Debug)]
+pub struct Leadership {
+    lease: Lease,
+}
+
+impl Leadership {
+    /// `false` once the lease has been lost; stop leader-only work.
+    pub fn is_leader(&self) -> bool {
+        self.lease.is_held()
+    }
+
+    /// Step down now so a follower can take over without waiting for the
+    /// TTL.
+    pub fn resign(self) -> io::Result<()> {
+        self.lease.release()
+    }
+}
+
+/// A candidate that lost the campaign and may take over later.
+#[derive(Debug)]
+pub struct Follower {
+    election: Election,
+}
+
+impl Follower {
+    /// The most recent leader's lease record (check
+    /// [`LeaseRecord::is_expired_at`] to see if it is still live).
+    pub fn leader(&self) -> io::Result<Option<LeaseRecord>> {
+        lease::read(&self.election.path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.election.path
+    }
+
+    /// Campaign once more.
+    pub fn retry(self) -> io::Result<Role> {
+        self.election.campaign()
+    }
+
+    /// Block until this candidate becomes leader.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::WouldBlock` if the current leader still
+    /// holds the lease after `timeout`.
+    pub fn wait(self, timeout: Duration) -> io::Result<Leadership> {
+        let lease = self.election.lease().acquire(timeout)?;
+        Ok(Leadership { lease })
+    }
+
+    /// Campaign every `interval` on a background thread and call
+    /// `on_elected` once this candidate wins. Dropping the returned handle
+    /// stops campaigning.
+    pub fn watch(
+        self,
+        interval: Duration,
+        on_elected: impl FnOnce(Leadership) + Send + 'static,
+    ) -> FollowerWatch {
+        let shutdown = ShutdownToken::new();
+        let token = shutdown.clone();
+        let election = self.election;
+        let thread = thread::spawn(move || {
+            while !token.wait_timeout(interval) {
+                if let Ok(Some(lease)) = election.lease().try_acquire() {
+                    on_elected(Leadership { lease });
+                    return;
+                }
+            }
+        });
+        FollowerWatch {
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Stops a [`Follower::watch`] thread when dropped.
+#[derive(Debug)]
+pub struct FollowerWatch {
+    shutdown: ShutdownToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for FollowerWatch {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lock::LockFile;
+    use crate::testutil::TempDir;
+    use std::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_one_leader_and_resign_handover() {
+        let tmp = TempDir::new("apiari-election-test-resign").unwrap();
+        let path = tmp.join("maintenance.lease");
+        let Role::Leader(leader) = Election::new(&path).owner("a").campaign().unwrap() else {
+            panic!("first candidate should lead");
+        };
+        let Role::Follower(follower) = Election::new(&path).owner("b").campaign().unwrap() else {
+            panic!("second candidate should follow");
+        };
+        assert_eq!(follower.leader().unwrap().unwrap().owner, "a");
+        assert!(leader.is_leader());
+
+        let follower = match follower.retry().unwrap() {
+            Role::Follower(follower) => follower,
+            Role::Leader(_) => panic!("leader is still alive"),
+        };
+        leader.resign().unwrap();
+        let leadership = follower.wait(WAIT).unwrap();
+        assert!(leadership.is_leader());
+        assert!(!campaign(&path).unwrap().is_leader());
+    }
+
+    #[test]
+    fn test_watch_takeover_and_loss_callback() {
+        let tmp = TempDir::new("apiari-election-test-watch").unwrap();
+        let path = tmp.join("maintenance.lease");
+        let ttl = Duration::from_millis(150);
+        let (lost_tx, lost_rx) = mpsc::channel();
+        let Role::Leader(leader) = Election::new(&path)
+            .ttl(ttl)
+            .on_lost(move || lost_tx.send(()).unwrap())
+            .campaign()
+            .unwrap()
+        else {
+            panic!("expected leader");
+        };
+        let Role::Follower(follower) = Election::new(&path).ttl(ttl).campaign().unwrap() else {
+            panic!("expected follower");
+        };
+        let (tx, rx) = mpsc::channel();
+        let _watch = follower.watch(Duration::from_millis(10), move |leadership| {
+            tx.send(leadership).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // Another process overwrites the lease (as if this one had been
+        // suspended past its TTL): the old leader is told it lost. Writers
+        // hold `<path>.lock`, as the renewer does.
+        let guard = LockFile::acquire(tmp.join("maintenance.lease.lock"), WAIT).unwrap();
+        let mut record = lease::read(&path).unwrap().unwrap();
+        record.token = "someone-else".to_string();
+        crate::state::save_state(&path, &record).unwrap();
+        drop(guard);
+        lost_rx.recv_timeout(WAIT).unwrap();
+        assert!(!leader.is_leader());
+        drop(leader);
+
+        // The usurper never renews, so the watcher wins after the TTL.
+        let new_leader = rx.recv_timeout(WAIT).unwrap();
+        assert!(new_leader.is_leader());
+    }
+}
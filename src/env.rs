@@ -0,0 +1,233 @@
+//! Typed environment variable parsing.
+//!
+//! Wraps `std::env::var` with parsing and uniform error messages that always
+//! name the offending variable. An empty value is treated the same as an
+//! unset variable, so `FOO= tool` behaves like `tool`.
+
+use crate::parse;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Error reading or parsing an environment variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvError {
+    /// A required variable is unset or empty.
+    Missing { name: String },
+    /// The variable is set but is not valid Unicode.
+    NotUnicode { name: String },
+    /// The variable is set but its value could not be parsed.
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl EnvError {
+    /// The name of the variable the error refers to.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Missing { name } | Self::NotUnicode { name } | Self::Invalid { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { name } => write!(f, "environment variable {name} is not set"),
+            Self::NotUnicode { name } => {
+                write!(f, "environment variable {name} is not valid unicode")
+            }
+            Self::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(
+                f,
+                "environment variable {name} has invalid value {value:?}: {reason}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+impl From<EnvError> for io::Error {
+    fn from(e: EnvError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// Read a variable as a string, treating unset and empty the same.
+fn raw(name: &str) -> Result<Option<String>, EnvError> {
+    match std::env::var(name) {
+        Ok(v) if v.is_empty() => Ok(None),
+        Ok(v) => Ok(Some(v)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(_)) => Err(EnvError::NotUnicode {
+            name: name.to_string(),
+        }),
+    }
+}
+
+fn invalid(name: &str, value: &str, reason: impl fmt::Display) -> EnvError {
+    EnvError::Invalid {
+        name: name.to_string(),
+        value: value.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Read and parse a variable. Returns `Ok(None)` if it is unset or empty.
+///
+/// Surrounding whitespace is trimmed before parsing.
+///
+/// # Errors
+///
+/// Returns [`EnvError::Invalid`] if the value does not parse as `T`, or
+/// [`EnvError::NotUnicode`] if it is not valid Unicode.
+pub fn get<T>(name: &str) -> Result<Option<T>, EnvError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match raw(name)? {
+        Some(v) => v.trim().parse().map(Some).map_err(|e| invalid(name, &v, e)),
+        None => Ok(None),
+    }
+}
+
+/// Read and parse a variable, falling back to `default` if it is unset or
+/// empty. A value that is set but invalid is still an error.
+pub fn get_or<T>(name: &str, default: T) -> Result<T, EnvError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Ok(get(name)?.unwrap_or(default))
+}
+
+/// Read a required variable as a string.
+///
+/// # Errors
+///
+/// Returns [`EnvError::Missing`] if it is unset or empty.
+pub fn require(name: &str) -> Result<String, EnvError> {
+    raw(name)?.ok_or_else(|| EnvError::Missing {
+        name: name.to_string(),
+    })
+}
+
+/// Read a boolean flag.
+///
+/// Accepts (case-insensitively) `1`/`0`, `true`/`false`, `yes`/`no`, and
+/// `on`/`off`.
+pub fn get_bool(name: &str) -> Result<Option<bool>, EnvError> {
+    let Some(v) = raw(name)? else {
+        return Ok(None);
+    };
+    match v.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(Some(true)),
+        "0" | "false" | "no" | "off" => Ok(Some(false)),
+        _ => Err(invalid(
+            name,
+            &v,
+            "expected one of 1/0, true/false, yes/no, on/off",
+        )),
+    }
+}
+
+/// Read a duration in the format accepted by [`crate::parse::duration`]
+/// (e.g. `30s`, `5m`, `1h30m`, `500ms`; a bare number is seconds).
+pub fn get_duration(name: &str) -> Result<Option<Duration>, EnvError> {
+    let Some(v) = raw(name)? else {
+        return Ok(None);
+    };
+    parse::duration(&v)
+        .map(Some)
+        .map_err(|e| invalid(name, &v, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test uses its own variable names so tests can run in parallel.
+    fn set(name: &str, value: &str) {
+        unsafe { std::env::set_var(name, value) };
+    }
+
+    #[test]
+    fn test_get_parses_and_handles_unset() {
+        set("APIARI_ENV_TEST_GET", " 42 ");
+        assert_eq!(get::<u32>("APIARI_ENV_TEST_GET").unwrap(), Some(42));
+        assert_eq!(get::<u32>("APIARI_ENV_TEST_GET_UNSET").unwrap(), None);
+
+        set("APIARI_ENV_TEST_GET_EMPTY", "");
+        assert_eq!(get::<u32>("APIARI_ENV_TEST_GET_EMPTY").unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_value_names_variable() {
+        set("APIARI_ENV_TEST_INVALID", "abc");
+        let err = get::<u32>("APIARI_ENV_TEST_INVALID").unwrap_err();
+        assert_eq!(err.name(), "APIARI_ENV_TEST_INVALID");
+        let msg = err.to_string();
+        assert!(msg.contains("APIARI_ENV_TEST_INVALID"), "{msg}");
+        assert!(msg.contains("\"abc\""), "{msg}");
+
+        // A set-but-invalid value is not masked by the default.
+        assert!(get_or("APIARI_ENV_TEST_INVALID", 7u32).is_err());
+    }
+
+    #[test]
+    fn test_get_or_and_require() {
+        assert_eq!(get_or("APIARI_ENV_TEST_DEFAULT_UNSET", 7u32).unwrap(), 7);
+
+        let err = require("APIARI_ENV_TEST_REQUIRE_UNSET").unwrap_err();
+        assert_eq!(
+            err,
+            EnvError::Missing {
+                name: "APIARI_ENV_TEST_REQUIRE_UNSET".into()
+            }
+        );
+
+        set("APIARI_ENV_TEST_REQUIRE", "value");
+        assert_eq!(require("APIARI_ENV_TEST_REQUIRE").unwrap(), "value");
+    }
+
+    #[test]
+    fn test_get_bool() {
+        set("APIARI_ENV_TEST_BOOL_YES", "Yes");
+        set("APIARI_ENV_TEST_BOOL_OFF", "off");
+        set("APIARI_ENV_TEST_BOOL_BAD", "maybe");
+        assert_eq!(get_bool("APIARI_ENV_TEST_BOOL_YES").unwrap(), Some(true));
+        assert_eq!(get_bool("APIARI_ENV_TEST_BOOL_OFF").unwrap(), Some(false));
+        assert!(get_bool("APIARI_ENV_TEST_BOOL_BAD").is_err());
+        assert_eq!(get_bool("APIARI_ENV_TEST_BOOL_UNSET").unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_duration() {
+        set("APIARI_ENV_TEST_DUR_BARE", "30");
+        set("APIARI_ENV_TEST_DUR_MS", "250ms");
+        set("APIARI_ENV_TEST_DUR_M", "1h30m");
+        set("APIARI_ENV_TEST_DUR_BAD", "soon");
+        assert_eq!(
+            get_duration("APIARI_ENV_TEST_DUR_BARE").unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            get_duration("APIARI_ENV_TEST_DUR_MS").unwrap(),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            get_duration("APIARI_ENV_TEST_DUR_M").unwrap(),
+            Some(Duration::from_secs(5400))
+        );
+        assert!(get_duration("APIARI_ENV_TEST_DUR_BAD").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Shared exit codes and their classification.
+//!
+//! Every Apiari tool exits with one of the codes in this registry, so a
+//! supervisor can tell a usage mistake (don't retry, tell the user) from a
+//! transient failure (retry later). The codes follow BSD `sysexits.h` where
+//! one fits, and shells' `128 + SIGINT` for cancellation:
+//!
+//! | Class       | Code | Meaning                                      |
+//! |-------------|------|----------------------------------------------|
+//! | `Success`   | 0    |                                              |
+//! | `Usage`     | 64   | bad arguments or input (`EX_USAGE`)          |
+//! | `Fatal`     | 70   | a bug or unrecoverable error (`EX_SOFTWARE`) |
+//! | `Transient` | 75   | try again later (`EX_TEMPFAIL`)              |
+//! | `Config`    | 78   | bad or missing configuration (`EX_CONFIG`)   |
+//! | `Cancelled` | 130  | interrupted by the user or a shutdown        |
+//!
+//! [`classify`] maps any code, including those of tools that don't use this
+//! registry, onto a [`Class`]; [`classify_status`] also handles death by
+//! signal.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::process::{ExitCode, ExitStatus};
+
+pub const SUCCESS: i32 = 0;
+pub const USAGE: i32 = 64;
+pub const FATAL: i32 = 70;
+pub const TRANSIENT: i32 = 75;
+pub const CONFIG: i32 = 78;
+pub const CANCELLED: i32 = 130;
+
+/// What an exit code says about how the process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Class {
+    Success,
+    Usage,
+    Config,
+    Transient,
+    Fatal,
+    Cancelled,
+}
+
+impl Class {
+    /// The registry code for this class.
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Success => SUCCESS,
+            Self::Usage => USAGE,
+            Self::Config => CONFIG,
+            Self::Transient => TRANSIENT,
+            Self::Fatal => FATAL,
+            Self::Cancelled => CANCELLED,
+        }
+    }
+
+    /// Whether running the same command again may succeed.
+    pub fn is_retryable(self) -> bool {
+        self == Self::Transient
+    }
+
+    /// Exit the process with this class's code.
+    pub fn exit(self) -> ! {
+        std::process::exit(self.code())
+    }
+
+    /// The class for a tool that fails with `error`: timeouts and busy or
+    /// dropped connections are transient, bad input is a usage error,
+    /// everything else is fatal.
+    pub fn of_io_error(error: &io::Error) -> Self {
+        use io::ErrorKind::*;
+        match error.kind() {
+            TimedOut | WouldBlock | Interrupted | ConnectionRefused | ConnectionReset
+            | ConnectionAborted | BrokenPipe | ResourceBusy | StorageFull => Self::Transient,
+            InvalidInput => Self::Usage,
+            _ => Self::Fatal,
+        }
+    }
+}
+
+impl From<Class> for ExitCode {
+    fn from(class: Class) -> Self {
+        // Every registry code fits in a u8.
+        ExitCode::from(class.code() as u8)
+    }
+}
+
+/// Classify an exit code from any tool.
+///
+/// Besides the registry's own codes, this knows the rest of `sysexits.h`,
+/// the common `1` (general failure) and `2` (misuse of a shell builtin or
+/// bad arguments), `124` from `timeout(1)`, and `128 + n` for a shell
+/// reporting a child killed by signal `n`.
+pub fn classify(code: i32) -> Class {
+    match code {
+        SUCCESS => Class::Success,
+        USAGE | 2 | 65 | 66 | 67 | 68 => Class::Usage,
+        CONFIG | 77 => Class::Config,
+        TRANSIENT | 69 | 71 | 74 | 124 => Class::Transient,
+        129..=192 => classify_signal(code - 128),
+        _ => Class::Fatal,
+    }
+}
+
+/// Classify a child's exit status, including death by signal on Unix.
+pub fn classify_status(status: ExitStatus) -> Class {
+    if let Some(code) = status.code() {
+        return classify(code);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return classify_signal(signal);
+        }
+    }
+    Class::Fatal
+}
+
+/// SIGHUP, SIGINT, and SIGTERM are requests to stop; SIGKILL is usually the
+/// OOM killer or a watchdog, worth a retry; anything else is a crash.
+fn classify_signal(signal: i32) -> Class {
+    match signal {
+        1 | 2 | 15 => Class::Cancelled,
+        9 => Class::Transient,
+        _ => Class::Fatal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_round_trips() {
+        for class in [
+            Class::Success,
+            Class::Usage,
+            Class::Config,
+            Class::Transient,
+            Class::Fatal,
+            Class::Cancelled,
+        ] {
+            assert_eq!(classify(class.code()), class);
+        }
+        assert_eq!(classify(1), Class::Fatal);
+        assert_eq!(classify(2), Class::Usage);
+        assert_eq!(classify(124), Class::Transient);
+        assert_eq!(classify(128 + 15), Class::Cancelled);
+        assert_eq!(classify(128 + 11), Class::Fatal);
+        assert_eq!(classify(-1), Class::Fatal);
+        assert!(Class::Transient.is_retryable() && !Class::Usage.is_retryable());
+        let timed_out = io::Error::new(io::ErrorKind::TimedOut, "slow");
+        assert_eq!(Class::of_io_error(&timed_out), Class::Transient);
+        assert_eq!(
+            serde_json::to_string(&Class::Cancelled).unwrap(),
+            "\"cancelled\""
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_classify_child_status() {
+        use std::process::Command;
+
+        let run = |script: &str| {
+            let status = Command::new("sh").arg("-c").arg(script).status().unwrap();
+            classify_status(status)
+        };
+        assert_eq!(run("exit 0"), Class::Success);
+        assert_eq!(run("exit 75"), Class::Transient);
+        assert_eq!(run("kill -TERM $$"), Class::Cancelled);
+        assert_eq!(run("kill -KILL $$"), Class::Transient);
+        assert_eq!(run("kill -SEGV $$"), Class::Fatal);
+    }
+}
@@ -0,0 +1,291 @@
+//! Feature flags shared between Apiari tools.
+//!
+//! Flags live in a JSON object file (`{"new_scheduler": true, "max_workers":
+//! 8}`) that every tool reads. An environment variable named
+//! `APIARI_FLAG_<NAME>` (upper-cased, non-alphanumerics as `_`) overrides
+//! the file for one process, which makes it easy to try a flag without
+//! touching the shared file.
+//!
+//! Writes go through [`update`], which rewrites the file atomically, and
+//! [`Flags::watch`] keeps a live copy that reloads whenever the file changes.
+
+use crate::env;
+use crate::state::{load_state, save_state};
+use crate::watch::{self, FileWatcher};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Prefix of the per-flag environment overrides.
+pub const ENV_PREFIX: &str = "APIARI_FLAG_";
+
+/// A set of flags, with environment overrides applied on lookup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flags {
+    values: BTreeMap<String, Value>,
+    env_prefix: Option<String>,
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Self {
+            values: BTreeMap::new(),
+            env_prefix: Some(ENV_PREFIX.to_string()),
+        }
+    }
+}
+
+impl Flags {
+    /// Create an empty flag set that honours environment overrides.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load flags from a JSON object file. A missing file means no flags.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::InvalidData` if the file is not a JSON object.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            values: load_state(path.as_ref())?,
+            ..Self::default()
+        })
+    }
+
+    /// Use a different environment prefix.
+    pub fn with_env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Ignore environment overrides.
+    pub fn without_env(mut self) -> Self {
+        self.env_prefix = None;
+        self
+    }
+
+    /// The raw value of a flag, from the environment or the file.
+    pub fn value(&self, name: &str) -> Option<Value> {
+        self.env_value(name)
+            .or_else(|| self.values.get(name).cloned())
+    }
+
+    /// The flag decoded as `T`, or `None` if it is unset or has another type.
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        serde_json::from_value(self.value(name)?).ok()
+    }
+
+    /// The flag decoded as `T`, or `default`.
+    pub fn get_or<T: DeserializeOwned>(&self, name: &str, default: T) -> T {
+        self.get(name).unwrap_or(default)
+    }
+
+    /// Return `true` only if the flag is set to `true`.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.get(name).unwrap_or(false)
+    }
+
+    /// Set a flag in memory. Use [`save`](Self::save) or [`update`] to
+    /// persist it.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Serialize) -> io::Result<()> {
+        let value = serde_json::to_value(value).map_err(io::Error::other)?;
+        self.values.insert(name.into(), value);
+        Ok(())
+    }
+
+    /// Remove a flag in memory.
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.values.remove(name)
+    }
+
+    /// Names of the flags in the file (environment overrides excluded).
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+
+    /// Atomically write the flags (without environment overrides) to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save_state(path.as_ref(), &self.values)
+    }
+
+    /// Load the flags and keep them current as the file changes.
+    ///
+    /// The file is checked every `interval`. A reload that fails to parse
+    /// keeps the previous flags, so a half-edited file never flips flags off.
+    pub fn watch(path: impl Into<PathBuf>, interval: Duration) -> io::Result<LiveFlags> {
+        let path = path.into();
+        let current = Arc::new(RwLock::new(Arc::new(Self::load(&path)?)));
+        let target = Arc::clone(&current);
+        let watcher = watch::watch(&path, interval, move |path| {
+            if let Ok(flags) = Self::load(path) {
+                *target.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(flags);
+            }
+        });
+        Ok(LiveFlags {
+            current,
+            _watcher: watcher,
+        })
+    }
+
+    fn env_value(&self, name: &str) -> Option<Value> {
+        let prefix = self.env_prefix.as_deref()?;
+        let var: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let var = format!("{prefix}{var}");
+        let raw: String = env::get(&var).ok()??;
+        if let Ok(value) = serde_json::from_str(&raw) {
+            return Some(value);
+        }
+        Some(match env::get_bool(&var) {
+            Ok(Some(b)) => Value::Bool(b),
+            _ => Value::String(raw),
+        })
+    }
+}
+
+/// Flags that reload when their file changes; see [`Flags::watch`].
+#[derive(Debug)]
+pub struct LiveFlags {
+    current: Arc<RwLock<Arc<Flags>>>,
+    _watcher: FileWatcher,
+}
+
+impl LiveFlags {
+    /// The most recently loaded flags.
+    pub fn snapshot(&self) -> Arc<Flags> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Load the flags at `path`, apply `change`, and write them back atomically.
+///
+/// Concurrent writers should hold a lock around this; the atomic rename only
+/// guarantees readers never see a partial file.
+pub fn update(path: impl AsRef<Path>, change: impl FnOnce(&mut Flags)) -> io::Result<Flags> {
+    let path = path.as_ref();
+    let mut flags = Flags::load(path)?.without_env();
+    change(&mut flags);
+    flags.save(path)?;
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use serde_json::json;
+    use std::fs;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn test_typed_accessors() {
+        let tmp = TempDir::new("apiari-flags-test-typed").unwrap();
+        assert!(
+            Flags::load(tmp.join("missing.json"))
+                .unwrap()
+                .names()
+                .next()
+                .is_none()
+        );
+
+        let path = tmp
+            .state_file(
+                "flags.json",
+                &json!({ "new_ui": true, "max_workers": 8, "channel": "beta" }),
+            )
+            .unwrap();
+        let flags = Flags::load(&path).unwrap().without_env();
+        assert!(flags.is_enabled("new_ui"));
+        assert!(!flags.is_enabled("max_workers"), "non-bool is not enabled");
+        assert!(!flags.is_enabled("unknown"));
+        assert_eq!(flags.get::<u32>("max_workers"), Some(8));
+        assert_eq!(flags.get::<u32>("channel"), None);
+        assert_eq!(flags.get_or("channel", "stable".to_string()), "beta");
+        assert_eq!(flags.get_or("retries", 3), 3);
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        unsafe {
+            std::env::set_var("APIARI_FLAG_TEST_ENV_UI", "off");
+            std::env::set_var("APIARI_FLAG_TEST_ENV_WORKERS", "16");
+            std::env::set_var("APIARI_FLAG_TEST_ENV_CHANNEL", "nightly");
+        }
+        let mut flags = Flags::new();
+        flags.set("test-env-ui", true).unwrap();
+        flags.set("test-env-workers", 8).unwrap();
+
+        assert!(!flags.is_enabled("test-env-ui"));
+        assert_eq!(flags.get::<u32>("test-env-workers"), Some(16));
+        assert_eq!(flags.value("test-env-channel"), Some(json!("nightly")));
+        assert!(flags.clone().without_env().is_enabled("test-env-ui"));
+        assert_eq!(
+            flags
+                .with_env_prefix("OTHER_")
+                .get::<u32>("test-env-workers"),
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn test_update_writes_atomically() {
+        let tmp = TempDir::new("apiari-flags-test-update").unwrap();
+        let path = tmp.join("flags.json");
+        update(&path, |f| f.set("a", true).unwrap()).unwrap();
+        let flags = update(&path, |f| {
+            f.set("b", 2).unwrap();
+            f.remove("a");
+        })
+        .unwrap();
+
+        assert_eq!(flags.names().collect::<Vec<_>>(), ["b"]);
+        let on_disk: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(on_disk, json!({ "b": 2 }));
+        assert!(!tmp.join("flags.json.tmp").exists());
+    }
+
+    fn wait_for(mut cond: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if cond() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    #[test]
+    fn test_watch_reloads_and_keeps_last_good() {
+        let tmp = TempDir::new("apiari-flags-test-watch").unwrap();
+        let path = tmp.join("flags.json");
+        update(&path, |f| f.set("rollout", 10).unwrap()).unwrap();
+
+        let live = Flags::watch(&path, Duration::from_millis(10)).unwrap();
+        assert_eq!(live.snapshot().get::<u32>("rollout"), Some(10));
+
+        update(&path, |f| f.set("rollout", 50).unwrap()).unwrap();
+        assert!(wait_for(
+            || live.snapshot().get::<u32>("rollout") == Some(50)
+        ));
+
+        fs::write(&path, "{ half-written").unwrap();
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(live.snapshot().get::<u32>("rollout"), Some(50));
+    }
+}
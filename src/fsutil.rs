@@ -0,0 +1,400 @@
+//! Filesystem operations beyond what `std::fs` offers.
+//!
+//! [`copy_atomic`] copies a file so that readers of the destination only
+//! ever see the old file or the complete new one: data goes to a temporary
+//! sibling, is flushed to disk, and is renamed into place. Permissions and
+//! the modification time are carried over, and a callback receives progress
+//! for large artifacts.
+//!
+//! [`remove`] makes destructive cleanup recoverable. In
+//! [`RemovalMode::Trash`] it moves the path to the desktop trash (`gio
+//! trash` or `trash-put` on Linux, Finder on macOS, the Recycle Bin on
+//! Windows). Where no trash is available (headless hosts, containers) the
+//! path is moved into [`quarantine_dir`] instead, and
+//! [`purge_quarantine`] deletes entries older than [`QUARANTINE_TTL`];
+//! every quarantining removal runs that purge as well.
+
+use crate::clock::now_ms;
+use crate::dirs;
+use crate::guard;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::time::Duration;
+
+const CHUNK: usize = 64 * 1024;
+
+/// How long quarantined paths are kept before [`purge_quarantine`] deletes
+/// them.
+pub const QUARANTINE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Bytes copied so far out of the source's size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub copied: u64,
+    pub total: u64,
+}
+
+/// Copy `src` to `dst` atomically, calling `on_progress` after every chunk.
+///
+/// Parent directories of `dst` are created. On failure the temporary file is
+/// removed and `dst` is untouched. Returns the number of bytes copied.
+pub fn copy_atomic(
+    src: &Path,
+    dst: &Path,
+    mut on_progress: impl FnMut(Progress),
+) -> io::Result<u64> {
+    let mut input = File::open(src)?;
+    let meta = input.metadata()?;
+    if !meta.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a regular file", src.display()),
+        ));
+    }
+    if let Some(parent) = dst.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = guard::remove_file_on_drop(temp_sibling(dst));
+    let mut output = File::create(&*tmp)?;
+    let mut progress = Progress {
+        copied: 0,
+        total: meta.len(),
+    };
+    let mut buf = vec![0; CHUNK];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        output.write_all(&buf[..n])?;
+        progress.copied += n as u64;
+        // The source may grow while we copy.
+        progress.total = progress.total.max(progress.copied);
+        on_progress(progress);
+    }
+    if let Ok(modified) = meta.modified() {
+        output.set_modified(modified)?;
+    }
+    output.sync_all()?;
+    drop(output);
+    fs::set_permissions(&*tmp, meta.permissions())?;
+    fs::rename(&*tmp, dst)?;
+    tmp.disarm();
+    Ok(progress.copied)
+}
+
+/// `<dst>.tmp-<pid>`, so concurrent writers don't share a temp file.
+fn temp_sibling(dst: &Path) -> PathBuf {
+    let mut tmp = dst.as_os_str().to_os_string();
+    tmp.push(format!(".tmp-{}", process::id()));
+    PathBuf::from(tmp)
+}
+
+/// How [`remove`] disposes of a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalMode {
+    /// Recoverably: the platform trash, or the quarantine directory.
+    Trash,
+    /// Delete immediately.
+    Permanent,
+}
+
+/// Where a removed path went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Removed {
+    Trashed,
+    /// Moved to this path inside the quarantine directory.
+    Quarantined(PathBuf),
+    Deleted,
+}
+
+/// Remove a file, directory tree, or symlink (not its target).
+pub fn remove(path: &Path, mode: RemovalMode) -> io::Result<Removed> {
+    remove_in(path, mode, true, quarantine_dir)
+}
+
+/// Where [`remove`] quarantines paths when no trash is available.
+pub fn quarantine_dir() -> io::Result<PathBuf> {
+    Ok(dirs::data_dir()?.join("quarantine"))
+}
+
+/// Delete entries in `dir` that were quarantined more than `max_age` ago,
+/// returning how many were deleted.
+pub fn purge_quarantine(dir: &Path, max_age: Duration) -> io::Result<usize> {
+    let read = match fs::read_dir(dir) {
+        Ok(read) => read,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let cutoff = now_ms().saturating_sub(max_age.as_millis() as u64);
+    let mut purged = 0;
+    for entry in read {
+        let path = entry?.path();
+        let stamp = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.split_once('-'))
+            .and_then(|(ms, _)| ms.parse::<u64>().ok());
+        if stamp.is_some_and(|ms| ms <= cutoff) && delete(&path).is_ok() {
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+fn remove_in(
+    path: &Path,
+    mode: RemovalMode,
+    platform_trash: bool,
+    quarantine: impl FnOnce() -> io::Result<PathBuf>,
+) -> io::Result<Removed> {
+    // Fail early (and uniformly) for paths that don't exist.
+    fs::symlink_metadata(path)?;
+    if mode == RemovalMode::Permanent {
+        delete(path)?;
+        return Ok(Removed::Deleted);
+    }
+    if platform_trash && trash(path) {
+        return Ok(Removed::Trashed);
+    }
+    let dir = quarantine()?;
+    fs::create_dir_all(&dir)?;
+    let name = path
+        .file_name()
+        .map_or_else(|| "root".into(), |n| n.to_string_lossy().into_owned());
+    let dest = dir.join(format!("{}-{}-{name}", now_ms(), process::id()));
+    move_path(path, &dest)?;
+    let _ = purge_quarantine(&dir, QUARANTINE_TTL);
+    Ok(Removed::Quarantined(dest))
+}
+
+fn delete(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Rename, or copy and delete when `dest` is on another filesystem.
+fn move_path(src: &Path, dest: &Path) -> io::Result<()> {
+    match fs::rename(src, dest) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            if let Err(e) = copy_tree(src, dest) {
+                let _ = delete(dest);
+                return Err(e);
+            }
+            delete(src)
+        }
+        other => other,
+    }
+}
+
+fn copy_tree(src: &Path, dest: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(src)?;
+    if meta.is_dir() {
+        fs::create_dir(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else if meta.is_symlink() {
+        copy_symlink(src, dest)
+    } else {
+        fs::copy(src, dest).map(drop)
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dest)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::copy(src, dest).map(drop)
+}
+
+/// Move `path` to the platform trash, returning `false` if no trash
+/// command is available or it failed.
+fn trash(path: &Path) -> bool {
+    let Ok(path) = std::path::absolute(path) else {
+        return false;
+    };
+    let attempts: Vec<Command> = if cfg!(target_os = "macos") {
+        let script = format!(
+            "tell application \"Finder\" to delete POSIX file \"{}\"",
+            path.display()
+                .to_string()
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+        );
+        let mut osascript = Command::new("osascript");
+        osascript.args(["-e", &script]);
+        vec![osascript]
+    } else if cfg!(windows) {
+        let method = if path.is_dir() {
+            "DeleteDirectory"
+        } else {
+            "DeleteFile"
+        };
+        let script = format!(
+            "Add-Type -AssemblyName Microsoft.VisualBasic; \
+             [Microsoft.VisualBasic.FileIO.FileSystem]::{method}('{}', 'OnlyErrorDialogs', 'SendToRecycleBin')",
+            path.display().to_string().replace('\'', "''")
+        );
+        let mut powershell = Command::new("powershell");
+        powershell.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+        vec![powershell]
+    } else {
+        let mut gio = Command::new("gio");
+        gio.arg("trash").arg(&path);
+        let mut trash_put = Command::new("trash-put");
+        trash_put.arg(&path);
+        vec![gio, trash_put]
+    };
+    attempts.into_iter().any(|mut command| {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+            && fs::symlink_metadata(&path).is_err()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_copy_atomic_reports_progress_and_preserves_metadata() {
+        let tmp = TempDir::new("apiari-fsutil-test-copy").unwrap();
+        let src = tmp.join("artifact.bin");
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        fs::write(&src, &data).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        File::options()
+            .write(true)
+            .open(&src)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&src, fs::Permissions::from_mode(0o750)).unwrap();
+        }
+
+        let dst = tmp.join("workspace/bin/artifact.bin");
+        let mut updates = Vec::new();
+        let copied = copy_atomic(&src, &dst, |p| updates.push(p)).unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(fs::read(&dst).unwrap(), data);
+        assert!(updates.len() >= 3);
+        assert!(updates.windows(2).all(|w| w[0].copied < w[1].copied));
+        assert_eq!(
+            updates.last(),
+            Some(&Progress {
+                copied,
+                total: copied
+            })
+        );
+        let meta = fs::metadata(&dst).unwrap();
+        assert_eq!(meta.modified().unwrap(), mtime);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(meta.permissions().mode() & 0o777, 0o750);
+        }
+        assert!(!temp_sibling(&dst).exists());
+    }
+
+    #[test]
+    fn test_copy_atomic_failure_leaves_destination() {
+        let tmp = TempDir::new("apiari-fsutil-test-copy-fail").unwrap();
+        let dst = tmp.join("out.txt");
+        fs::write(&dst, "old").unwrap();
+        let err = copy_atomic(&tmp.join("missing"), &dst, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = copy_atomic(tmp.path(), &dst, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_remove_permanent_and_missing() {
+        let tmp = TempDir::new("apiari-fsutil-test-remove").unwrap();
+        let dir = tmp.join("workspace");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        assert_eq!(
+            remove(&dir, RemovalMode::Permanent).unwrap(),
+            Removed::Deleted
+        );
+        assert!(!dir.exists());
+        let err = remove(&dir, RemovalMode::Trash).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_quarantine_fallback_and_purge() {
+        let tmp = TempDir::new("apiari-fsutil-test-quarantine").unwrap();
+        let quarantine = tmp.join("quarantine");
+        let dir = tmp.join("old-session");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("events.jsonl"), "{}\n").unwrap();
+
+        let removed =
+            remove_in(&dir, RemovalMode::Trash, false, || Ok(quarantine.clone())).unwrap();
+        let Removed::Quarantined(dest) = removed else {
+            panic!("expected quarantine, got {removed:?}");
+        };
+        assert!(!dir.exists());
+        assert!(dest.starts_with(&quarantine));
+        assert!(dest.to_string_lossy().ends_with("-old-session"));
+        assert_eq!(
+            fs::read_to_string(dest.join("events.jsonl")).unwrap(),
+            "{}\n"
+        );
+
+        fs::create_dir(quarantine.join("1000-1-ancient")).unwrap();
+        fs::write(quarantine.join("unrelated"), "").unwrap();
+        assert_eq!(purge_quarantine(&quarantine, QUARANTINE_TTL).unwrap(), 1);
+        assert!(dest.exists());
+        assert!(quarantine.join("unrelated").exists());
+        assert_eq!(purge_quarantine(&quarantine, Duration::ZERO).unwrap(), 1);
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn test_copy_tree_preserves_structure() {
+        let tmp = TempDir::new("apiari-fsutil-test-copy-tree").unwrap();
+        let src = tmp.join("src");
+        fs::create_dir_all(src.join("a/b")).unwrap();
+        fs::write(src.join("a/b/file.txt"), "hi").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a/b/file.txt", src.join("link")).unwrap();
+        move_path(&src, &tmp.join("dest")).unwrap();
+        copy_tree(&tmp.join("dest"), &tmp.join("copy")).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.join("copy/a/b/file.txt")).unwrap(),
+            "hi"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(tmp.join("copy/link")).unwrap(),
+            Path::new("a/b/file.txt")
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Runtime directory garbage collection.
+//!
+//! Crashed runs leave behind workspaces, lock files, sockets, and JSONL logs.
+//! [`sweep`] scans the top level of a runtime directory, identifies artifacts
+//! whose owner is gone and which are older than the policy threshold, and
+//! either reports them (the default) or removes them.
+//!
+//! An artifact's owner is the PID written in it (for `.lock` / `.pid` files)
+//! or in a `<name>.pid` sidecar next to it. Artifacts with no recorded owner
+//! are treated as orphaned once they are old enough.
+
+use crate::clock::{Clock, SystemClock};
+use crate::process;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Controls which artifacts [`sweep`] considers orphaned and whether it
+/// deletes them.
+#[derive(Debug, Clone)]
+pub struct GcPolicy {
+    /// Artifacts modified more recently than this are never collected.
+    pub min_age: Duration,
+    /// When `false` (the default), [`sweep`] only reports candidates.
+    pub delete: bool,
+}
+
+impl Default for GcPolicy {
+    fn default() -> Self {
+        Self {
+            min_age: Duration::from_secs(24 * 60 * 60),
+            delete: false,
+        }
+    }
+}
+
+/// The kind of runtime artifact found during a sweep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    /// A directory (agent workspace, scratch dir, ...).
+    Workspace,
+    /// A `.lock` or `.pid` file.
+    Lock,
+    /// A `.sock` file.
+    Socket,
+    /// A `.jsonl` log.
+    Log,
+}
+
+/// An orphaned artifact identified by [`sweep`].
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub kind: ArtifactKind,
+    /// The recorded owner PID, if any (it is no longer running).
+    pub owner_pid: Option<u32>,
+    /// Time since the artifact was last modified.
+    pub age: Duration,
+}
+
+/// Result of a [`sweep`].
+#[derive(Debug, Default)]
+pub struct SweepReport {
+    /// Orphaned artifacts, whether or not they were deleted.
+    pub orphans: Vec<Artifact>,
+    /// Paths that were actually removed.
+    pub removed: Vec<PathBuf>,
+    /// Paths that could not be removed, with the reason.
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+/// Scan `root` for orphaned artifacts and, if `policy.delete` is set,
+/// remove them.
+///
+/// Only the top level of `root` is inspected; workspace directories are
+/// removed recursively as a unit. A missing `root` yields an empty report.
+///
+/// # Errors
+///
+/// Returns `io::Error` if `root` exists but cannot be listed. Failures to
+/// remove individual artifacts are collected in [`SweepReport::errors`].
+pub fn sweep(root: &Path, policy: &GcPolicy) -> io::Result<SweepReport> {
+    sweep_with_clock(root, policy, &SystemClock)
+}
+
+/// [`sweep`], measuring artifact ages against `clock`.
+///
+/// # Errors
+///
+/// As for [`sweep`].
+pub fn sweep_with_clock(
+    root: &Path,
+    policy: &GcPolicy,
+    clock: &impl Clock,
+) -> io::Result<SweepReport> {
+    let mut report = SweepReport::default();
+
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(report),
+        Err(e) => return Err(e),
+    };
+
+    let now = clock.now();
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+    paths.sort();
+
+    for path in paths {
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        let Some(kind) = classify(&path, meta.is_dir()) else {
+            continue;
+        };
+
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|m| now.duration_since(m).ok())
+            .unwrap_or_default();
+        if age < policy.min_age {
+            continue;
+        }
+
+        let owner_pid = owner_pid(&path, kind);
+        if owner_pid.is_some_and(process::is_alive) {
+            continue;
+        }
+
+        report.orphans.push(Artifact {
+            path,
+            kind,
+            owner_pid,
+            age,
+        });
+    }
+
+    if policy.delete {
+        for artifact in &report.orphans {
+            let result = match artifact.kind {
+                ArtifactKind::Workspace => fs::remove_dir_all(&artifact.path),
+                _ => fs::remove_file(&artifact.path),
+            };
+            match result {
+                Ok(()) => report.removed.push(artifact.path.clone()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => report.errors.push((artifact.path.clone(), e)),
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn classify(path: &Path, is_dir: bool) -> Option<ArtifactKind> {
+    if is_dir {
+        return Some(ArtifactKind::Workspace);
+    }
+    match path.extension()?.to_str()? {
+        "lock" | "pid" => Some(ArtifactKind::Lock),
+        "sock" => Some(ArtifactKind::Socket),
+        "jsonl" => Some(ArtifactKind::Log),
+        _ => None,
+    }
+}
+
+/// Read the owner PID for an artifact: lock files carry it themselves,
+/// everything else may have a `<name>.pid` sidecar.
+fn owner_pid(path: &Path, kind: ArtifactKind) -> Option<u32> {
+    let pid_file = if kind == ArtifactKind::Lock {
+        path.to_path_buf()
+    } else {
+        let mut name = path.file_name()?.to_os_string();
+        name.push(".pid");
+        path.with_file_name(name)
+    };
+    let data = fs::read_to_string(pid_file).ok()?;
+    data.lines().next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{MockClock, TempDir};
+    use std::time::SystemTime;
+
+    const DEAD_PID: u32 = 999_999_999;
+
+    fn policy(delete: bool) -> GcPolicy {
+        GcPolicy {
+            min_age: Duration::ZERO,
+            delete,
+        }
+    }
+
+    #[test]
+    fn test_dry_run_reports_without_deleting() {
+        let tmp = TempDir::new("apiari-gc-test-dry-run").unwrap();
+        let dir = tmp.path();
+        let lock = dir.join("worker.lock");
+        fs::write(&lock, DEAD_PID.to_string()).unwrap();
+
+        let report = sweep(dir, &policy(false)).unwrap();
+        assert_eq!(report.orphans.len(), 1);
+        assert_eq!(report.orphans[0].kind, ArtifactKind::Lock);
+        assert_eq!(report.orphans[0].owner_pid, Some(DEAD_PID));
+        assert!(report.removed.is_empty());
+        assert!(lock.exists());
+    }
+
+    #[test]
+    fn test_sweep_removes_orphans() {
+        let tmp = TempDir::new("apiari-gc-test-remove").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("ws-1/src")).unwrap();
+        fs::write(dir.join("ws-1.pid"), DEAD_PID.to_string()).unwrap();
+        fs::write(dir.join("events.jsonl"), "{}\n").unwrap();
+        fs::write(dir.join("notes.txt"), "keep me").unwrap();
+
+        let report = sweep(dir, &policy(true)).unwrap();
+        assert_eq!(report.removed.len(), 3);
+        assert!(report.errors.is_empty());
+        assert!(!dir.join("ws-1").exists());
+        assert!(!dir.join("ws-1.pid").exists());
+        assert!(!dir.join("events.jsonl").exists());
+        assert!(dir.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_live_owner_is_kept() {
+        let tmp = TempDir::new("apiari-gc-test-live-owner").unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("ws")).unwrap();
+        let me = std::process::id().to_string();
+        fs::write(dir.join("ws.pid"), &me).unwrap();
+        fs::write(dir.join("daemon.lock"), &me).unwrap();
+
+        let report = sweep(dir, &policy(true)).unwrap();
+        assert!(report.orphans.is_empty());
+        assert!(dir.join("ws").exists());
+        assert!(dir.join("daemon.lock").exists());
+    }
+
+    #[test]
+    fn test_recent_artifacts_are_kept() {
+        let tmp = TempDir::new("apiari-gc-test-recent").unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("old.sock"), "").unwrap();
+
+        let policy = GcPolicy {
+            min_age: Duration::from_secs(3600),
+            delete: true,
+        };
+        let clock = MockClock::new(SystemTime::now());
+        let report = sweep_with_clock(dir, &policy, &clock).unwrap();
+        assert!(report.orphans.is_empty());
+        assert!(dir.join("old.sock").exists());
+
+        clock.advance(Duration::from_secs(7200));
+        let report = sweep_with_clock(dir, &policy, &clock).unwrap();
+        assert_eq!(report.orphans.len(), 1);
+        assert!(report.orphans[0].age >= Duration::from_secs(7200));
+        assert!(!dir.join("old.sock").exists());
+    }
+
+    #[test]
+    fn test_missing_root() {
+        let tmp = TempDir::new("apiari-gc-test-missing-root").unwrap();
+
+        let report = sweep(&tmp.join("missing"), &GcPolicy::default()).unwrap();
+        assert!(report.orphans.is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+//! Ignore patterns with `.gitignore` semantics.
+//!
+//! A [`Matcher`] is built from pattern lines and answers whether a
+//! `/`-separated relative path is ignored:
+//!
+//! - `*` matches within one path component, `?` one character, `[a-z]` /
+//!   `[!a-z]` a character class, and `**` any number of components.
+//! - A pattern without a slash (other than a trailing one) matches at any
+//!   depth (`*.log`, `target`); one with a slash is anchored to the root
+//!   (`build/cache`, `/Cargo.lock`).
+//! - A trailing `/` matches directories only.
+//! - A leading `!` re-includes a path excluded by an earlier pattern; the
+//!   last matching pattern wins. As in git, a path inside an ignored
+//!   directory cannot be re-included.
+//! - Blank lines and `#` comments are skipped; `\#`, `\!`, and `\ ` escape.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+/// A compiled set of ignore patterns.
+#[derive(Debug, Clone, Default)]
+pub struct Matcher {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    negated: bool,
+    dir_only: bool,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// `**`: zero or more whole components.
+    AnyDepth,
+    Glob(Vec<Token>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// `*`
+    Any,
+    /// `?`
+    One,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Matcher {
+    /// Compile `patterns`, one `.gitignore` line each.
+    pub fn new<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut matcher = Self::default();
+        for pattern in patterns {
+            matcher.add_pattern(pattern.as_ref());
+        }
+        matcher
+    }
+
+    /// Load patterns from an ignore file. A missing file yields an empty
+    /// matcher.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut matcher = Self::default();
+        matcher.add_file(path)?;
+        Ok(matcher)
+    }
+
+    /// Append the patterns in an ignore file (missing files are skipped).
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                text.lines().for_each(|line| self.add_pattern(line));
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Append one pattern line. Blank lines and comments are ignored.
+    pub fn add_pattern(&mut self, line: &str) {
+        if let Some(rule) = Rule::parse(line) {
+            self.rules.push(rule);
+        }
+    }
+
+    /// Return `true` if there are no patterns.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Return `true` if the file at relative `path` is ignored, either
+    /// directly or because a parent directory is.
+    pub fn matches(&self, path: impl AsRef<Path>) -> bool {
+        self.check(path.as_ref(), false)
+    }
+
+    /// Like [`matches`](Self::matches), for a directory.
+    pub fn matches_dir(&self, path: impl AsRef<Path>) -> bool {
+        self.check(path.as_ref(), true)
+    }
+
+    fn check(&self, path: &Path, is_dir: bool) -> bool {
+        let parts: Vec<String> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+        (1..parts.len()).any(|depth| self.decide(&parts[..depth], true))
+            || self.decide(&parts, is_dir)
+    }
+
+    /// Apply the rules to exactly this path; the last match wins.
+    fn decide(&self, parts: &[&str], is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && match_segments(&rule.segments, parts))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let mut line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let negated = line.starts_with('!');
+        // Drop the `!` marker, or the backslash of an escaped `\!` / `\#`.
+        if negated || line.starts_with("\\!") || line.starts_with("\\#") {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        let mut segments: Vec<Segment> = line
+            .trim_start_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                if s == "**" {
+                    Segment::AnyDepth
+                } else {
+                    Segment::Glob(tokenize(s))
+                }
+            })
+            .collect();
+        if !anchored {
+            segments.insert(0, Segment::AnyDepth);
+        }
+        Some(Self {
+            negated,
+            dir_only,
+            segments,
+        })
+    }
+}
+
+/// Drop trailing spaces unless escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut end = line.trim_end_matches(['\r', '\n']).len();
+    while end > 0 && line.as_bytes()[end - 1] == b' ' {
+        if end >= 2 && line.as_bytes()[end - 2] == b'\\' {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+fn tokenize(segment: &str) -> Vec<Token> {
+    let chars: Vec<char> = segment.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Literal(chars[i + 1]));
+                i += 2;
+                continue;
+            }
+            '*' => {
+                if tokens.last() != Some(&Token::Any) {
+                    tokens.push(Token::Any);
+                }
+            }
+            '?' => tokens.push(Token::One),
+            '[' => {
+                if let Some((class, len)) = parse_class(&chars[i..]) {
+                    tokens.push(class);
+                    i += len;
+                    continue;
+                }
+                // An unclosed `[` is literal.
+                tokens.push(Token::Literal('['));
+            }
+            c => tokens.push(Token::Literal(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parse `[...]` at the start of `chars`, returning the token and its length.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 1;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let start = i;
+    while i < chars.len() {
+        // `]` right after the opening bracket is a literal member.
+        if chars[i] == ']' && i > start {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        let lo = chars[i];
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|c| *c != ']') {
+            ranges.push((lo, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((lo, lo));
+            i += 1;
+        }
+    }
+    None
+}
+
+fn match_segments(segments: &[Segment], parts: &[&str]) -> bool {
+    match segments.split_first() {
+        None => parts.is_empty(),
+        Some((Segment::AnyDepth, rest)) => {
+            (0..=parts.len()).any(|skip| match_segments(rest, &parts[skip..]))
+        }
+        Some((Segment::Glob(tokens), rest)) => {
+            !parts.is_empty() && match_tokens(tokens, parts[0]) && match_segments(rest, &parts[1..])
+        }
+    }
+}
+
+/// Match one path component, backtracking on `*`.
+fn match_tokens(tokens: &[Token], text: &str) -> bool {
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        let single = match tokens.get(pi) {
+            Some(Token::Literal(c)) => *c == t[ti],
+            Some(Token::One) => true,
+            Some(Token::Class { negated, ranges }) => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&t[ti])) != *negated
+            }
+            _ => false,
+        };
+        if single {
+            pi += 1;
+            ti += 1;
+        } else if tokens.get(pi) == Some(&Token::Any) {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    tokens[pi..].iter().all(|t| *t == Token::Any)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_unanchored_and_anchored() {
+        let m = Matcher::new(["*.log", "target", "/Cargo.lock", "docs/*.md"]);
+        assert!(m.matches("debug.log"));
+        assert!(m.matches("a/b/trace.log"));
+        assert!(m.matches("target/debug/app"));
+        assert!(m.matches("crates/x/target"));
+        assert!(m.matches("Cargo.lock"));
+        assert!(!m.matches("crates/x/Cargo.lock"));
+        assert!(m.matches("docs/intro.md"));
+        assert!(!m.matches("docs/api/intro.md"));
+        assert!(!m.matches("src/lib.rs"));
+    }
+
+    #[test]
+    fn test_negation_and_directory_only() {
+        let m = Matcher::new([
+            "*.log",
+            "!keep.log",
+            "build/",
+            "logs/",
+            "!logs/important.txt",
+        ]);
+        assert!(m.matches("x.log"));
+        assert!(!m.matches("keep.log"));
+        assert!(m.matches_dir("build"));
+        assert!(!m.matches("build"));
+        assert!(m.matches("build/out.o"));
+        // Parent exclusion cannot be undone.
+        assert!(m.matches("logs/important.txt"));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let m = Matcher::new([
+            "a/**/z",
+            "file[0-9].txt",
+            "x[!ab]y",
+            "**/cache/**",
+            "lit\\*",
+        ]);
+        assert!(m.matches("a/z"));
+        assert!(m.matches("a/b/c/z"));
+        assert!(m.matches("file7.txt"));
+        assert!(!m.matches("fileA.txt"));
+        assert!(m.matches("xcy"));
+        assert!(!m.matches("xay"));
+        assert!(m.matches("deep/cache/entry"));
+        assert!(m.matches("lit*"));
+        assert!(!m.matches("literal"));
+    }
+
+    #[test]
+    fn test_from_file() {
+        let tmp = TempDir::new("apiari-globs-test-file").unwrap();
+        let path = tmp.join(".apiariignore");
+        fs::write(
+            &path,
+            "# comment\n\n*.tmp  \n\\#notes\n\\!bang\ntrailing\\ \n",
+        )
+        .unwrap();
+        let m = Matcher::from_file(&path).unwrap();
+        assert!(m.matches("a.tmp"));
+        assert!(m.matches("#notes"));
+        assert!(m.matches("!bang"));
+        assert!(m.matches("trailing "));
+        assert!(!m.matches("comment"));
+        assert!(Matcher::from_file(tmp.join("missing")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+//! Scope guards that run cleanup when a scope exits, including by panic.
+//!
+//! Code that creates a temp file, takes a lock, or sets up a worktree and
+//! then does fallible work leaks the resource if it returns early with `?`
+//! or panics before the cleanup line. A guard ties the cleanup to a value's
+//! lifetime instead:
+//!
+//! ```ignore
+//! let tmp = guard::remove_file_on_drop(dst.with_extension("tmp"));
+//! fs::write(&*tmp, data)?;          // an error removes the temp file
+//! fs::rename(&*tmp, dst)?;
+//! tmp.disarm();                     // success: nothing left to clean up
+//! ```
+//!
+//! [`defer`] runs a closure; [`CleanupGuard`] owns a resource, derefs to it,
+//! and hands it to the cleanup function. [`on_unwind`] runs only when the
+//! scope is left by a panic. Each guard can be [disarmed](Defer::disarm)
+//! once the work succeeded.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::thread;
+
+/// Run `f` when the returned guard is dropped.
+pub fn defer<F: FnOnce()>(f: F) -> Defer<F> {
+    Defer {
+        f: Some(f),
+        only_on_unwind: false,
+    }
+}
+
+/// Run `f` when the returned guard is dropped during a panic, and not on a
+/// normal exit.
+pub fn on_unwind<F: FnOnce()>(f: F) -> Defer<F> {
+    Defer {
+        f: Some(f),
+        only_on_unwind: true,
+    }
+}
+
+/// A closure run on drop. Created by [`defer`] and [`on_unwind`].
+#[must_use = "the closure runs as soon as the guard is dropped"]
+pub struct Defer<F: FnOnce()> {
+    f: Option<F>,
+    only_on_unwind: bool,
+}
+
+impl<F: FnOnce()> Defer<F> {
+    /// Cancel the deferred closure.
+    pub fn disarm(mut self) {
+        self.f = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for Defer<F> {
+    fn drop(&mut self) {
+        if let Some(f) = self.f.take()
+            && (!self.only_on_unwind || thread::panicking())
+        {
+            f();
+        }
+    }
+}
+
+impl<F: FnOnce()> fmt::Debug for Defer<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Defer")
+            .field("armed", &self.f.is_some())
+            .field("only_on_unwind", &self.only_on_unwind)
+            .finish()
+    }
+}
+
+/// Owns a resource and passes it to a cleanup function on drop.
+#[must_use = "the resource is cleaned up as soon as the guard is dropped"]
+pub struct CleanupGuard<T, F: FnOnce(T)> {
+    inner: Option<(T, F)>,
+}
+
+impl<T, F: FnOnce(T)> CleanupGuard<T, F> {
+    pub fn new(resource: T, cleanup: F) -> Self {
+        Self {
+            inner: Some((resource, cleanup)),
+        }
+    }
+
+    /// Cancel the cleanup and take the resource back.
+    pub fn disarm(mut self) -> T {
+        let (resource, _) = self.inner.take().expect("guard is armed");
+        resource
+    }
+
+    /// Run the cleanup now rather than at the end of the scope.
+    pub fn cleanup(mut self) {
+        if let Some((resource, cleanup)) = self.inner.take() {
+            cleanup(resource);
+        }
+    }
+}
+
+impl<T, F: FnOnce(T)> Deref for CleanupGuard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.as_ref().expect("guard is armed").0
+    }
+}
+
+impl<T, F: FnOnce(T)> DerefMut for CleanupGuard<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner.as_mut().expect("guard is armed").0
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for CleanupGuard<T, F> {
+    fn drop(&mut self) {
+        if let Some((resource, cleanup)) = self.inner.take() {
+            cleanup(resource);
+        }
+    }
+}
+
+impl<T: fmt::Debug, F: FnOnce(T)> fmt::Debug for CleanupGuard<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            Some((resource, _)) => f.debug_tuple("CleanupGuard").field(resource).finish(),
+            None => f.write_str("CleanupGuard(<disarmed>)"),
+        }
+    }
+}
+
+/// A path removed (ignoring errors) when the guard is dropped.
+pub type RemoveOnDrop = CleanupGuard<PathBuf, fn(PathBuf)>;
+
+/// Remove the file at `path` on drop unless disarmed. Suited to temp files
+/// that are renamed into place on success.
+pub fn remove_file_on_drop(path: impl Into<PathBuf>) -> RemoveOnDrop {
+    CleanupGuard::new(path.into(), |path| ignore_missing(fs::remove_file(path)))
+}
+
+/// Remove the directory tree at `path` on drop unless disarmed.
+pub fn remove_dir_on_drop(path: impl Into<PathBuf>) -> RemoveOnDrop {
+    CleanupGuard::new(path.into(), |path| ignore_missing(fs::remove_dir_all(path)))
+}
+
+// Cleanup runs from Drop, possibly while unwinding, so errors can only be
+// dropped; a missing path is the expected case after a failed create.
+fn ignore_missing(_result: io::Result<()>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::cell::Cell;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn test_defer_runs_unless_disarmed() {
+        let ran = Cell::new(0);
+        {
+            let _guard = defer(|| ran.set(ran.get() + 1));
+        }
+        defer(|| ran.set(ran.get() + 10)).disarm();
+        {
+            let _unwind = on_unwind(|| ran.set(ran.get() + 100));
+        }
+        assert_eq!(ran.get(), 1);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = defer(|| ran.set(ran.get() + 1));
+            let _unwind = on_unwind(|| ran.set(ran.get() + 100));
+            panic!("boom");
+        }));
+        assert!(result.is_err());
+        assert_eq!(ran.get(), 102);
+    }
+
+    #[test]
+    fn test_cleanup_guard_owns_resource() {
+        let cleaned = Cell::new(Vec::new());
+        let record = |v: Vec<i32>| cleaned.set(v);
+        {
+            let mut guard = CleanupGuard::new(vec![1], record);
+            guard.push(2);
+            assert_eq!(guard.len(), 2);
+        }
+        assert_eq!(cleaned.take(), [1, 2]);
+
+        let guard = CleanupGuard::new(vec![3], record);
+        assert_eq!(guard.disarm(), [3]);
+        assert!(cleaned.take().is_empty());
+        CleanupGuard::new(vec![4], record).cleanup();
+        assert_eq!(cleaned.take(), [4]);
+    }
+
+    #[test]
+    fn test_remove_on_drop() {
+        let tmp = TempDir::new("apiari-guard-test-remove").unwrap();
+        let file = tmp.join("scratch.tmp");
+        let dir = tmp.join("worktree");
+        let result = panic::catch_unwind(|| {
+            let file_guard = remove_file_on_drop(&file);
+            fs::write(&*file_guard, "partial").unwrap();
+            let dir_guard = remove_dir_on_drop(&dir);
+            fs::create_dir_all(dir_guard.join("src")).unwrap();
+            panic!("interrupted");
+        });
+        assert!(result.is_err());
+        assert!(!file.exists());
+        assert!(!dir.exists());
+
+        let kept = remove_file_on_drop(&file);
+        fs::write(&*kept, "done").unwrap();
+        assert_eq!(kept.disarm(), file);
+        assert!(file.exists());
+        drop(remove_file_on_drop(tmp.join("never-created")));
+    }
+}
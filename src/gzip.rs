@@ -0,0 +1,550 @@
+//! Gzip compression (RFC 1951/1952).
+//!
+//! A small, dependency-free codec for archives and rotated logs.
+//! [`compress`] emits one fixed-Huffman deflate block with LZ77 matching —
+//! not as tight as zlib, but effective on repetitive logs and JSON, and
+//! byte-for-byte deterministic (the header's mtime is zero). [`decompress`]
+//! reads any single-member gzip stream, including ones written by `gzip`
+//! and zlib, and [`decompress_limited`] caps the output so a hostile input
+//! cannot exhaust memory.
+
+use std::io;
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored (RFC 1951 3.2.7).
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 (IEEE) of `data`, as used in gzip trailers.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, b| {
+        CRC_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Compress `data` into a gzip stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Header: magic, deflate, no flags, mtime 0, no extra flags, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    let mut bits = BitWriter { out, acc: 0, n: 0 };
+    bits.write(1, 1); // BFINAL
+    bits.write(1, 2); // BTYPE = fixed Huffman
+    deflate_fixed(data, &mut bits);
+    write_fixed_literal(&mut bits, 256);
+    out = bits.finish();
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Decompress a gzip stream.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    decompress_limited(data, usize::MAX)
+}
+
+/// Decompress a gzip stream, failing with `InvalidData` if the output would
+/// exceed `max_len` bytes.
+pub fn decompress_limited(data: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+        return Err(invalid("not a gzip stream"));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & 0x04 != 0 {
+        let len = *data.get(pos).ok_or_else(truncated)? as usize
+            | (*data.get(pos + 1).ok_or_else(truncated)? as usize) << 8;
+        pos += 2 + len;
+    }
+    for flag in [0x08, 0x10] {
+        // Zero-terminated file name, then comment.
+        if flags & flag != 0 {
+            let end = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|b| *b == 0))
+                .ok_or_else(truncated)?;
+            pos += end + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+    let body = data.get(pos..).ok_or_else(truncated)?;
+    let mut inflater = Inflater {
+        bits: BitReader {
+            data: body,
+            pos: 0,
+            acc: 0,
+            n: 0,
+        },
+        out: Vec::new(),
+        max_len,
+    };
+    inflater.run()?;
+    let used = inflater.bits.pos;
+    let out = inflater.out;
+    let trailer = body.get(used..used + 8).ok_or_else(truncated)?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    if crc != crc32(&out) || size != out.len() as u32 {
+        return Err(invalid("gzip checksum mismatch"));
+    }
+    Ok(out)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn truncated() -> io::Error {
+    invalid("truncated gzip stream")
+}
+
+// ---------------------------------------------------------------------------
+// Compression
+// ---------------------------------------------------------------------------
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    n: u32,
+}
+
+impl BitWriter {
+    /// Write the low `count` bits of `value`, least significant first.
+    fn write(&mut self, value: u32, count: u32) {
+        self.acc |= (value as u64) << self.n;
+        self.n += count;
+        while self.n >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    /// Write a Huffman code, most significant bit first.
+    fn write_code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn write_fixed_literal(bits: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE
+        .iter()
+        .rposition(|b| *b as usize <= len)
+        .unwrap();
+    write_fixed_literal(bits, 257 + li as u32);
+    bits.write(
+        (len - LENGTH_BASE[li] as usize) as u32,
+        LENGTH_EXTRA[li] as u32,
+    );
+    let di = DIST_BASE.iter().rposition(|b| *b as usize <= dist).unwrap();
+    bits.write_code(di as u32, 5);
+    bits.write(
+        (dist - DIST_BASE[di] as usize) as u32,
+        DIST_EXTRA[di] as u32,
+    );
+}
+
+fn hash3(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Greedy LZ77 over hash chains, emitted with the fixed Huffman codes.
+fn deflate_fixed(data: &[u8], bits: &mut BitWriter) {
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let insert = |head: &mut [usize], prev: &mut [usize], i: usize| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash3(data, i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i;
+        }
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash3(data, i)];
+            let max = MAX_MATCH.min(data.len() - i);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW - 1 {
+                    break;
+                }
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+            }
+        }
+        if best_len >= MIN_MATCH {
+            write_match(bits, best_len, best_dist);
+            for j in i..i + best_len {
+                insert(&mut head, &mut prev, j);
+            }
+            i += best_len;
+        } else {
+            write_fixed_literal(bits, data[i] as u32);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Decompression
+// ---------------------------------------------------------------------------
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Next unread byte.
+    pos: usize,
+    acc: u32,
+    n: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.n < count {
+            let byte = *self.data.get(self.pos).ok_or_else(truncated)?;
+            self.pos += 1;
+            self.acc |= (byte as u32) << self.n;
+            self.n += 8;
+        }
+        let value = self.acc & ((1u64 << count) - 1) as u32;
+        self.acc >>= count;
+        self.n -= count;
+        Ok(value)
+    }
+
+    /// Discard bits up to the next byte boundary.
+    fn align(&mut self) {
+        self.acc = 0;
+        self.n = 0;
+    }
+}
+
+/// Canonical Huffman decoding table (counts per length, symbols by code).
+struct Huffman {
+    count: [u16; 16],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut count = [0u16; 16];
+        for len in lengths {
+            count[*len as usize] += 1;
+        }
+        count[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (sym, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbol[offsets[*len as usize] as usize] = sym as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Self { count, symbol }
+    }
+}
+
+struct Inflater<'a> {
+    bits: BitReader<'a>,
+    out: Vec<u8>,
+    max_len: usize,
+}
+
+impl Inflater<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let last = self.bits.bits(1)? == 1;
+            match self.bits.bits(2)? {
+                0 => self.stored()?,
+                1 => {
+                    let mut lengths = [0u8; 288];
+                    lengths[..144].fill(8);
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    lengths[280..].fill(8);
+                    let lit = Huffman::new(&lengths);
+                    let dist = Huffman::new(&[5; 30]);
+                    self.codes(&lit, &dist)?;
+                }
+                2 => {
+                    let (lit, dist) = self.dynamic_tables()?;
+                    self.codes(&lit, &dist)?;
+                }
+                _ => return Err(invalid("invalid deflate block type")),
+            }
+            if last {
+                // Return unused whole bytes so the trailer lines up.
+                self.bits.pos -= (self.bits.n / 8) as usize;
+                return Ok(());
+            }
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> io::Result<()> {
+        if self.out.len() >= self.max_len {
+            return Err(invalid("gzip output exceeds size limit"));
+        }
+        self.out.push(byte);
+        Ok(())
+    }
+
+    fn stored(&mut self) -> io::Result<()> {
+        self.bits.align();
+        let header = self
+            .bits
+            .data
+            .get(self.bits.pos..self.bits.pos + 4)
+            .ok_or_else(truncated)?;
+        let len = u16::from_le_bytes([header[0], header[1]]);
+        let nlen = u16::from_le_bytes([header[2], header[3]]);
+        if len != !nlen {
+            return Err(invalid("corrupt stored block"));
+        }
+        self.bits.pos += 4;
+        let end = self.bits.pos + len as usize;
+        let block = self
+            .bits
+            .data
+            .get(self.bits.pos..end)
+            .ok_or_else(truncated)?;
+        for byte in block {
+            self.push(*byte)?;
+        }
+        self.bits.pos = end;
+        Ok(())
+    }
+
+    fn decode(&mut self, h: &Huffman) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= self.bits.bits(1)? as i32;
+            let count = h.count[len] as i32;
+            if code - first < count {
+                return Ok(h.symbol[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+
+    fn dynamic_tables(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let nlit = self.bits.bits(5)? as usize + 257;
+        let ndist = self.bits.bits(5)? as usize + 1;
+        let nclen = self.bits.bits(4)? as usize + 4;
+        let mut clen = [0u8; 19];
+        for i in 0..nclen {
+            clen[CLEN_ORDER[i]] = self.bits.bits(3)? as u8;
+        }
+        let clen = Huffman::new(&clen);
+        let mut lengths = vec![0u8; nlit + ndist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let sym = self.decode(&clen)?;
+            let (value, repeat) = match sym {
+                0..=15 => (sym as u8, 1),
+                16 => {
+                    let prev = *lengths
+                        .get(i.wrapping_sub(1))
+                        .ok_or_else(|| invalid("repeat with no previous length"))?;
+                    (prev, 3 + self.bits.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.bits.bits(3)? as usize),
+                _ => (0, 11 + self.bits.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(invalid("too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        Ok((
+            Huffman::new(&lengths[..nlit]),
+            Huffman::new(&lengths[nlit..]),
+        ))
+    }
+
+    fn codes(&mut self, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+        loop {
+            let sym = self.decode(lit)? as usize;
+            match sym {
+                0..=255 => self.push(sym as u8)?,
+                256 => return Ok(()),
+                _ => {
+                    let li = sym - 257;
+                    if li >= LENGTH_BASE.len() {
+                        return Err(invalid("invalid length symbol"));
+                    }
+                    let len = LENGTH_BASE[li] as usize
+                        + self.bits.bits(LENGTH_EXTRA[li] as u32)? as usize;
+                    let di = self.decode(dist)? as usize;
+                    if di >= DIST_BASE.len() {
+                        return Err(invalid("invalid distance symbol"));
+                    }
+                    let d =
+                        DIST_BASE[di] as usize + self.bits.bits(DIST_EXTRA[di] as u32)? as usize;
+                    if d > self.out.len() {
+                        return Err(invalid("distance too far back"));
+                    }
+                    for _ in 0..len {
+                        let byte = self.out[self.out.len() - d];
+                        self.push(byte)?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut log = String::new();
+        for i in 0..2000 {
+            log.push_str(&format!(
+                "{{\"ts\":{i},\"level\":\"info\",\"msg\":\"tick\"}}\n"
+            ));
+        }
+        let noise: Vec<u8> = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for input in [
+            &b""[..],
+            b"a",
+            b"aaaaaaaaaaaaaaaaaaaa",
+            log.as_bytes(),
+            &noise,
+        ] {
+            let packed = compress(input);
+            assert_eq!(decompress(&packed).unwrap(), input);
+        }
+        assert!(compress(log.as_bytes()).len() < log.len() / 4);
+        assert_eq!(compress(log.as_bytes()), compress(log.as_bytes()));
+    }
+
+    #[test]
+    fn test_reads_zlib_output() {
+        // Python's `gzip.compress(text, 9, mtime=0)`: one dynamic-Huffman block.
+        let text = concat!(
+            "drone wax comb drone bee drone honey ant bee comb ant bee drone bee ",
+            "drone ant bee hive drone hive queen wax bee wax hive ant ant hive ",
+            "honey drone honey honey bee wax bee ant honey honey wax drone ant ",
+            "comb honey queen honey\n"
+        );
+        let gz = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x5d, 0x4e, 0x49, 0x0e,
+            0x80, 0x20, 0x0c, 0xbc, 0xfb, 0x8a, 0x7e, 0x0d, 0xa4, 0x09, 0x1e, 0x84, 0x68, 0x5c,
+            0x7f, 0xaf, 0x33, 0xb5, 0x4a, 0x3c, 0xd0, 0xce, 0xd6, 0x0c, 0x69, 0xae, 0x45, 0x65,
+            0x0f, 0x87, 0xf4, 0x75, 0x8c, 0x92, 0x48, 0xa3, 0xea, 0x83, 0xf2, 0x3d, 0x4e, 0x09,
+            0x65, 0xa1, 0xc6, 0x88, 0x93, 0x7f, 0xd4, 0xf5, 0x3c, 0x6c, 0xef, 0x35, 0xe0, 0xb4,
+            0xaa, 0x16, 0x36, 0xc0, 0xc5, 0xa6, 0x8c, 0x38, 0x1e, 0x89, 0xd5, 0xb4, 0x95, 0x36,
+            0xfd, 0x02, 0x9b, 0xe1, 0xc6, 0x83, 0xfe, 0x35, 0xf3, 0x6b, 0x66, 0x58, 0x21, 0x71,
+            0x77, 0x01, 0x9a, 0xe0, 0x10, 0xa2, 0xdf, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(decompress(&gz).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn test_limit_and_corruption() {
+        let packed = compress(&[b'x'; 10_000]);
+        let err = decompress_limited(&packed, 100).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut corrupt = packed.clone();
+        let n = corrupt.len();
+        corrupt[n - 8] ^= 1;
+        assert!(decompress(&corrupt).is_err());
+        assert!(decompress(b"not gzip at all, not gzip").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! Random identifiers and the stable installation ID.
+//!
+//! [`install_id`] returns an anonymous UUID that identifies this
+//! installation across runs. It is generated on first use and stored as
+//! `install_id` in [`dirs::data_dir`]; creation happens under a
+//! [`LockFile`] and is written atomically, so concurrent first runs agree on
+//! one ID.
+
+use crate::clock::now_ns;
+use crate::dirs;
+use crate::guard;
+use crate::lock::LockFile;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const FILE_NAME: &str = "install_id";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Return this installation's ID, creating it on first use.
+///
+/// The value is cached for the life of the process.
+pub fn install_id() -> io::Result<String> {
+    static CACHED: OnceLock<String> = OnceLock::new();
+    if let Some(id) = CACHED.get() {
+        return Ok(id.clone());
+    }
+    let id = install_id_in(&dirs::data_dir()?)?;
+    Ok(CACHED.get_or_init(|| id).clone())
+}
+
+/// Return the installation ID stored in `dir`, creating it if needed.
+pub fn install_id_in(dir: &Path) -> io::Result<String> {
+    let path = dir.join(FILE_NAME);
+    if let Some(id) = read_id(&path)? {
+        return Ok(id);
+    }
+
+    let _lock = LockFile::acquire(dir.join(format!("{FILE_NAME}.lock")), LOCK_TIMEOUT)?;
+    // Another process may have created it while we waited for the lock.
+    if let Some(id) = read_id(&path)? {
+        return Ok(id);
+    }
+    let id = new_uuid();
+    let tmp = guard::remove_file_on_drop(dir.join(format!("{FILE_NAME}.tmp")));
+    fs::write(&*tmp, format!("{id}\n"))?;
+    fs::rename(&*tmp, &path)?;
+    tmp.disarm();
+    Ok(id)
+}
+
+/// Read a stored ID; a missing or corrupt file reads as `None`.
+fn read_id(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(data) => {
+            let id = data.trim();
+            Ok(is_uuid(id).then(|| id.to_string()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Generate a random (version 4) UUID in lowercase hyphenated form.
+pub fn new_uuid() -> String {
+    let mut bytes = random_bytes();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = crate::hash::to_hex(&bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Return `true` if `s` is a lowercase hyphenated UUID.
+pub fn is_uuid(s: &str) -> bool {
+    s.len() == 36
+        && s.bytes().enumerate().all(|(i, b)| match i {
+            8 | 13 | 18 | 23 => b == b'-',
+            _ => b.is_ascii_digit() || (b'a'..=b'f').contains(&b),
+        })
+}
+
+/// 16 random bytes from the OS where available, otherwise from std's
+/// randomly keyed hasher mixed with the time, PID, and a counter.
+pub(crate) fn random_bytes() -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    if fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .is_ok()
+    {
+        return bytes;
+    }
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = now_ns();
+    for chunk in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(nanos);
+        hasher.write_u32(std::process::id());
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::collections::HashSet;
+    use std::thread;
+
+    #[test]
+    fn test_uuid_format() {
+        let ids: HashSet<String> = (0..100).map(|_| new_uuid()).collect();
+        assert_eq!(ids.len(), 100);
+        for id in &ids {
+            assert!(is_uuid(id), "{id}");
+            assert_eq!(&id[14..15], "4");
+            assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"));
+        }
+        assert!(!is_uuid("not-a-uuid"));
+        assert!(!is_uuid("6F9619FF-8B86-D011-B42D-00C04FC964FF"));
+    }
+
+    #[test]
+    fn test_install_id_persists() {
+        let tmp = TempDir::new("apiari-id-test-persist").unwrap();
+        let first = install_id_in(tmp.path()).unwrap();
+        assert_eq!(install_id_in(tmp.path()).unwrap(), first);
+        assert_eq!(
+            fs::read_to_string(tmp.join(FILE_NAME)).unwrap().trim(),
+            first
+        );
+        assert!(!tmp.join("install_id.lock").exists());
+    }
+
+    #[test]
+    fn test_corrupt_id_is_replaced() {
+        let tmp = TempDir::new("apiari-id-test-corrupt").unwrap();
+        fs::write(tmp.join(FILE_NAME), "garbage").unwrap();
+        let id = install_id_in(tmp.path()).unwrap();
+        assert!(is_uuid(&id));
+    }
+
+    #[test]
+    fn test_concurrent_first_use_agrees() {
+        let tmp = TempDir::new("apiari-id-test-race").unwrap();
+        let dir = tmp.path().to_path_buf();
+        let ids: HashSet<String> = (0..8)
+            .map(|_| {
+                let dir = dir.clone();
+                thread::spawn(move || install_id_in(&dir).unwrap())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect();
+        assert_eq!(ids.len(), 1);
+    }
+}
@@ -3,20 +3,45 @@
 //! Provides [`JsonlReader`] and [`JsonlWriter`] for line-delimited JSON files.
 //! The reader tracks a byte offset so that each call to [`JsonlReader::poll`]
 //! only returns newly appended records since the last read.
+//!
+//! Both types default to the real filesystem; the `*_in` constructors accept
+//! any [`FileSystem`] (e.g. [`crate::vfs::MemoryFs`] in tests).
+//!
+//! With feature `sqlite`, [`export_sqlite`] copies a JSONL file into a
+//! SQLite table for analysis.
+//!
+//! [`backfill`] rewrites a log written by older tools into the current
+//! record format, running each record through versioned [`Upgraders`].
+//! [`fsck`] finds torn and corrupt lines and can truncate or quarantine them.
+
+mod backfill;
+mod fsck;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
+pub use backfill::{BackfillReport, Skipped, Upgraders, backfill};
+pub use fsck::{Damage, DamageKind, FsckReport, RepairMode, fsck, quarantine_path};
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::{ExportReport, TableMapping, export_sqlite};
+
+use crate::vfs::{FileSystem, StdFs};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use std::fs::{self, OpenOptions};
-use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::io;
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 
+/// How much of the file [`JsonlReader::poll`] reads at a time.
+const POLL_CHUNK: usize = 64 * 1024;
+
 /// Reads JSONL records from a file, tracking the byte offset so that
 /// each poll only returns lines appended since the previous read.
 ///
-/// Generic over any `T: DeserializeOwned`.
+/// Generic over any `T: DeserializeOwned` and any [`FileSystem`].
 #[derive(Debug)]
-pub struct JsonlReader<T> {
+pub struct JsonlReader<T, F = StdFs> {
+    fs: F,
     path: PathBuf,
     offset: u64,
     _marker: PhantomData<T>,
@@ -25,11 +50,7 @@ pub struct JsonlReader<T> {
 impl<T: DeserializeOwned> JsonlReader<T> {
     /// Create a new reader for the given path, starting at byte offset 0.
     pub fn new(path: impl Into<PathBuf>) -> Self {
-        Self {
-            path: path.into(),
-            offset: 0,
-            _marker: PhantomData,
-        }
+        Self::new_in(StdFs, path)
     }
 
     /// Create a new reader starting at the given byte offset.
@@ -37,13 +58,31 @@ impl<T: DeserializeOwned> JsonlReader<T> {
     /// Useful when restoring from persisted state — you can resume reading
     /// from where you left off without replaying old messages.
     pub fn with_offset(path: impl Into<PathBuf>, offset: u64) -> Self {
+        Self::with_offset_in(StdFs, path, offset)
+    }
+}
+
+impl<T: DeserializeOwned, F: FileSystem> JsonlReader<T, F> {
+    /// Create a new reader on the given filesystem, starting at offset 0.
+    pub fn new_in(fs: F, path: impl Into<PathBuf>) -> Self {
+        Self::with_offset_in(fs, path, 0)
+    }
+
+    /// Create a new reader on the given filesystem at the given offset.
+    pub fn with_offset_in(fs: F, path: impl Into<PathBuf>, offset: u64) -> Self {
         Self {
+            fs,
             path: path.into(),
             offset,
             _marker: PhantomData,
         }
     }
 
+    /// Return the file path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     /// Return the current byte offset.
     pub fn offset(&self) -> u64 {
         self.offset
@@ -58,9 +97,9 @@ impl<T: DeserializeOwned> JsonlReader<T> {
     ///
     /// Returns the new offset, or 0 if the file does not exist.
     pub fn skip_to_end(&mut self) -> io::Result<u64> {
-        match fs::metadata(&self.path) {
+        match self.fs.metadata(&self.path) {
             Ok(meta) => {
-                self.offset = meta.len();
+                self.offset = meta.len;
                 Ok(self.offset)
             }
             Err(e) if e.kind() == io::ErrorKind::NotFound => {
@@ -76,51 +115,64 @@ impl<T: DeserializeOwned> JsonlReader<T> {
     /// Returns a vector of successfully deserialized records. Malformed lines
     /// are silently skipped (the offset still advances past them).
     pub fn poll(&mut self) -> io::Result<Vec<T>> {
-        if !self.path.exists() {
-            return Ok(Vec::new());
-        }
-
-        let file = fs::File::open(&self.path)?;
-        let file_len = file.metadata()?.len();
+        let file_len = match self.fs.metadata(&self.path) {
+            Ok(meta) => meta.len,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
 
         if file_len <= self.offset {
             return Ok(Vec::new());
         }
 
-        let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::Start(self.offset))?;
-
+        // Read in bounded chunks so a reader far behind (or starting at 0 on
+        // a large log) doesn't load the whole tail at once.
         let mut records = Vec::new();
-        let mut line = String::new();
-
-        loop {
-            line.clear();
-            let bytes_read = reader.read_line(&mut line)?;
-            if bytes_read == 0 {
+        let mut pending = Vec::new();
+        let mut pos = self.offset;
+        while pos < file_len {
+            let want = usize::try_from(file_len - pos).map_or(POLL_CHUNK, |n| n.min(POLL_CHUNK));
+            let chunk = self.fs.read_range(&self.path, pos, want)?;
+            if chunk.is_empty() {
                 break;
             }
-            self.offset += bytes_read as u64;
+            pos += chunk.len() as u64;
+            pending.extend_from_slice(&chunk);
+            if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+                let partial = pending.split_off(end + 1);
+                self.parse_lines(&pending, &mut records);
+                pending = partial;
+            }
+        }
+        self.parse_lines(&pending, &mut records);
 
-            let trimmed = line.trim();
+        Ok(records)
+    }
+
+    /// Parse whole lines from `data`, advancing the offset past each.
+    fn parse_lines(&mut self, data: &[u8], records: &mut Vec<T>) {
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            self.offset += line.len() as u64;
+
+            let trimmed = line.trim_ascii();
             if trimmed.is_empty() {
                 continue;
             }
 
-            if let Ok(record) = serde_json::from_str::<T>(trimmed) {
+            if let Ok(record) = serde_json::from_slice::<T>(trimmed) {
                 records.push(record);
             }
             // Malformed lines are silently skipped.
         }
-
-        Ok(records)
     }
 }
 
 /// Appends JSONL records to a file, creating parent directories as needed.
 ///
-/// Generic over any `T: Serialize`.
+/// Generic over any `T: Serialize` and any [`FileSystem`].
 #[derive(Debug)]
-pub struct JsonlWriter<T> {
+pub struct JsonlWriter<T, F = StdFs> {
+    fs: F,
     path: PathBuf,
     _marker: PhantomData<T>,
 }
@@ -128,7 +180,15 @@ pub struct JsonlWriter<T> {
 impl<T: Serialize> JsonlWriter<T> {
     /// Create a new writer for the given path.
     pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self::new_in(StdFs, path)
+    }
+}
+
+impl<T: Serialize, F: FileSystem> JsonlWriter<T, F> {
+    /// Create a new writer on the given filesystem.
+    pub fn new_in(fs: F, path: impl Into<PathBuf>) -> Self {
         Self {
+            fs,
             path: path.into(),
             _marker: PhantomData,
         }
@@ -144,25 +204,23 @@ impl<T: Serialize> JsonlWriter<T> {
     /// Creates parent directories and the file itself if they don't exist.
     pub fn append(&self, record: &T) -> io::Result<()> {
         if let Some(parent) = self.path.parent() {
-            fs::create_dir_all(parent)?;
+            self.fs.create_dir_all(parent)?;
         }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.path)?;
-
-        let json = serde_json::to_string(record)
+        let mut json = serde_json::to_string(record)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        writeln!(file, "{}", json)?;
-        Ok(())
+        json.push('\n');
+        self.fs.append(&self.path, json.as_bytes())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vfs::MemoryFs;
     use serde::{Deserialize, Serialize};
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
 
     #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
     struct TestMsg {
@@ -328,4 +386,47 @@ mod tests {
 
         let _ = fs::remove_dir_all(&dir);
     }
+
+    #[test]
+    fn test_memory_fs_round_trip() {
+        let fs = MemoryFs::new();
+        let path = Path::new("/runtime/inbox.jsonl");
+
+        let writer = JsonlWriter::<TestMsg, _>::new_in(fs.clone(), path);
+        let mut reader = JsonlReader::<TestMsg, _>::new_in(fs.clone(), path);
+        assert!(reader.poll().unwrap().is_empty());
+
+        writer
+            .append(&TestMsg {
+                id: 1,
+                text: "in memory".into(),
+            })
+            .unwrap();
+        let records = reader.poll().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].text, "in memory");
+        assert_eq!(reader.offset(), fs.metadata(path).unwrap().len);
+    }
+
+    #[test]
+    fn test_poll_reads_in_chunks() {
+        let fs = MemoryFs::new();
+        let path = Path::new("/big.jsonl");
+        let writer = JsonlWriter::<TestMsg, _>::new_in(fs.clone(), path);
+        // Lines of varying length, so they straddle chunk boundaries.
+        for id in 0..300 {
+            let text = "x".repeat(100 + id as usize * 7);
+            writer.append(&TestMsg { id, text }).unwrap();
+        }
+        fs.append(path, b"{\"id\":").unwrap();
+        let len = fs.metadata(path).unwrap().len;
+        assert!(len > 3 * POLL_CHUNK as u64);
+
+        let mut reader = JsonlReader::<TestMsg, _>::new_in(fs.clone(), path);
+        let records = reader.poll().unwrap();
+        assert_eq!(records.len(), 300);
+        assert!(records.iter().enumerate().all(|(i, r)| r.id == i as u32));
+        // A torn final line is consumed, as before.
+        assert_eq!(reader.offset(), len);
+    }
 }
@@ -0,0 +1,280 @@
+//! Rewriting old JSONL logs into the current record format.
+//!
+//! After a record's shape changes, logs written by older tools can no longer
+//! be read by current consumers. [`backfill`] streams such a log through
+//! [`Upgraders`] — one step per version, like [`crate::layout::Migrator`] —
+//! and writes a log where every record is at the target version.
+//!
+//! A record's version is read from its version field (`"version"` by
+//! default; a record without one is version 0). With a payload field set,
+//! only that field is handed to the steps and the rest of the envelope
+//! (event name, timestamps, ids) is copied unchanged. Records that can't be
+//! parsed or upgraded are left out of the new log and listed in the
+//! [`BackfillReport`], so one bad line doesn't sink the rest.
+
+use crate::guard;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+type Step = Box<dyn Fn(Value) -> io::Result<Value> + Send + Sync>;
+
+/// Version steps for one record type.
+pub struct Upgraders {
+    version_field: String,
+    payload_field: Option<String>,
+    steps: BTreeMap<u32, Step>,
+}
+
+impl Default for Upgraders {
+    fn default() -> Self {
+        Self {
+            version_field: "version".to_string(),
+            payload_field: None,
+            steps: BTreeMap::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Upgraders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraders")
+            .field("version_field", &self.version_field)
+            .field("payload_field", &self.payload_field)
+            .field("steps", &self.steps.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Upgraders {
+    /// No steps (target version 0), versions read from `"version"`, and the
+    /// whole record upgraded.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The top-level field holding each record's version.
+    pub fn version_field(mut self, field: impl Into<String>) -> Self {
+        self.version_field = field.into();
+        self
+    }
+
+    /// Upgrade only this top-level field, keeping the rest of the envelope.
+    pub fn payload_field(mut self, field: impl Into<String>) -> Self {
+        self.payload_field = Some(field.into());
+        self
+    }
+
+    /// Register the step that upgrades version `from` to `from + 1`.
+    pub fn step(
+        mut self,
+        from: u32,
+        upgrade: impl Fn(Value) -> io::Result<Value> + Send + Sync + 'static,
+    ) -> Self {
+        self.steps.insert(from, Box::new(upgrade));
+        self
+    }
+
+    /// The version every record is brought to.
+    pub fn target(&self) -> u32 {
+        self.steps.keys().next_back().map_or(0, |from| from + 1)
+    }
+
+    /// Bring one record to [`target`](Self::target). Returns whether it
+    /// changed. On error the record may be left half-upgraded.
+    pub fn upgrade(&self, record: &mut Value) -> io::Result<bool> {
+        let Value::Object(fields) = record else {
+            return Err(invalid("record is not a JSON object".into()));
+        };
+        let mut version = match fields.get(&self.version_field) {
+            None => 0,
+            Some(v) => v
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| invalid(format!("bad {}: {v}", self.version_field)))?,
+        };
+        let target = self.target();
+        if version > target {
+            return Err(invalid(format!(
+                "version {version} is newer than the target {target}"
+            )));
+        }
+        if version == target {
+            return Ok(false);
+        }
+
+        let mut data = match &self.payload_field {
+            Some(field) => fields
+                .remove(field)
+                .ok_or_else(|| invalid(format!("missing {field}")))?,
+            None => Value::Object(std::mem::take(fields)),
+        };
+        while version < target {
+            let step = self
+                .steps
+                .get(&version)
+                .ok_or_else(|| invalid(format!("no upgrade from version {version}")))?;
+            data =
+                step(data).map_err(|e| invalid(format!("upgrade from version {version}: {e}")))?;
+            version += 1;
+        }
+        match &self.payload_field {
+            Some(field) => {
+                fields.insert(field.clone(), data);
+            }
+            None => match data {
+                Value::Object(upgraded) => *fields = upgraded,
+                _ => return Err(invalid("upgrade returned a non-object record".into())),
+            },
+        }
+        fields.insert(self.version_field.clone(), Value::from(version));
+        Ok(true)
+    }
+}
+
+/// A source line [`backfill`] left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    /// 1-based line number in the source log.
+    pub line: usize,
+    pub error: String,
+}
+
+/// What [`backfill`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackfillReport {
+    /// Records written to the new log.
+    pub written: usize,
+    /// Of those, records that needed upgrading.
+    pub upgraded: usize,
+    pub skipped: Vec<Skipped>,
+}
+
+/// Rewrite the JSONL log `src` into `dst` with every record upgraded to
+/// `upgraders.target()`. `dst` is replaced atomically; `src` is untouched
+/// (pass the same path for both to rewrite in place).
+///
+/// # Errors
+///
+/// Returns `io::Error` only if reading `src` or writing `dst` fails; bad
+/// records are reported in [`BackfillReport::skipped`].
+pub fn backfill(src: &Path, dst: &Path, upgraders: &Upgraders) -> io::Result<BackfillReport> {
+    let reader = BufReader::new(File::open(src)?);
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = dst.as_os_str().to_owned();
+    tmp_name.push(".backfill.tmp");
+    let tmp = guard::remove_file_on_drop(tmp_name);
+    let mut out = BufWriter::new(File::create(&*tmp)?);
+
+    let mut report = BackfillReport::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = serde_json::from_str::<Value>(&line)
+            .map_err(|e| invalid(e.to_string()))
+            .and_then(|mut record| upgraders.upgrade(&mut record).map(|up| (record, up)));
+        match result {
+            Ok((record, upgraded)) => {
+                serde_json::to_writer(&mut out, &record).map_err(io::Error::other)?;
+                out.write_all(b"\n")?;
+                report.written += 1;
+                report.upgraded += usize::from(upgraded);
+            }
+            Err(e) => report.skipped.push(Skipped {
+                line: i + 1,
+                error: e.to_string(),
+            }),
+        }
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&*tmp, dst)?;
+    tmp.disarm();
+    Ok(report)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use serde_json::json;
+
+    fn upgraders() -> Upgraders {
+        Upgraders::new()
+            .payload_field("payload")
+            // v0 -> v1: `task` renamed to `task_id`.
+            .step(0, |mut p| {
+                let task = p["task"].take();
+                p["task_id"] = task;
+                p.as_object_mut().unwrap().remove("task");
+                Ok(p)
+            })
+            // v1 -> v2: durations in ms instead of seconds.
+            .step(1, |mut p| {
+                let secs = p["secs"]
+                    .as_u64()
+                    .ok_or_else(|| invalid("no secs".into()))?;
+                p["ms"] = json!(secs * 1000);
+                p.as_object_mut().unwrap().remove("secs");
+                Ok(p)
+            })
+    }
+
+    #[test]
+    fn test_upgrade_keeps_envelope() {
+        let up = upgraders();
+        assert_eq!(up.target(), 2);
+        let mut record = json!({"event": "done", "ts": 5, "payload": {"task": "t1", "secs": 2}});
+        assert!(up.upgrade(&mut record).unwrap());
+        assert_eq!(
+            record,
+            json!({"event": "done", "ts": 5, "version": 2, "payload": {"task_id": "t1", "ms": 2000}})
+        );
+        assert!(!up.upgrade(&mut record).unwrap());
+
+        let mut future = json!({"version": 3, "payload": {}});
+        assert!(up.upgrade(&mut future).is_err());
+    }
+
+    #[test]
+    fn test_backfill_reports_unconvertible_records() {
+        let tmp = TempDir::new("apiari-ipc-test-backfill").unwrap();
+        let src = tmp.path().join("old.jsonl");
+        let dst = tmp.path().join("new/events.jsonl");
+        fs::write(
+            &src,
+            concat!(
+                "{\"payload\":{\"task\":\"a\",\"secs\":1}}\n",
+                "not json\n",
+                "\n",
+                "{\"version\":1,\"payload\":{\"task_id\":\"b\"}}\n",
+                "{\"version\":2,\"payload\":{\"task_id\":\"c\",\"ms\":7}}\n",
+            ),
+        )
+        .unwrap();
+
+        let report = backfill(&src, &dst, &upgraders()).unwrap();
+        assert_eq!((report.written, report.upgraded), (2, 1));
+        let lines: Vec<usize> = report.skipped.iter().map(|s| s.line).collect();
+        assert_eq!(lines, [2, 4]);
+        assert!(report.skipped[1].error.contains("upgrade from version 1"));
+
+        let out = fs::read_to_string(&dst).unwrap();
+        let ids: Vec<Value> = out
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap()["payload"]["task_id"].clone())
+            .collect();
+        assert_eq!(ids, ["a", "c"]);
+        assert_eq!(fs::read_dir(dst.parent().unwrap()).unwrap().count(), 1);
+    }
+}
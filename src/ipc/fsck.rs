@@ -0,0 +1,248 @@
+//! Checking and repairing damaged JSONL files.
+//!
+//! A crash mid-append leaves a torn final line with no newline; a bad disk
+//! or a stray writer can leave lines that aren't JSON at all. [`fsck`] finds
+//! both, with their byte offsets, and depending on the [`RepairMode`] leaves
+//! the file alone, truncates the torn tail, or moves every damaged line to a
+//! `<path>.quarantine` file so nothing is lost. A final line that is a
+//! complete record and only lacks its newline is kept; repairs add the
+//! newline.
+//!
+//! Repairs rewrite or truncate the file, so run them while no writer has it
+//! open: a live writer's half-written line looks exactly like a torn tail.
+
+use crate::guard;
+use serde::de::IgnoredAny;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// What [`fsck`] does about damage it finds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepairMode {
+    /// Change nothing.
+    #[default]
+    Report,
+    /// Cut off a torn final line. Corrupt lines before it are only reported.
+    Truncate,
+    /// Move the torn tail and every corrupt line to `<path>.quarantine`,
+    /// keeping the good lines in order.
+    Quarantine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DamageKind {
+    /// The last line has no terminating newline and isn't valid JSON.
+    Torn,
+    /// The last line is a valid record but has no terminating newline.
+    /// Repairs append the newline.
+    MissingNewline,
+    /// A complete line that isn't valid JSON.
+    Corrupt,
+}
+
+/// One damaged line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    pub kind: DamageKind,
+    /// Byte offset of the line's start.
+    pub offset: u64,
+    /// Length in bytes, not counting the newline.
+    pub len: u64,
+    /// 1-based line number.
+    pub line: usize,
+    pub error: String,
+}
+
+/// What [`fsck`] found and did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Valid records.
+    pub records: usize,
+    pub damage: Vec<Damage>,
+    /// How many of the damaged lines were removed from the file.
+    pub repaired: usize,
+    /// Where removed lines were moved, in [`RepairMode::Quarantine`].
+    pub quarantine: Option<PathBuf>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.damage.is_empty()
+    }
+}
+
+/// The quarantine file for `path`.
+pub fn quarantine_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".quarantine");
+    PathBuf::from(name)
+}
+
+/// Scan the JSONL file at `path` for torn and corrupt lines, repairing them
+/// as `mode` says. Blank lines are ignored.
+///
+/// # Errors
+///
+/// Returns `io::Error` if the file can't be read or a repair fails.
+pub fn fsck(path: &Path, mode: RepairMode) -> io::Result<FsckReport> {
+    let data = fs::read(path)?;
+    let mut report = FsckReport::default();
+    let mut offset = 0usize;
+    let mut spans = Vec::new();
+    for (i, line) in data.split_inclusive(|&b| b == b'\n').enumerate() {
+        let start = offset;
+        offset += line.len();
+        let torn = !line.ends_with(b"\n");
+        let body = line.strip_suffix(b"\n").unwrap_or(line);
+        if !torn && body.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let (kind, error) = match serde_json::from_slice::<IgnoredAny>(body) {
+            Ok(_) if !torn => {
+                report.records += 1;
+                continue;
+            }
+            Ok(_) => {
+                report.records += 1;
+                (DamageKind::MissingNewline, "missing newline".to_string())
+            }
+            Err(e) if torn => (DamageKind::Torn, e.to_string()),
+            Err(e) => (DamageKind::Corrupt, e.to_string()),
+        };
+        report.damage.push(Damage {
+            kind,
+            offset: start as u64,
+            len: body.len() as u64,
+            line: i + 1,
+            error,
+        });
+        if kind != DamageKind::MissingNewline {
+            spans.push(start..offset);
+        }
+    }
+
+    match mode {
+        RepairMode::Report => {}
+        RepairMode::Truncate => {
+            if let Some(torn) = report.damage.last().filter(|d| d.kind == DamageKind::Torn) {
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(torn.offset)?;
+                report.repaired = 1;
+            }
+        }
+        RepairMode::Quarantine if !spans.is_empty() => {
+            let quarantine = quarantine_path(path);
+            let mut bad = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&quarantine)?;
+            for span in &spans {
+                bad.write_all(&data[span.clone()])?;
+                if !data[..span.end].ends_with(b"\n") {
+                    bad.write_all(b"\n")?;
+                }
+            }
+            bad.sync_all()?;
+
+            let mut tmp_name = path.as_os_str().to_owned();
+            tmp_name.push(".fsck.tmp");
+            let tmp = guard::remove_file_on_drop(tmp_name);
+            let mut good = BufWriter::new(File::create(&*tmp)?);
+            let mut kept = 0;
+            for span in &spans {
+                good.write_all(&data[kept..span.start])?;
+                kept = span.end;
+            }
+            good.write_all(&data[kept..])?;
+            good.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            fs::rename(&*tmp, path)?;
+            tmp.disarm();
+            report.repaired = spans.len();
+            report.quarantine = Some(quarantine);
+        }
+        RepairMode::Quarantine => {}
+    }
+    let unterminated = report.damage.last().map(|d| d.kind) == Some(DamageKind::MissingNewline);
+    if mode != RepairMode::Report && unterminated {
+        OpenOptions::new()
+            .append(true)
+            .open(path)?
+            .write_all(b"\n")?;
+        report.repaired += 1;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    const DAMAGED: &[u8] = b"{\"n\":1}\n\xff\xfe garbage\n\n{\"n\":2}\n{\"n\":3";
+
+    #[test]
+    fn test_report_and_truncate() {
+        let tmp = TempDir::new("apiari-ipc-test-fsck-truncate").unwrap();
+        let path = tmp.path().join("events.jsonl");
+        fs::write(&path, DAMAGED).unwrap();
+
+        let report = fsck(&path, RepairMode::Report).unwrap();
+        assert_eq!(report.records, 2);
+        let found: Vec<(DamageKind, u64, usize)> = report
+            .damage
+            .iter()
+            .map(|d| (d.kind, d.offset, d.line))
+            .collect();
+        assert_eq!(
+            found,
+            [(DamageKind::Corrupt, 8, 2), (DamageKind::Torn, 28, 5)]
+        );
+        assert_eq!(fs::read(&path).unwrap(), DAMAGED);
+
+        let report = fsck(&path, RepairMode::Truncate).unwrap();
+        assert_eq!(report.repaired, 1);
+        assert_eq!(fs::read(&path).unwrap(), &DAMAGED[..28]);
+        let report = fsck(&path, RepairMode::Truncate).unwrap();
+        assert_eq!((report.damage.len(), report.repaired), (1, 0));
+    }
+
+    #[test]
+    fn test_quarantine_moves_bad_lines() {
+        let tmp = TempDir::new("apiari-ipc-test-fsck-quarantine").unwrap();
+        let path = tmp.path().join("events.jsonl");
+        fs::write(&path, DAMAGED).unwrap();
+
+        let report = fsck(&path, RepairMode::Quarantine).unwrap();
+        assert_eq!(report.repaired, 2);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "{\"n\":1}\n\n{\"n\":2}\n"
+        );
+        let quarantine = report.quarantine.unwrap();
+        assert_eq!(
+            fs::read(&quarantine).unwrap(),
+            b"\xff\xfe garbage\n{\"n\":3\n"
+        );
+        assert!(fsck(&path, RepairMode::Report).unwrap().is_clean());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_complete_final_record_gets_its_newline() {
+        let tmp = TempDir::new("apiari-ipc-test-fsck-newline").unwrap();
+        let path = tmp.path().join("events.jsonl");
+        fs::write(&path, "{\"n\":1}\n{\"n\":2}").unwrap();
+
+        let report = fsck(&path, RepairMode::Report).unwrap();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.damage[0].kind, DamageKind::MissingNewline);
+
+        let report = fsck(&path, RepairMode::Truncate).unwrap();
+        assert_eq!(report.repaired, 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+        assert!(fsck(&path, RepairMode::Quarantine).unwrap().is_clean());
+    }
+}
@@ -0,0 +1,361 @@
+//! JSONL to SQLite export (feature `sqlite`).
+//!
+//! [`export_sqlite`] copies records from a JSONL file into a SQLite table for
+//! ad-hoc SQL analysis. A [`TableMapping`] picks which record fields become
+//! columns and which of those get an index; the whole record is also kept in
+//! a `record` column for `json_extract` queries on anything else.
+//!
+//! Rows are keyed by the byte offset of their line, and the offset reached is
+//! saved in the database (table `apiari_export_cursor`) in the same
+//! transaction as the rows. Re-running an export therefore only ingests lines
+//! appended since the last run, and an interrupted export is simply redone.
+//! If the file has shrunk (it was rotated or rewritten), the table is cleared
+//! and exported from the start.
+//!
+//! The crate doesn't link SQLite: the `sqlite3` command-line shell must be on
+//! `PATH`.
+
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const CURSOR_TABLE: &str = "apiari_export_cursor";
+
+/// Records per transaction, so a huge backlog doesn't build one giant
+/// script.
+const BATCH: usize = 5_000;
+
+/// Which record fields become columns of the export table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableMapping {
+    table: String,
+    columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Column {
+    name: String,
+    pointer: String,
+    indexed: bool,
+}
+
+impl TableMapping {
+    /// Export into `table`, with no columns besides the raw record.
+    pub fn new(table: impl Into<String>) -> Self {
+        Self {
+            table: table.into(),
+            columns: Vec::new(),
+        }
+    }
+
+    /// Add column `name` holding the field at `path` (`task_id`, or
+    /// `payload.task.id` for nested fields). Missing fields are `NULL`;
+    /// objects and arrays are stored as JSON text.
+    pub fn column(self, name: impl Into<String>, path: &str) -> Self {
+        self.add(name.into(), path, false)
+    }
+
+    /// Like [`column`](Self::column), and create an index on it.
+    pub fn indexed(self, name: impl Into<String>, path: &str) -> Self {
+        self.add(name.into(), path, true)
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    fn add(mut self, name: String, path: &str, indexed: bool) -> Self {
+        let pointer = path
+            .split('.')
+            .map(|part| format!("/{}", part.replace('~', "~0").replace('/', "~1")))
+            .collect();
+        self.columns.retain(|c| c.name != name);
+        self.columns.push(Column {
+            name,
+            pointer,
+            indexed,
+        });
+        self
+    }
+
+    fn schema(&self) -> String {
+        let table = ident(&self.table);
+        let mut columns = vec!["\"line_offset\" INTEGER PRIMARY KEY".to_string()];
+        columns.extend(self.columns.iter().map(|c| ident(&c.name)));
+        columns.push("\"record\" TEXT NOT NULL".to_string());
+        let mut sql = format!(
+            "CREATE TABLE IF NOT EXISTS {table} ({});\n",
+            columns.join(", ")
+        );
+        for column in self.columns.iter().filter(|c| c.indexed) {
+            sql.push_str(&format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {table} ({});\n",
+                ident(&format!("{}_{}", self.table, column.name)),
+                ident(&column.name)
+            ));
+        }
+        sql
+    }
+
+    fn insert(&self, offset: u64, record: &Value, raw: &str) -> String {
+        let mut values = vec![offset.to_string()];
+        values.extend(
+            self.columns
+                .iter()
+                .map(|c| literal(record.pointer(&c.pointer))),
+        );
+        values.push(text(raw));
+        format!(
+            "INSERT OR REPLACE INTO {} VALUES ({});\n",
+            ident(&self.table),
+            values.join(", ")
+        )
+    }
+}
+
+/// What [`export_sqlite`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    /// Rows written in this run.
+    pub exported: u64,
+    /// Lines skipped because they weren't JSON.
+    pub skipped: u64,
+    /// Byte offset in the JSONL file the next export resumes from.
+    pub offset: u64,
+    /// `true` if the file had shrunk and the table was rebuilt.
+    pub restarted: bool,
+}
+
+/// Export records appended to `jsonl_path` since the last export into
+/// `db_path`, creating the database and table as needed.
+///
+/// A trailing line without a newline is left for the next run, since the
+/// writer may still be appending it.
+///
+/// # Errors
+///
+/// Returns `NotFound` if the `sqlite3` shell is not installed, and any
+/// error it reports (the database keeps the previous batch's cursor).
+pub fn export_sqlite(
+    jsonl_path: &Path,
+    db_path: &Path,
+    mapping: &TableMapping,
+) -> io::Result<ExportReport> {
+    let cursor = read_cursor(db_path, mapping)?;
+    let mut file = File::open(jsonl_path)?;
+    let len = file.metadata()?.len();
+    let mut report = ExportReport {
+        offset: cursor,
+        ..ExportReport::default()
+    };
+    let mut script = String::new();
+    if len < cursor {
+        report.offset = 0;
+        report.restarted = true;
+        script.push_str(&format!("DELETE FROM {};\n", ident(&mapping.table)));
+    }
+    file.seek(SeekFrom::Start(report.offset))?;
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut batch = 0;
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)?;
+        if n == 0 || line.last() != Some(&b'\n') {
+            break;
+        }
+        let start = report.offset;
+        report.offset += n as u64;
+        let raw = String::from_utf8_lossy(&line);
+        let raw = raw.trim_end();
+        match serde_json::from_str::<Value>(raw) {
+            Ok(record) => {
+                script.push_str(&mapping.insert(start, &record, raw));
+                report.exported += 1;
+                batch += 1;
+            }
+            Err(_) => report.skipped += 1,
+        }
+        if batch >= BATCH {
+            commit(db_path, mapping, jsonl_path, &script, report.offset)?;
+            script.clear();
+            batch = 0;
+        }
+    }
+    if !script.is_empty() || report.offset != cursor {
+        commit(db_path, mapping, jsonl_path, &script, report.offset)?;
+    }
+    Ok(report)
+}
+
+fn cursor_schema() -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {CURSOR_TABLE} \
+         (\"table_name\" TEXT PRIMARY KEY, \"source\" TEXT NOT NULL, \"offset\" INTEGER NOT NULL);\n"
+    )
+}
+
+fn read_cursor(db_path: &Path, mapping: &TableMapping) -> io::Result<u64> {
+    let script = format!(
+        "{}SELECT \"offset\" FROM {CURSOR_TABLE} WHERE \"table_name\" = {};\n",
+        cursor_schema(),
+        text(&mapping.table)
+    );
+    let out = run(db_path, &script)?;
+    Ok(out.trim().parse().unwrap_or(0))
+}
+
+fn commit(
+    db_path: &Path,
+    mapping: &TableMapping,
+    source: &Path,
+    rows: &str,
+    offset: u64,
+) -> io::Result<()> {
+    let script = format!(
+        "BEGIN;\n{}{}{rows}INSERT OR REPLACE INTO {CURSOR_TABLE} VALUES ({}, {}, {offset});\nCOMMIT;\n",
+        cursor_schema(),
+        mapping.schema(),
+        text(&mapping.table),
+        text(&source.to_string_lossy()),
+    );
+    run(db_path, &script).map(drop)
+}
+
+/// Feed `script` to `sqlite3 -bail` and return its stdout.
+fn run(db_path: &Path, script: &str) -> io::Result<String> {
+    if let Some(parent) = db_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut child = Command::new("sqlite3")
+        .arg("-bail")
+        .arg(db_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                io::Error::new(e.kind(), "the sqlite3 shell was not found on PATH")
+            }
+            _ => e,
+        })?;
+    let write = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(script.as_bytes());
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "sqlite3 failed: {}",
+            stderr.trim()
+        )));
+    }
+    write?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A quoted SQL identifier.
+fn ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// A quoted SQL string literal.
+fn text(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn literal(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "NULL".to_string(),
+        Some(Value::Bool(b)) => u8::from(*b).to_string(),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::String(s)) => text(s),
+        Some(other) => text(&other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::fs::{self, OpenOptions};
+
+    fn has_sqlite() -> bool {
+        Command::new("sqlite3").arg("-version").output().is_ok()
+    }
+
+    fn query(db: &Path, sql: &str) -> String {
+        run(db, sql).unwrap().trim().to_string()
+    }
+
+    fn mapping() -> TableMapping {
+        TableMapping::new("events")
+            .indexed("ts", "ts")
+            .indexed("type", "type")
+            .indexed("task_id", "payload.task_id")
+            .column("ok", "ok")
+    }
+
+    #[test]
+    fn test_export_and_incremental_reexport() {
+        if !has_sqlite() {
+            return;
+        }
+        let tmp = TempDir::new("apiari-ipc-sqlite-test-export").unwrap();
+        let log = tmp.join("events.jsonl");
+        let db = tmp.join("out/events.db");
+        fs::write(
+            &log,
+            "{\"ts\":1,\"type\":\"start\",\"payload\":{\"task_id\":\"t1\"},\"ok\":true}\n\
+             not json\n\
+             {\"ts\":2,\"type\":\"it's done\",\"payload\":{\"task_id\":\"t1\"}}\n\
+             {\"ts\":3,\"type\":\"part",
+        )
+        .unwrap();
+        let report = export_sqlite(&log, &db, &mapping()).unwrap();
+        assert_eq!((report.exported, report.skipped), (2, 1));
+        assert_eq!(
+            query(&db, "SELECT ts, type, task_id, ok FROM events ORDER BY ts;"),
+            "1|start|t1|1\n2|it's done|t1|"
+        );
+        assert!(query(&db, "PRAGMA index_list(events);").contains("events_task_id"));
+
+        let mut file = OpenOptions::new().append(true).open(&log).unwrap();
+        file.write_all(b"ial\"}\n").unwrap();
+        let again = export_sqlite(&log, &db, &mapping()).unwrap();
+        assert_eq!(again.exported, 1);
+        assert_eq!(again.offset, fs::metadata(&log).unwrap().len());
+        assert_eq!(query(&db, "SELECT count(*) FROM events;"), "3");
+        assert_eq!(
+            query(
+                &db,
+                "SELECT json_extract(record, '$.type') FROM events WHERE ts = 3;"
+            ),
+            "partial"
+        );
+        assert_eq!(export_sqlite(&log, &db, &mapping()).unwrap().exported, 0);
+    }
+
+    #[test]
+    fn test_rotated_file_restarts() {
+        if !has_sqlite() {
+            return;
+        }
+        let tmp = TempDir::new("apiari-ipc-sqlite-test-rotated").unwrap();
+        let log = tmp.join("events.jsonl");
+        let db = tmp.join("events.db");
+        fs::write(&log, "{\"ts\":1}\n{\"ts\":2}\n{\"ts\":3}\n").unwrap();
+        export_sqlite(&log, &db, &mapping()).unwrap();
+        fs::write(&log, "{\"ts\":9}\n").unwrap();
+        let report = export_sqlite(&log, &db, &mapping()).unwrap();
+        assert!(report.restarted);
+        assert_eq!(query(&db, "SELECT group_concat(ts) FROM events;"), "9");
+    }
+}
@@ -0,0 +1,451 @@
+//! Bounded pool for one-off background work.
+//!
+//! Where [`scheduler`](crate::scheduler) runs recurring jobs, a [`JobRunner`]
+//! runs ad-hoc ones (uploads, GC passes, compaction) on a fixed number of
+//! worker threads, queueing the rest in submission order. Each job is named,
+//! may fail with an `io::Error`, and is isolated: a panic is recorded as
+//! [`JobOutcome::Panicked`] and the worker carries on.
+//!
+//! A job submitted with a timeout runs through
+//! [`with_timeout`]; if it overruns, its
+//! worker moves on and the job's thread is abandoned (see the
+//! [`timeout`](crate::timeout) module docs).
+//!
+//! [`JobRunner::shutdown`] stops accepting work and waits for queued and
+//! running jobs to finish. Dropping the runner does the same without
+//! waiting: the workers finish the queue in the background.
+
+use crate::timeout::with_timeout;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How a job ended.
+#[derive(Debug)]
+pub enum JobOutcome {
+    Succeeded,
+    Failed(io::Error),
+    Panicked,
+    TimedOut,
+    /// The runner shut down before the job reported back.
+    Lost,
+}
+
+impl JobOutcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Succeeded)
+    }
+}
+
+impl fmt::Display for JobOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Succeeded => f.write_str("succeeded"),
+            Self::Failed(e) => write!(f, "failed: {e}"),
+            Self::Panicked => f.write_str("panicked"),
+            Self::TimedOut => f.write_str("timed out"),
+            Self::Lost => f.write_str("lost"),
+        }
+    }
+}
+
+/// Counters for a [`JobRunner`], as returned by [`JobRunner::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunnerStats {
+    /// Jobs waiting for a worker.
+    pub queued: usize,
+    /// Jobs currently running.
+    pub running: usize,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub panicked: u64,
+    pub timed_out: u64,
+}
+
+/// Configures and starts a [`JobRunner`].
+#[derive(Debug, Clone)]
+pub struct JobRunnerBuilder {
+    threads: usize,
+    name: String,
+    default_timeout: Option<Duration>,
+}
+
+impl JobRunnerBuilder {
+    /// Number of worker threads (default: 2, minimum 1).
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Prefix for worker thread names (default `apiari-jobs`).
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Timeout for jobs submitted without one.
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+
+    /// Spawn the workers.
+    pub fn start(self) -> io::Result<JobRunner> {
+        let shared = Arc::new(Shared::default());
+        let workers = (0..self.threads)
+            .map(|i| {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(format!("{}-{i}", self.name))
+                    .spawn(move || work(&shared))
+            })
+            .collect::<io::Result<Vec<_>>>();
+        let workers = match workers {
+            Ok(workers) => workers,
+            Err(e) => {
+                shared.close();
+                return Err(e);
+            }
+        };
+        Ok(JobRunner {
+            shared,
+            workers,
+            default_timeout: self.default_timeout,
+        })
+    }
+}
+
+type Task = Box<dyn FnOnce() -> io::Result<()> + Send>;
+
+struct Queued {
+    timeout: Option<Duration>,
+    task: Task,
+    done: mpsc::Sender<JobOutcome>,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<Queued>,
+    running: usize,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signalled when a job is queued or the runner closes.
+    available: Condvar,
+    /// Signalled when a job finishes.
+    finished: Condvar,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    panicked: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.available.notify_all();
+    }
+}
+
+/// A fixed pool of worker threads running named one-off jobs.
+///
+/// ```ignore
+/// let jobs = JobRunner::builder().threads(4).name("uploads").start()?;
+/// let upload = jobs.submit_with_timeout("upload", Duration::from_secs(60), move || push(&bundle));
+/// jobs.submit("gc", || gc::sweep(&sessions_dir).map(drop));
+/// if !upload.wait().is_success() { ... }
+/// jobs.shutdown(Duration::from_secs(10))?;
+/// ```
+pub struct JobRunner {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+    default_timeout: Option<Duration>,
+}
+
+impl JobRunner {
+    pub fn builder() -> JobRunnerBuilder {
+        JobRunnerBuilder {
+            threads: 2,
+            name: "apiari-jobs".to_string(),
+            default_timeout: None,
+        }
+    }
+
+    /// Queue `task` under the runner's default timeout.
+    pub fn submit(
+        &self,
+        name: impl Into<String>,
+        task: impl FnOnce() -> io::Result<()> + Send + 'static,
+    ) -> JobHandle {
+        self.enqueue(name.into(), self.default_timeout, Box::new(task))
+    }
+
+    /// Queue `task`, giving up on it after `timeout` of running.
+    pub fn submit_with_timeout(
+        &self,
+        name: impl Into<String>,
+        timeout: Duration,
+        task: impl FnOnce() -> io::Result<()> + Send + 'static,
+    ) -> JobHandle {
+        self.enqueue(name.into(), Some(timeout), Box::new(task))
+    }
+
+    fn enqueue(&self, name: String, timeout: Option<Duration>, task: Task) -> JobHandle {
+        let (done, outcome) = mpsc::channel();
+        self.shared.lock().jobs.push_back(Queued {
+            timeout,
+            task,
+            done,
+        });
+        self.shared.available.notify_one();
+        JobHandle { name, outcome }
+    }
+
+    pub fn stats(&self) -> RunnerStats {
+        let (queued, running) = {
+            let queue = self.shared.lock();
+            (queue.jobs.len(), queue.running)
+        };
+        RunnerStats {
+            queued,
+            running,
+            succeeded: self.shared.succeeded.load(Ordering::Relaxed),
+            failed: self.shared.failed.load(Ordering::Relaxed),
+            panicked: self.shared.panicked.load(Ordering::Relaxed),
+            timed_out: self.shared.timed_out.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stop accepting jobs and wait up to `timeout` for the queue to drain.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::TimedOut` if jobs were still queued or
+    /// running at the deadline; they keep going in the background.
+    pub fn shutdown(mut self, timeout: Duration) -> io::Result<()> {
+        self.shared.close();
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.shared.lock();
+        while !queue.jobs.is_empty() || queue.running > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "{} queued and {} running jobs after {timeout:?}",
+                        queue.jobs.len(),
+                        queue.running
+                    ),
+                ));
+            }
+            queue = self
+                .shared
+                .finished
+                .wait_timeout(queue, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        drop(queue);
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for JobRunner {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+impl fmt::Debug for JobRunner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JobRunner")
+            .field("threads", &self.workers.len())
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Handle to a submitted job.
+#[derive(Debug)]
+pub struct JobHandle {
+    name: String,
+    outcome: Receiver<JobOutcome>,
+}
+
+impl JobHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Block until the job ends.
+    pub fn wait(self) -> JobOutcome {
+        self.outcome.recv().unwrap_or(JobOutcome::Lost)
+    }
+
+    /// The outcome if the job has ended, without blocking.
+    pub fn try_outcome(&self) -> Option<JobOutcome> {
+        match self.outcome.try_recv() {
+            Ok(outcome) => Some(outcome),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(JobOutcome::Lost),
+        }
+    }
+}
+
+fn work(shared: &Shared) {
+    loop {
+        let job = {
+            let mut queue = shared.lock();
+            loop {
+                if let Some(job) = queue.jobs.pop_front() {
+                    queue.running += 1;
+                    break job;
+                }
+                if queue.closed {
+                    return;
+                }
+                queue = shared
+                    .available
+                    .wait(queue)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        };
+        let outcome = run(job.timeout, job.task);
+        let counter = match &outcome {
+            JobOutcome::Succeeded => &shared.succeeded,
+            JobOutcome::Failed(_) | JobOutcome::Lost => &shared.failed,
+            JobOutcome::Panicked => &shared.panicked,
+            JobOutcome::TimedOut => &shared.timed_out,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let _ = job.done.send(outcome);
+        shared.lock().running -= 1;
+        shared.finished.notify_all();
+    }
+}
+
+fn run(timeout: Option<Duration>, task: Task) -> JobOutcome {
+    let caught = match timeout {
+        None => panic::catch_unwind(AssertUnwindSafe(task)),
+        // The outer error can only come from `with_timeout` itself, so a
+        // task that fails with `TimedOut` is not mistaken for an overrun.
+        Some(timeout) => {
+            match with_timeout(timeout, || Ok(panic::catch_unwind(AssertUnwindSafe(task)))) {
+                Ok(caught) => caught,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return JobOutcome::TimedOut,
+                Err(e) => return JobOutcome::Failed(e),
+            }
+        }
+    };
+    match caught {
+        Ok(Ok(())) => JobOutcome::Succeeded,
+        Ok(Err(e)) => JobOutcome::Failed(e),
+        Err(_) => JobOutcome::Panicked,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_jobs_run_with_bounded_concurrency() {
+        let runner = JobRunner::builder().threads(2).start().unwrap();
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..6)
+            .map(|i| {
+                let active = Arc::clone(&active);
+                let peak = Arc::clone(&peak);
+                runner.submit(format!("job-{i}"), move || {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    Ok(())
+                })
+            })
+            .collect();
+        assert_eq!(handles[3].name(), "job-3");
+        for handle in handles {
+            assert!(handle.wait().is_success());
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(runner.stats().succeeded, 6);
+    }
+
+    #[test]
+    fn test_failures_panics_and_timeouts_are_isolated() {
+        let runner = JobRunner::builder().threads(1).start().unwrap();
+        let failed = runner.submit("fails", || Err(io::Error::other("disk full")));
+        let panicked = runner.submit("panics", || panic!("boom"));
+        let slow = runner.submit_with_timeout("slow", Duration::from_millis(20), || {
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        });
+        let own_timeout = runner.submit_with_timeout("own", Duration::from_secs(5), || {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "upstream"))
+        });
+        let after = runner.submit("after", || Ok(()));
+
+        assert!(matches!(failed.wait(), JobOutcome::Failed(e) if e.to_string() == "disk full"));
+        assert!(matches!(panicked.wait(), JobOutcome::Panicked));
+        assert!(matches!(slow.wait(), JobOutcome::TimedOut));
+        assert!(matches!(own_timeout.wait(), JobOutcome::Failed(_)));
+        assert!(after.wait().is_success());
+        let stats = runner.stats();
+        assert_eq!(
+            (
+                stats.succeeded,
+                stats.failed,
+                stats.panicked,
+                stats.timed_out
+            ),
+            (1, 2, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_shutdown_drains_queue() {
+        let runner = JobRunner::builder().threads(1).start().unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let done = Arc::clone(&done);
+                runner.submit("work", move || {
+                    thread::sleep(Duration::from_millis(10));
+                    done.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+            })
+            .collect();
+        runner.shutdown(Duration::from_secs(5)).unwrap();
+        assert_eq!(done.load(Ordering::SeqCst), 3);
+        assert!(handles.iter().all(|h| h.try_outcome().is_some()));
+
+        let runner = JobRunner::builder().start().unwrap();
+        runner.submit("stuck", || {
+            thread::sleep(Duration::from_millis(300));
+            Ok(())
+        });
+        let err = runner.shutdown(Duration::from_millis(20)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,654 @@
+//! JSON-RPC 2.0 over line-delimited JSON.
+//!
+//! Every message is one JSON value on one line, exactly like the rest of our
+//! IPC. A [`Transport`] moves those lines: [`StreamTransport`] wraps any
+//! reader/writer pair (stdio, Unix sockets, pipes) and [`FileTransport`]
+//! uses a pair of JSONL files.
+//!
+//! [`Server`] dispatches requests, notifications, and batches to typed
+//! handlers and answers with spec-compliant error objects. [`Client`] makes
+//! typed calls and waits for the matching response.
+//!
+//! ```ignore
+//! let server = Server::new().method("add", |(a, b): (i64, i64)| Ok(a + b));
+//! std::thread::spawn(move || server.serve(&mut StreamTransport::stdio()));
+//!
+//! let mut client = Client::new(StreamTransport::new(reader, writer));
+//! let sum: i64 = client.call("add", (2, 3))?;
+//! ```
+
+use crate::ipc::{JsonlReader, JsonlWriter};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Stdin, Stdout, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal JSON-RPC error.
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    /// Create an error with no `data`.
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Attach structured `data` to the error.
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rpc error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Moves JSON-RPC messages, one per line.
+pub trait Transport {
+    /// Send one message line (without the trailing newline).
+    fn send(&mut self, line: &str) -> io::Result<()>;
+
+    /// Receive the next message line, or `None` once the peer is gone.
+    fn recv(&mut self) -> io::Result<Option<String>>;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        (**self).send(line)
+    }
+
+    fn recv(&mut self) -> io::Result<Option<String>> {
+        (**self).recv()
+    }
+}
+
+/// Transport over a buffered reader and a writer.
+#[derive(Debug)]
+pub struct StreamTransport<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> StreamTransport<R, W> {
+    /// Wrap a reader/writer pair.
+    pub fn new(reader: R, writer: W) -> Self {
+        Self { reader, writer }
+    }
+
+    /// Return the reader and writer.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+}
+
+impl StreamTransport<BufReader<Stdin>, Stdout> {
+    /// Read from stdin and write to stdout.
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stdout())
+    }
+}
+
+#[cfg(unix)]
+impl StreamTransport<BufReader<std::os::unix::net::UnixStream>, std::os::unix::net::UnixStream> {
+    /// Use a connected Unix socket in both directions.
+    pub fn unix(stream: std::os::unix::net::UnixStream) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(stream.try_clone()?), stream))
+    }
+}
+
+impl<R: BufRead, W: Write> Transport for StreamTransport<R, W> {
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(line.len() + 1);
+        buf.extend_from_slice(line.as_bytes());
+        buf.push(b'\n');
+        self.writer.write_all(&buf)?;
+        self.writer.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                return Ok(Some(line.trim_end().to_string()));
+            }
+        }
+    }
+}
+
+/// Transport over two JSONL files: messages are read from `inbox` and
+/// appended to `outbox`.
+///
+/// The peer uses the same two files the other way round. Lines that are not
+/// valid JSON are skipped by the underlying [`JsonlReader`].
+#[derive(Debug)]
+pub struct FileTransport {
+    inbox: JsonlReader<Value>,
+    outbox: JsonlWriter<Value>,
+    pending: VecDeque<Value>,
+    poll_interval: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl FileTransport {
+    /// Read from `inbox` (from its start) and append to `outbox`. Polls every
+    /// 50 ms and never times out.
+    pub fn new(inbox: impl Into<PathBuf>, outbox: impl Into<PathBuf>) -> Self {
+        Self {
+            inbox: JsonlReader::new(inbox),
+            outbox: JsonlWriter::new(outbox),
+            pending: VecDeque::new(),
+            poll_interval: Duration::from_millis(50),
+            idle_timeout: None,
+        }
+    }
+
+    /// Ignore messages already in the inbox.
+    pub fn skip_existing(mut self) -> io::Result<Self> {
+        self.inbox.skip_to_end()?;
+        Ok(self)
+    }
+
+    /// Set how often the inbox is polled.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Treat the peer as gone (`recv` returns `None`) after this long
+    /// without a message.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+}
+
+impl Transport for FileTransport {
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        let value: Value = serde_json::from_str(line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.outbox.append(&value)
+    }
+
+    fn recv(&mut self) -> io::Result<Option<String>> {
+        let started = Instant::now();
+        loop {
+            if let Some(value) = self.pending.pop_front() {
+                return Ok(Some(value.to_string()));
+            }
+            self.pending.extend(self.inbox.poll()?);
+            if !self.pending.is_empty() {
+                continue;
+            }
+            if self.idle_timeout.is_some_and(|t| started.elapsed() >= t) {
+                return Ok(None);
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+type Handler = Box<dyn Fn(Value) -> Result<Value, RpcError> + Send + Sync>;
+
+/// Dispatches JSON-RPC requests to registered methods.
+#[derive(Default)]
+pub struct Server {
+    methods: HashMap<String, Handler>,
+}
+
+impl Server {
+    /// Create a server with no methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a method. `params` are deserialized into `P` (a missing
+    /// `params` member deserializes from `null`, so `()` works); failures
+    /// answer with [`INVALID_PARAMS`].
+    pub fn method<P, R, F>(mut self, name: impl Into<String>, handler: F) -> Self
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(P) -> Result<R, RpcError> + Send + Sync + 'static,
+    {
+        let handler = move |params: Value| {
+            let params = serde_json::from_value(params)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            let result = handler(params)?;
+            serde_json::to_value(result).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+        };
+        self.methods.insert(name.into(), Box::new(handler));
+        self
+    }
+
+    /// Handle one incoming line, returning the response line if one is due
+    /// (notifications and all-notification batches get none).
+    pub fn handle_str(&self, line: &str) -> Option<String> {
+        let response = match serde_json::from_str::<Value>(line) {
+            Ok(message) => self.handle(message)?,
+            Err(e) => error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())),
+        };
+        Some(response.to_string())
+    }
+
+    /// Handle a parsed message (single request or batch).
+    pub fn handle(&self, message: Value) -> Option<Value> {
+        match message {
+            Value::Array(batch) if batch.is_empty() => Some(error_response(
+                Value::Null,
+                RpcError::new(INVALID_REQUEST, "empty batch"),
+            )),
+            Value::Array(batch) => {
+                let responses: Vec<Value> = batch
+                    .into_iter()
+                    .filter_map(|m| self.handle_single(m))
+                    .collect();
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            other => self.handle_single(other),
+        }
+    }
+
+    /// Answer messages from `transport` until it reports the peer is gone.
+    pub fn serve(&self, mut transport: impl Transport) -> io::Result<()> {
+        while let Some(line) = transport.recv()? {
+            if let Some(response) = self.handle_str(&line) {
+                transport.send(&response)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_single(&self, message: Value) -> Option<Value> {
+        let Value::Object(mut obj) = message else {
+            return Some(error_response(
+                Value::Null,
+                RpcError::new(INVALID_REQUEST, "request must be an object"),
+            ));
+        };
+        let id = obj.remove("id");
+        let id_ok = id
+            .as_ref()
+            .is_none_or(|id| id.is_null() || id.is_string() || id.is_i64() || id.is_u64());
+        let method = match obj.remove("method") {
+            Some(Value::String(method)) if id_ok && obj.get("jsonrpc") == Some(&json!("2.0")) => {
+                method
+            }
+            _ => {
+                let id = id.filter(|_| id_ok).unwrap_or(Value::Null);
+                return Some(error_response(
+                    id,
+                    RpcError::new(INVALID_REQUEST, "invalid request"),
+                ));
+            }
+        };
+        let params = obj.remove("params").unwrap_or(Value::Null);
+        if !matches!(params, Value::Null | Value::Array(_) | Value::Object(_)) {
+            return id.map(|id| {
+                error_response(
+                    id,
+                    RpcError::new(INVALID_REQUEST, "params must be structured"),
+                )
+            });
+        }
+
+        let outcome = match self.methods.get(&method) {
+            Some(handler) => handler(params),
+            None => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("method not found: {method}"),
+            )),
+        };
+        // Notifications never get a response, even on error.
+        let id = id?;
+        Some(match outcome {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(error) => error_response(id, error),
+        })
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": error, "id": id })
+}
+
+/// Error from a [`Client`] call.
+#[derive(Debug)]
+pub enum CallError {
+    /// The transport failed, the peer went away, or the response could not
+    /// be decoded.
+    Io(io::Error),
+    /// The server answered with an error object.
+    Rpc(RpcError),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "rpc transport error: {e}"),
+            Self::Rpc(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+impl From<io::Error> for CallError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<CallError> for io::Error {
+    fn from(e: CallError) -> Self {
+        match e {
+            CallError::Io(e) => e,
+            CallError::Rpc(e) => io::Error::other(e),
+        }
+    }
+}
+
+/// Makes JSON-RPC calls over a [`Transport`], one at a time.
+///
+/// Responses for ids other than the one being awaited and server-sent
+/// notifications are discarded. Each client numbers its requests from a
+/// random starting id, so replies left over from an earlier session (e.g.
+/// in a [`FileTransport`] read from the start) don't match its calls.
+#[derive(Debug)]
+pub struct Client<T> {
+    transport: T,
+    next_id: u64,
+}
+
+impl<T: Transport> Client<T> {
+    /// Create a client. Request ids start at a random value below 2^52, so
+    /// they stay exact in peers that parse JSON numbers as doubles.
+    pub fn new(transport: T) -> Self {
+        let bytes = crate::id::random_bytes();
+        let seed = u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"));
+        Self {
+            transport,
+            next_id: (seed >> 12) + 1,
+        }
+    }
+
+    /// Call `method` and decode its result. `params` should serialize to an
+    /// array or object; `()` sends no params.
+    pub fn call<P: Serialize, R: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: P,
+    ) -> Result<R, CallError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = request(method, params, Some(id))?;
+        self.transport.send(&request.to_string())?;
+
+        loop {
+            let message = self.recv()?;
+            if message.get("id") == Some(&json!(id)) {
+                let result = outcome(message).map_err(CallError::Rpc)?;
+                return serde_json::from_value(result)
+                    .map_err(|e| CallError::Io(io::Error::new(io::ErrorKind::InvalidData, e)));
+            }
+        }
+    }
+
+    /// Send a notification (no response is expected).
+    pub fn notify<P: Serialize>(&mut self, method: &str, params: P) -> io::Result<()> {
+        let request = request(method, params, None)?;
+        self.transport.send(&request.to_string())
+    }
+
+    /// Send several calls as one batch. Results are returned in the order
+    /// of `calls`.
+    pub fn batch(
+        &mut self,
+        calls: &[(&str, Value)],
+    ) -> Result<Vec<Result<Value, RpcError>>, CallError> {
+        let first = self.next_id;
+        self.next_id += calls.len() as u64;
+        let requests = calls
+            .iter()
+            .zip(first..)
+            .map(|((method, params), id)| request(method, params, Some(id)))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.transport.send(&Value::Array(requests).to_string())?;
+
+        let ids = first..self.next_id;
+        loop {
+            let Value::Array(responses) = self.recv()? else {
+                continue;
+            };
+            let mut results: Vec<Option<Result<Value, RpcError>>> = vec![None; calls.len()];
+            for response in responses {
+                if let Some(id) = response["id"].as_u64().filter(|id| ids.contains(id)) {
+                    results[(id - first) as usize] = Some(outcome(response));
+                }
+            }
+            if results.iter().any(Option::is_some) {
+                return Ok(results
+                    .into_iter()
+                    .map(|r| r.unwrap_or_else(|| Err(RpcError::new(INTERNAL_ERROR, "no response"))))
+                    .collect());
+            }
+        }
+    }
+
+    /// Return the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    fn recv(&mut self) -> io::Result<Value> {
+        loop {
+            let line = self.transport.recv()?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "rpc peer disconnected")
+            })?;
+            if let Ok(message) = serde_json::from_str(&line) {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+fn request<P: Serialize>(method: &str, params: P, id: Option<u64>) -> io::Result<Value> {
+    let params = serde_json::to_value(params).map_err(io::Error::other)?;
+    let mut obj = Map::new();
+    obj.insert("jsonrpc".into(), json!("2.0"));
+    obj.insert("method".into(), json!(method));
+    if !params.is_null() {
+        obj.insert("params".into(), params);
+    }
+    if let Some(id) = id {
+        obj.insert("id".into(), json!(id));
+    }
+    Ok(Value::Object(obj))
+}
+
+fn outcome(mut response: Value) -> Result<Value, RpcError> {
+    if let Some(error) = response.get_mut("error").map(Value::take) {
+        return Err(serde_json::from_value(error)
+            .unwrap_or_else(|_| RpcError::new(INTERNAL_ERROR, "malformed error object")));
+    }
+    Ok(response
+        .get_mut("result")
+        .map(Value::take)
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn server() -> Server {
+        Server::new()
+            .method("add", |(a, b): (i64, i64)| Ok(a + b))
+            .method("ping", |()| Ok("pong"))
+            .method("fail", |()| -> Result<(), RpcError> {
+                Err(RpcError::new(-1, "nope").with_data(json!({ "why": "test" })))
+            })
+    }
+
+    fn handle(server: &Server, line: &str) -> Option<Value> {
+        server
+            .handle_str(line)
+            .map(|r| serde_json::from_str(&r).unwrap())
+    }
+
+    #[test]
+    fn test_single_requests() {
+        let server = server();
+        assert_eq!(
+            handle(
+                &server,
+                r#"{"jsonrpc":"2.0","method":"add","params":[2,3],"id":1}"#
+            ),
+            Some(json!({ "jsonrpc": "2.0", "result": 5, "id": 1 }))
+        );
+        assert_eq!(
+            handle(&server, r#"{"jsonrpc":"2.0","method":"ping","id":"a"}"#),
+            Some(json!({ "jsonrpc": "2.0", "result": "pong", "id": "a" }))
+        );
+        assert_eq!(
+            handle(&server, r#"{"jsonrpc":"2.0","method":"fail","id":2}"#).unwrap()["error"],
+            json!({ "code": -1, "message": "nope", "data": { "why": "test" } })
+        );
+        assert_eq!(
+            handle(&server, r#"{"jsonrpc":"2.0","method":"ping"}"#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let server = server();
+        let code = |line: &str| handle(&server, line).unwrap()["error"]["code"].clone();
+        assert_eq!(code("{not json"), json!(PARSE_ERROR));
+        assert_eq!(code(r#"{"method":"ping","id":1}"#), json!(INVALID_REQUEST));
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","method":1,"id":1}"#),
+            json!(INVALID_REQUEST)
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","method":"nope","id":1}"#),
+            json!(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","method":"add","params":["x"],"id":1}"#),
+            json!(INVALID_PARAMS)
+        );
+        assert_eq!(code("[]"), json!(INVALID_REQUEST));
+    }
+
+    #[test]
+    fn test_batches() {
+        let server = server();
+        let response = handle(
+            &server,
+            r#"[
+                {"jsonrpc":"2.0","method":"add","params":[1,1],"id":1},
+                {"jsonrpc":"2.0","method":"ping"},
+                {"jsonrpc":"2.0","method":"nope","id":2},
+                1
+            ]"#,
+        )
+        .unwrap();
+        let items = response.as_array().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0]["result"], json!(2));
+        assert_eq!(items[1]["error"]["code"], json!(METHOD_NOT_FOUND));
+        assert_eq!(items[2]["error"]["code"], json!(INVALID_REQUEST));
+
+        assert_eq!(
+            handle(&server, r#"[{"jsonrpc":"2.0","method":"ping"}]"#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_client_over_pipes() {
+        let (server_in, client_out) = io::pipe().unwrap();
+        let (client_in, server_out) = io::pipe().unwrap();
+        let serving = thread::spawn(move || {
+            server().serve(StreamTransport::new(BufReader::new(server_in), server_out))
+        });
+
+        let mut client = Client::new(StreamTransport::new(BufReader::new(client_in), client_out));
+        assert_eq!(client.call::<_, i64>("add", (20, 22)).unwrap(), 42);
+        assert_eq!(client.call::<_, String>("ping", ()).unwrap(), "pong");
+        match client.call::<_, ()>("fail", ()) {
+            Err(CallError::Rpc(e)) => assert_eq!(e.code, -1),
+            other => panic!("expected rpc error, got {other:?}"),
+        }
+        client.notify("ping", ()).unwrap();
+
+        let results = client
+            .batch(&[("add", json!([1, 2])), ("nope", Value::Null)])
+            .unwrap();
+        assert_eq!(results[0], Ok(json!(3)));
+        assert_eq!(results[1].as_ref().unwrap_err().code, METHOD_NOT_FOUND);
+
+        drop(client);
+        serving.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_client_over_files() {
+        let tmp = TempDir::new("apiari-jsonrpc-test-files").unwrap();
+        let (requests, responses) = (tmp.join("requests.jsonl"), tmp.join("responses.jsonl"));
+
+        // Stale traffic from an earlier session.
+        tmp.jsonl_file("responses.jsonl")
+            .raw_line(r#"{"jsonrpc":"2.0","result":0,"id":1}"#)
+            .write()
+            .unwrap();
+
+        let server_transport = FileTransport::new(&requests, &responses)
+            .poll_interval(Duration::from_millis(5))
+            .idle_timeout(Duration::from_millis(300));
+        let serving = thread::spawn(move || server().serve(server_transport));
+
+        // Read from the start: the stale reply's id can't match ours.
+        let transport =
+            FileTransport::new(&responses, &requests).poll_interval(Duration::from_millis(5));
+        let mut client = Client::new(transport);
+        assert_eq!(client.call::<_, i64>("add", (1, 2)).unwrap(), 3);
+        assert_eq!(client.call::<_, i64>("add", (3, 4)).unwrap(), 7);
+        serving.join().unwrap().unwrap();
+    }
+}
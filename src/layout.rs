@@ -0,0 +1,284 @@
+//! Versioned on-disk layout with ordered migrations.
+//!
+//! The data directory records its layout version in a [`MARKER`] file. When
+//! a newer tool opens an older directory, [`migrate`] runs each registered
+//! step from the recorded version up to [`CURRENT_VERSION`]. The marker is
+//! rewritten after every step, so an interrupted migration resumes where it
+//! stopped. Migrations hold a lock file, so tools starting at the same time
+//! don't run them twice.
+//!
+//! A directory without a marker is version 0. If it is also empty (a fresh
+//! install), it is stamped with the current version and no steps run. A
+//! directory stamped with a version newer than the tool knows about is
+//! rejected rather than guessed at.
+//!
+//! Each step moves the layout from `from` to `from + 1`, and should be safe
+//! to re-run in case it was interrupted partway. Other tools can version
+//! their own directories with a [`Migrator`].
+
+use crate::lock::LockFile;
+use crate::state::save_state;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Name of the version marker file inside the root.
+pub const MARKER: &str = ".layout-version";
+
+/// Layout version of the Apiari data directory this crate writes.
+pub const CURRENT_VERSION: u32 = 1;
+
+const LOCK: &str = ".layout.lock";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize)]
+struct Marker {
+    version: u32,
+}
+
+type Step = Box<dyn Fn(&Path) -> io::Result<()> + Send + Sync>;
+
+struct Migration {
+    from: u32,
+    name: String,
+    run: Step,
+}
+
+/// What [`Migrator::migrate`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from: u32,
+    pub to: u32,
+    /// Names of the steps that ran, in order.
+    pub applied: Vec<String>,
+}
+
+/// An ordered set of migration steps for one directory layout.
+#[derive(Default)]
+pub struct Migrator {
+    steps: Vec<Migration>,
+}
+
+impl Migrator {
+    /// Create a migrator with no steps (target version 0).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The steps for the Apiari data directory.
+    pub fn builtin() -> Self {
+        // Version 1 introduced the marker itself; older installs need no
+        // other changes.
+        Self::new().step(0, "add layout version marker", |_| Ok(()))
+    }
+
+    /// Register the step that upgrades version `from` to `from + 1`.
+    pub fn step(
+        mut self,
+        from: u32,
+        name: impl Into<String>,
+        run: impl Fn(&Path) -> io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.steps.retain(|s| s.from != from);
+        self.steps.push(Migration {
+            from,
+            name: name.into(),
+            run: Box::new(run),
+        });
+        self.steps.sort_by_key(|s| s.from);
+        self
+    }
+
+    /// The version [`migrate`](Self::migrate) brings a directory to.
+    pub fn target(&self) -> u32 {
+        self.steps.last().map_or(0, |s| s.from + 1)
+    }
+
+    /// Bring `root` up to [`target`](Self::target), creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::Unsupported` if `root` is newer than the
+    /// target, `InvalidData` if the marker is corrupt or a step is missing,
+    /// and any error from a step (the marker then names the last completed
+    /// version).
+    pub fn migrate(&self, root: &Path) -> io::Result<MigrationReport> {
+        fs::create_dir_all(root)?;
+        let _lock = LockFile::acquire(root.join(LOCK), LOCK_TIMEOUT)?;
+        let target = self.target();
+        let from = match read_marker(root)? {
+            Some(version) => version,
+            None if is_fresh(root)? => {
+                write_marker(root, target)?;
+                return Ok(MigrationReport {
+                    from: target,
+                    to: target,
+                    applied: Vec::new(),
+                });
+            }
+            None => 0,
+        };
+        if from > target {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} has layout version {from}, newer than the supported {target}",
+                    root.display()
+                ),
+            ));
+        }
+        let mut applied = Vec::new();
+        for version in from..target {
+            let step = self
+                .steps
+                .iter()
+                .find(|s| s.from == version)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("no migration from layout version {version}"),
+                    )
+                })?;
+            (step.run)(root).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("layout migration `{}` failed: {e}", step.name),
+                )
+            })?;
+            write_marker(root, version + 1)?;
+            applied.push(step.name.clone());
+        }
+        Ok(MigrationReport {
+            from,
+            to: target,
+            applied,
+        })
+    }
+}
+
+impl fmt::Debug for Migrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.steps.iter().map(|s| (s.from, &s.name)))
+            .finish()
+    }
+}
+
+/// Migrate the Apiari data directory at `root` to [`CURRENT_VERSION`].
+pub fn migrate(root: &Path) -> io::Result<MigrationReport> {
+    Migrator::builtin().migrate(root)
+}
+
+/// The layout version recorded in `root` (0 if there is no marker).
+pub fn version(root: &Path) -> io::Result<u32> {
+    Ok(read_marker(root)?.unwrap_or(0))
+}
+
+fn read_marker(root: &Path) -> io::Result<Option<u32>> {
+    match fs::read(root.join(MARKER)) {
+        Ok(data) => serde_json::from_slice::<Marker>(&data)
+            .map(|m| Some(m.version))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_marker(root: &Path, version: u32) -> io::Result<()> {
+    save_state(&root.join(MARKER), &Marker { version })
+}
+
+/// True if `root` holds nothing but the migration lock.
+fn is_fresh(root: &Path) -> io::Result<bool> {
+    for entry in fs::read_dir(root)? {
+        if entry?.file_name() != LOCK {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn migrator() -> Migrator {
+        Migrator::builtin()
+            .step(2, "split config", |root| {
+                fs::write(root.join("config.json"), "{}")?;
+                Ok(())
+            })
+            .step(1, "rename sessions", |root| {
+                let old = root.join("runs");
+                if old.exists() {
+                    fs::rename(old, root.join("sessions"))?;
+                }
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn test_fresh_directory_is_stamped() {
+        let tmp = TempDir::new("apiari-layout-test-fresh").unwrap();
+        let root = tmp.join("data");
+        let report = migrator().migrate(&root).unwrap();
+        assert_eq!((report.from, report.to), (3, 3));
+        assert!(report.applied.is_empty());
+        assert_eq!(version(&root).unwrap(), 3);
+        assert!(!root.join("config.json").exists());
+        assert!(!root.join(LOCK).exists());
+        assert_eq!(migrate(&tmp.join("other")).unwrap().to, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_old_install_runs_steps_in_order() {
+        let tmp = TempDir::new("apiari-layout-test-old").unwrap();
+        fs::create_dir_all(tmp.join("runs/abc")).unwrap();
+        let report = migrator().migrate(tmp.path()).unwrap();
+        assert_eq!(
+            report.applied,
+            [
+                "add layout version marker",
+                "rename sessions",
+                "split config"
+            ]
+        );
+        assert!(tmp.join("sessions/abc").is_dir());
+        assert!(tmp.join("config.json").exists());
+        assert_eq!(version(tmp.path()).unwrap(), 3);
+
+        let again = migrator().migrate(tmp.path()).unwrap();
+        assert_eq!((again.from, again.to), (3, 3));
+        assert!(again.applied.is_empty());
+    }
+
+    #[test]
+    fn test_failed_step_resumes_and_newer_is_rejected() {
+        let tmp = TempDir::new("apiari-layout-test-resume").unwrap();
+        write_marker(tmp.path(), 1).unwrap();
+        fs::write(tmp.join("keep"), "").unwrap();
+        let failing = Migrator::builtin()
+            .step(1, "ok", |_| Ok(()))
+            .step(2, "broken", |_| Err(io::Error::other("disk full")));
+        let err = failing.migrate(tmp.path()).unwrap_err();
+        assert!(
+            err.to_string().contains("`broken` failed: disk full"),
+            "{err}"
+        );
+        assert_eq!(version(tmp.path()).unwrap(), 2);
+
+        let report = migrator().migrate(tmp.path()).unwrap();
+        assert_eq!(report.applied, ["split config"]);
+
+        write_marker(tmp.path(), 9).unwrap();
+        let err = migrator().migrate(tmp.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        fs::write(tmp.join(MARKER), "garbage").unwrap();
+        let err = migrator().migrate(tmp.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
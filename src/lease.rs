@@ -0,0 +1,416 @@
+//! Time-limited exclusive leases with background renewal.
+//!
+//! A lease file records who holds it and until when. The holder's
+//! background thread pushes the expiry forward every `renew_interval`;
+//! other processes may take the lease over only once the expiry has passed,
+//! so a crashed or hung holder loses the lease after at most `ttl`, while a
+//! healthy one keeps it indefinitely.
+//!
+//! Reads and writes of the lease file happen under a short-lived
+//! [`LockFile`] (`<path>.lock`) and are written atomically via
+//! [`save_state`]. If renewal finds the lease was taken over (for example
+//! after the holder was suspended past its expiry), the holder is told
+//! through [`Lease::is_held`] and the optional `on_lost` callback.
+
+use crate::clock::{Clock, SystemClock, unix_ms};
+use crate::id;
+use crate::lock::LockFile;
+use crate::shutdown::ShutdownToken;
+use crate::state::save_state;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const GUARD_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_millis(25);
+
+/// The contents of a lease file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaseRecord {
+    /// Human-readable holder name.
+    pub owner: String,
+    pub pid: u32,
+    /// Unique per acquisition; distinguishes holders with the same name.
+    pub token: String,
+    pub acquired_ms: u64,
+    pub expires_ms: u64,
+}
+
+impl LeaseRecord {
+    /// Return `true` if the lease has expired at `now_ms`.
+    pub fn is_expired_at(&self, now_ms: u64) -> bool {
+        now_ms >= self.expires_ms
+    }
+}
+
+/// Read the current lease record at `path`, expired or not.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Option<LeaseRecord>> {
+    match fs::read(path.as_ref()) {
+        Ok(data) => Ok(serde_json::from_slice(&data).ok()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Configures how a lease is acquired and held.
+pub struct LeaseBuilder {
+    path: PathBuf,
+    owner: String,
+    ttl: Duration,
+    renew_interval: Option<Duration>,
+    on_lost: Option<Box<dyn FnOnce() + Send>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl LeaseBuilder {
+    /// Name the holder (shown to other processes). Defaults to `pid-<pid>`.
+    pub fn owner(mut self, owner: impl Into<String>) -> Self {
+        self.owner = owner.into();
+        self
+    }
+
+    /// How long the lease survives without renewal. Defaults to 30 seconds.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// How often to renew. Defaults to a third of the TTL.
+    pub fn renew_interval(mut self, interval: Duration) -> Self {
+        self.renew_interval = Some(interval);
+        self
+    }
+
+    /// Decide expiry with `clock` instead of the system clock. Every process
+    /// sharing the lease file should agree on the time.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Called once, from the renewal thread, if the lease is lost.
+    pub fn on_lost(mut self, callback: impl FnOnce() + Send + 'static) -> Self {
+        self.on_lost = Some(Box::new(callback));
+        self
+    }
+
+    /// Take the lease if it is free or expired. Returns `Ok(None)` if
+    /// another holder's lease is still valid.
+    pub fn try_acquire(mut self) -> io::Result<Option<Lease>> {
+        Ok(self.attempt()?.map(|record| Lease::start(self, record)))
+    }
+
+    /// Wait up to `timeout` for the lease.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::WouldBlock` if it is still held elsewhere
+    /// when the timeout expires.
+    pub fn acquire(mut self, timeout: Duration) -> io::Result<Lease> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(record) = self.attempt()? {
+                return Ok(Lease::start(self, record));
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("lease {} is held elsewhere", self.path.display()),
+                ));
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    /// Write a fresh record if the current one is missing or expired.
+    fn attempt(&mut self) -> io::Result<Option<LeaseRecord>> {
+        let _guard = guard(&self.path)?;
+        let now = unix_ms(self.clock.now());
+        if read(&self.path)?.is_some_and(|current| !current.is_expired_at(now)) {
+            return Ok(None);
+        }
+        let record = LeaseRecord {
+            owner: self.owner.clone(),
+            pid: std::process::id(),
+            token: id::new_uuid(),
+            acquired_ms: now,
+            expires_ms: now + self.ttl.as_millis() as u64,
+        };
+        save_state(&self.path, &record)?;
+        Ok(Some(record))
+    }
+}
+
+/// A held lease. Dropping it stops renewal and releases the lease.
+#[derive(Debug)]
+pub struct Lease {
+    path: PathBuf,
+    token: String,
+    held: Arc<AtomicBool>,
+    shutdown: ShutdownToken,
+    renewer: Option<JoinHandle<()>>,
+}
+
+impl Lease {
+    /// Start configuring a lease on `path`.
+    pub fn builder(path: impl Into<PathBuf>) -> LeaseBuilder {
+        LeaseBuilder {
+            path: path.into(),
+            owner: format!("pid-{}", std::process::id()),
+            ttl: Duration::from_secs(30),
+            renew_interval: None,
+            on_lost: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    fn start(builder: LeaseBuilder, record: LeaseRecord) -> Self {
+        let held = Arc::new(AtomicBool::new(true));
+        let shutdown = ShutdownToken::new();
+        let renewer = Renewer {
+            path: builder.path.clone(),
+            token: record.token.clone(),
+            ttl: builder.ttl,
+            interval: builder.renew_interval.unwrap_or(builder.ttl / 3),
+            expires_ms: record.expires_ms,
+            held: Arc::clone(&held),
+            on_lost: builder.on_lost,
+            clock: builder.clock,
+        };
+        let token = shutdown.clone();
+        let thread = thread::spawn(move || renewer.run(token));
+        Self {
+            path: builder.path,
+            token: record.token,
+            held,
+            shutdown,
+            renewer: Some(thread),
+        }
+    }
+
+    /// Return `true` while the lease is held (renewal has not found it
+    /// taken over or let it expire).
+    pub fn is_held(&self) -> bool {
+        self.held.load(Ordering::SeqCst)
+    }
+
+    /// The lease file path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop renewing and release the lease now.
+    pub fn release(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.renewer.take() {
+            let _ = thread.join();
+        }
+        if !self.held.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let _guard = guard(&self.path)?;
+        if read(&self.path)?.is_some_and(|r| r.token == self.token) {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+struct Renewer {
+    path: PathBuf,
+    token: String,
+    ttl: Duration,
+    interval: Duration,
+    expires_ms: u64,
+    held: Arc<AtomicBool>,
+    on_lost: Option<Box<dyn FnOnce() + Send>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Renewer {
+    fn run(mut self, shutdown: ShutdownToken) {
+        while !shutdown.wait_timeout(self.interval) {
+            match self.renew() {
+                Ok(true) => {}
+                Ok(false) => return self.lost(),
+                // Transient I/O trouble: keep trying until the lease lapses.
+                Err(_) if unix_ms(self.clock.now()) < self.expires_ms => {}
+                Err(_) => return self.lost(),
+            }
+        }
+    }
+
+    /// Extend the lease; `Ok(false)` if it is no longer ours.
+    fn renew(&mut self) -> io::Result<bool> {
+        let _guard = guard(&self.path)?;
+        let now = unix_ms(self.clock.now());
+        let Some(mut record) = read(&self.path)? else {
+            return Ok(false);
+        };
+        if record.token != self.token || record.is_expired_at(now) {
+            return Ok(false);
+        }
+        record.expires_ms = now + self.ttl.as_millis() as u64;
+        save_state(&self.path, &record)?;
+        self.expires_ms = record.expires_ms;
+        Ok(true)
+    }
+
+    fn lost(mut self) {
+        self.held.store(false, Ordering::SeqCst);
+        if let Some(callback) = self.on_lost.take() {
+            callback();
+        }
+    }
+}
+
+fn guard(path: &Path) -> io::Result<LockFile> {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".lock");
+    LockFile::acquire(PathBuf::from(name), GUARD_TIMEOUT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::now_ms;
+    use crate::testutil::{MockClock, TempDir};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_exclusive_until_released() {
+        let tmp = TempDir::new("apiari-lease-test-exclusive").unwrap();
+        let path = tmp.join("work.lease");
+        let lease = Lease::builder(&path)
+            .owner("a")
+            .try_acquire()
+            .unwrap()
+            .unwrap();
+        assert!(lease.is_held());
+        assert_eq!(read(&path).unwrap().unwrap().owner, "a");
+        assert!(Lease::builder(&path).try_acquire().unwrap().is_none());
+
+        lease.release().unwrap();
+        assert!(!path.exists());
+        assert!(Lease::builder(&path).try_acquire().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_expired_lease_taken_over() {
+        let tmp = TempDir::new("apiari-lease-test-expired").unwrap();
+        let path = tmp.join("work.lease");
+        let stale = LeaseRecord {
+            owner: "crashed".into(),
+            pid: 1,
+            token: id::new_uuid(),
+            acquired_ms: 0,
+            expires_ms: now_ms() - 1,
+        };
+        save_state(&path, &stale).unwrap();
+        let lease = Lease::builder(&path)
+            .owner("b")
+            .try_acquire()
+            .unwrap()
+            .unwrap();
+        assert_eq!(read(lease.path()).unwrap().unwrap().owner, "b");
+    }
+
+    #[test]
+    fn test_expiry_follows_the_clock() {
+        let tmp = TempDir::new("apiari-lease-test-clock").unwrap();
+        let path = tmp.join("work.lease");
+        let clock = MockClock::at_unix_secs(1_000_000);
+        let builder = |owner: &str| {
+            Lease::builder(&path)
+                .owner(owner)
+                .ttl(Duration::from_secs(30))
+                .renew_interval(Duration::from_secs(3600))
+                .clock(clock.clone())
+        };
+        let _held = builder("a").try_acquire().unwrap().unwrap();
+        assert_eq!(read(&path).unwrap().unwrap().expires_ms, 1_000_030_000);
+        assert!(builder("b").try_acquire().unwrap().is_none());
+
+        clock.advance(Duration::from_secs(30));
+        let lease = builder("b").try_acquire().unwrap().unwrap();
+        assert_eq!(read(lease.path()).unwrap().unwrap().owner, "b");
+    }
+
+    #[test]
+    fn test_renewal_keeps_lease_past_ttl() {
+        let tmp = TempDir::new("apiari-lease-test-renew").unwrap();
+        let path = tmp.join("work.lease");
+        let _lease = Lease::builder(&path)
+            .ttl(Duration::from_millis(150))
+            .renew_interval(Duration::from_millis(30))
+            .try_acquire()
+            .unwrap()
+            .unwrap();
+        thread::sleep(Duration::from_millis(400));
+        assert!(Lease::builder(&path).try_acquire().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_acquire_waits_for_expiry() {
+        let tmp = TempDir::new("apiari-lease-test-wait").unwrap();
+        let path = tmp.join("work.lease");
+        let now = now_ms();
+        let hung = LeaseRecord {
+            owner: "hung".into(),
+            pid: 1,
+            token: id::new_uuid(),
+            acquired_ms: now,
+            expires_ms: now + 100,
+        };
+        save_state(&path, &hung).unwrap();
+        let err = Lease::builder(&path)
+            .acquire(Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(
+            Lease::builder(&path)
+                .acquire(Duration::from_secs(5))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_takeover_reports_loss() {
+        let tmp = TempDir::new("apiari-lease-test-lost").unwrap();
+        let path = tmp.join("work.lease");
+        let (tx, rx) = mpsc::channel();
+        let lease = Lease::builder(&path)
+            .renew_interval(Duration::from_millis(20))
+            .on_lost(move || tx.send(()).unwrap())
+            .try_acquire()
+            .unwrap()
+            .unwrap();
+        // Simulate another process taking over after our lease lapsed.
+        let lock = guard(&path).unwrap();
+        let mut record = read(&path).unwrap().unwrap();
+        record.token = id::new_uuid();
+        save_state(&path, &record).unwrap();
+        drop(lock);
+
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!lease.is_held());
+        drop(lease);
+        // The new holder's file is left alone.
+        assert_eq!(read(&path).unwrap().unwrap().token, record.token);
+    }
+}
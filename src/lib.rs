@@ -1,2 +1,65 @@
+pub mod agent;
+pub mod archive;
+pub mod audit;
+#[cfg(feature = "backup")]
+pub mod backup;
+pub mod cache;
+pub mod clock;
+pub mod config;
+pub mod crash;
+pub mod debounce;
+pub mod diff;
+pub mod dirs;
+pub mod election;
+pub mod env;
+pub mod exitcode;
+pub mod flags;
+pub mod fsutil;
+pub mod gc;
+pub mod globs;
+pub mod guard;
+pub mod gzip;
+pub mod hash;
+pub mod id;
 pub mod ipc;
+pub mod jobs;
+pub mod jsonrpc;
+pub mod layout;
+pub mod lease;
+pub mod lock;
+pub mod log;
+pub mod merge;
+pub mod metrics;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod outbox;
+pub mod parse;
+pub mod patch;
+pub mod paths;
+pub mod plugins;
+pub mod poll;
+pub mod process;
+pub mod prometheus;
+pub mod redact;
+pub mod replay;
+pub mod router;
+pub mod scheduler;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod secrets;
+pub mod shutdown;
+pub mod snapshot;
 pub mod state;
+pub mod sync;
+pub mod sysinfo;
+pub mod telemetry;
+pub mod term;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+pub mod textbudget;
+pub mod timeout;
+pub mod transcript;
+pub mod version;
+pub mod vfs;
+pub mod watch;
+pub mod workspace;
@@ -0,0 +1,557 @@
+//! Cross-process lock files.
+//!
+//! A [`LockFile`] is held by creating its file exclusively and writing the
+//! owner's PID as the first line (the format [`crate::gc`] understands),
+//! followed by the owner's start-time [`process::Fingerprint`] token.
+//! Dropping it deletes the file. A lock whose owner is no longer alive, or
+//! whose PID now belongs to a different process, is stale and is taken over
+//! automatically; stale recovery is best-effort and assumes processes don't
+//! race to recover the same dead lock.
+//!
+//! [`PathLocks`] coordinates threads within one process: it hands out
+//! reader/writer guards keyed by canonicalized path, so `./a.rs` and
+//! `src/../a.rs` contend for the same lock. [`PathLocks::lock_many`] takes
+//! several paths in a fixed (sorted) order, so two tasks locking overlapping
+//! sets can't deadlock each other. Waiting writers block new readers, so a
+//! steady stream of reads can't starve a write. Guards are not reentrant.
+
+use crate::guard;
+use crate::process::{self, Fingerprint};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// An empty lock file (its owner died between create and write) is treated
+/// as stale once it is this old.
+const EMPTY_GRACE: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A held lock file, released on drop.
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    /// Take the lock if it is free (or stale). Returns `Ok(None)` if another
+    /// live process holds it.
+    pub fn try_acquire(path: impl Into<PathBuf>) -> io::Result<Option<Self>> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // Until the owner is recorded, the file is ours to clean up.
+                    let created = guard::remove_file_on_drop(path);
+                    file.write_all(owner_record().as_bytes())?;
+                    return Ok(Some(Self {
+                        path: created.disarm(),
+                    }));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if !is_stale(&path)? {
+                        return Ok(None);
+                    }
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                        _ => {}
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Wait up to `timeout` for the lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::WouldBlock` if the lock is still held when the
+    /// timeout expires.
+    pub fn acquire(path: impl Into<PathBuf>, timeout: Duration) -> io::Result<Self> {
+        let path = path.into();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(lock) = Self::try_acquire(&path)? {
+                return Ok(lock);
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("lock {} is held by another process", path.display()),
+                ));
+            }
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    /// The lock file path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Return the PID recorded in a lock file, if any.
+pub fn owner(path: impl AsRef<Path>) -> io::Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(data.lines().next().and_then(|l| l.trim().parse().ok())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// What a lock file holds: this process's PID and, where the OS reports
+/// it, its start time.
+fn owner_record() -> String {
+    static RECORD: OnceLock<String> = OnceLock::new();
+    RECORD
+        .get_or_init(|| {
+            let pid = std::process::id();
+            match process::start_time(pid) {
+                Ok(token) => format!("{pid}\n{token}\n"),
+                Err(_) => format!("{pid}\n"),
+            }
+        })
+        .clone()
+}
+
+/// Whether the lock at `path` is free to take: missing, owned by a dead or
+/// replaced process, or empty for longer than the creation grace period.
+pub(crate) fn is_stale(path: &Path) -> io::Result<bool> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    let mut lines = data.lines().map(str::trim);
+    if let Some(pid) = lines.next().and_then(|l| l.parse().ok()) {
+        // A recycled PID is alive but started at a different time.
+        return Ok(match lines.next().filter(|t| !t.is_empty()) {
+            Some(token) => !process::verify(pid, &Fingerprint::StartTime(token.to_string())),
+            None => !process::is_alive(pid),
+        });
+    }
+    let age = match fs::metadata(path) {
+        Ok(meta) => meta
+            .modified()
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok())
+            .unwrap_or_default(),
+        // Released between our create attempt and now: retry.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    Ok(age >= EMPTY_GRACE)
+}
+
+/// Shared or exclusive access to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// In-process reader/writer locks keyed by path. Cloning shares the
+/// registry.
+#[derive(Clone, Default)]
+pub struct PathLocks {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    table: Mutex<HashMap<PathBuf, PathState>>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PathState {
+    readers: usize,
+    writer: bool,
+    waiting_readers: usize,
+    waiting_writers: usize,
+}
+
+impl PathState {
+    fn available(&self, access: Access) -> bool {
+        match access {
+            Access::Read => !self.writer && self.waiting_writers == 0,
+            Access::Write => !self.writer && self.readers == 0,
+        }
+    }
+
+    /// Nobody holds or waits for the path, so its entry can go.
+    fn idle(&self) -> bool {
+        self.readers == 0 && !self.writer && self.waiting_readers == 0 && self.waiting_writers == 0
+    }
+
+    fn waiting(&mut self, access: Access) -> &mut usize {
+        match access {
+            Access::Read => &mut self.waiting_readers,
+            Access::Write => &mut self.waiting_writers,
+        }
+    }
+}
+
+impl PathLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for shared access to `path`.
+    pub fn read(&self, path: impl AsRef<Path>) -> PathGuard {
+        self.acquire(canonical(path.as_ref()), Access::Read)
+    }
+
+    /// Wait for exclusive access to `path`.
+    pub fn write(&self, path: impl AsRef<Path>) -> PathGuard {
+        self.acquire(canonical(path.as_ref()), Access::Write)
+    }
+
+    /// Shared access to `path` if no writer holds or is waiting for it.
+    pub fn try_read(&self, path: impl AsRef<Path>) -> Option<PathGuard> {
+        self.try_acquire(canonical(path.as_ref()), Access::Read)
+    }
+
+    /// Exclusive access to `path` if nobody holds it.
+    pub fn try_write(&self, path: impl AsRef<Path>) -> Option<PathGuard> {
+        self.try_acquire(canonical(path.as_ref()), Access::Write)
+    }
+
+    /// Lock several paths, waiting for each in sorted order. A path listed
+    /// more than once (under any spelling) is locked once, for writing if
+    /// any entry asks to write. Guards are returned in that sorted order.
+    pub fn lock_many<P: AsRef<Path>>(&self, paths: &[(P, Access)]) -> Vec<PathGuard> {
+        let mut wanted: Vec<(PathBuf, Access)> = paths
+            .iter()
+            .map(|(p, access)| (canonical(p.as_ref()), *access))
+            .collect();
+        // Sort by path with Write before Read, so dedup keeps the write.
+        wanted.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        wanted.dedup_by(|later, earlier| later.0 == earlier.0);
+        wanted
+            .into_iter()
+            .map(|(path, access)| self.acquire(path, access))
+            .collect()
+    }
+
+    /// Return `true` if any guard for `path` is held.
+    pub fn is_locked(&self, path: impl AsRef<Path>) -> bool {
+        let table = self.table();
+        table
+            .get(&canonical(path.as_ref()))
+            .is_some_and(|s| s.readers > 0 || s.writer)
+    }
+
+    fn acquire(&self, path: PathBuf, access: Access) -> PathGuard {
+        let mut table = self.table();
+        if !table.entry(path.clone()).or_default().available(access) {
+            // Being counted as a waiter keeps the entry alive while we sleep.
+            *table.get_mut(&path).expect("entry exists").waiting(access) += 1;
+            while !table[&path].available(access) {
+                table = self
+                    .shared
+                    .released
+                    .wait(table)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+            *table.get_mut(&path).expect("entry exists").waiting(access) -= 1;
+        }
+        self.grant(table, path, access)
+    }
+
+    fn try_acquire(&self, path: PathBuf, access: Access) -> Option<PathGuard> {
+        let table = self.table();
+        if table.get(&path).is_some_and(|s| !s.available(access)) {
+            return None;
+        }
+        Some(self.grant(table, path, access))
+    }
+
+    fn grant(
+        &self,
+        mut table: MutexGuard<'_, HashMap<PathBuf, PathState>>,
+        path: PathBuf,
+        access: Access,
+    ) -> PathGuard {
+        let state = table.entry(path.clone()).or_default();
+        match access {
+            Access::Read => state.readers += 1,
+            Access::Write => state.writer = true,
+        }
+        PathGuard {
+            shared: Arc::clone(&self.shared),
+            path,
+            access,
+        }
+    }
+
+    fn table(&self) -> MutexGuard<'_, HashMap<PathBuf, PathState>> {
+        self.shared.table.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for PathLocks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PathLocks")
+            .field("locked", &self.table().len())
+            .finish()
+    }
+}
+
+/// Access to a path from [`PathLocks`], released on drop.
+#[derive(Debug)]
+#[must_use = "the lock is released when the guard is dropped"]
+pub struct PathGuard {
+    shared: Arc<Shared>,
+    path: PathBuf,
+    access: Access,
+}
+
+impl PathGuard {
+    /// The canonicalized path this guard locks.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn access(&self) -> Access {
+        self.access
+    }
+}
+
+impl Drop for PathGuard {
+    fn drop(&mut self) {
+        let mut table = self.shared.table.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(state) = table.get_mut(&self.path) {
+            match self.access {
+                Access::Read => state.readers -= 1,
+                Access::Write => state.writer = false,
+            }
+            if state.idle() {
+                table.remove(&self.path);
+            }
+        }
+        drop(table);
+        self.shared.released.notify_all();
+    }
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Shared(..)")
+    }
+}
+
+/// Resolve symlinks and `..` so every spelling of a path gets one key. A
+/// path that doesn't exist yet is resolved through its nearest existing
+/// ancestor.
+fn canonical(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().unwrap_or_default().join(path)
+    };
+    let mut normal = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::CurDir => {}
+            other => normal.push(other),
+        }
+    }
+    let mut missing = Vec::new();
+    let mut base = normal.as_path();
+    while let Some(parent) = base.parent() {
+        missing.push(base.file_name().unwrap_or_default().to_os_string());
+        if let Ok(resolved) = fs::canonicalize(parent) {
+            return missing.iter().rev().fold(resolved, |p, name| p.join(name));
+        }
+        base = parent;
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_exclusive_until_dropped() {
+        let tmp = TempDir::new("apiari-lock-test-exclusive").unwrap();
+        let path = tmp.join("state.lock");
+        let lock = LockFile::try_acquire(&path).unwrap().unwrap();
+        assert_eq!(owner(&path).unwrap(), Some(std::process::id()));
+        assert!(LockFile::try_acquire(&path).unwrap().is_none());
+
+        drop(lock);
+        assert!(!path.exists());
+        assert!(LockFile::try_acquire(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_stale_lock_taken_over() {
+        let tmp = TempDir::new("apiari-lock-test-stale").unwrap();
+        let path = tmp.join("state.lock");
+        fs::write(&path, "999999999\n").unwrap();
+        let lock = LockFile::try_acquire(&path).unwrap().unwrap();
+        assert_eq!(owner(lock.path()).unwrap(), Some(std::process::id()));
+    }
+
+    #[test]
+    fn test_recycled_pid_is_stale() {
+        let tmp = TempDir::new("apiari-lock-test-recycled").unwrap();
+        let path = tmp.join("state.lock");
+        // Our own live PID, but a start time that isn't ours.
+        fs::write(
+            &path,
+            format!("{}\nnot-our-start-time\n", std::process::id()),
+        )
+        .unwrap();
+        let lock = LockFile::try_acquire(&path).unwrap().unwrap();
+        let record = fs::read_to_string(lock.path()).unwrap();
+        assert_eq!(record, owner_record());
+        assert!(LockFile::try_acquire(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_fresh_empty_lock_is_respected() {
+        let tmp = TempDir::new("apiari-lock-test-empty").unwrap();
+        let path = tmp.join("state.lock");
+        fs::write(&path, "").unwrap();
+        assert!(LockFile::try_acquire(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_acquire_waits_then_times_out() {
+        let tmp = TempDir::new("apiari-lock-test-wait").unwrap();
+        let path = tmp.join("state.lock");
+        let held = LockFile::try_acquire(&path).unwrap().unwrap();
+        let err = LockFile::acquire(&path, Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        let waiter = {
+            let path = path.clone();
+            thread::spawn(move || LockFile::acquire(path, Duration::from_secs(5)).is_ok())
+        };
+        thread::sleep(Duration::from_millis(30));
+        drop(held);
+        assert!(waiter.join().unwrap());
+    }
+
+    #[test]
+    fn test_path_locks_share_reads_and_exclude_writes() {
+        let dir = TempDir::new("apiari-lock-test-paths").unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("a.rs"), "").unwrap();
+        let locks = PathLocks::new();
+
+        let r1 = locks.read(dir.path().join("a.rs"));
+        let r2 = locks.try_read(dir.path().join("src/../a.rs")).unwrap();
+        assert_eq!(r1.path(), r2.path());
+        assert!(locks.try_write(dir.path().join("./a.rs")).is_none());
+        drop((r1, r2));
+
+        // A file that doesn't exist yet gets the same key under any spelling.
+        let w = locks.write(dir.path().join("src/new.rs"));
+        assert!(locks.is_locked(dir.path().join("src/../src/new.rs")));
+        assert!(locks.try_read(dir.path().join("src/new.rs")).is_none());
+        drop(w);
+        assert!(!locks.is_locked(dir.path().join("src/new.rs")));
+    }
+
+    #[test]
+    fn test_lock_many_orders_and_waits() {
+        let dir = TempDir::new("apiari-lock-test-many").unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        let locks = PathLocks::new();
+
+        let guards =
+            locks.lock_many(&[(&b, Access::Read), (&a, Access::Read), (&b, Access::Write)]);
+        assert_eq!(guards.len(), 2);
+        assert!(guards[0].path().ends_with("a") && guards[1].path().ends_with("b"));
+        assert_eq!(guards[1].access(), Access::Write);
+
+        // Two threads locking the same pair in opposite orders both finish.
+        drop(guards);
+        let handles: Vec<_> = [(a.clone(), b.clone()), (b, a)]
+            .into_iter()
+            .map(|(x, y)| {
+                let locks = locks.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        let _g = locks.lock_many(&[(&x, Access::Write), (&y, Access::Write)]);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        // A waiting writer is woken when the reader drops.
+        let r = locks.read(dir.path().join("a"));
+        let waiter = {
+            let locks = locks.clone();
+            let a = dir.path().join("a");
+            thread::spawn(move || drop(locks.write(a)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        // New readers queue behind the waiting writer.
+        assert!(locks.try_read(dir.path().join("a")).is_none());
+        drop(r);
+        waiter.join().unwrap();
+        assert!(!locks.is_locked(dir.path().join("a")));
+    }
+
+    #[test]
+    fn test_path_locks_waiters_survive_release() {
+        let dir = TempDir::new("apiari-lock-test-waiters").unwrap();
+        let path = dir.path().join("shared.json");
+        let locks = PathLocks::new();
+        let spawn = |access| {
+            let (locks, path) = (locks.clone(), path.clone());
+            thread::spawn(move || match access {
+                Access::Read => drop(locks.read(path)),
+                Access::Write => drop(locks.write(path)),
+            })
+        };
+
+        // A reader blocked on a writer.
+        let w = locks.write(&path);
+        let reader = spawn(Access::Read);
+        thread::sleep(Duration::from_millis(50));
+        drop(w);
+        reader.join().unwrap();
+
+        // A writer blocked on readers.
+        let (r1, r2) = (locks.read(&path), locks.read(&path));
+        let writer = spawn(Access::Write);
+        thread::sleep(Duration::from_millis(50));
+        drop(r1);
+        drop(r2);
+        writer.join().unwrap();
+        assert!(!locks.is_locked(&path));
+        assert_eq!(locks.table().len(), 0);
+    }
+}
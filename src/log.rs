@@ -0,0 +1,951 @@
+//! JSONL logging with per-target filtering and file rotation.
+//!
+//! A [`Logger`] appends one [`Record`] per line to a log file. Which records
+//! are written is decided by a [`Filter`] of `target=level` directives in the
+//! familiar `env_logger` style:
+//!
+//! ```text
+//! warn,apiari::ipc=debug,apiari::gc=off
+//! ```
+//!
+//! A bare level sets the default, and a target matches itself and its
+//! `::`-separated children (the most specific directive wins). The filter
+//! can be replaced while the daemon runs: from a filter file that is watched
+//! for changes ([`LoggerBuilder::filter_file`]), from `APIARI_LOG`
+//! ([`Logger::reload_from_env`]), or directly ([`Logger::set_filter`]). A
+//! filter file that fails to parse leaves the previous filter in place.
+//!
+//! The log file is rotated when it would grow past [`Rotation::max_bytes`]
+//! or has been open longer than [`Rotation::max_age`]. Rotated files are
+//! renamed to `<name>.<unix-ms>` (optionally gzipped), and the oldest ones
+//! are pruned down to [`Rotation::keep`] and [`Rotation::retention`].
+//!
+//! A [`Dedupe`] policy stops a failing loop from flooding the log. The first
+//! record with a given level, target, and message is written as usual;
+//! identical records within the target's window are suppressed, and when the
+//! window ends one more copy is written with [`Record::repeat_count`] set to
+//! the number suppressed. Pending summaries are written by the next record
+//! after the window, by [`Logger::flush_repeats`], or when the last clone of
+//! the logger is dropped.
+
+use crate::clock::now_ms;
+use crate::gzip;
+use crate::parse::ParseError;
+use crate::watch::{FileWatcher, watch};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Environment variable holding filter directives.
+pub const ENV_VAR: &str = "APIARI_LOG";
+
+/// Severity of a record, from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Trace => "trace",
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "trace" => Ok(Self::Trace),
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" | "warning" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => Err(ParseError::new(s, "unknown log level")),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Minimum level per target; `None` means off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: Option<Level>,
+    targets: Vec<(String, Option<Level>)>,
+}
+
+impl Default for Filter {
+    /// `info` for every target.
+    fn default() -> Self {
+        Self::new(Level::Info)
+    }
+}
+
+impl Filter {
+    /// A filter passing `level` and above for every target.
+    pub fn new(level: Level) -> Self {
+        Self {
+            default: Some(level),
+            targets: Vec::new(),
+        }
+    }
+
+    /// Set the minimum level for `target` and its children.
+    pub fn target(mut self, target: impl Into<String>, level: Option<Level>) -> Self {
+        let target = target.into();
+        self.targets.retain(|(t, _)| *t != target);
+        self.targets.push((target, level));
+        self
+    }
+
+    /// Parse directives from `APIARI_LOG`, if it is set and non-empty.
+    pub fn from_env() -> Result<Option<Self>, ParseError> {
+        match env::var(ENV_VAR) {
+            Ok(value) if !value.trim().is_empty() => value.parse().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Return `true` if a record at `level` from `target` passes.
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        let min = most_specific(&self.targets, target).unwrap_or(self.default);
+        min.is_some_and(|min| level >= min)
+    }
+}
+
+/// The setting of the longest directive matching `target` or one of its
+/// `::` parents.
+fn most_specific<T: Copy>(directives: &[(String, T)], target: &str) -> Option<T> {
+    directives
+        .iter()
+        .filter(|(t, _)| {
+            target == t
+                || target
+                    .strip_prefix(t.as_str())
+                    .is_some_and(|rest| rest.starts_with("::"))
+        })
+        .max_by_key(|(t, _)| t.len())
+        .map(|(_, setting)| *setting)
+}
+
+impl FromStr for Filter {
+    type Err = ParseError;
+
+    /// Parse comma- or newline-separated directives; `#` starts a comment.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let mut filter = Self::default();
+        let directives = s
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(code, _)| code))
+            .flat_map(|line| line.split(','))
+            .map(str::trim)
+            .filter(|d| !d.is_empty());
+        for directive in directives {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(ParseError::new(directive, "missing target"));
+                    }
+                    filter = filter.target(target, parse_level(level)?);
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+fn parse_level(s: &str) -> Result<Option<Level>, ParseError> {
+    if s.trim().eq_ignore_ascii_case("off") {
+        Ok(None)
+    } else {
+        s.parse().map(Some)
+    }
+}
+
+/// One log line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Milliseconds since the Unix epoch.
+    pub ts: u64,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    /// Structured context.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
+    /// On a [`Dedupe`] summary, how many identical records were suppressed
+    /// since the first was written. The fields are the last suppressed
+    /// record's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_count: Option<u64>,
+}
+
+/// Which targets have repeated records collapsed, and over what window.
+/// Records are identical if their level, target, and message match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dedupe {
+    default: Option<Duration>,
+    targets: Vec<(String, Option<Duration>)>,
+}
+
+impl Dedupe {
+    /// Collapse repeats within `window` for every target.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            default: Some(window),
+            targets: Vec::new(),
+        }
+    }
+
+    /// Set the window for `target` and its children; `None` disables
+    /// deduplication there.
+    pub fn target(mut self, target: impl Into<String>, window: Option<Duration>) -> Self {
+        let target = target.into();
+        self.targets.retain(|(t, _)| *t != target);
+        self.targets.push((target, window));
+        self
+    }
+
+    /// The window for records from `target`, if they are deduplicated.
+    pub fn window(&self, target: &str) -> Option<Duration> {
+        most_specific(&self.targets, target).unwrap_or(self.default)
+    }
+}
+
+/// When to rotate the log file and how many old files to keep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rotation {
+    /// Rotate before the file would grow past this size.
+    pub max_bytes: Option<u64>,
+    /// Rotate once the file has been written for this long.
+    pub max_age: Option<Duration>,
+    /// Number of rotated files to keep.
+    pub keep: usize,
+    /// Delete rotated files older than this, regardless of `keep`.
+    pub retention: Option<Duration>,
+    /// Gzip rotated files.
+    pub compress: bool,
+}
+
+impl Default for Rotation {
+    /// 10 MiB per file, five rotated files kept, uncompressed.
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(10 * 1024 * 1024),
+            max_age: None,
+            keep: 5,
+            retention: None,
+            compress: false,
+        }
+    }
+}
+
+/// Configures a [`Logger`].
+#[derive(Debug, Clone)]
+pub struct LoggerBuilder {
+    path: PathBuf,
+    filter: Filter,
+    use_env: bool,
+    filter_file: Option<(PathBuf, Duration)>,
+    rotation: Rotation,
+    dedupe: Dedupe,
+    forwards: Vec<Forward>,
+}
+
+/// A callback that receives every written record.
+#[derive(Clone)]
+struct Forward(Arc<dyn Fn(&Record) + Send + Sync>);
+
+impl fmt::Debug for Forward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Forward(..)")
+    }
+}
+
+impl LoggerBuilder {
+    /// Initial filter, used when neither `APIARI_LOG` nor the filter file
+    /// provides one. Defaults to `info`.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Whether `APIARI_LOG` overrides the initial filter (default `true`).
+    pub fn use_env(mut self, use_env: bool) -> Self {
+        self.use_env = use_env;
+        self
+    }
+
+    /// Read directives from `path` and reload them whenever it changes,
+    /// checking every `interval`. Takes precedence over `APIARI_LOG`.
+    pub fn filter_file(mut self, path: impl Into<PathBuf>, interval: Duration) -> Self {
+        self.filter_file = Some((path.into(), interval));
+        self
+    }
+
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Collapse repeated records (off by default).
+    pub fn dedupe(mut self, dedupe: Dedupe) -> Self {
+        self.dedupe = dedupe;
+        self
+    }
+
+    /// Also hand every record that passes the filter to `forward` (for
+    /// example an `otel::LogShipper`), after it is written
+    /// to the file. `forward` runs on the logging thread and should not
+    /// block.
+    pub fn forward(mut self, forward: impl Fn(&Record) + Send + Sync + 'static) -> Self {
+        self.forwards.push(Forward(Arc::new(forward)));
+        self
+    }
+
+    /// Open the logger. The log file is created on the first write.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if `APIARI_LOG` or the filter file holds
+    /// invalid directives.
+    pub fn build(self) -> io::Result<Logger> {
+        let mut filter = self.filter;
+        if self.use_env
+            && let Some(env_filter) = Filter::from_env().map_err(invalid_input)?
+        {
+            filter = env_filter;
+        }
+        if let Some((path, _)) = &self.filter_file
+            && let Some(file_filter) = read_filter_file(path)?
+        {
+            filter = file_filter;
+        }
+        let inner = Arc::new(Inner {
+            filter: RwLock::new(filter),
+            sink: Mutex::new(Sink {
+                path: self.path,
+                rotation: self.rotation,
+                file: None,
+                size: 0,
+                started: SystemTime::now(),
+            }),
+            filter_file: self.filter_file.as_ref().map(|(path, _)| path.clone()),
+            watcher: Mutex::new(None),
+            dedupe: self.dedupe,
+            repeats: Mutex::new(HashMap::new()),
+            forwards: self.forwards,
+        });
+        if let Some((path, interval)) = self.filter_file {
+            let weak: Weak<Inner> = Arc::downgrade(&inner);
+            // If the last `Logger` goes away while this runs, `Inner` and
+            // its watcher drop on the watcher thread; `FileWatcher` then
+            // lets its thread finish instead of joining itself.
+            let watcher = watch(path, interval, move |_| {
+                if let Some(inner) = weak.upgrade() {
+                    Logger { inner }.reload_filter_file();
+                }
+            });
+            *lock(&inner.watcher) = Some(watcher);
+        }
+        Ok(Logger { inner })
+    }
+}
+
+struct Inner {
+    filter: RwLock<Filter>,
+    sink: Mutex<Sink>,
+    filter_file: Option<PathBuf>,
+    watcher: Mutex<Option<FileWatcher>>,
+    dedupe: Dedupe,
+    repeats: Mutex<HashMap<RepeatKey, Repeats>>,
+    forwards: Vec<Forward>,
+}
+
+type RepeatKey = (Level, String, String);
+
+/// Suppressed copies of one record within its dedupe window.
+struct Repeats {
+    until: Instant,
+    suppressed: u64,
+    last: Option<Record>,
+}
+
+impl Repeats {
+    fn summary(self) -> Option<Record> {
+        let mut record = self.last?;
+        record.repeat_count = Some(self.suppressed);
+        Some(record)
+    }
+}
+
+impl Inner {
+    fn emit(&self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        lock(&self.sink).write(line.as_bytes())?;
+        for forward in &self.forwards {
+            (forward.0)(record);
+        }
+        Ok(())
+    }
+
+    /// Remove windows that ended before `now` (all of them if `None`) and
+    /// return their summaries, oldest first.
+    fn take_repeats(&self, now: Option<Instant>) -> Vec<Record> {
+        let mut summaries: Vec<Record> = lock(&self.repeats)
+            .extract_if(|_, r| now.is_none_or(|now| now >= r.until))
+            .filter_map(|(_, r)| r.summary())
+            .collect();
+        summaries.sort_by_key(|r| r.ts);
+        summaries
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        for summary in self.take_repeats(None) {
+            let _ = self.emit(&summary);
+        }
+    }
+}
+
+/// A JSONL log file with a reloadable filter. Cloning shares the file.
+#[derive(Clone)]
+pub struct Logger {
+    inner: Arc<Inner>,
+}
+
+impl Logger {
+    /// Start configuring a logger writing to `path`.
+    pub fn builder(path: impl Into<PathBuf>) -> LoggerBuilder {
+        LoggerBuilder {
+            path: path.into(),
+            filter: Filter::default(),
+            use_env: true,
+            filter_file: None,
+            rotation: Rotation::default(),
+            dedupe: Dedupe::default(),
+            forwards: Vec::new(),
+        }
+    }
+
+    /// Return `true` if a record at `level` from `target` would be written.
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        self.filter_ref().enabled(target, level)
+    }
+
+    /// Write a record if the filter allows it.
+    pub fn log(&self, level: Level, target: &str, message: impl Into<String>) -> io::Result<()> {
+        self.log_with(level, target, message, Map::new())
+    }
+
+    /// Write a record with structured fields if the filter allows it.
+    pub fn log_with(
+        &self,
+        level: Level,
+        target: &str,
+        message: impl Into<String>,
+        fields: Map<String, Value>,
+    ) -> io::Result<()> {
+        if !self.enabled(target, level) {
+            return Ok(());
+        }
+        let record = Record {
+            ts: now_ms(),
+            level,
+            target: target.to_string(),
+            message: message.into(),
+            fields,
+            repeat_count: None,
+        };
+        self.write(&record)
+    }
+
+    pub fn trace(&self, target: &str, message: impl Into<String>) -> io::Result<()> {
+        self.log(Level::Trace, target, message)
+    }
+
+    pub fn debug(&self, target: &str, message: impl Into<String>) -> io::Result<()> {
+        self.log(Level::Debug, target, message)
+    }
+
+    pub fn info(&self, target: &str, message: impl Into<String>) -> io::Result<()> {
+        self.log(Level::Info, target, message)
+    }
+
+    pub fn warn(&self, target: &str, message: impl Into<String>) -> io::Result<()> {
+        self.log(Level::Warn, target, message)
+    }
+
+    pub fn error(&self, target: &str, message: impl Into<String>) -> io::Result<()> {
+        self.log(Level::Error, target, message)
+    }
+
+    /// Write `record`, bypassing the filter but not [`Dedupe`].
+    pub fn write(&self, record: &Record) -> io::Result<()> {
+        let now = Instant::now();
+        for summary in self.inner.take_repeats(Some(now)) {
+            self.inner.emit(&summary)?;
+        }
+        if let Some(window) = self.inner.dedupe.window(&record.target)
+            && record.repeat_count.is_none()
+        {
+            let key = (record.level, record.target.clone(), record.message.clone());
+            let mut repeats = lock(&self.inner.repeats);
+            if let Some(repeats) = repeats.get_mut(&key) {
+                repeats.suppressed += 1;
+                repeats.last = Some(record.clone());
+                return Ok(());
+            }
+            repeats.insert(
+                key,
+                Repeats {
+                    until: now + window,
+                    suppressed: 0,
+                    last: None,
+                },
+            );
+        }
+        self.inner.emit(record)
+    }
+
+    /// Write the summaries of all pending dedupe windows now, without
+    /// waiting for them to end.
+    pub fn flush_repeats(&self) -> io::Result<()> {
+        for summary in self.inner.take_repeats(None) {
+            self.inner.emit(&summary)?;
+        }
+        Ok(())
+    }
+
+    /// The current filter.
+    pub fn filter(&self) -> Filter {
+        self.filter_ref().clone()
+    }
+
+    /// Replace the filter.
+    pub fn set_filter(&self, filter: Filter) {
+        *self.inner.filter.write().unwrap_or_else(|e| e.into_inner()) = filter;
+    }
+
+    /// Replace the filter from `APIARI_LOG`. Returns `false` (and keeps
+    /// the current filter) if the variable is unset.
+    pub fn reload_from_env(&self) -> Result<bool, ParseError> {
+        Ok(match Filter::from_env()? {
+            Some(filter) => {
+                self.set_filter(filter);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Rotate the log file now.
+    pub fn rotate(&self) -> io::Result<()> {
+        lock(&self.inner.sink).rotate()
+    }
+
+    /// The path of the active log file.
+    pub fn path(&self) -> PathBuf {
+        lock(&self.inner.sink).path.clone()
+    }
+
+    fn filter_ref(&self) -> std::sync::RwLockReadGuard<'_, Filter> {
+        self.inner.filter.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn reload_filter_file(&self) {
+        let Some(path) = &self.inner.filter_file else {
+            return;
+        };
+        match read_filter_file(path) {
+            Ok(Some(filter)) => self.set_filter(filter),
+            // A deleted filter file keeps the last filter.
+            Ok(None) => {}
+            Err(e) => {
+                let record = Record {
+                    ts: now_ms(),
+                    level: Level::Warn,
+                    target: "apiari::log".to_string(),
+                    message: format!("ignoring filter file {}: {e}", path.display()),
+                    fields: Map::new(),
+                    repeat_count: None,
+                };
+                let _ = self.write(&record);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logger")
+            .field("path", &self.path())
+            .field("filter", &*self.filter_ref())
+            .finish_non_exhaustive()
+    }
+}
+
+fn read_filter_file(path: &Path) -> io::Result<Option<Filter>> {
+    match fs::read_to_string(path) {
+        Ok(text) => text.parse().map(Some).map_err(invalid_input),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn invalid_input(e: ParseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+}
+
+struct Sink {
+    path: PathBuf,
+    rotation: Rotation,
+    file: Option<File>,
+    size: u64,
+    /// When the current file was started, for age-based rotation.
+    started: SystemTime,
+}
+
+impl Sink {
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.file.is_none() {
+            self.open()?;
+        }
+        let too_big = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max| self.size > 0 && self.size + line.len() as u64 > max);
+        let too_old = self.rotation.max_age.is_some_and(|max| {
+            self.size > 0
+                && SystemTime::now()
+                    .duration_since(self.started)
+                    .is_ok_and(|age| age >= max)
+        });
+        if too_big || too_old {
+            self.rotate()?;
+            self.open()?;
+        }
+        let file = self.file.as_mut().expect("log file is open");
+        file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let meta = file.metadata()?;
+        self.size = meta.len();
+        self.started = if meta.len() == 0 {
+            SystemTime::now()
+        } else {
+            meta.created()
+                .or_else(|_| meta.modified())
+                .unwrap_or_else(|_| SystemTime::now())
+        };
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Move the current file aside and prune old rotations.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        self.size = 0;
+        if fs::metadata(&self.path).is_ok_and(|m| m.len() > 0) {
+            let mut ts = now_ms();
+            let rotated = loop {
+                let candidate = self.rotated_path(ts);
+                if !candidate.exists() && !gz_path(&candidate).exists() {
+                    break candidate;
+                }
+                ts += 1;
+            };
+            fs::rename(&self.path, &rotated)?;
+            if self.rotation.compress {
+                let data = fs::read(&rotated)?;
+                fs::write(gz_path(&rotated), gzip::compress(&data))?;
+                fs::remove_file(&rotated)?;
+            }
+        }
+        self.prune()
+    }
+
+    fn rotated_path(&self, ts: u64) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{ts}"));
+        PathBuf::from(name)
+    }
+
+    /// Rotated files of this log, newest first.
+    fn rotated(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        let Some(name) = self.path.file_name().and_then(|n| n.to_str()) else {
+            return Ok(Vec::new());
+        };
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let prefix = format!("{name}.");
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let ts = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix(&prefix))
+                .map(|rest| rest.strip_suffix(".gz").unwrap_or(rest))
+                .and_then(|ts| ts.parse::<u64>().ok());
+            if let Some(ts) = ts {
+                files.push((ts, path));
+            }
+        }
+        files.sort_by(|a, b| b.cmp(a));
+        Ok(files)
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let cutoff = self
+            .rotation
+            .retention
+            .map(|r| now_ms().saturating_sub(r.as_millis() as u64));
+        for (i, (ts, path)) in self.rotated()?.into_iter().enumerate() {
+            if i >= self.rotation.keep || cutoff.is_some_and(|c| ts < c) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn lines(path: &Path) -> Vec<Record> {
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_directives() {
+        let filter: Filter = "warn, apiari::ipc=debug,apiari::ipc::noisy=off # quiet\napiari=info"
+            .parse()
+            .unwrap();
+        assert!(filter.enabled("other", Level::Warn));
+        assert!(!filter.enabled("other", Level::Info));
+        assert!(filter.enabled("apiari", Level::Info));
+        assert!(filter.enabled("apiari::gc", Level::Info));
+        assert!(filter.enabled("apiari::ipc", Level::Debug));
+        assert!(filter.enabled("apiari::ipc::reader", Level::Debug));
+        assert!(!filter.enabled("apiari::ipc::noisy", Level::Error));
+        assert!(!filter.enabled("apiarix", Level::Info));
+        assert!("verbose".parse::<Filter>().is_err());
+        assert!("=debug".parse::<Filter>().is_err());
+        assert_eq!("".parse::<Filter>().unwrap(), Filter::default());
+        assert_eq!("WARNING".parse::<Level>().unwrap(), Level::Warn);
+    }
+
+    #[test]
+    fn test_logger_writes_filtered_jsonl() {
+        let tmp = TempDir::new("apiari-log-test-write").unwrap();
+        let path = tmp.join("logs/daemon.jsonl");
+        let forwarded = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&forwarded);
+        let logger = Logger::builder(&path)
+            .use_env(false)
+            .filter("info,apiari::ipc=debug".parse().unwrap())
+            .forward(move |r| lock(&sink).push(r.message.clone()))
+            .build()
+            .unwrap();
+        logger.debug("apiari::gc", "hidden").unwrap();
+        logger.debug("apiari::ipc", "shown").unwrap();
+        let mut fields = Map::new();
+        fields.insert("session".to_string(), Value::from("abc"));
+        logger
+            .log_with(Level::Warn, "apiari::gc", "slow sweep", fields)
+            .unwrap();
+        logger.set_filter(Filter::new(Level::Error));
+        logger.warn("apiari::gc", "hidden").unwrap();
+
+        let records = lines(&path);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].message, "shown");
+        assert_eq!(records[1].level, Level::Warn);
+        assert_eq!(records[1].fields["session"], "abc");
+        assert_eq!(*lock(&forwarded), ["shown", "slow sweep"]);
+    }
+
+    #[test]
+    fn test_size_rotation_keeps_and_compresses() {
+        let tmp = TempDir::new("apiari-log-test-rotate").unwrap();
+        let path = tmp.join("daemon.jsonl");
+        let logger = Logger::builder(&path)
+            .use_env(false)
+            .rotation(Rotation {
+                max_bytes: Some(300),
+                keep: 2,
+                compress: true,
+                ..Rotation::default()
+            })
+            .build()
+            .unwrap();
+        for i in 0..20 {
+            logger.info("t", format!("message number {i:03}")).unwrap();
+        }
+        let sink = lock(&logger.inner.sink);
+        let rotated = sink.rotated().unwrap();
+        assert_eq!(rotated.len(), 2);
+        let newest = gzip::decompress(&fs::read(&rotated[0].1).unwrap()).unwrap();
+        let newest = String::from_utf8(newest).unwrap();
+        assert!(newest.lines().count() >= 2);
+        assert!(fs::metadata(&path).unwrap().len() <= 300);
+        let last = lines(&path).pop().unwrap();
+        assert_eq!(last.message, "message number 019");
+    }
+
+    #[test]
+    fn test_age_rotation_and_retention() {
+        let tmp = TempDir::new("apiari-log-test-age").unwrap();
+        let path = tmp.join("daemon.jsonl");
+        fs::write(tmp.join("daemon.jsonl.1000"), "{}\n").unwrap();
+        let logger = Logger::builder(&path)
+            .use_env(false)
+            .rotation(Rotation {
+                max_bytes: None,
+                max_age: Some(Duration::from_millis(30)),
+                retention: Some(Duration::from_secs(3600)),
+                ..Rotation::default()
+            })
+            .build()
+            .unwrap();
+        logger.info("t", "first").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        logger.info("t", "second").unwrap();
+
+        let rotated = lock(&logger.inner.sink).rotated().unwrap();
+        assert_eq!(rotated.len(), 1, "{rotated:?}");
+        assert_eq!(lines(&rotated[0].1)[0].message, "first");
+        assert_eq!(lines(&path)[0].message, "second");
+    }
+
+    #[test]
+    fn test_dedupe_collapses_repeats() {
+        let tmp = TempDir::new("apiari-log-test-dedupe").unwrap();
+        let path = tmp.join("daemon.jsonl");
+        let logger = Logger::builder(&path)
+            .use_env(false)
+            .dedupe(
+                Dedupe::new(Duration::from_millis(300))
+                    .target("apiari::audit", None)
+                    .target("apiari::poll", Some(Duration::from_secs(3600))),
+            )
+            .build()
+            .unwrap();
+        for _ in 0..100 {
+            logger.error("apiari::ipc", "connection refused").unwrap();
+            logger.error("apiari::audit", "checked").unwrap();
+        }
+        logger.warn("apiari::ipc", "connection refused").unwrap();
+        for attempt in 0..3 {
+            let mut fields = Map::new();
+            fields.insert("attempt".to_string(), Value::from(attempt));
+            logger
+                .log_with(Level::Info, "apiari::poll::x", "retrying", fields)
+                .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(350));
+        logger.info("apiari::ipc", "recovered").unwrap();
+
+        let records = lines(&path);
+        assert_eq!(records.len(), 105, "{records:?}");
+        let summary = &records[records.len() - 2];
+        assert_eq!(summary.message, "connection refused");
+        assert_eq!(summary.level, Level::Error);
+        assert_eq!(summary.repeat_count, Some(99));
+        assert_eq!(records.last().unwrap().message, "recovered");
+
+        // The hour-long window is still open until flushed.
+        logger.flush_repeats().unwrap();
+        let summary = lines(&path).pop().unwrap();
+        assert_eq!(summary.message, "retrying");
+        assert_eq!(summary.repeat_count, Some(2));
+        assert_eq!(summary.fields["attempt"], 2);
+    }
+
+    #[test]
+    fn test_filter_file_reload() {
+        let tmp = TempDir::new("apiari-log-test-reload").unwrap();
+        let path = tmp.join("daemon.jsonl");
+        let filter_path = tmp.join("log-filter");
+        fs::write(&filter_path, "error").unwrap();
+        let logger = Logger::builder(&path)
+            .use_env(false)
+            .filter_file(&filter_path, Duration::from_millis(10))
+            .build()
+            .unwrap();
+        assert!(!logger.enabled("t", Level::Warn));
+
+        fs::write(&filter_path, "debug # investigating").unwrap();
+        crate::poll::poll_until(Duration::from_millis(5), Duration::from_secs(5), || {
+            logger.enabled("t", Level::Debug).then_some(())
+        })
+        .unwrap();
+
+        fs::write(&filter_path, "nonsense").unwrap();
+        crate::poll::poll_until(Duration::from_millis(5), Duration::from_secs(5), || {
+            (!lines(&path).is_empty()).then_some(())
+        })
+        .unwrap();
+        assert!(logger.enabled("t", Level::Debug));
+        assert!(lines(&path)[0].message.starts_with("ignoring filter file"));
+    }
+}
@@ -0,0 +1,183 @@
+//! Deterministic deep merging of JSON values.
+//!
+//! Used for layered configuration (defaults, then file, then overrides) and
+//! for patching state. The rules, applied recursively:
+//!
+//! - Objects merge key by key.
+//! - A `null` in the overlay **deletes** the key from the result.
+//! - Arrays follow the chosen [`ArrayStrategy`].
+//! - Anything else in the overlay replaces the base value.
+
+use serde_json::{Map, Value};
+
+/// How arrays in the overlay combine with arrays in the base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArrayStrategy {
+    /// The overlay array replaces the base array.
+    Replace,
+    /// Overlay elements are appended to the base elements.
+    Concat,
+    /// Object elements with the same value under this key are merged
+    /// (recursively, with the same strategy); other overlay elements are
+    /// appended. Base order is preserved.
+    MergeByKey(String),
+}
+
+/// Merge `overlay` onto `base` and return the result.
+pub fn deep(base: Value, overlay: Value, strategy: &ArrayStrategy) -> Value {
+    let mut base = base;
+    deep_in_place(&mut base, overlay, strategy);
+    base
+}
+
+/// Merge `overlay` onto `base` in place.
+pub fn deep_in_place(base: &mut Value, overlay: Value, strategy: &ArrayStrategy) {
+    match overlay {
+        Value::Object(overlay) => {
+            if !base.is_object() {
+                *base = Value::Object(Map::new());
+            }
+            let map = base.as_object_mut().expect("just ensured object");
+            merge_objects(map, overlay, strategy);
+        }
+        Value::Array(overlay) => match base {
+            Value::Array(items) => merge_arrays(items, overlay, strategy),
+            _ => *base = Value::Array(overlay.into_iter().map(strip_nulls).collect()),
+        },
+        other => *base = other,
+    }
+}
+
+fn merge_objects(
+    base: &mut Map<String, Value>,
+    overlay: Map<String, Value>,
+    strategy: &ArrayStrategy,
+) {
+    for (key, value) in overlay {
+        if value.is_null() {
+            base.remove(&key);
+            continue;
+        }
+        match base.get_mut(&key) {
+            Some(existing) => deep_in_place(existing, value, strategy),
+            None => {
+                base.insert(key, strip_nulls(value));
+            }
+        }
+    }
+}
+
+fn merge_arrays(base: &mut Vec<Value>, overlay: Vec<Value>, strategy: &ArrayStrategy) {
+    match strategy {
+        ArrayStrategy::Replace => *base = overlay.into_iter().map(strip_nulls).collect(),
+        ArrayStrategy::Concat => base.extend(overlay.into_iter().map(strip_nulls)),
+        ArrayStrategy::MergeByKey(key) => {
+            for item in overlay {
+                let id = item.get(key).filter(|id| !id.is_null()).cloned();
+                let target = id.and_then(|id| base.iter_mut().find(|b| b.get(key) == Some(&id)));
+                match target {
+                    Some(existing) => deep_in_place(existing, item, strategy),
+                    None => base.push(strip_nulls(item)),
+                }
+            }
+        }
+    }
+}
+
+/// Remove `null` object members recursively, so that "delete" markers in
+/// newly inserted subtrees don't leak into the result.
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, strip_nulls(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(strip_nulls).collect()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_nested_objects_merge() {
+        let base = json!({ "server": { "host": "localhost", "port": 80 }, "debug": false });
+        let overlay = json!({ "server": { "port": 8080 }, "debug": true });
+        assert_eq!(
+            deep(base, overlay, &ArrayStrategy::Replace),
+            json!({ "server": { "host": "localhost", "port": 8080 }, "debug": true })
+        );
+    }
+
+    #[test]
+    fn test_null_deletes_keys() {
+        let base = json!({ "a": 1, "nested": { "b": 2, "c": 3 } });
+        let overlay = json!({ "a": null, "nested": { "c": null }, "missing": null });
+        assert_eq!(
+            deep(base, overlay, &ArrayStrategy::Replace),
+            json!({ "nested": { "b": 2 } })
+        );
+    }
+
+    #[test]
+    fn test_new_subtrees_have_nulls_stripped() {
+        let base = json!({});
+        let overlay = json!({ "new": { "keep": 1, "drop": null } });
+        assert_eq!(
+            deep(base, overlay, &ArrayStrategy::Replace),
+            json!({ "new": { "keep": 1 } })
+        );
+    }
+
+    #[test]
+    fn test_array_replace_and_concat() {
+        let base = json!({ "tags": ["a", "b"] });
+        let overlay = json!({ "tags": ["c"] });
+        assert_eq!(
+            deep(base.clone(), overlay.clone(), &ArrayStrategy::Replace),
+            json!({ "tags": ["c"] })
+        );
+        assert_eq!(
+            deep(base, overlay, &ArrayStrategy::Concat),
+            json!({ "tags": ["a", "b", "c"] })
+        );
+    }
+
+    #[test]
+    fn test_array_merge_by_key() {
+        let base = json!({ "workers": [
+            { "name": "build", "cpus": 2, "env": { "A": "1" } },
+            { "name": "test", "cpus": 1 }
+        ]});
+        let overlay = json!({ "workers": [
+            { "name": "test", "cpus": 4 },
+            { "name": "build", "env": { "B": "2", "A": null } },
+            { "name": "lint", "cpus": 1 },
+            "not-an-object"
+        ]});
+        assert_eq!(
+            deep(base, overlay, &ArrayStrategy::MergeByKey("name".into())),
+            json!({ "workers": [
+                { "name": "build", "cpus": 2, "env": { "B": "2" } },
+                { "name": "test", "cpus": 4 },
+                { "name": "lint", "cpus": 1 },
+                "not-an-object"
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_type_changes_replace() {
+        let base = json!({ "value": { "nested": true }, "list": [1] });
+        let overlay = json!({ "value": 5, "list": { "x": 1 } });
+        assert_eq!(
+            deep(base, overlay, &ArrayStrategy::Concat),
+            json!({ "value": 5, "list": { "x": 1 } })
+        );
+    }
+}
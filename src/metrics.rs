@@ -0,0 +1,353 @@
+//! In-process metrics: counters, gauges, and histograms.
+//!
+//! A [`Registry`] hands out cheap, clonable metric handles keyed by name and
+//! label set; asking for the same name and labels twice returns the same
+//! series. Updating a handle is a lock-free atomic (histograms take a short
+//! lock). Exporters read the registry through [`Registry::snapshot`], so
+//! instrumented code never depends on how metrics leave the process (see
+//! [`crate::prometheus`]).
+//!
+//! Most tools use the process-wide [`global`] registry; tests and embedded
+//! components can keep their own.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+/// Default histogram buckets, in seconds (suited to request latencies).
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The process-wide registry.
+pub fn global() -> &'static Registry {
+    static GLOBAL: OnceLock<Registry> = OnceLock::new();
+    GLOBAL.get_or_init(Registry::new)
+}
+
+/// What a metric measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// A monotonically increasing count.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, delta: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + delta).to_bits())
+            });
+    }
+
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1.0);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Observations counted into buckets by upper bound. Counts are stored per
+/// bucket; exporters accumulate them (see [`HistogramData::counts`]).
+#[derive(Debug, Clone)]
+pub struct Histogram(Arc<Mutex<HistogramData>>);
+
+/// A histogram's state at one point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramData {
+    /// Upper bounds, ascending; an implicit `+Inf` bucket follows.
+    pub bounds: Vec<f64>,
+    /// Observations in each bucket: `<=` its bound and `>` the previous
+    /// one (not cumulative).
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        let mut bounds: Vec<f64> = bounds.iter().copied().filter(|b| b.is_finite()).collect();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        Self(Arc::new(Mutex::new(HistogramData {
+            counts: vec![0; bounds.len()],
+            bounds,
+            sum: 0.0,
+            count: 0,
+        })))
+    }
+
+    /// Record `value`. NaN and infinities are ignored, since one would stick
+    /// in `sum` for good.
+    pub fn observe(&self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+        let mut data = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(i) = data.bounds.iter().position(|b| value <= *b) {
+            data.counts[i] += 1;
+        }
+        data.sum += value;
+        data.count += 1;
+    }
+
+    /// Observe a duration in seconds.
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    pub fn get(&self) -> HistogramData {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Sorted `(name, value)` label pairs.
+pub type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone)]
+enum Series {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+struct Family {
+    help: String,
+    kind: Kind,
+    series: BTreeMap<Labels, Series>,
+}
+
+/// A read-only copy of one metric's series, for exporters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+    pub name: String,
+    pub help: String,
+    pub kind: Kind,
+    pub samples: Vec<(Labels, Value)>,
+}
+
+/// A series' value in a [`MetricFamily`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Counter(u64),
+    Gauge(f64),
+    Histogram(HistogramData),
+}
+
+/// A set of named metrics. Cloning shares the registry.
+#[derive(Clone, Default)]
+pub struct Registry {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(&self, name: &str, help: &str) -> Counter {
+        self.counter_with(name, help, &[])
+    }
+
+    pub fn counter_with(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Counter {
+        match self.series(name, help, Kind::Counter, labels, || {
+            Series::Counter(Counter::default())
+        }) {
+            Series::Counter(c) => c,
+            _ => unreachable!("kind checked by series()"),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str) -> Gauge {
+        self.gauge_with(name, help, &[])
+    }
+
+    pub fn gauge_with(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Gauge {
+        match self.series(name, help, Kind::Gauge, labels, || {
+            Series::Gauge(Gauge::default())
+        }) {
+            Series::Gauge(g) => g,
+            _ => unreachable!("kind checked by series()"),
+        }
+    }
+
+    /// A histogram with [`DEFAULT_BUCKETS`].
+    pub fn histogram(&self, name: &str, help: &str) -> Histogram {
+        self.histogram_with(name, help, DEFAULT_BUCKETS, &[])
+    }
+
+    /// A histogram with the given bucket bounds. Bounds only apply when the
+    /// series is first created.
+    pub fn histogram_with(
+        &self,
+        name: &str,
+        help: &str,
+        buckets: &[f64],
+        labels: &[(&str, &str)],
+    ) -> Histogram {
+        match self.series(name, help, Kind::Histogram, labels, || {
+            Series::Histogram(Histogram::new(buckets))
+        }) {
+            Series::Histogram(h) => h,
+            _ => unreachable!("kind checked by series()"),
+        }
+    }
+
+    /// Copy every metric's current values, sorted by name.
+    pub fn snapshot(&self) -> Vec<MetricFamily> {
+        let families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        families
+            .iter()
+            .map(|(name, family)| MetricFamily {
+                name: name.clone(),
+                help: family.help.clone(),
+                kind: family.kind,
+                samples: family
+                    .series
+                    .iter()
+                    .map(|(labels, series)| {
+                        let value = match series {
+                            Series::Counter(c) => Value::Counter(c.get()),
+                            Series::Gauge(g) => Value::Gauge(g.get()),
+                            Series::Histogram(h) => Value::Histogram(h.get()),
+                        };
+                        (labels.clone(), value)
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Find or create a series.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is already registered as a different kind; that is a
+    /// programming error, like registering two metrics with one name.
+    fn series(
+        &self,
+        name: &str,
+        help: &str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        create: impl FnOnce() -> Series,
+    ) -> Series {
+        let mut labels: Labels = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        labels.sort();
+        let mut families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            kind,
+            series: BTreeMap::new(),
+        });
+        assert_eq!(
+            family.kind, kind,
+            "metric `{name}` is already registered as a {:?}",
+            family.kind
+        );
+        family.series.entry(labels).or_insert_with(create).clone()
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("Registry")
+            .field("metrics", &families.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handles_share_series() {
+        let registry = Registry::new();
+        let ok = registry.counter_with("jobs", "Jobs run", &[("outcome", "ok"), ("pool", "a")]);
+        ok.inc();
+        registry
+            .counter_with("jobs", "Jobs run", &[("pool", "a"), ("outcome", "ok")])
+            .add(2);
+        registry
+            .counter_with("jobs", "Jobs run", &[("outcome", "failed")])
+            .inc();
+        assert_eq!(ok.get(), 3);
+
+        let queued = registry.gauge("queued", "Queued jobs");
+        queued.set(4.0);
+        queued.dec();
+        queued.add(0.5);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].name, "jobs");
+        assert_eq!(snapshot[0].samples.len(), 2);
+        assert_eq!(snapshot[1].samples[0].1, Value::Gauge(3.5));
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let registry = Registry::new();
+        let latency = registry.histogram_with("latency", "", &[1.0, 0.1, f64::INFINITY], &[]);
+        for v in [0.05, 0.1, 0.5, 7.0] {
+            latency.observe(v);
+        }
+        latency.observe_duration(Duration::from_millis(20));
+        latency.observe(f64::NAN);
+        latency.observe(f64::INFINITY);
+        let data = latency.get();
+        assert_eq!(data.bounds, [0.1, 1.0]);
+        assert_eq!(data.counts, [3, 1]);
+        assert_eq!(data.count, 5);
+        assert!((data.sum - 7.67).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered")]
+    fn test_kind_mismatch_panics() {
+        let registry = Registry::new();
+        registry.counter("x", "");
+        registry.gauge("x", "");
+    }
+}
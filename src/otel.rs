@@ -0,0 +1,818 @@
+//! OpenTelemetry export (feature `otel`).
+//!
+//! Bridges the [metrics registry](crate::metrics) and [log](crate::log)
+//! records to an OpenTelemetry collector over OTLP/HTTP with JSON encoding.
+//! The JSONL sinks stay the default; this is for users who already run an
+//! observability stack.
+//!
+//! Configuration comes from the standard OpenTelemetry environment
+//! variables ([`OtlpConfig::from_env`]):
+//!
+//! | Variable | Default |
+//! |---|---|
+//! | `OTEL_EXPORTER_OTLP_ENDPOINT` | `http://localhost:4318` |
+//! | `OTEL_EXPORTER_OTLP_METRICS_ENDPOINT` | `<endpoint>/v1/metrics` |
+//! | `OTEL_EXPORTER_OTLP_LOGS_ENDPOINT` | `<endpoint>/v1/logs` |
+//! | `OTEL_EXPORTER_OTLP_HEADERS` | none (`key=value,...`) |
+//! | `OTEL_EXPORTER_OTLP_TIMEOUT` | `10000` (ms) |
+//! | `OTEL_SERVICE_NAME` | `apiari` |
+//! | `OTEL_RESOURCE_ATTRIBUTES` | none (`key=value,...`) |
+//! | `OTEL_METRIC_EXPORT_INTERVAL` | `60000` (ms) |
+//!
+//! Like the rest of the crate this adds no dependencies: `http://`
+//! endpoints are posted to over a plain TCP connection, and `https://`
+//! endpoints go through the `curl` command.
+
+use crate::clock::now_ns;
+use crate::guard;
+use crate::log::{Level, Record};
+use crate::metrics::{Kind, Labels, Registry, Value as MetricValue};
+use crate::shutdown::ShutdownToken;
+use serde_json::{Map, Value, json};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const SCOPE: &str = "apiari-common";
+/// Logs are sent when this many are queued, or every [`LOG_FLUSH`].
+const LOG_BATCH: usize = 512;
+const LOG_FLUSH: Duration = Duration::from_secs(1);
+
+/// Where and how to send OTLP data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtlpConfig {
+    pub metrics_endpoint: String,
+    pub logs_endpoint: String,
+    /// Extra HTTP headers (for example authentication).
+    pub headers: Vec<(String, String)>,
+    pub timeout: Duration,
+    pub service_name: String,
+    /// Resource attributes besides `service.name`.
+    pub resource: Vec<(String, String)>,
+    /// How often [`OtlpExporter::push_metrics`] sends.
+    pub interval: Duration,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self::from_lookup(|_| None).expect("defaults are valid")
+    }
+}
+
+impl OtlpConfig {
+    /// Read the `OTEL_*` environment variables.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` for a malformed header, attribute, or
+    /// millisecond value.
+    pub fn from_env() -> io::Result<Self> {
+        Self::from_lookup(|name| env::var(name).ok().filter(|v| !v.trim().is_empty()))
+    }
+
+    fn from_lookup(var: impl Fn(&str) -> Option<String>) -> io::Result<Self> {
+        let base = var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .unwrap_or_else(|| "http://localhost:4318".to_string());
+        let base = base.trim_end_matches('/');
+        let millis = |name: &str, default: u64| -> io::Result<Duration> {
+            match var(name) {
+                Some(v) => v
+                    .trim()
+                    .parse()
+                    .map(Duration::from_millis)
+                    .map_err(|_| invalid(format!("{name}: expected milliseconds, got `{v}`"))),
+                None => Ok(Duration::from_millis(default)),
+            }
+        };
+        Ok(Self {
+            metrics_endpoint: var("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT")
+                .unwrap_or_else(|| format!("{base}/v1/metrics")),
+            logs_endpoint: var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT")
+                .unwrap_or_else(|| format!("{base}/v1/logs")),
+            headers: pairs(
+                "OTEL_EXPORTER_OTLP_HEADERS",
+                var("OTEL_EXPORTER_OTLP_HEADERS"),
+            )?,
+            timeout: millis("OTEL_EXPORTER_OTLP_TIMEOUT", 10_000)?,
+            service_name: var("OTEL_SERVICE_NAME").unwrap_or_else(|| "apiari".to_string()),
+            resource: pairs("OTEL_RESOURCE_ATTRIBUTES", var("OTEL_RESOURCE_ATTRIBUTES"))?,
+            interval: millis("OTEL_METRIC_EXPORT_INTERVAL", 60_000)?,
+        })
+    }
+
+    fn resource_json(&self) -> Value {
+        let mut attributes = vec![attribute("service.name", &json!(self.service_name))];
+        attributes.extend(
+            self.resource
+                .iter()
+                .filter(|(k, _)| k != "service.name")
+                .map(|(k, v)| attribute(k, &json!(v))),
+        );
+        json!({ "attributes": attributes })
+    }
+}
+
+/// Parse `key=value,...` (values may be percent-encoded). Line breaks and
+/// NULs are rejected after decoding, since headers are written out verbatim.
+fn pairs(name: &str, value: Option<String>) -> io::Result<Vec<(String, String)>> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    value
+        .split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() => {
+                let (k, v) = (k.trim().to_string(), percent_decode(v.trim()));
+                if [&k, &v].iter().any(|s| s.contains(['\r', '\n', '\0'])) {
+                    return Err(invalid(format!(
+                        "{name}: `{k}` contains a line break or NUL"
+                    )));
+                }
+                Ok((k, v))
+            }
+            _ => Err(invalid(format!("{name}: expected key=value, got `{p}`"))),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Sends metrics and logs to an OTLP endpoint.
+#[derive(Debug, Clone)]
+pub struct OtlpExporter {
+    config: OtlpConfig,
+    /// Start of the cumulative metric window.
+    started_ns: u64,
+}
+
+impl OtlpExporter {
+    pub fn new(config: OtlpConfig) -> Self {
+        Self {
+            config,
+            started_ns: now_ns(),
+        }
+    }
+
+    /// An exporter configured from the environment.
+    pub fn from_env() -> io::Result<Self> {
+        OtlpConfig::from_env().map(Self::new)
+    }
+
+    pub fn config(&self) -> &OtlpConfig {
+        &self.config
+    }
+
+    /// Send the registry's current values once.
+    pub fn export_metrics(&self, registry: &Registry) -> io::Result<()> {
+        let body = self.metrics_json(registry);
+        post(&self.config, &self.config.metrics_endpoint, &body)
+    }
+
+    /// Send a batch of log records once.
+    pub fn export_logs(&self, records: &[Record]) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let body = self.logs_json(records);
+        post(&self.config, &self.config.logs_endpoint, &body)
+    }
+
+    /// Send `registry` every [`OtlpConfig::interval`] on a background
+    /// thread until the handle is dropped (which sends once more). Send
+    /// errors are ignored; the next interval tries again.
+    pub fn push_metrics(&self, registry: &Registry) -> io::Result<PushHandle> {
+        let exporter = self.clone();
+        let registry = registry.clone();
+        let shutdown = ShutdownToken::new();
+        let token = shutdown.clone();
+        let thread = thread::Builder::new()
+            .name("apiari-otel-metrics".to_string())
+            .spawn(move || {
+                while !token.wait_timeout(exporter.config.interval) {
+                    let _ = exporter.export_metrics(&registry);
+                }
+                let _ = exporter.export_metrics(&registry);
+            })?;
+        Ok(PushHandle {
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    fn metrics_json(&self, registry: &Registry) -> Value {
+        let now = now_ns().to_string();
+        let start = self.started_ns.to_string();
+        let metrics: Vec<Value> = registry
+            .snapshot()
+            .into_iter()
+            .map(|family| {
+                let points = |f: &dyn Fn(&MetricValue) -> Map<String, Value>| -> Vec<Value> {
+                    family
+                        .samples
+                        .iter()
+                        .map(|(labels, value)| {
+                            let mut point = f(value);
+                            point.insert("attributes".into(), labels_json(labels));
+                            point.insert("startTimeUnixNano".into(), json!(start));
+                            point.insert("timeUnixNano".into(), json!(now));
+                            Value::Object(point)
+                        })
+                        .collect()
+                };
+                let data = match family.kind {
+                    Kind::Counter => json!({ "sum": {
+                        "dataPoints": points(&|v| match v {
+                            MetricValue::Counter(n) => object(json!({ "asInt": n.to_string() })),
+                            _ => Map::new(),
+                        }),
+                        "aggregationTemporality": 2,
+                        "isMonotonic": true,
+                    }}),
+                    Kind::Gauge => json!({ "gauge": {
+                        "dataPoints": points(&|v| match v {
+                            MetricValue::Gauge(g) => object(json!({ "asDouble": finite(*g) })),
+                            _ => Map::new(),
+                        }),
+                    }}),
+                    Kind::Histogram => json!({ "histogram": {
+                        "dataPoints": points(&|v| match v {
+                            MetricValue::Histogram(h) => {
+                                let mut counts: Vec<String> =
+                                    h.counts.iter().map(u64::to_string).collect();
+                                let overflow = h.count - h.counts.iter().sum::<u64>();
+                                counts.push(overflow.to_string());
+                                object(json!({
+                                    "count": h.count.to_string(),
+                                    "sum": finite(h.sum),
+                                    "bucketCounts": counts,
+                                    "explicitBounds": h.bounds,
+                                }))
+                            }
+                            _ => Map::new(),
+                        }),
+                        "aggregationTemporality": 2,
+                    }}),
+                };
+                let mut metric = object(json!({
+                    "name": family.name,
+                    "description": family.help,
+                }));
+                metric.extend(object(data));
+                Value::Object(metric)
+            })
+            .collect();
+        json!({ "resourceMetrics": [{
+            "resource": self.config.resource_json(),
+            "scopeMetrics": [{ "scope": { "name": SCOPE }, "metrics": metrics }],
+        }]})
+    }
+
+    fn logs_json(&self, records: &[Record]) -> Value {
+        // One scope per target, in first-seen order.
+        let mut scopes: Vec<(&str, Vec<Value>)> = Vec::new();
+        for record in records {
+            let mut attributes: Vec<Value> =
+                record.fields.iter().map(|(k, v)| attribute(k, v)).collect();
+            if let Some(count) = record.repeat_count {
+                attributes.push(attribute("repeat_count", &json!(count)));
+            }
+            let entry = json!({
+                "timeUnixNano": (record.ts * 1_000_000).to_string(),
+                "severityNumber": severity(record.level),
+                "severityText": record.level.as_str().to_ascii_uppercase(),
+                "body": { "stringValue": record.message },
+                "attributes": attributes,
+            });
+            match scopes.iter_mut().find(|(t, _)| *t == record.target) {
+                Some((_, logs)) => logs.push(entry),
+                None => scopes.push((&record.target, vec![entry])),
+            }
+        }
+        let scopes: Vec<Value> = scopes
+            .into_iter()
+            .map(|(target, logs)| json!({ "scope": { "name": target }, "logRecords": logs }))
+            .collect();
+        json!({ "resourceLogs": [{
+            "resource": self.config.resource_json(),
+            "scopeLogs": scopes,
+        }]})
+    }
+}
+
+/// Stops [`OtlpExporter::push_metrics`] when dropped.
+#[derive(Debug)]
+pub struct PushHandle {
+    shutdown: ShutdownToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for PushHandle {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Batches log records and sends them from a background thread.
+///
+/// Connect it to a [`Logger`](crate::log::Logger) with
+/// [`LoggerBuilder::forward`](crate::log::LoggerBuilder::forward):
+///
+/// ```ignore
+/// let shipper = LogShipper::start(OtlpExporter::from_env()?)?;
+/// let sink = shipper.sink();
+/// let logger = Logger::builder(path).forward(move |r| sink.send(r)).build()?;
+/// ```
+///
+/// Dropping the shipper sends what is queued and stops the thread.
+#[derive(Debug)]
+pub struct LogShipper {
+    sink: LogSink,
+    shutdown: ShutdownToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LogShipper {
+    pub fn start(exporter: OtlpExporter) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let shutdown = ShutdownToken::new();
+        let token = shutdown.clone();
+        let thread = thread::Builder::new()
+            .name("apiari-otel-logs".to_string())
+            .spawn(move || ship(&exporter, &rx, &token))?;
+        Ok(Self {
+            sink: LogSink(tx),
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// A clonable handle that queues records for this shipper.
+    pub fn sink(&self) -> LogSink {
+        self.sink.clone()
+    }
+}
+
+impl Drop for LogShipper {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Queues records for a [`LogShipper`]; never blocks.
+#[derive(Debug, Clone)]
+pub struct LogSink(Sender<Record>);
+
+impl LogSink {
+    /// Queue `record`. Records sent after the shipper stopped are dropped.
+    pub fn send(&self, record: &Record) {
+        let _ = self.0.send(record.clone());
+    }
+}
+
+fn ship(exporter: &OtlpExporter, rx: &Receiver<Record>, token: &ShutdownToken) {
+    let mut batch = Vec::new();
+    let mut deadline = Instant::now() + LOG_FLUSH;
+    loop {
+        let stopping = token.is_shutdown();
+        let wait = if stopping {
+            Duration::ZERO
+        } else {
+            deadline.saturating_duration_since(Instant::now())
+        };
+        match rx.recv_timeout(wait) {
+            Ok(record) => {
+                batch.push(record);
+                if batch.len() < LOG_BATCH {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if !stopping && Instant::now() < deadline => continue,
+            Err(_) => {}
+        }
+        let _ = exporter.export_logs(&batch);
+        batch.clear();
+        deadline = Instant::now() + LOG_FLUSH;
+        if stopping {
+            // Keep draining until the queue is empty.
+            match rx.try_recv() {
+                Ok(record) => batch.push(record),
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Trace => 1,
+        Level::Debug => 5,
+        Level::Info => 9,
+        Level::Warn => 13,
+        Level::Error => 17,
+    }
+}
+
+fn labels_json(labels: &Labels) -> Value {
+    Value::Array(
+        labels
+            .iter()
+            .map(|(k, v)| attribute(k, &json!(v)))
+            .collect(),
+    )
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    json!({ "key": key, "value": any_value(value) })
+}
+
+/// Convert JSON to an OTLP `AnyValue`.
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({}),
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => json!({ "intValue": i.to_string() }),
+            None => json!({ "doubleValue": n.as_f64() }),
+        },
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Array(items) => {
+            json!({ "arrayValue": { "values": items.iter().map(any_value).collect::<Vec<_>>() } })
+        }
+        Value::Object(map) => json!({ "kvlistValue": {
+            "values": map.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>(),
+        }}),
+    }
+}
+
+/// JSON has no infinities; OTLP/JSON spells them as strings.
+fn finite(v: f64) -> Value {
+    if v.is_finite() {
+        json!(v)
+    } else if v.is_nan() {
+        json!("NaN")
+    } else if v > 0.0 {
+        json!("Infinity")
+    } else {
+        json!("-Infinity")
+    }
+}
+
+fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+fn post(config: &OtlpConfig, url: &str, body: &Value) -> io::Result<()> {
+    let body = serde_json::to_vec(body)?;
+    if let Some(rest) = url.strip_prefix("http://") {
+        post_http(config, rest, &body)
+    } else if url.starts_with("https://") {
+        post_curl(config, url, &body)
+    } else {
+        Err(invalid(format!("unsupported OTLP endpoint `{url}`")))
+    }
+}
+
+/// POST over plain HTTP/1.1. `rest` is the URL without `http://`.
+fn post_http(config: &OtlpConfig, rest: &str, body: &[u8]) -> io::Result<()> {
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let addr_str = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+    let addr = addr_str
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid(format!("cannot resolve `{authority}`")))?;
+    let mut stream = TcpStream::connect_timeout(&addr, config.timeout)?;
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+    let mut request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {authority}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    );
+    for (k, v) in &config.headers {
+        request.push_str(&format!("{k}: {v}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    let code = status.split_whitespace().nth(1).unwrap_or_default();
+    if code.starts_with('2') {
+        return Ok(());
+    }
+    let mut response = String::new();
+    let _ = reader.take(4096).read_to_string(&mut response);
+    let detail = response.split("\r\n\r\n").nth(1).unwrap_or_default().trim();
+    Err(io::Error::other(format!(
+        "OTLP endpoint returned {}: {detail}",
+        status.trim()
+    )))
+}
+
+/// POST through `curl`. Header values are often credentials, so they go to
+/// curl on stdin (`-H @-`) rather than in its argv, where any local user
+/// could read them; the body is staged in a temp file instead.
+fn post_curl(config: &OtlpConfig, url: &str, body: &[u8]) -> io::Result<()> {
+    let tmp = guard::remove_file_on_drop(body_tmp());
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&*tmp)?
+        .write_all(body)?;
+    let (mut cmd, headers) = curl_command(config, url, &tmp);
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                io::Error::new(e.kind(), "curl was not found on PATH".to_string())
+            }
+            _ => e,
+        })?;
+    let write = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(&headers);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "curl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    write
+}
+
+/// The curl invocation for posting `body_file` to `url`, and the header
+/// lines to feed it on stdin.
+fn curl_command(config: &OtlpConfig, url: &str, body_file: &Path) -> (Command, Vec<u8>) {
+    let mut cmd = Command::new("curl");
+    cmd.args(["--silent", "--show-error", "--fail", "-X", "POST"])
+        .arg("--max-time")
+        .arg(format!("{:.3}", config.timeout.as_secs_f64()))
+        .args(["-H", "Content-Type: application/json"])
+        .args(["-H", "@-"])
+        .arg("--data-binary")
+        .arg(format!("@{}", body_file.display()))
+        .arg(url);
+    let mut headers = Vec::new();
+    for (k, v) in &config.headers {
+        headers.extend_from_slice(format!("{k}: {v}\n").as_bytes());
+    }
+    (cmd, headers)
+}
+
+/// A fresh path in the system temp directory for a request body.
+fn body_tmp() -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    env::temp_dir().join(format!("apiari-otel-{}-{n}.json", std::process::id()))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+
+    type Request = (String, HashMap<String, String>, Value);
+
+    /// Accept `n` requests (or up to one for `/done`), answering each with
+    /// `status`, and return `(path, headers, body)` for each.
+    fn collector(n: usize, status: &'static str) -> (String, JoinHandle<Vec<Request>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let thread = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(n) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap().to_string();
+                if path == "/done" {
+                    break;
+                }
+                let mut headers = HashMap::new();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let Some((k, v)) = header.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    headers.insert(k.to_ascii_lowercase(), v.to_string());
+                }
+                let len: usize = headers["content-length"].parse().unwrap();
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 4\r\n\r\nnope").unwrap();
+                requests.push((path, headers, serde_json::from_slice(&body).unwrap()));
+            }
+            requests
+        });
+        (base, thread)
+    }
+
+    fn config(base: &str) -> OtlpConfig {
+        let vars: HashMap<&str, String> = [
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", format!("{base}/")),
+            (
+                "OTEL_EXPORTER_OTLP_HEADERS",
+                "x-api-key=s%3Dcret".to_string(),
+            ),
+            ("OTEL_SERVICE_NAME", "swarm".to_string()),
+            ("OTEL_RESOURCE_ATTRIBUTES", "host.name=box".to_string()),
+            ("OTEL_METRIC_EXPORT_INTERVAL", "20".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        OtlpConfig::from_lookup(|name| vars.get(name).cloned()).unwrap()
+    }
+
+    #[test]
+    fn test_config_from_env_vars() {
+        let config = config("http://collector:4318");
+        assert_eq!(config.metrics_endpoint, "http://collector:4318/v1/metrics");
+        assert_eq!(config.logs_endpoint, "http://collector:4318/v1/logs");
+        assert_eq!(config.headers, [("x-api-key".into(), "s=cret".into())]);
+        assert_eq!(config.interval, Duration::from_millis(20));
+        assert_eq!(config.timeout, Duration::from_secs(10));
+        assert_eq!(OtlpConfig::default().service_name, "apiari");
+
+        let bad = OtlpConfig::from_lookup(|name| {
+            (name == "OTEL_EXPORTER_OTLP_TIMEOUT").then(|| "soon".to_string())
+        });
+        assert_eq!(bad.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let injected = OtlpConfig::from_lookup(|name| {
+            (name == "OTEL_EXPORTER_OTLP_HEADERS").then(|| "a=b%0D%0AX-Evil: 1".to_string())
+        });
+        assert_eq!(injected.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_curl_headers_stay_out_of_argv() {
+        let config = config("https://collector");
+        let (cmd, stdin) = curl_command(&config, "https://collector/v1/logs", Path::new("/b"));
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert!(args.iter().all(|a| !a.contains("s=cret")), "{args:?}");
+        assert!(args.iter().any(|a| a == "@/b"));
+        assert_eq!(stdin, b"x-api-key: s=cret\n");
+    }
+
+    #[test]
+    fn test_metrics_export() {
+        let (base, server) = collector(2, "200 OK");
+        let registry = Registry::new();
+        registry
+            .counter_with("jobs_run", "Jobs run", &[("outcome", "ok")])
+            .add(3);
+        registry
+            .histogram_with("latency", "", &[0.1, 1.0], &[])
+            .observe(5.0);
+        let exporter = OtlpExporter::new(config(&base));
+        exporter.export_metrics(&registry).unwrap();
+        drop(exporter.push_metrics(&registry).unwrap());
+
+        let requests = server.join().unwrap();
+        let (path, headers, body) = &requests[0];
+        assert_eq!(path, "/v1/metrics");
+        assert_eq!(headers["x-api-key"], "s=cret");
+        let resource = &body["resourceMetrics"][0]["resource"]["attributes"];
+        assert_eq!(resource[0]["value"]["stringValue"], "swarm");
+        assert_eq!(resource[1]["key"], "host.name");
+        let metrics = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        let counter = &metrics[0];
+        assert_eq!(counter["name"], "jobs_run");
+        assert_eq!(counter["sum"]["isMonotonic"], true);
+        let point = &counter["sum"]["dataPoints"][0];
+        assert_eq!(point["asInt"], "3");
+        assert_eq!(point["attributes"][0]["value"]["stringValue"], "ok");
+        let histogram = &metrics[1]["histogram"]["dataPoints"][0];
+        assert_eq!(histogram["bucketCounts"], json!(["0", "0", "1"]));
+        assert_eq!(histogram["explicitBounds"], json!([0.1, 1.0]));
+    }
+
+    #[test]
+    fn test_log_shipper_batches_and_reports_errors() {
+        let (base, server) = collector(1, "200 OK");
+        let shipper = LogShipper::start(OtlpExporter::new(config(&base))).unwrap();
+        let sink = shipper.sink();
+        for (target, level) in [("apiari::ipc", Level::Info), ("apiari::gc", Level::Error)] {
+            let mut fields = Map::new();
+            fields.insert("attempt".into(), json!(2));
+            sink.send(&Record {
+                ts: 1_700_000_000_000,
+                level,
+                target: target.into(),
+                message: "hello".into(),
+                fields,
+                repeat_count: None,
+            });
+        }
+        drop(shipper);
+        let requests = server.join().unwrap();
+        let (path, _, body) = &requests[0];
+        assert_eq!(path, "/v1/logs");
+        let scopes = &body["resourceLogs"][0]["scopeLogs"];
+        assert_eq!(scopes[0]["scope"]["name"], "apiari::ipc");
+        let log = &scopes[1]["logRecords"][0];
+        assert_eq!(log["severityNumber"], 17);
+        assert_eq!(log["timeUnixNano"], "1700000000000000000");
+        assert_eq!(log["attributes"][0]["value"]["intValue"], "2");
+
+        let (base, server) = collector(1, "400 Bad Request");
+        let exporter = OtlpExporter::new(config(&base));
+        let err = exporter.export_metrics(&Registry::new()).unwrap_err();
+        assert!(err.to_string().contains("400 Bad Request: nope"), "{err}");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_log_shipper_drains_everything_at_shutdown() {
+        let (base, server) = collector(usize::MAX, "200 OK");
+        let exporter = OtlpExporter::new(config(&base));
+        let (tx, rx) = mpsc::channel();
+        for i in 0..2 * LOG_BATCH + 1 {
+            tx.send(Record {
+                ts: i as u64,
+                level: Level::Info,
+                target: "apiari::test".into(),
+                message: "queued".into(),
+                fields: Map::new(),
+                repeat_count: None,
+            })
+            .unwrap();
+        }
+        let token = ShutdownToken::new();
+        token.shutdown();
+        ship(&exporter, &rx, &token);
+        let _ = post(&exporter.config, &format!("{base}/done"), &json!({}));
+
+        let requests = server.join().unwrap();
+        let shipped: usize = requests
+            .iter()
+            .map(|(_, _, body)| {
+                body["resourceLogs"][0]["scopeLogs"][0]["logRecords"]
+                    .as_array()
+                    .unwrap()
+                    .len()
+            })
+            .sum();
+        assert_eq!(shipped, 2 * LOG_BATCH + 1);
+    }
+}
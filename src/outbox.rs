@@ -0,0 +1,245 @@
+//! Atomic state update plus event emission (the outbox pattern).
+//!
+//! Saving a state file and appending the matching events to a JSONL channel
+//! are two writes; a crash between them leaves the state saying one thing
+//! and the channel another. [`commit`] first writes both to a journal next
+//! to the state file (`<state>.outbox`), then saves the state, appends the
+//! events, and removes the journal. If the process dies part way, the next
+//! [`commit`] or an explicit [`recover`] finishes the job from the journal.
+//!
+//! Recovery publishes each event exactly once. The journal records the
+//! channel's length before publishing; on recovery, the events already
+//! found after that offset (in order) are skipped and only the rest are
+//! appended.
+//!
+//! The channel is never truncated, since other processes may be appending
+//! to it. If it ends in an incomplete line (torn by a crash), a batch starts
+//! with a newline so its first event isn't glued to the fragment; JSONL
+//! readers skip the fragment as a malformed line.
+//!
+//! Like [`save_state`], this assumes one writer per state file.
+
+use crate::state::save_state;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    channel: PathBuf,
+    /// Channel length before any of `events` were appended.
+    offset: u64,
+    state: Value,
+    events: Vec<Value>,
+}
+
+/// The journal file for `state_path`.
+pub fn journal_path(state_path: &Path) -> PathBuf {
+    let mut name = state_path.as_os_str().to_owned();
+    name.push(".outbox");
+    PathBuf::from(name)
+}
+
+/// Save `new_state` to `state_path` and append `events` to the JSONL file
+/// `channel`, such that after a crash [`recover`] completes both.
+///
+/// An unfinished commit from an earlier crash is recovered first, so
+/// events reach the channel in commit order.
+///
+/// # Errors
+///
+/// Returns `io::Error` if serialization or any write fails. The journal is
+/// kept, so a later call finishes the commit.
+pub fn commit<S: Serialize, E: Serialize>(
+    state_path: &Path,
+    new_state: &S,
+    events: &[E],
+    channel: &Path,
+) -> io::Result<()> {
+    recover(state_path)?;
+    let journal = Journal {
+        channel: channel.to_path_buf(),
+        offset: match fs::metadata(channel) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        },
+        state: to_value(new_state)?,
+        events: events.iter().map(to_value).collect::<io::Result<_>>()?,
+    };
+    save_state(&journal_path(state_path), &journal)?;
+    finish(state_path, &journal, false)?;
+    Ok(())
+}
+
+/// Finish a commit interrupted by a crash, if there is one. Returns the
+/// number of events appended to the channel (0 if there was nothing to
+/// recover or every event had already been published).
+///
+/// # Errors
+///
+/// Returns `io::Error` if the journal can't be read or a write fails.
+pub fn recover(state_path: &Path) -> io::Result<usize> {
+    let journal: Journal = match fs::read(journal_path(state_path)) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    finish(state_path, &journal, true)
+}
+
+fn finish(state_path: &Path, journal: &Journal, recovering: bool) -> io::Result<usize> {
+    save_state(state_path, &journal.state)?;
+    let published = publish(journal, recovering)?;
+    fs::remove_file(journal_path(state_path))?;
+    Ok(published)
+}
+
+/// Append the journal's events. When `recovering`, events already in the
+/// channel after the journaled offset are skipped.
+fn publish(journal: &Journal, recovering: bool) -> io::Result<usize> {
+    if let Some(parent) = journal.channel.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(&journal.channel)?;
+    let len = file.metadata()?.len();
+
+    let mut done = 0;
+    if recovering {
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(journal.offset.min(len)))?;
+        file.read_to_end(&mut tail)?;
+        // Only complete lines count; a torn one is republished.
+        let complete = tail.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        for line in tail[..complete].split(|&b| b == b'\n') {
+            if done < journal.events.len()
+                && serde_json::from_slice::<Value>(line).is_ok_and(|v| v == journal.events[done])
+            {
+                done += 1;
+            }
+        }
+    }
+
+    let pending = &journal.events[done..];
+    if pending.is_empty() {
+        return Ok(0);
+    }
+    let mut batch = String::new();
+    if len > 0 {
+        let mut last = [0u8];
+        file.seek(SeekFrom::Start(len - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            batch.push('\n');
+        }
+    }
+    for event in pending {
+        batch.push_str(&event.to_string());
+        batch.push('\n');
+    }
+    file.write_all(batch.as_bytes())?;
+    file.sync_data()?;
+    Ok(pending.len())
+}
+
+fn to_value<T: Serialize>(value: &T) -> io::Result<Value> {
+    serde_json::to_value(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::load_state;
+    use crate::testutil::TempDir;
+    use serde_json::json;
+
+    /// The channel's records, skipping malformed lines as readers do.
+    fn lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect()
+    }
+
+    #[test]
+    fn test_commit_saves_state_and_appends_events() {
+        let tmp = TempDir::new("apiari-outbox-test-commit").unwrap();
+        let state = tmp.path().join("state.json");
+        let channel = tmp.path().join("events/channel.jsonl");
+
+        commit(&state, &json!({"n": 1}), &[json!({"ev": "a"})], &channel).unwrap();
+        commit(
+            &state,
+            &json!({"n": 2}),
+            &[json!({"ev": "b"}), json!({"ev": "c"})],
+            &channel,
+        )
+        .unwrap();
+        assert_eq!(load_state::<Value>(&state).unwrap(), json!({"n": 2}));
+        assert_eq!(
+            lines(&channel),
+            [json!({"ev": "a"}), json!({"ev": "b"}), json!({"ev": "c"})]
+        );
+        assert!(!journal_path(&state).exists());
+        assert_eq!(recover(&state).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_recover_publishes_each_event_once() {
+        let tmp = TempDir::new("apiari-outbox-test-recover").unwrap();
+        let state = tmp.path().join("state.json");
+        let channel = tmp.path().join("channel.jsonl");
+        fs::write(&channel, "{\"ev\":\"old\"}\n").unwrap();
+
+        // Crash after the state was journaled and one and a half events
+        // were appended.
+        let journal = Journal {
+            channel: channel.clone(),
+            offset: fs::metadata(&channel).unwrap().len(),
+            state: json!({"n": 5}),
+            events: vec![json!({"ev": "x"}), json!({"ev": "y"}), json!({"ev": "z"})],
+        };
+        save_state(&journal_path(&state), &journal).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&channel).unwrap();
+        file.write_all(b"{\"ev\":\"x\"}\n{\"ev\":").unwrap();
+
+        // The next commit finishes the old one before its own.
+        commit(&state, &json!({"n": 6}), &[json!({"ev": "w"})], &channel).unwrap();
+        assert_eq!(load_state::<Value>(&state).unwrap(), json!({"n": 6}));
+        let evs: Vec<Value> = lines(&channel)
+            .into_iter()
+            .map(|v| v["ev"].clone())
+            .collect();
+        assert_eq!(evs, ["old", "x", "y", "z", "w"]);
+        // The torn fragment is left in place on its own line.
+        assert!(
+            fs::read_to_string(&channel)
+                .unwrap()
+                .contains("\n{\"ev\":\n")
+        );
+        assert!(!journal_path(&state).exists());
+    }
+
+    #[test]
+    fn test_commit_never_truncates_the_channel() {
+        let tmp = TempDir::new("apiari-outbox-test-shared").unwrap();
+        let state = tmp.path().join("state.json");
+        let channel = tmp.path().join("channel.jsonl");
+        // Another writer's append, still in progress.
+        fs::write(&channel, "{\"ev\":\"other\"}\n{\"ev\":\"oth").unwrap();
+
+        commit(&state, &json!({"n": 1}), &[json!({"ev": "mine"})], &channel).unwrap();
+        assert_eq!(
+            fs::read_to_string(&channel).unwrap(),
+            "{\"ev\":\"other\"}\n{\"ev\":\"oth\n{\"ev\":\"mine\"}\n"
+        );
+    }
+}
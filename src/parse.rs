@@ -0,0 +1,275 @@
+//! Human-friendly duration and size parsing.
+//!
+//! [`duration`] accepts values like `30s`, `5m`, `1h30m`, or `250ms`;
+//! [`bytes`] accepts values like `512MiB`, `10MB`, or `1.5G`. The matching
+//! [`format_duration`] and [`format_bytes`] produce strings that parse back to
+//! exactly the same value, so configs can be rewritten without drift.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Error returned when a duration or size string cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    input: String,
+    reason: &'static str,
+}
+
+impl ParseError {
+    pub(crate) fn new(input: &str, reason: &'static str) -> Self {
+        Self {
+            input: input.to_string(),
+            reason,
+        }
+    }
+
+    /// The string that failed to parse.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Why the string was rejected.
+    pub fn reason(&self) -> &str {
+        self.reason
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid value {:?}: {}", self.input, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for io::Error {
+    fn from(e: ParseError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+const DURATION_UNITS: &[(&str, u128)] = &[
+    ("d", 86_400_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+/// Parse a duration such as `30s`, `1h30m`, or `250ms`.
+///
+/// The input is one or more `<integer><unit>` components, optionally
+/// separated by whitespace. Units are `d`, `h`, `m`, `s`, `ms`, `us`, and
+/// `ns`. A bare integer is interpreted as seconds.
+///
+/// # Errors
+///
+/// Returns [`ParseError`] for empty input, unknown units, or overflow.
+pub fn duration(input: &str) -> Result<Duration, ParseError> {
+    let s = input.trim();
+    if s.is_empty() {
+        return Err(ParseError::new(input, "empty duration"));
+    }
+    if s.bytes().all(|b| b.is_ascii_digit()) {
+        let secs = s
+            .parse()
+            .map_err(|_| ParseError::new(input, "number too large"))?;
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total: u128 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(ParseError::new(input, "expected a number"));
+        }
+        let n: u128 = rest[..digits]
+            .parse()
+            .map_err(|_| ParseError::new(input, "number too large"))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        let nanos = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, nanos)| *nanos)
+            .ok_or_else(|| ParseError::new(input, "unknown unit (use d, h, m, s, ms, us, ns)"))?;
+        total = n
+            .checked_mul(nanos)
+            .and_then(|v| total.checked_add(v))
+            .ok_or_else(|| ParseError::new(input, "duration too large"))?;
+        rest = rest[unit_len..].trim_start();
+    }
+
+    let secs = u64::try_from(total / 1_000_000_000)
+        .map_err(|_| ParseError::new(input, "duration too large"))?;
+    Ok(Duration::new(secs, (total % 1_000_000_000) as u32))
+}
+
+/// Format a duration so that [`duration`] parses it back exactly.
+///
+/// Uses the largest units first: `5400s` becomes `1h30m`, `1.25s` becomes
+/// `1s250ms`. A zero duration is rendered as `0s`.
+pub fn format_duration(d: Duration) -> String {
+    let mut nanos = d.as_nanos();
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+    let mut out = String::new();
+    for (unit, size) in DURATION_UNITS {
+        let n = nanos / size;
+        if n > 0 {
+            out.push_str(&format!("{n}{unit}"));
+            nanos %= size;
+        }
+    }
+    out
+}
+
+const BYTE_UNITS: &[(&str, u64)] = &[
+    ("b", 1),
+    ("kb", 1_000),
+    ("mb", 1_000_000),
+    ("gb", 1_000_000_000),
+    ("tb", 1_000_000_000_000),
+    ("k", 1 << 10),
+    ("kib", 1 << 10),
+    ("m", 1 << 20),
+    ("mib", 1 << 20),
+    ("g", 1 << 30),
+    ("gib", 1 << 30),
+    ("t", 1 << 40),
+    ("tib", 1 << 40),
+];
+
+/// Parse a byte size such as `512MiB`, `10MB`, or `1.5G`.
+///
+/// Units are case-insensitive. `KB`/`MB`/`GB`/`TB` are decimal (powers of
+/// 1000); `KiB`/`MiB`/`GiB`/`TiB` and the single-letter forms `K`/`M`/`G`/`T`
+/// are binary (powers of 1024). A bare number is bytes. Fractional values are
+/// allowed and rounded down to a whole byte.
+///
+/// # Errors
+///
+/// Returns [`ParseError`] for empty input, unknown units, or overflow.
+pub fn bytes(input: &str) -> Result<u64, ParseError> {
+    let s = input.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    if num.is_empty() {
+        return Err(ParseError::new(input, "expected a number"));
+    }
+    let unit = unit.trim().to_ascii_lowercase();
+    let multiplier = BYTE_UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .map(|(_, m)| *m)
+        .or(unit.is_empty().then_some(1))
+        .ok_or_else(|| ParseError::new(input, "unknown unit (use B, KB, KiB, MB, MiB, ...)"))?;
+
+    if let Ok(n) = num.parse::<u64>() {
+        return n
+            .checked_mul(multiplier)
+            .ok_or_else(|| ParseError::new(input, "size too large"));
+    }
+    let n: f64 = num
+        .parse()
+        .map_err(|_| ParseError::new(input, "expected a number"))?;
+    let value = n * multiplier as f64;
+    if value >= u64::MAX as f64 {
+        return Err(ParseError::new(input, "size too large"));
+    }
+    Ok(value as u64)
+}
+
+/// Format a byte count so that [`bytes`] parses it back exactly.
+///
+/// Picks the largest binary unit that divides the value evenly: `536870912`
+/// becomes `512MiB`, while `1500` stays `1500B`.
+pub fn format_bytes(n: u64) -> String {
+    for (unit, size) in [
+        ("TiB", 1u64 << 40),
+        ("GiB", 1 << 30),
+        ("MiB", 1 << 20),
+        ("KiB", 1 << 10),
+    ] {
+        if n != 0 && n.is_multiple_of(size) {
+            return format!("{}{unit}", n / size);
+        }
+    }
+    format!("{n}B")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration_simple_and_compound() {
+        assert_eq!(duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(duration("1h 30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(duration("2d").unwrap(), Duration::from_secs(172_800));
+        assert_eq!(duration("45").unwrap(), Duration::from_secs(45));
+    }
+
+    #[test]
+    fn test_duration_errors() {
+        assert!(duration("").is_err());
+        assert!(duration("soon").is_err());
+        assert!(duration("5x").is_err());
+        assert!(duration("m5").is_err());
+        let err = duration("10y").unwrap_err();
+        assert_eq!(err.input(), "10y");
+        assert!(err.to_string().contains("unknown unit"));
+    }
+
+    #[test]
+    fn test_duration_round_trip() {
+        for d in [
+            Duration::ZERO,
+            Duration::from_secs(5400),
+            Duration::from_millis(1250),
+            Duration::new(90_061, 7),
+        ] {
+            assert_eq!(duration(&format_duration(d)).unwrap(), d);
+        }
+        assert_eq!(format_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(format_duration(Duration::from_millis(1250)), "1s250ms");
+    }
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(bytes("512MiB").unwrap(), 512 << 20);
+        assert_eq!(bytes("512mib").unwrap(), 512 << 20);
+        assert_eq!(bytes("10MB").unwrap(), 10_000_000);
+        assert_eq!(bytes("1.5G").unwrap(), 3 << 29);
+        assert_eq!(bytes("100").unwrap(), 100);
+        assert_eq!(bytes("4 KiB").unwrap(), 4096);
+        assert!(bytes("MiB").is_err());
+        assert!(bytes("12 parsecs").is_err());
+        assert!(bytes("99999999999TiB").is_err());
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        for n in [0, 1, 1500, 4096, 512 << 20, 3 << 40] {
+            assert_eq!(bytes(&format_bytes(n)).unwrap(), n);
+        }
+        assert_eq!(format_bytes(512 << 20), "512MiB");
+        assert_eq!(format_bytes(1500), "1500B");
+    }
+}
@@ -0,0 +1,417 @@
+//! Applying unified diffs to text in memory.
+//!
+//! [`apply`] takes the original text and a single-file unified diff (as
+//! produced by `diff -u`, `git diff`, or an agent) and returns the patched
+//! text, without shelling out to `patch` or `git apply`.
+//!
+//! Like `patch`, each hunk is first looked for at its stated line, then at
+//! increasing distances in both directions (an *offset*, e.g. when earlier
+//! edits shifted the file). If it still doesn't match, up to
+//! [`ApplyOptions::fuzz`] leading and trailing context lines are ignored.
+//! Hunks that cannot be placed are reported as [`HunkConflict`]s, together
+//! with the text produced by the hunks that did apply.
+//!
+//! Lines are compared with their line endings, so CRLF files round-trip;
+//! `\ No newline at end of file` markers are honored.
+
+use std::error::Error;
+use std::fmt;
+
+/// How strictly hunks must match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplyOptions {
+    /// Context lines that may be ignored at each end of a hunk (as
+    /// `patch -F`). Default 2.
+    pub fuzz: usize,
+    /// Farthest a hunk may move from its stated line; `None` for anywhere.
+    pub max_offset: Option<usize>,
+    /// Compare lines ignoring leading/trailing whitespace.
+    pub ignore_whitespace: bool,
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        Self {
+            fuzz: 2,
+            max_offset: None,
+            ignore_whitespace: false,
+        }
+    }
+}
+
+/// Where and how a hunk was applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HunkPlacement {
+    /// Lines between the stated and actual position (negative = earlier).
+    pub offset: isize,
+    /// Context lines ignored at each end to make it match.
+    pub fuzz: usize,
+}
+
+/// The result of a successful [`apply_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Applied {
+    pub text: String,
+    /// One entry per hunk, in diff order.
+    pub hunks: Vec<HunkPlacement>,
+}
+
+/// A hunk that could not be placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HunkConflict {
+    /// Zero-based hunk index within the diff.
+    pub index: usize,
+    /// The hunk's stated starting line in the original (1-based).
+    pub old_start: usize,
+    /// The lines the hunk expected to find (context and removals).
+    pub expected: Vec<String>,
+}
+
+/// Why a diff could not be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    /// The diff itself is malformed. `line` is 1-based within the diff.
+    Malformed { line: usize, message: String },
+    /// Some hunks did not match. `partial` has every other hunk applied.
+    Conflicts {
+        conflicts: Vec<HunkConflict>,
+        partial: String,
+    },
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed { line, message } => {
+                write!(f, "malformed diff at line {line}: {message}")
+            }
+            Self::Conflicts { conflicts, .. } => {
+                let hunks: Vec<String> = conflicts
+                    .iter()
+                    .map(|c| format!("#{} (line {})", c.index + 1, c.old_start))
+                    .collect();
+                write!(f, "hunks failed to apply: {}", hunks.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for ApplyError {}
+
+/// Apply `unified_diff` to `original` with default options.
+pub fn apply(original: &str, unified_diff: &str) -> Result<String, ApplyError> {
+    apply_with(original, unified_diff, &ApplyOptions::default()).map(|a| a.text)
+}
+
+/// Apply `unified_diff` to `original`, reporting where each hunk landed.
+pub fn apply_with(
+    original: &str,
+    unified_diff: &str,
+    options: &ApplyOptions,
+) -> Result<Applied, ApplyError> {
+    let hunks = parse(unified_diff)?;
+    let lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let eq = |a: &str, b: &str| {
+        if options.ignore_whitespace {
+            a.trim() == b.trim()
+        } else {
+            a == b
+        }
+    };
+
+    let mut out = String::with_capacity(original.len());
+    let mut placements = Vec::new();
+    let mut conflicts = Vec::new();
+    // Next unconsumed original line, and the offset of the last placed hunk.
+    let mut cursor = 0;
+    let mut drift: isize = 0;
+    for (index, hunk) in hunks.iter().enumerate() {
+        let stated = hunk.old_start.saturating_sub(1);
+        let expected = (stated as isize + drift).max(cursor as isize) as usize;
+        match place(hunk, &lines, cursor, expected, options, &eq) {
+            Some((pos, fuzz)) => {
+                let (lead, trail) = hunk.trim(fuzz);
+                let old_len = hunk.old.len() - lead - trail;
+                lines[cursor..pos].iter().for_each(|l| out.push_str(l));
+                hunk.new[lead..hunk.new.len() - trail]
+                    .iter()
+                    .for_each(|l| out.push_str(l));
+                cursor = pos + old_len;
+                let offset = pos as isize - (stated + lead) as isize;
+                drift = offset;
+                placements.push(HunkPlacement { offset, fuzz });
+            }
+            None => conflicts.push(HunkConflict {
+                index,
+                old_start: hunk.old_start,
+                expected: hunk.old.iter().map(|l| l.to_string()).collect(),
+            }),
+        }
+    }
+    lines[cursor..].iter().for_each(|l| out.push_str(l));
+
+    if conflicts.is_empty() {
+        Ok(Applied {
+            text: out,
+            hunks: placements,
+        })
+    } else {
+        Err(ApplyError::Conflicts {
+            conflicts,
+            partial: out,
+        })
+    }
+}
+
+struct Hunk<'a> {
+    old_start: usize,
+    /// Context and removed lines, with line endings.
+    old: Vec<&'a str>,
+    /// Context and added lines, with line endings.
+    new: Vec<&'a str>,
+    /// Context lines before the first change / after the last change.
+    leading: usize,
+    trailing: usize,
+}
+
+impl Hunk<'_> {
+    /// Context lines dropped from each end at `fuzz`.
+    fn trim(&self, fuzz: usize) -> (usize, usize) {
+        (fuzz.min(self.leading), fuzz.min(self.trailing))
+    }
+}
+
+/// Find the position of `hunk` (with increasing fuzz), searching outward
+/// from `expected` but never before `min`.
+fn place(
+    hunk: &Hunk,
+    lines: &[&str],
+    min: usize,
+    expected: usize,
+    options: &ApplyOptions,
+    eq: &dyn Fn(&str, &str) -> bool,
+) -> Option<(usize, usize)> {
+    for fuzz in 0..=options.fuzz {
+        let (lead, trail) = hunk.trim(fuzz);
+        if fuzz > 0 && (lead, trail) == hunk.trim(fuzz - 1) {
+            continue;
+        }
+        let old = &hunk.old[lead..hunk.old.len() - trail];
+        if old.len() > lines.len() {
+            continue;
+        }
+        let last = lines.len() - old.len();
+        let target = (expected + lead).clamp(min, last.max(min));
+        let matches_at = |pos: usize| {
+            pos >= min && pos <= last && old.iter().zip(&lines[pos..]).all(|(a, b)| eq(a, b))
+        };
+        let reach = options.max_offset.unwrap_or(lines.len());
+        for distance in 0..=reach {
+            if matches_at(target + distance) {
+                return Some((target + distance, fuzz));
+            }
+            if distance > 0 && distance <= target && matches_at(target - distance) {
+                return Some((target - distance, fuzz));
+            }
+            if target + distance > last && distance >= target.saturating_sub(min) {
+                break;
+            }
+        }
+    }
+    None
+}
+
+fn parse(diff: &str) -> Result<Vec<Hunk<'_>>, ApplyError> {
+    let malformed = |line: usize, message: &str| ApplyError::Malformed {
+        line,
+        message: message.to_string(),
+    };
+    let mut hunks = Vec::new();
+    let mut lines = diff.split_inclusive('\n').enumerate().peekable();
+    let mut seen_file = false;
+    while let Some((n, line)) = lines.next() {
+        if line.starts_with("--- ") {
+            if seen_file && !hunks.is_empty() {
+                return Err(malformed(n + 1, "diff touches more than one file"));
+            }
+            seen_file = true;
+            continue;
+        }
+        if !line.starts_with("@@") {
+            continue;
+        }
+        let (old_start, old_count, new_count) =
+            parse_header(line).ok_or_else(|| malformed(n + 1, "bad hunk header"))?;
+        let mut hunk = Hunk {
+            old_start,
+            old: Vec::new(),
+            new: Vec::new(),
+            leading: 0,
+            trailing: 0,
+        };
+        let (mut old_left, mut new_left) = (old_count, new_count);
+        let mut changed = false;
+        // Which side(s) the previous line went to, for `\ No newline`.
+        let mut last = (false, false);
+        while old_left > 0 || new_left > 0 || lines.peek().is_some_and(|(_, l)| l.starts_with('\\'))
+        {
+            let Some((n, line)) = lines.next() else {
+                return Err(malformed(diff.lines().count(), "hunk is truncated"));
+            };
+            let (tag, body) = match line.chars().next() {
+                // Some tools strip the space from empty context lines.
+                Some('\n') | Some('\r') => (' ', line),
+                Some(c @ (' ' | '+' | '-' | '\\')) => (c, &line[1..]),
+                Some(_) => return Err(malformed(n + 1, "line does not fit the hunk")),
+                None => (' ', line),
+            };
+            match tag {
+                ' ' if old_left > 0 && new_left > 0 => {
+                    hunk.old.push(body);
+                    hunk.new.push(body);
+                    if changed {
+                        hunk.trailing += 1;
+                    } else {
+                        hunk.leading += 1;
+                    }
+                    (old_left, new_left) = (old_left - 1, new_left - 1);
+                    last = (true, true);
+                }
+                '-' if old_left > 0 => {
+                    hunk.old.push(body);
+                    old_left -= 1;
+                    changed = true;
+                    hunk.trailing = 0;
+                    last = (true, false);
+                }
+                '+' if new_left > 0 => {
+                    hunk.new.push(body);
+                    new_left -= 1;
+                    changed = true;
+                    hunk.trailing = 0;
+                    last = (false, true);
+                }
+                '\\' => {
+                    if last.0 {
+                        strip_newline(&mut hunk.old);
+                    }
+                    if last.1 {
+                        strip_newline(&mut hunk.new);
+                    }
+                }
+                _ => return Err(malformed(n + 1, "line does not fit the hunk")),
+            }
+        }
+        hunks.push(hunk);
+    }
+    if hunks.is_empty() {
+        return Err(malformed(1, "no hunks found"));
+    }
+    Ok(hunks)
+}
+
+fn strip_newline(lines: &mut [&str]) {
+    if let Some(last) = lines.last_mut() {
+        *last = last
+            .strip_suffix("\r\n")
+            .or_else(|| last.strip_suffix('\n'))
+            .unwrap_or(last);
+    }
+}
+
+/// `@@ -12,5 +12,6 @@ ...` → (12, 5, 6). Omitted counts are 1.
+fn parse_header(line: &str) -> Option<(usize, usize, usize)> {
+    let mut parts = line.strip_prefix("@@ ")?.split_whitespace();
+    let range = |text: &str| -> Option<(usize, usize)> {
+        match text.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((text.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(parts.next()?.strip_prefix('-')?)?;
+    let (_, new_count) = range(parts.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_count, new_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+
+    #[test]
+    fn test_apply_exact() {
+        let diff = "--- a/f\n+++ b/f\n@@ -2,3 +2,3 @@\n two\n-three\n+THREE\n four\n@@ -6,2 +6,3 @@\n six\n seven\n+eight\n";
+        let applied = apply_with(ORIGINAL, diff, &ApplyOptions::default()).unwrap();
+        assert_eq!(
+            applied.text,
+            "one\ntwo\nTHREE\nfour\nfive\nsix\nseven\neight\n"
+        );
+        assert_eq!(applied.hunks, [HunkPlacement { offset: 0, fuzz: 0 }; 2]);
+    }
+
+    #[test]
+    fn test_offset_and_fuzz() {
+        let shifted = format!("zero\nzero\n{ORIGINAL}");
+        let diff = "@@ -2,3 +2,3 @@\n two\n-three\n+THREE\n four\n";
+        let applied = apply_with(&shifted, diff, &ApplyOptions::default()).unwrap();
+        assert_eq!(applied.hunks[0], HunkPlacement { offset: 2, fuzz: 0 });
+        assert!(applied.text.contains("two\nTHREE\nfour"));
+
+        // The leading context line no longer matches; fuzz 1 ignores it.
+        let edited = ORIGINAL.replace("two", "2");
+        let applied = apply_with(&edited, diff, &ApplyOptions::default()).unwrap();
+        assert_eq!(applied.hunks[0].fuzz, 1);
+        assert!(applied.text.contains("2\nTHREE\nfour"));
+        let strict = ApplyOptions {
+            fuzz: 0,
+            ..ApplyOptions::default()
+        };
+        assert!(apply_with(&edited, diff, &strict).is_err());
+    }
+
+    #[test]
+    fn test_conflicts_are_reported() {
+        let diff = "@@ -1,2 +1,2 @@\n-one\n+ONE\n two\n@@ -5,1 +5,1 @@\n-FIVE\n+5\n";
+        match apply(ORIGINAL, diff).unwrap_err() {
+            ApplyError::Conflicts { conflicts, partial } => {
+                assert_eq!(conflicts.len(), 1);
+                assert_eq!(conflicts[0].index, 1);
+                assert_eq!(conflicts[0].old_start, 5);
+                assert_eq!(conflicts[0].expected, ["FIVE\n"]);
+                assert!(partial.starts_with("ONE\ntwo\n"));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_no_newline_and_crlf() {
+        let diff = "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n";
+        assert_eq!(apply("a\nb", diff).unwrap(), "a\nc");
+
+        let crlf = "a\r\nb\r\n";
+        let diff = "@@ -1,2 +1,2 @@\n a\r\n-b\r\n+c\r\n";
+        assert_eq!(apply(crlf, diff).unwrap(), "a\r\nc\r\n");
+    }
+
+    #[test]
+    fn test_new_file_and_malformed() {
+        let diff = "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+hello\n+world\n";
+        assert_eq!(apply("", diff).unwrap(), "hello\nworld\n");
+
+        let err = apply(ORIGINAL, "@@ -1,2 +1,2 @@\n one\n").unwrap_err();
+        assert!(matches!(err, ApplyError::Malformed { .. }));
+        let err = apply(ORIGINAL, "just some text\n").unwrap_err();
+        assert_eq!(err.to_string(), "malformed diff at line 1: no hunks found");
+        let two_files = "--- a\n+++ a\n@@ -1 +1 @@\n-one\n+1\n--- b\n+++ b\n@@ -1 +1 @@\n-x\n+y\n";
+        assert!(matches!(
+            apply(ORIGINAL, two_files),
+            Err(ApplyError::Malformed { line: 6, .. })
+        ));
+        assert!(matches!(
+            apply("a\n", "--- a\n+++ b\n@@ -1,1 +1,1 @@\nébad\n"),
+            Err(ApplyError::Malformed { line: 4, .. })
+        ));
+    }
+}
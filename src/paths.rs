@@ -0,0 +1,183 @@
+//! Short, readable renderings of paths for UIs and log lines.
+//!
+//! [`display`] picks the shortest of three forms: relative to the current
+//! directory, `~`-shortened, or absolute. [`relative_to`] computes the
+//! relative form lexically (no filesystem access, symlinks are not
+//! resolved) and returns `None` when no relative path exists, such as
+//! between different Windows drives.
+
+use crate::dirs;
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// Render `path` in its shortest readable form.
+pub fn display(path: impl AsRef<Path>) -> String {
+    display_from(
+        path.as_ref(),
+        env::current_dir().ok().as_deref(),
+        dirs::home_dir().as_deref(),
+    )
+}
+
+/// [`display`] with an explicit working directory and home directory.
+pub fn display_from(path: &Path, cwd: Option<&Path>, home: Option<&Path>) -> String {
+    let absolute = match cwd {
+        Some(cwd) if path.is_relative() => normalize(&cwd.join(path)),
+        _ => normalize(path),
+    };
+    let mut best = absolute.display().to_string();
+    let mut consider = |candidate: String| {
+        if candidate.chars().count() < best.chars().count() {
+            best = candidate;
+        }
+    };
+    if let Some(home) = home
+        && let Ok(rest) = absolute.strip_prefix(normalize(home))
+    {
+        consider(if rest.as_os_str().is_empty() {
+            "~".to_string()
+        } else {
+            Path::new("~").join(rest).display().to_string()
+        });
+    }
+    if let Some(cwd) = cwd
+        && let Some(relative) = relative_to(cwd, &absolute)
+    {
+        consider(relative.display().to_string());
+    }
+    best
+}
+
+/// The path that leads from directory `base` to `path`, using `..` where
+/// needed. Returns `None` if one path is absolute and the other is not, or
+/// they are on different roots (e.g. `C:\` and `D:\`), or `base` climbs
+/// above its own starting point with `..`.
+pub fn relative_to(base: impl AsRef<Path>, path: impl AsRef<Path>) -> Option<PathBuf> {
+    let base = normalize(base.as_ref());
+    let path = normalize(path.as_ref());
+    if base.is_absolute() != path.is_absolute() {
+        return None;
+    }
+    let mut base_parts = base.components().peekable();
+    let mut path_parts = path.components().peekable();
+    // Roots and prefixes must match exactly.
+    while let (Some(b), Some(p)) = (base_parts.peek(), path_parts.peek()) {
+        let is_root = |c: &Component| matches!(c, Component::Prefix(_) | Component::RootDir);
+        if is_root(b) || is_root(p) {
+            if !same_component(b, p) {
+                return None;
+            }
+            base_parts.next();
+            path_parts.next();
+        } else {
+            break;
+        }
+    }
+    while let (Some(b), Some(p)) = (base_parts.peek(), path_parts.peek()) {
+        if !same_component(b, p) {
+            break;
+        }
+        base_parts.next();
+        path_parts.next();
+    }
+    let mut relative = PathBuf::new();
+    for part in base_parts {
+        // A leftover `..` in the base names a directory we can't see.
+        if !matches!(part, Component::Normal(_)) {
+            return None;
+        }
+        relative.push("..");
+    }
+    for part in path_parts {
+        if matches!(part, Component::Prefix(_) | Component::RootDir) {
+            return None;
+        }
+        relative.push(part);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
+/// Resolve `.` and `..` components lexically.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                let can_pop = matches!(out.components().next_back(), Some(Component::Normal(_)));
+                if can_pop {
+                    out.pop();
+                } else if !out.has_root() {
+                    out.push("..");
+                }
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Windows drive letters and file names compare case-insensitively.
+fn same_component(a: &Component, b: &Component) -> bool {
+    if cfg!(windows) {
+        a.as_os_str().eq_ignore_ascii_case(b.as_os_str())
+    } else {
+        a == b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_to() {
+        let rel = |b: &str, p: &str| relative_to(b, p).map(|p| p.display().to_string());
+        assert_eq!(rel("/a/b", "/a/b/c/d").as_deref(), Some("c/d"));
+        assert_eq!(rel("/a/b/c", "/a/x").as_deref(), Some("../../x"));
+        assert_eq!(rel("/a/b", "/a/b").as_deref(), Some("."));
+        assert_eq!(rel("/a/./b/../b", "/a/b/c").as_deref(), Some("c"));
+        assert_eq!(rel("/", "/etc").as_deref(), Some("etc"));
+        assert_eq!(rel("a/b", "a/c").as_deref(), Some("../c"));
+        assert_eq!(rel("/a", "a"), None);
+        assert_eq!(rel("../a", "b"), None);
+    }
+
+    #[test]
+    fn test_display_prefers_shortest() {
+        let cwd = Path::new("/home/ada/src/hive");
+        let home = Path::new("/home/ada");
+        let show = |p: &str| display_from(Path::new(p), Some(cwd), Some(home));
+        assert_eq!(show("/home/ada/src/hive/src/lib.rs"), "src/lib.rs");
+        assert_eq!(
+            show("/home/ada/.config/apiari/x.json"),
+            "~/.config/apiari/x.json"
+        );
+        assert_eq!(show("/home/ada"), "~");
+        assert_eq!(show("/etc/hosts"), "/etc/hosts");
+        assert_eq!(show("../hive/./Cargo.toml"), "Cargo.toml");
+        assert_eq!(show("/home/ada/src/other"), "../other");
+    }
+
+    #[test]
+    fn test_display_without_context() {
+        assert_eq!(
+            display_from(Path::new("/var/log/x"), None, None),
+            "/var/log/x"
+        );
+        assert_eq!(display_from(Path::new("rel/x"), None, None), "rel/x");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_different_drives() {
+        assert_eq!(relative_to(r"C:\work", r"D:\data"), None);
+        assert_eq!(
+            relative_to(r"C:\work", r"c:\work\src"),
+            Some(PathBuf::from("src"))
+        );
+    }
+}
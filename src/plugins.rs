@@ -0,0 +1,272 @@
+//! Discovery of external plugin executables.
+//!
+//! A plugin is any executable named `apiari-<name>` (with `.exe`, `.cmd`, or
+//! `.bat` on Windows) in one of the searched directories. [`discover`] runs
+//! each candidate with [`MANIFEST_FLAG`] and expects a JSON
+//! [`PluginManifest`] on stdout. Candidates that fail the handshake are
+//! reported in [`Discovery::failures`] rather than aborting the scan.
+//!
+//! Directories are searched in order and the first plugin with a given file
+//! name wins, as with `PATH`.
+
+use crate::dirs;
+use crate::version::{RequiredVersion, Version};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// File name prefix that marks an executable as a plugin.
+pub const PREFIX: &str = "apiari-";
+
+/// Argument passed to a plugin to request its manifest.
+pub const MANIFEST_FLAG: &str = "--apiari-manifest";
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a plugin reports about itself during the handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: Version,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Feature names the plugin implements, e.g. `"hooks"` or `"commands"`.
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Host versions the plugin works with, if it cares.
+    #[serde(default)]
+    pub requires: Option<RequiredVersion>,
+}
+
+impl PluginManifest {
+    /// Return `true` if the plugin declares `capability`.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Return `true` if the plugin accepts host version `host`.
+    pub fn supports_host(&self, host: &Version) -> bool {
+        self.requires.as_ref().is_none_or(|r| r.matches(host))
+    }
+}
+
+/// A plugin that completed the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    pub path: PathBuf,
+    pub manifest: PluginManifest,
+}
+
+/// The result of a discovery scan.
+#[derive(Debug, Default)]
+pub struct Discovery {
+    pub plugins: Vec<Plugin>,
+    /// Candidates whose handshake failed, with the reason.
+    pub failures: Vec<(PathBuf, io::Error)>,
+}
+
+impl Discovery {
+    /// Find a discovered plugin by manifest name.
+    pub fn get(&self, name: &str) -> Option<&Plugin> {
+        self.plugins.iter().find(|p| p.manifest.name == name)
+    }
+}
+
+/// The default search path: `dirs::data_dir()/plugins`, then `PATH`.
+pub fn default_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = dirs::data_dir()
+        .map(|d| d.join("plugins"))
+        .into_iter()
+        .collect();
+    if let Some(path) = env::var_os("PATH") {
+        dirs.extend(env::split_paths(&path));
+    }
+    dirs
+}
+
+/// Scan `dirs` for plugins and handshake with each one.
+///
+/// Missing or unreadable directories are skipped.
+pub fn discover<I, P>(dirs: I) -> Discovery
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let mut discovery = Discovery::default();
+    for path in candidates(dirs) {
+        match handshake(&path, HANDSHAKE_TIMEOUT) {
+            Ok(manifest) => discovery.plugins.push(Plugin { path, manifest }),
+            Err(e) => discovery.failures.push((path, e)),
+        }
+    }
+    discovery
+}
+
+/// List plugin executables in `dirs` without running them.
+pub fn candidates<I, P>(dirs: I) -> Vec<PathBuf>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir.as_ref()) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| plugin_name(p).is_some() && is_executable(p))
+            .collect();
+        paths.sort();
+        for path in paths {
+            if seen.insert(path.file_name().map(|n| n.to_os_string())) {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+/// Run `path --apiari-manifest` and parse its stdout.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::TimedOut` if the plugin doesn't exit within
+/// `timeout` (it is killed), or `InvalidData` if it exits unsuccessfully or
+/// prints something other than a manifest.
+pub fn handshake(path: &Path, timeout: Duration) -> io::Result<PluginManifest> {
+    let mut child = Command::new(path)
+        .arg(MANIFEST_FLAG)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut out = Vec::new();
+        stdout.read_to_end(&mut out).map(|_| out)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} did not answer the manifest handshake", path.display()),
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let out = reader
+        .join()
+        .map_err(|_| io::Error::other("manifest reader panicked"))??;
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} exited with {status} during handshake", path.display()),
+        ));
+    }
+    serde_json::from_slice(&out).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The plugin name encoded in a file name (`apiari-foo.exe` → `foo`).
+fn plugin_name(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    let name = if cfg!(windows) {
+        [".exe", ".cmd", ".bat"]
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext))?
+    } else {
+        name
+    };
+    name.strip_prefix(PREFIX).filter(|n| !n.is_empty())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_candidates_follow_naming_and_order() {
+        let tmp = TempDir::new("apiari-plugins-test-candidates").unwrap();
+        let first = tmp.join("a");
+        let second = tmp.join("b");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        script(&first, "apiari-lint", "true");
+        script(&second, "apiari-lint", "true");
+        script(&second, "apiari-fmt", "true");
+        script(&second, "other-tool", "true");
+        fs::write(second.join("apiari-notes"), "not executable").unwrap();
+
+        let found = candidates([&first, &second, &tmp.join("missing")]);
+        assert_eq!(
+            found,
+            [first.join("apiari-lint"), second.join("apiari-fmt")]
+        );
+    }
+
+    #[test]
+    fn test_discover_reads_manifests() {
+        let tmp = TempDir::new("apiari-plugins-test-discover").unwrap();
+        script(
+            tmp.path(),
+            "apiari-lint",
+            r#"[ "$1" = "--apiari-manifest" ] || exit 2
+echo '{"name":"lint","version":"1.4.0","capabilities":["hooks"],"requires":"^0.3"}'"#,
+        );
+        script(tmp.path(), "apiari-broken", "echo not json");
+
+        let discovery = discover([tmp.path()]);
+        let lint = discovery.get("lint").unwrap();
+        assert_eq!(lint.manifest.version, "1.4.0".parse().unwrap());
+        assert!(lint.manifest.has_capability("hooks"));
+        assert!(lint.manifest.supports_host(&"0.3.9".parse().unwrap()));
+        assert!(!lint.manifest.supports_host(&"0.4.0".parse().unwrap()));
+
+        assert_eq!(discovery.failures.len(), 1);
+        assert_eq!(discovery.failures[0].0, tmp.join("apiari-broken"));
+        assert_eq!(discovery.failures[0].1.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_handshake_times_out() {
+        let tmp = TempDir::new("apiari-plugins-test-timeout").unwrap();
+        let path = script(tmp.path(), "apiari-slow", "exec sleep 5");
+        let err = handshake(&path, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
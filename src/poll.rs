@@ -0,0 +1,147 @@
+//! Waiting for a condition with backoff.
+//!
+//! [`poll_until`] replaces hand-written sleep loops: it calls a probe until
+//! it returns `Some`, sleeping between attempts with a delay that starts at
+//! `interval` and grows by half each time, up to [`MAX_BACKOFF`] (or
+//! `interval` itself if that is larger). The probe always runs at least
+//! once, and a final attempt is made at the deadline.
+//!
+//! [`wait_for_file`] and [`wait_for_state`] cover the common "wait for
+//! another process" cases.
+
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Upper bound on the delay between attempts.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+const FILE_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Call `probe` until it returns `Some`, or fail after `timeout`.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::TimedOut` if `probe` never returned `Some`.
+pub fn poll_until<T>(
+    interval: Duration,
+    timeout: Duration,
+    mut probe: impl FnMut() -> Option<T>,
+) -> io::Result<T> {
+    let deadline = Instant::now() + timeout;
+    let cap = MAX_BACKOFF.max(interval);
+    let mut delay = interval;
+    loop {
+        if let Some(value) = probe() {
+            return Ok(value);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("condition not met within {timeout:?}"),
+            ));
+        }
+        thread::sleep(delay.min(deadline - now));
+        delay = (delay + delay / 2).min(cap);
+    }
+}
+
+/// Wait until `path` exists.
+pub fn wait_for_file(path: impl AsRef<Path>, timeout: Duration) -> io::Result<()> {
+    let path = path.as_ref();
+    poll_until(FILE_INTERVAL, timeout, || path.exists().then_some(()))
+}
+
+/// Wait until the JSON file at `path` parses as `T` and satisfies
+/// `predicate`, returning the matching value.
+///
+/// A missing, partially written, or unparsable file counts as "not yet".
+pub fn wait_for_state<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    timeout: Duration,
+    predicate: impl Fn(&T) -> bool,
+) -> io::Result<T> {
+    let path = path.as_ref();
+    poll_until(FILE_INTERVAL, timeout, || {
+        let data = fs::read(path).ok()?;
+        let state: T = serde_json::from_slice(&data).ok()?;
+        predicate(&state).then_some(state)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::save_state;
+    use crate::testutil::TempDir;
+    use serde_json::{Value, json};
+
+    #[test]
+    fn test_poll_until_backs_off_and_times_out() {
+        let mut calls = 0;
+        let start = Instant::now();
+        let err = poll_until(
+            Duration::from_millis(10),
+            Duration::from_millis(200),
+            || {
+                calls += 1;
+                None::<()>
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        // Without backoff there would be about 20 attempts.
+        assert!(calls < 15, "{calls} attempts");
+
+        let mut n = 0;
+        let value = poll_until(Duration::from_millis(1), Duration::from_secs(5), || {
+            n += 1;
+            (n == 3).then_some("ready")
+        })
+        .unwrap();
+        assert_eq!(value, "ready");
+    }
+
+    #[test]
+    fn test_wait_for_file() {
+        let tmp = TempDir::new("apiari-poll-test-file").unwrap();
+        let path = tmp.join("ready.flag");
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                fs::write(path, "").unwrap();
+            })
+        };
+        wait_for_file(&path, Duration::from_secs(5)).unwrap();
+        writer.join().unwrap();
+
+        let err = wait_for_file(tmp.join("never"), Duration::from_millis(30)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_wait_for_state() {
+        let tmp = TempDir::new("apiari-poll-test-state").unwrap();
+        let path = tmp.join("worker.json");
+        fs::write(&path, "{\"ready\": fal").unwrap();
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                save_state(&path, &json!({"ready": true, "port": 4100})).unwrap();
+            })
+        };
+        let state: Value = wait_for_state(&path, Duration::from_secs(5), |s: &Value| {
+            s["ready"] == true
+        })
+        .unwrap();
+        assert_eq!(state["port"], 4100);
+        writer.join().unwrap();
+    }
+}
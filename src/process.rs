@@ -0,0 +1,1206 @@
+//! Supervised child processes.
+//!
+//! [`Supervisor`] spawns a [`CommandSpec`], watches it exit, and restarts it
+//! according to a [`RestartPolicy`]. Every lifecycle transition is reported
+//! as a [`SupervisorEvent`] to any number of sinks (an mpsc channel and/or a
+//! JSONL file), so daemons no longer need hand-written restart loops.
+//!
+//! [`is_alive`] and [`verify`] answer "is PID X still *my* worker?". A PID
+//! existing is not enough once the OS has recycled it, so [`verify`]
+//! cross-checks a [`Fingerprint`] (start time or command line) captured when
+//! the process was spawned. Linux reads procfs; macOS and other Unixes use
+//! `ps`; Windows uses `tasklist` and PowerShell.
+//!
+//! [`sample`] reads a process's CPU, memory, and open descriptor counts
+//! through the same backends, and [`ResourceSampler`] reports them
+//! periodically to the same kinds of sinks as the supervisor. Only the
+//! Linux backend is free: elsewhere every sample spawns a `ps` (macOS,
+//! other Unixes) or `powershell` (Windows) process, which costs several
+//! milliseconds on Unix and a few hundred on Windows, so keep sampling
+//! intervals in seconds there. `ps` has no descriptor count, so
+//! [`ResourceUsage::open_fds`] is always `None` on macOS.
+//!
+//! [`Watchdog`] runs commands that prove liveness by touching a heartbeat
+//! file (see [`heartbeat`]). When a heartbeat goes stale, it kills the
+//! process with all of its descendants ([`kill_tree`]) and relaunches it.
+
+mod watchdog;
+
+pub use watchdog::{Watchdog, WatchdogEvent, WatchdogHandle, heartbeat};
+
+use crate::clock::now_ms;
+use crate::ipc::JsonlWriter;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the supervisor checks whether the child has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A serializable description of a command to run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandSpec {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+}
+
+impl CommandSpec {
+    /// Create a spec for `program` with no arguments.
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            env: Vec::new(),
+            cwd: None,
+        }
+    }
+
+    /// Append an argument.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Append several arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set an environment variable for the child.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the child's working directory.
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// Build a `std::process::Command` from this spec.
+    pub fn to_command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd
+    }
+
+    /// Spawn the command.
+    pub fn spawn(&self) -> io::Result<Child> {
+        self.to_command().spawn()
+    }
+}
+
+/// When a supervised process should be restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Run once; never restart.
+    Never,
+    /// Restart after every exit, successful or not.
+    Always,
+    /// Restart only after a non-zero exit or death by signal.
+    OnFailure,
+    /// Like `OnFailure`, but the delay doubles after each consecutive
+    /// failure, from `initial` up to `max`. A run that lasts longer than
+    /// `max` resets the delay.
+    Backoff { initial: Duration, max: Duration },
+}
+
+impl RestartPolicy {
+    /// Whether a run that ended with `status` (`None` if it could not be
+    /// spawned or was killed) should be followed by another.
+    fn restarts(self, status: Option<ExitStatus>) -> bool {
+        let failed = status.is_none_or(|s| !s.success());
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure | RestartPolicy::Backoff { .. } => failed,
+        }
+    }
+}
+
+/// Restart delays under a [`RestartPolicy`]: `fixed` for `Always` and
+/// `OnFailure`, doubling for `Backoff`.
+struct Backoff {
+    policy: RestartPolicy,
+    fixed: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    fn new(policy: RestartPolicy, fixed: Duration) -> Self {
+        let next = match policy {
+            RestartPolicy::Backoff { initial, .. } => initial,
+            _ => fixed,
+        };
+        Self {
+            policy,
+            fixed,
+            next,
+        }
+    }
+
+    /// The delay before restarting a run that lasted `ran`.
+    fn delay(&mut self, ran: Duration) -> Duration {
+        let RestartPolicy::Backoff { initial, max } = self.policy else {
+            return self.fixed;
+        };
+        if ran > max {
+            self.next = initial;
+        }
+        let delay = self.next;
+        self.next = (delay * 2).min(max);
+        delay
+    }
+}
+
+/// A lifecycle event emitted by a [`Supervisor`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SupervisorEvent {
+    /// The child was spawned. `attempt` is 0 for the first run.
+    Started { pid: u32, attempt: u32 },
+    /// The child could not be spawned.
+    SpawnFailed { attempt: u32, error: String },
+    /// The child exited. `code` is `None` if it was killed by a signal.
+    Exited {
+        pid: u32,
+        code: Option<i32>,
+        success: bool,
+    },
+    /// The supervisor will respawn the child after `delay_ms`.
+    Restarting { attempt: u32, delay_ms: u64 },
+    /// The restart limit was reached; supervision has ended.
+    GaveUp { restarts: u32 },
+    /// Supervision ended because the policy does not restart.
+    Finished,
+    /// Supervision was stopped via [`SupervisorHandle::stop`] (or by
+    /// dropping the handle).
+    Stopped,
+}
+
+enum Sink<T> {
+    Channel(Sender<T>),
+    Jsonl(JsonlWriter<T>),
+}
+
+impl<T: Serialize + Clone> Sink<T> {
+    fn emit(&self, event: &T) {
+        // Event delivery is best-effort; a gone receiver or a full disk must
+        // not take the supervised process down with it.
+        match self {
+            Sink::Channel(tx) => {
+                let _ = tx.send(event.clone());
+            }
+            Sink::Jsonl(writer) => {
+                let _ = writer.append(event);
+            }
+        }
+    }
+}
+
+/// Spawns a command and keeps it running according to a restart policy.
+///
+/// ```ignore
+/// let (tx, rx) = std::sync::mpsc::channel();
+/// let handle = Supervisor::new(CommandSpec::new("worker").arg("--queue=a"))
+///     .policy(RestartPolicy::Backoff { initial: 1s, max: 60s })
+///     .max_restarts(10)
+///     .events_to_channel(tx)
+///     .start();
+/// ```
+pub struct Supervisor {
+    spec: CommandSpec,
+    policy: RestartPolicy,
+    max_restarts: Option<u32>,
+    restart_delay: Duration,
+    sinks: Vec<Sink<SupervisorEvent>>,
+}
+
+impl Supervisor {
+    /// Create a supervisor for `spec` with [`RestartPolicy::OnFailure`], no
+    /// restart limit, and a one-second restart delay.
+    pub fn new(spec: CommandSpec) -> Self {
+        Self {
+            spec,
+            policy: RestartPolicy::OnFailure,
+            max_restarts: None,
+            restart_delay: Duration::from_secs(1),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Set the restart policy.
+    pub fn policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Give up after this many restarts.
+    pub fn max_restarts(mut self, max: u32) -> Self {
+        self.max_restarts = Some(max);
+        self
+    }
+
+    /// Delay before restarting under `Always` / `OnFailure`.
+    pub fn restart_delay(mut self, delay: Duration) -> Self {
+        self.restart_delay = delay;
+        self
+    }
+
+    /// Send lifecycle events to a channel.
+    pub fn events_to_channel(mut self, tx: Sender<SupervisorEvent>) -> Self {
+        self.sinks.push(Sink::Channel(tx));
+        self
+    }
+
+    /// Append lifecycle events to a JSONL file.
+    pub fn events_to_jsonl(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sinks.push(Sink::Jsonl(JsonlWriter::new(path)));
+        self
+    }
+
+    /// Start supervising on a background thread.
+    pub fn start(self) -> SupervisorHandle {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let thread = thread::spawn(move || self.run(stop_rx));
+        SupervisorHandle {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        }
+    }
+
+    fn emit(&self, event: SupervisorEvent) {
+        for sink in &self.sinks {
+            sink.emit(&event);
+        }
+    }
+
+    fn run(self, stop_rx: mpsc::Receiver<()>) {
+        let mut attempt = 0u32;
+        let mut backoff = Backoff::new(self.policy, self.restart_delay);
+
+        loop {
+            let started = Instant::now();
+            let status = match self.spec.spawn() {
+                Ok(mut child) => {
+                    let pid = child.id();
+                    self.emit(SupervisorEvent::Started { pid, attempt });
+                    let status = loop {
+                        match child.try_wait() {
+                            Ok(Some(status)) => break Some(status),
+                            Ok(None) => {}
+                            Err(_) => break None,
+                        }
+                        if interruptible_sleep(&stop_rx, POLL_INTERVAL) {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                            self.emit(SupervisorEvent::Stopped);
+                            return;
+                        }
+                    };
+                    self.emit(SupervisorEvent::Exited {
+                        pid,
+                        code: status.and_then(|s| s.code()),
+                        success: status.is_some_and(|s| s.success()),
+                    });
+                    status
+                }
+                Err(e) => {
+                    self.emit(SupervisorEvent::SpawnFailed {
+                        attempt,
+                        error: e.to_string(),
+                    });
+                    None
+                }
+            };
+
+            if !self.policy.restarts(status) {
+                self.emit(SupervisorEvent::Finished);
+                return;
+            }
+            if self.max_restarts.is_some_and(|max| attempt >= max) {
+                self.emit(SupervisorEvent::GaveUp { restarts: attempt });
+                return;
+            }
+
+            let delay = backoff.delay(started.elapsed());
+
+            attempt += 1;
+            self.emit(SupervisorEvent::Restarting {
+                attempt,
+                delay_ms: delay.as_millis() as u64,
+            });
+            if interruptible_sleep(&stop_rx, delay) {
+                self.emit(SupervisorEvent::Stopped);
+                return;
+            }
+        }
+    }
+}
+
+/// Sleep for `d`, returning `true` if a stop was requested meanwhile.
+fn interruptible_sleep(stop_rx: &mpsc::Receiver<()>, d: Duration) -> bool {
+    !matches!(stop_rx.recv_timeout(d), Err(RecvTimeoutError::Timeout))
+}
+
+/// Handle to a running [`Supervisor`].
+///
+/// Dropping the handle stops supervision and kills the child.
+pub struct SupervisorHandle {
+    stop_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SupervisorHandle {
+    /// Kill the child (if running), stop restarting, and wait for the
+    /// supervisor thread to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    /// Wait until supervision ends on its own (the policy stops restarting
+    /// or the restart limit is reached).
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Return `true` once the supervisor thread has exited.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(tx) = self.stop_tx.take() {
+            let _ = tx.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SupervisorHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Identifies a specific process instance, surviving PID reuse.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Fingerprint {
+    /// The opaque start-time token returned by [`start_time`].
+    StartTime(String),
+    /// The command line returned by [`cmdline`].
+    Cmdline(Vec<String>),
+}
+
+impl Fingerprint {
+    /// Capture the start-time fingerprint of a running process.
+    pub fn of(pid: u32) -> io::Result<Self> {
+        start_time(pid).map(Fingerprint::StartTime)
+    }
+}
+
+/// Return `true` if a process with this PID exists (and is not a zombie).
+pub fn is_alive(pid: u32) -> bool {
+    if pid == 0 {
+        return false;
+    }
+    if cfg!(target_os = "linux") {
+        match read_proc_stat(pid) {
+            Ok(fields) => fields.first().is_none_or(|state| state != "Z"),
+            Err(_) => false,
+        }
+    } else if cfg!(windows) {
+        run_capture(
+            "tasklist",
+            &["/FI", &format!("PID eq {pid}"), "/NH", "/FO", "CSV"],
+        )
+        .is_ok_and(|out| out.contains(&format!("\"{pid}\"")))
+    } else {
+        run_capture("ps", &["-p", &pid.to_string(), "-o", "pid="])
+            .is_ok_and(|out| out.trim() == pid.to_string())
+    }
+}
+
+/// Return an opaque token identifying when `pid` started.
+///
+/// The token is only meaningful for comparison with another token from the
+/// same machine (Linux: clock ticks since boot; elsewhere: the start time as
+/// reported by the OS).
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::NotFound` if the process does not exist.
+pub fn start_time(pid: u32) -> io::Result<String> {
+    let token = if cfg!(target_os = "linux") {
+        // Field 22 of /proc/<pid>/stat; index 19 after the state field.
+        read_proc_stat(pid)?
+            .get(19)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short /proc stat line"))?
+    } else if cfg!(windows) {
+        run_capture(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                &format!("(Get-Process -Id {pid}).StartTime.ToUniversalTime().Ticks"),
+            ],
+        )?
+    } else {
+        run_capture("ps", &["-p", &pid.to_string(), "-o", "lstart="])?
+    };
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        return Err(no_such_process(pid));
+    }
+    Ok(token)
+}
+
+/// Return the command line of `pid`.
+///
+/// On Linux this is exact; elsewhere the OS reports a single string, which
+/// is split on whitespace.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::NotFound` if the process does not exist.
+pub fn cmdline(pid: u32) -> io::Result<Vec<String>> {
+    if cfg!(target_os = "linux") {
+        let raw = std::fs::read(format!("/proc/{pid}/cmdline")).map_err(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                no_such_process(pid)
+            } else {
+                e
+            }
+        })?;
+        return Ok(raw
+            .split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect());
+    }
+    let out = if cfg!(windows) {
+        run_capture(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                &format!("(Get-CimInstance Win32_Process -Filter \"ProcessId={pid}\").CommandLine"),
+            ],
+        )?
+    } else {
+        run_capture("ps", &["-p", &pid.to_string(), "-o", "command="])?
+    };
+    if out.trim().is_empty() {
+        return Err(no_such_process(pid));
+    }
+    Ok(out.split_whitespace().map(str::to_string).collect())
+}
+
+/// Return `true` if `pid` is alive *and* matches `expected`.
+///
+/// Command lines are compared after joining with spaces, so a fingerprint
+/// captured on one platform backend compares sensibly with the lossy
+/// whitespace-split form reported by another.
+pub fn verify(pid: u32, expected: &Fingerprint) -> bool {
+    if !is_alive(pid) {
+        return false;
+    }
+    match expected {
+        Fingerprint::StartTime(t) => start_time(pid).is_ok_and(|actual| actual == *t),
+        Fingerprint::Cmdline(args) => {
+            cmdline(pid).is_ok_and(|actual| actual.join(" ") == args.join(" "))
+        }
+    }
+}
+
+/// Resource usage of one process at one moment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub pid: u32,
+    /// When the sample was taken (ms since the Unix epoch).
+    pub ts_ms: u64,
+    /// CPU use as a percentage of one core. For [`sample`] this is the
+    /// average since the process started; for [`ResourceSampler`] it covers
+    /// the interval since the previous sample.
+    pub cpu_percent: f64,
+    /// Total user + system CPU time consumed so far, in milliseconds.
+    pub cpu_time_ms: u64,
+    /// Resident set size in bytes.
+    pub rss: u64,
+    /// Open file descriptors (handles on Windows), if the OS reports them
+    /// to this user. Always `None` on macOS and other non-Linux Unixes.
+    pub open_fds: Option<u64>,
+}
+
+/// Raw counters read from the platform backend.
+struct RawUsage {
+    cpu_time: Duration,
+    /// Wall time since the process started.
+    elapsed: Duration,
+    rss: u64,
+    open_fds: Option<u64>,
+}
+
+/// Sample CPU, memory, and descriptor usage of `pid`.
+///
+/// Linux reads procfs; macOS and other Unixes parse `ps` (open descriptors
+/// are not reported there); Windows queries `Get-Process` via PowerShell.
+/// Outside Linux each call therefore spawns a child process.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::NotFound` if the process does not exist.
+pub fn sample(pid: u32) -> io::Result<ResourceUsage> {
+    let raw = raw_usage(pid)?;
+    let cpu_percent = percent(raw.cpu_time, raw.elapsed);
+    Ok(usage(pid, &raw, cpu_percent))
+}
+
+fn usage(pid: u32, raw: &RawUsage, cpu_percent: f64) -> ResourceUsage {
+    ResourceUsage {
+        pid,
+        ts_ms: now_ms(),
+        cpu_percent,
+        cpu_time_ms: raw.cpu_time.as_millis() as u64,
+        rss: raw.rss,
+        open_fds: raw.open_fds,
+    }
+}
+
+fn percent(cpu: Duration, wall: Duration) -> f64 {
+    if wall.is_zero() {
+        0.0
+    } else {
+        cpu.as_secs_f64() / wall.as_secs_f64() * 100.0
+    }
+}
+
+fn raw_usage(pid: u32) -> io::Result<RawUsage> {
+    if cfg!(target_os = "linux") {
+        // Kernel USER_HZ; 100 on every mainstream architecture.
+        const TICKS_PER_SEC: f64 = 100.0;
+        let stat = read_proc_stat(pid)?;
+        let field = |i: usize| -> io::Result<f64> {
+            stat.get(i)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short /proc stat line"))
+        };
+        // utime, stime, starttime: fields 14, 15, 22 (indices 11, 12, 19).
+        let cpu_ticks = field(11)? + field(12)?;
+        let start_ticks = field(19)?;
+        let uptime: f64 = std::fs::read_to_string("/proc/uptime")?
+            .split_whitespace()
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed /proc/uptime"))?;
+        let status = std::fs::read_to_string(format!("/proc/{pid}/status"))?;
+        let rss_kib: u64 = status
+            .lines()
+            .find_map(|l| l.strip_prefix("VmRSS:"))
+            .and_then(|v| v.split_whitespace().next()?.parse().ok())
+            .unwrap_or(0);
+        let open_fds = std::fs::read_dir(format!("/proc/{pid}/fd"))
+            .ok()
+            .map(|d| d.count() as u64);
+        Ok(RawUsage {
+            cpu_time: Duration::from_secs_f64(cpu_ticks / TICKS_PER_SEC),
+            elapsed: Duration::from_secs_f64((uptime - start_ticks / TICKS_PER_SEC).max(0.0)),
+            rss: rss_kib * 1024,
+            open_fds,
+        })
+    } else if cfg!(windows) {
+        let script = format!(
+            "$p = Get-Process -Id {pid}; \
+             \"$($p.WorkingSet64) $([math]::Round($p.TotalProcessorTime.TotalMilliseconds)) \
+             $([math]::Round(((Get-Date) - $p.StartTime).TotalMilliseconds)) $($p.HandleCount)\""
+        );
+        let out = run_capture("powershell", &["-NoProfile", "-Command", &script])?;
+        let nums: Vec<u64> = out
+            .split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        let [rss, cpu_ms, elapsed_ms, handles] = nums[..] else {
+            return Err(no_such_process(pid));
+        };
+        Ok(RawUsage {
+            cpu_time: Duration::from_millis(cpu_ms),
+            elapsed: Duration::from_millis(elapsed_ms),
+            rss,
+            open_fds: Some(handles),
+        })
+    } else {
+        let out = run_capture("ps", &["-p", &pid.to_string(), "-o", "rss=,time=,etime="])?;
+        let fields: Vec<&str> = out.split_whitespace().collect();
+        let [rss_kib, time, etime] = fields[..] else {
+            return Err(no_such_process(pid));
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "unexpected ps output");
+        Ok(RawUsage {
+            cpu_time: parse_ps_time(time).ok_or_else(invalid)?,
+            elapsed: parse_ps_time(etime).ok_or_else(invalid)?,
+            rss: rss_kib.parse::<u64>().map_err(|_| invalid())? * 1024,
+            open_fds: None,
+        })
+    }
+}
+
+/// Parse `ps` durations: `[[dd-]hh:]mm:ss[.ff]`.
+fn parse_ps_time(s: &str) -> Option<Duration> {
+    let (days, rest) = match s.split_once('-') {
+        Some((d, rest)) => (d.parse::<u64>().ok()?, rest),
+        None => (0, s),
+    };
+    let parts: Vec<&str> = rest.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+    let secs: f64 = parts.last()?.parse().ok()?;
+    let mut whole = 0u64;
+    for part in &parts[..parts.len() - 1] {
+        whole = whole * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(days * 86_400 + whole * 60) + Duration::from_secs_f64(secs))
+}
+
+/// Periodically samples a process and reports [`ResourceUsage`] to sinks.
+///
+/// Sampling stops when the process exits or the handle is stopped.
+pub struct ResourceSampler {
+    pid: u32,
+    interval: Duration,
+    sinks: Vec<Sink<ResourceUsage>>,
+}
+
+impl ResourceSampler {
+    /// Sample `pid` every five seconds.
+    pub fn new(pid: u32) -> Self {
+        Self {
+            pid,
+            interval: Duration::from_secs(5),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Set the sampling interval.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Send samples to a channel.
+    pub fn to_channel(mut self, tx: Sender<ResourceUsage>) -> Self {
+        self.sinks.push(Sink::Channel(tx));
+        self
+    }
+
+    /// Append samples to a JSONL file.
+    pub fn to_jsonl(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sinks.push(Sink::Jsonl(JsonlWriter::new(path)));
+        self
+    }
+
+    /// Start sampling on a background thread.
+    pub fn start(self) -> SamplerHandle {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let thread = thread::spawn(move || self.run(stop_rx));
+        SamplerHandle {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        }
+    }
+
+    fn run(self, stop_rx: mpsc::Receiver<()>) {
+        let mut previous: Option<(Instant, Duration)> = None;
+        loop {
+            // An exited but unreaped child still has readable counters.
+            if !is_alive(self.pid) {
+                return;
+            }
+            let Ok(raw) = raw_usage(self.pid) else {
+                return;
+            };
+            let now = Instant::now();
+            let cpu_percent = match previous {
+                Some((at, cpu)) => percent(raw.cpu_time.saturating_sub(cpu), now - at),
+                None => percent(raw.cpu_time, raw.elapsed),
+            };
+            previous = Some((now, raw.cpu_time));
+            let sample = usage(self.pid, &raw, cpu_percent);
+            for sink in &self.sinks {
+                sink.emit(&sample);
+            }
+            if interruptible_sleep(&stop_rx, self.interval) {
+                return;
+            }
+        }
+    }
+}
+
+/// Handle to a running [`ResourceSampler`]. Dropping it stops sampling.
+pub struct SamplerHandle {
+    stop_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SamplerHandle {
+    /// Stop sampling and wait for the sampler thread to exit.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    /// Wait until the sampled process exits.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(tx) = self.stop_tx.take() {
+            let _ = tx.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SamplerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Return the PIDs of all descendants of `pid`: its children, their
+/// children, and so on, parents before children.
+pub fn descendants(pid: u32) -> io::Result<Vec<u32>> {
+    let parents = parent_pids()?;
+    let mut tree = vec![pid];
+    let mut i = 0;
+    while i < tree.len() {
+        let parent = tree[i];
+        tree.extend(
+            parents
+                .iter()
+                .filter(|&&(child, ppid)| ppid == parent && child != parent)
+                .map(|&(child, _)| child),
+        );
+        i += 1;
+    }
+    tree.remove(0);
+    Ok(tree)
+}
+
+/// Forcibly kill `pid` and all of its descendants. Returns the descendants
+/// that were signalled.
+///
+/// The tree is listed before any process is killed, so children aren't
+/// lost to re-parenting; a process forked after the listing survives.
+pub fn kill_tree(pid: u32) -> io::Result<Vec<u32>> {
+    let descendants = descendants(pid)?;
+    if cfg!(windows) {
+        // taskkill walks the tree itself.
+        let _ = run_capture("taskkill", &["/T", "/F", "/PID", &pid.to_string()]);
+    } else {
+        let mut pids = vec![pid.to_string()];
+        pids.extend(descendants.iter().map(u32::to_string));
+        // `kill` fails if any PID is already gone but still signals the rest.
+        Command::new("kill")
+            .arg("-KILL")
+            .args(&pids)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+    }
+    Ok(descendants)
+}
+
+/// Forcibly kill every process still in the process group `pgid`, and
+/// return them. Used for processes spawned as group leaders, whose
+/// descendants can no longer be found through parent PIDs once the leader
+/// has exited. Windows has no process groups; it kills nothing there.
+fn kill_group(pgid: u32) -> io::Result<Vec<u32>> {
+    if cfg!(windows) {
+        return Ok(Vec::new());
+    }
+    let members: Vec<u32> = pid_pairs(2, "pgid")?
+        .into_iter()
+        .filter(|&(pid, group)| group == pgid && pid != pgid)
+        .map(|(pid, _)| pid)
+        .collect();
+    if !members.is_empty() {
+        Command::new("kill")
+            .args(["-KILL", "--", &format!("-{pgid}")])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+    }
+    Ok(members)
+}
+
+/// `(pid, parent pid)` for every process.
+fn parent_pids() -> io::Result<Vec<(u32, u32)>> {
+    if cfg!(windows) {
+        let out = run_capture(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Get-CimInstance Win32_Process | ForEach-Object { \"$($_.ProcessId) $($_.ParentProcessId)\" }",
+            ],
+        )?;
+        return Ok(parse_pid_pairs(&out));
+    }
+    pid_pairs(1, "ppid")
+}
+
+/// `(pid, value)` for every process, where the value is field `stat_index`
+/// of `/proc/<pid>/stat` (counting from the state after the command name)
+/// on Linux, and `ps` column `ps_column` elsewhere on Unix.
+fn pid_pairs(stat_index: usize, ps_column: &str) -> io::Result<Vec<(u32, u32)>> {
+    if cfg!(target_os = "linux") {
+        let mut pairs = Vec::new();
+        for entry in std::fs::read_dir("/proc")? {
+            let Some(pid) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            if let Ok(fields) = read_proc_stat(pid)
+                && let Some(value) = fields.get(stat_index).and_then(|p| p.parse().ok())
+            {
+                pairs.push((pid, value));
+            }
+        }
+        return Ok(pairs);
+    }
+    let out = run_capture("ps", &["-A", "-o", &format!("pid=,{ps_column}=")])?;
+    Ok(parse_pid_pairs(&out))
+}
+
+fn parse_pid_pairs(out: &str) -> Vec<(u32, u32)> {
+    out.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().map(|f| f.parse().ok());
+            Some((fields.next()??, fields.next()??))
+        })
+        .collect()
+}
+
+fn no_such_process(pid: u32) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no such process: {pid}"))
+}
+
+/// Read `/proc/<pid>/stat` and return the fields after the command name
+/// (which may itself contain spaces and parentheses).
+fn read_proc_stat(pid: u32) -> io::Result<Vec<String>> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            no_such_process(pid)
+        } else {
+            e
+        }
+    })?;
+    let rest = stat
+        .rfind(')')
+        .map(|i| &stat[i + 1..])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed /proc stat"))?;
+    Ok(rest.split_whitespace().map(str::to_string).collect())
+}
+
+/// Run a command and return its stdout, failing on a non-zero exit.
+fn run_capture(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{program} exited with {}", output.status),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::ipc::JsonlReader;
+    use crate::testutil::TempDir;
+
+    fn sh(script: &str) -> CommandSpec {
+        CommandSpec::new("sh").arg("-c").arg(script)
+    }
+
+    fn kinds(events: &[SupervisorEvent]) -> Vec<&'static str> {
+        events
+            .iter()
+            .map(|e| match e {
+                SupervisorEvent::Started { .. } => "started",
+                SupervisorEvent::SpawnFailed { .. } => "spawn_failed",
+                SupervisorEvent::Exited { .. } => "exited",
+                SupervisorEvent::Restarting { .. } => "restarting",
+                SupervisorEvent::GaveUp { .. } => "gave_up",
+                SupervisorEvent::Finished => "finished",
+                SupervisorEvent::Stopped => "stopped",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_on_failure_gives_up_after_max_restarts() {
+        let (tx, rx) = mpsc::channel();
+        Supervisor::new(sh("exit 3"))
+            .max_restarts(2)
+            .restart_delay(Duration::ZERO)
+            .events_to_channel(tx)
+            .start()
+            .wait();
+
+        let events: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            kinds(&events),
+            [
+                "started",
+                "exited",
+                "restarting",
+                "started",
+                "exited",
+                "restarting",
+                "started",
+                "exited",
+                "gave_up"
+            ]
+        );
+        assert!(matches!(
+            events[1],
+            SupervisorEvent::Exited {
+                code: Some(3),
+                success: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_success_is_not_restarted_on_failure_policy() {
+        let (tx, rx) = mpsc::channel();
+        Supervisor::new(sh("exit 0"))
+            .events_to_channel(tx)
+            .start()
+            .wait();
+
+        let events: Vec<_> = rx.try_iter().collect();
+        assert_eq!(kinds(&events), ["started", "exited", "finished"]);
+    }
+
+    #[test]
+    fn test_always_restarts_success() {
+        let (tx, rx) = mpsc::channel();
+        Supervisor::new(sh("exit 0"))
+            .policy(RestartPolicy::Always)
+            .max_restarts(1)
+            .restart_delay(Duration::ZERO)
+            .events_to_channel(tx)
+            .start()
+            .wait();
+
+        let events: Vec<_> = rx.try_iter().collect();
+        assert_eq!(
+            kinds(&events),
+            [
+                "started",
+                "exited",
+                "restarting",
+                "started",
+                "exited",
+                "gave_up"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backoff_doubles_delay() {
+        let (tx, rx) = mpsc::channel();
+        Supervisor::new(sh("exit 1"))
+            .policy(RestartPolicy::Backoff {
+                initial: Duration::from_millis(20),
+                max: Duration::from_millis(60),
+            })
+            .max_restarts(3)
+            .events_to_channel(tx)
+            .start()
+            .wait();
+
+        let delays: Vec<u64> = rx
+            .try_iter()
+            .filter_map(|e| match e {
+                SupervisorEvent::Restarting { delay_ms, .. } => Some(delay_ms),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(delays, [20, 40, 60]);
+    }
+
+    #[test]
+    fn test_stop_kills_child_and_writes_jsonl() {
+        let tmp = TempDir::new("apiari-process-test-stop").unwrap();
+        let log = tmp.join("events.jsonl");
+        let (tx, rx) = mpsc::channel();
+        let handle = Supervisor::new(sh("sleep 30"))
+            .events_to_channel(tx)
+            .events_to_jsonl(&log)
+            .start();
+
+        // Wait for the child to be running before stopping.
+        let first = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(first, SupervisorEvent::Started { .. }));
+        handle.stop();
+
+        let events = JsonlReader::<SupervisorEvent>::new(&log).poll().unwrap();
+        assert_eq!(kinds(&events), ["started", "stopped"]);
+    }
+
+    const DEAD_PID: u32 = 999_999_999;
+
+    #[test]
+    fn test_is_alive() {
+        assert!(is_alive(std::process::id()));
+        assert!(!is_alive(DEAD_PID));
+        assert!(!is_alive(0));
+    }
+
+    #[test]
+    fn test_kill_tree_kills_grandchildren() {
+        use std::io::{BufRead, BufReader};
+
+        let mut child = sh("sleep 30 & echo $!; wait")
+            .to_command()
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let grandchild: u32 = line.trim().parse().unwrap();
+        assert_eq!(descendants(child.id()).unwrap(), [grandchild]);
+
+        assert_eq!(kill_tree(child.id()).unwrap(), [grandchild]);
+        assert!(!child.wait().unwrap().success());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while is_alive(grandchild) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!is_alive(grandchild));
+    }
+
+    #[test]
+    fn test_verify_start_time() {
+        let me = std::process::id();
+        let fingerprint = Fingerprint::of(me).unwrap();
+        assert!(verify(me, &fingerprint));
+        assert!(!verify(me, &Fingerprint::StartTime("0".into())));
+        assert!(!verify(DEAD_PID, &fingerprint));
+        assert_eq!(
+            start_time(DEAD_PID).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_verify_cmdline_of_child() {
+        let mut child = CommandSpec::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id();
+        // Right after fork the child still shows our own command line until
+        // it execs, so give it a moment.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let args = loop {
+            let args = cmdline(pid).unwrap();
+            if args.first().is_some_and(|a| a == "sleep") || Instant::now() > deadline {
+                break args;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(args, ["sleep", "30"]);
+        assert!(verify(pid, &Fingerprint::Cmdline(args)));
+        assert!(!verify(pid, &Fingerprint::Cmdline(vec!["other".into()])));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(!is_alive(pid));
+    }
+
+    #[test]
+    fn test_sample_self() {
+        let usage = sample(std::process::id()).unwrap();
+        assert_eq!(usage.pid, std::process::id());
+        assert!(usage.rss > 0);
+        assert!(usage.cpu_percent >= 0.0);
+        if cfg!(target_os = "linux") {
+            assert!(usage.open_fds.unwrap() >= 3);
+        }
+        assert_eq!(
+            sample(DEAD_PID).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_parse_ps_time() {
+        assert_eq!(parse_ps_time("01:02"), Some(Duration::from_secs(62)));
+        assert_eq!(parse_ps_time("1:02:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(
+            parse_ps_time("2-00:00:01"),
+            Some(Duration::from_secs(2 * 86_400 + 1))
+        );
+        assert_eq!(parse_ps_time("0:01.50"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_ps_time("garbage"), None);
+    }
+
+    #[test]
+    fn test_sampler_reports_until_exit() {
+        let tmp = TempDir::new("apiari-process-test-sampler").unwrap();
+        let log = tmp.join("usage.jsonl");
+        let mut child = CommandSpec::new("sleep").arg("0.3").spawn().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let handle = ResourceSampler::new(child.id())
+            .interval(Duration::from_millis(50))
+            .to_channel(tx)
+            .to_jsonl(&log)
+            .start();
+        child.wait().unwrap();
+        handle.wait();
+
+        let samples: Vec<ResourceUsage> = rx.try_iter().collect();
+        assert!(samples.len() >= 2, "got {} samples", samples.len());
+        assert!(samples.iter().all(|s| s.pid == child.id()));
+        let logged: Vec<ResourceUsage> = JsonlReader::new(&log).poll().unwrap();
+        assert_eq!(logged.len(), samples.len());
+    }
+}
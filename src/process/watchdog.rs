@@ -0,0 +1,504 @@
+//! Restarting processes whose heartbeats go stale.
+
+use super::{
+    Backoff, CommandSpec, RestartPolicy, Sink, interruptible_sleep, kill_group, kill_tree,
+};
+use crate::ipc::JsonlWriter;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Record a heartbeat: write this process's PID to `path`, updating its
+/// modification time. Call it more often than the watchdog's `max_age`.
+pub fn heartbeat(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", std::process::id()))
+}
+
+/// An intervention or lifecycle event emitted by a [`Watchdog`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WatchdogEvent {
+    /// The watched command was spawned. `restarts` is 0 for the first run.
+    Started {
+        name: String,
+        pid: u32,
+        restarts: u32,
+    },
+    /// The command could not be spawned. Whether it is retried follows the
+    /// restart policy, like an exit.
+    SpawnFailed { name: String, error: String },
+    /// The heartbeat is older than the allowed maximum.
+    Stale { name: String, pid: u32, age_ms: u64 },
+    /// The process and its descendants were killed, or, after the process
+    /// exited on its own, the descendants it left behind.
+    Killed {
+        name: String,
+        pid: u32,
+        descendants: Vec<u32>,
+    },
+    /// The process exited on its own.
+    Exited {
+        name: String,
+        pid: u32,
+        code: Option<i32>,
+    },
+    /// The command will be launched again after `delay_ms`. `restarts`
+    /// counts this restart.
+    Restarting {
+        name: String,
+        restarts: u32,
+        delay_ms: u64,
+    },
+    /// The restart limit was reached; the command is no longer run.
+    GaveUp { name: String, restarts: u32 },
+    /// The policy does not restart after this exit; the command is no
+    /// longer run.
+    Finished { name: String },
+    /// The watchdog was stopped and killed everything it was running.
+    Stopped,
+}
+
+struct Watched {
+    name: String,
+    heartbeat: PathBuf,
+    max_age: Duration,
+    spec: CommandSpec,
+}
+
+struct Running {
+    child: Option<Child>,
+    /// Heartbeats older than this don't count against the current run.
+    since: SystemTime,
+    started: Instant,
+    restarts: u32,
+    /// When to launch again after the last run ended; `None` while the
+    /// command runs or once the watchdog has given up on it.
+    relaunch_at: Option<Instant>,
+    backoff: Backoff,
+}
+
+/// Runs a set of commands and restarts any whose heartbeat file goes stale
+/// or which exit.
+///
+/// A heartbeat's age is the time since the file was last modified (see
+/// [`heartbeat`]), counted from no earlier than the process's start, so a
+/// fresh process has `max_age` to write its first beat. A stale process is
+/// killed along with all of its descendants ([`kill_tree`]) before the
+/// command is launched again.
+///
+/// Relaunching follows a [`RestartPolicy`] just like a
+/// [`Supervisor`](super::Supervisor), with a stale kill counting as a
+/// failure. The default is [`RestartPolicy::Backoff`] from one second to a
+/// minute, so a command that exits straight away or cannot be spawned is
+/// not relaunched on every check.
+///
+/// On Unix each command runs as the leader of its own process group. When
+/// it exits on its own, whatever is left in that group is killed too.
+///
+/// ```ignore
+/// let handle = Watchdog::new()
+///     .watch("indexer", run_dir.join("indexer.beat"), 30s, CommandSpec::new("indexer"))
+///     .events_to_jsonl(run_dir.join("watchdog.jsonl"))
+///     .start();
+/// ```
+pub struct Watchdog {
+    watched: Vec<Watched>,
+    interval: Duration,
+    policy: RestartPolicy,
+    max_restarts: Option<u32>,
+    restart_delay: Duration,
+    sinks: Vec<Sink<WatchdogEvent>>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Watchdog {
+    /// A watchdog with nothing to watch, checking once a second and
+    /// restarting with a backoff from one second to a minute.
+    pub fn new() -> Self {
+        Self {
+            watched: Vec::new(),
+            interval: Duration::from_secs(1),
+            policy: RestartPolicy::Backoff {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(60),
+            },
+            max_restarts: None,
+            restart_delay: Duration::from_secs(1),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Run `spec`, restarting it when `heartbeat` is older than `max_age`.
+    pub fn watch(
+        mut self,
+        name: impl Into<String>,
+        heartbeat: impl Into<PathBuf>,
+        max_age: Duration,
+        spec: CommandSpec,
+    ) -> Self {
+        self.watched.push(Watched {
+            name: name.into(),
+            heartbeat: heartbeat.into(),
+            max_age,
+            spec,
+        });
+        self
+    }
+
+    /// How often heartbeats and exits are checked.
+    pub fn check_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the restart policy for every watched command.
+    pub fn policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Give up on a command after this many restarts.
+    pub fn max_restarts(mut self, max: u32) -> Self {
+        self.max_restarts = Some(max);
+        self
+    }
+
+    /// Delay before restarting under `Always` / `OnFailure`.
+    pub fn restart_delay(mut self, delay: Duration) -> Self {
+        self.restart_delay = delay;
+        self
+    }
+
+    /// Send events to a channel.
+    pub fn events_to_channel(mut self, tx: Sender<WatchdogEvent>) -> Self {
+        self.sinks.push(Sink::Channel(tx));
+        self
+    }
+
+    /// Append events to a JSONL file.
+    pub fn events_to_jsonl(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sinks.push(Sink::Jsonl(JsonlWriter::new(path)));
+        self
+    }
+
+    /// Spawn every watched command and start checking on a background
+    /// thread.
+    pub fn start(self) -> WatchdogHandle {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let thread = thread::spawn(move || self.run(stop_rx));
+        WatchdogHandle {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        }
+    }
+
+    fn emit(&self, event: WatchdogEvent) {
+        for sink in &self.sinks {
+            sink.emit(&event);
+        }
+    }
+
+    fn run(self, stop_rx: mpsc::Receiver<()>) {
+        let mut running: Vec<Running> = self
+            .watched
+            .iter()
+            .map(|w| {
+                let mut r = Running {
+                    child: None,
+                    since: SystemTime::now(),
+                    started: Instant::now(),
+                    restarts: 0,
+                    relaunch_at: None,
+                    backoff: Backoff::new(self.policy, self.restart_delay),
+                };
+                self.launch(w, &mut r);
+                r
+            })
+            .collect();
+        loop {
+            if interruptible_sleep(&stop_rx, self.interval) {
+                for (w, r) in self.watched.iter().zip(&mut running) {
+                    if let Some(mut child) = r.child.take() {
+                        self.kill(w, &mut child);
+                    }
+                }
+                self.emit(WatchdogEvent::Stopped);
+                return;
+            }
+            for (w, r) in self.watched.iter().zip(&mut running) {
+                if r.child.is_some() {
+                    if let Some(status) = self.check(w, r) {
+                        self.ended(w, r, status);
+                    }
+                } else if r.relaunch_at.is_some_and(|at| Instant::now() >= at) {
+                    r.restarts += 1;
+                    self.launch(w, r);
+                }
+            }
+        }
+    }
+
+    /// Return `Some` with the exit status (`None` if killed) once the run
+    /// has ended.
+    fn check(&self, w: &Watched, r: &mut Running) -> Option<Option<ExitStatus>> {
+        let child = r.child.as_mut()?;
+        let pid = child.id();
+        if let Ok(Some(status)) = child.try_wait() {
+            r.child = None;
+            self.emit(WatchdogEvent::Exited {
+                name: w.name.clone(),
+                pid,
+                code: status.code(),
+            });
+            // The leader is gone, so its descendants have been re-parented;
+            // the process group still finds them.
+            if let Ok(descendants) = kill_group(pid)
+                && !descendants.is_empty()
+            {
+                self.emit(WatchdogEvent::Killed {
+                    name: w.name.clone(),
+                    pid,
+                    descendants,
+                });
+            }
+            return Some(Some(status));
+        }
+        let last = fs::metadata(&w.heartbeat)
+            .and_then(|m| m.modified())
+            .map_or(r.since, |beat| beat.max(r.since));
+        let age = SystemTime::now().duration_since(last).unwrap_or_default();
+        if age <= w.max_age {
+            return None;
+        }
+        self.emit(WatchdogEvent::Stale {
+            name: w.name.clone(),
+            pid,
+            age_ms: age.as_millis() as u64,
+        });
+        let mut child = r.child.take().expect("checked above");
+        self.kill(w, &mut child);
+        Some(None)
+    }
+
+    fn kill(&self, w: &Watched, child: &mut Child) {
+        let pid = child.id();
+        let descendants = kill_tree(pid).unwrap_or_else(|_| {
+            let _ = child.kill();
+            Vec::new()
+        });
+        let _ = child.wait();
+        self.emit(WatchdogEvent::Killed {
+            name: w.name.clone(),
+            pid,
+            descendants,
+        });
+    }
+
+    fn launch(&self, w: &Watched, r: &mut Running) {
+        r.since = SystemTime::now();
+        r.started = Instant::now();
+        r.relaunch_at = None;
+        let mut command = w.spec.to_command();
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        match command.spawn() {
+            Ok(child) => {
+                self.emit(WatchdogEvent::Started {
+                    name: w.name.clone(),
+                    pid: child.id(),
+                    restarts: r.restarts,
+                });
+                r.child = Some(child);
+            }
+            Err(e) => {
+                self.emit(WatchdogEvent::SpawnFailed {
+                    name: w.name.clone(),
+                    error: e.to_string(),
+                });
+                self.ended(w, r, None);
+            }
+        }
+    }
+
+    /// Schedule the next launch after a run ended with `status`, or give up.
+    fn ended(&self, w: &Watched, r: &mut Running, status: Option<ExitStatus>) {
+        if !self.policy.restarts(status) {
+            self.emit(WatchdogEvent::Finished {
+                name: w.name.clone(),
+            });
+            return;
+        }
+        if self.max_restarts.is_some_and(|max| r.restarts >= max) {
+            self.emit(WatchdogEvent::GaveUp {
+                name: w.name.clone(),
+                restarts: r.restarts,
+            });
+            return;
+        }
+        let delay = r.backoff.delay(r.started.elapsed());
+        self.emit(WatchdogEvent::Restarting {
+            name: w.name.clone(),
+            restarts: r.restarts + 1,
+            delay_ms: delay.as_millis() as u64,
+        });
+        r.relaunch_at = Some(Instant::now() + delay);
+    }
+}
+
+/// Handle to a running [`Watchdog`].
+///
+/// Dropping the handle stops the watchdog and kills the watched processes.
+pub struct WatchdogHandle {
+    stop_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchdogHandle {
+    /// Kill every watched process tree and wait for the watchdog thread.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(tx) = self.stop_tx.take() {
+            let _ = tx.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WatchdogHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::process::is_alive;
+    use crate::testutil::TempDir;
+    use std::time::Instant;
+
+    #[test]
+    fn test_stale_heartbeat_kills_tree_and_restarts() {
+        let tmp = TempDir::new("apiari-watchdog-test").unwrap();
+        let beat = tmp.path().join("worker.beat");
+        // Beats once, then hangs with a child of its own.
+        let script = format!("touch '{}'; sleep 30 & wait", beat.display());
+        let (tx, rx) = mpsc::channel();
+        let handle = Watchdog::new()
+            .watch(
+                "worker",
+                &beat,
+                Duration::from_millis(300),
+                CommandSpec::new("sh").arg("-c").arg(script),
+            )
+            .check_interval(Duration::from_millis(50))
+            .events_to_channel(tx)
+            .start();
+
+        let mut events = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            let Ok(event) = rx.recv_timeout(Duration::from_millis(100)) else {
+                continue;
+            };
+            let restarted = matches!(event, WatchdogEvent::Started { restarts: 1, .. });
+            events.push(event);
+            if restarted {
+                break;
+            }
+        }
+        handle.stop();
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| match e {
+                WatchdogEvent::Started { .. } => "started",
+                WatchdogEvent::Stale { .. } => "stale",
+                WatchdogEvent::Killed { .. } => "killed",
+                WatchdogEvent::Restarting { .. } => "restarting",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            ["started", "stale", "killed", "restarting", "started"]
+        );
+        let WatchdogEvent::Killed { descendants, .. } = &events[2] else {
+            unreachable!()
+        };
+        assert_eq!(descendants.len(), 1);
+        thread::sleep(Duration::from_millis(100));
+        assert!(!is_alive(descendants[0]));
+    }
+
+    #[test]
+    fn test_exiting_command_backs_off_and_leaves_nothing_behind() {
+        let tmp = TempDir::new("apiari-watchdog-test-exit").unwrap();
+        let pids = tmp.path().join("pids");
+        let script = format!("sleep 30 & echo $! >> '{}'; exit 3", pids.display());
+        let (tx, rx) = mpsc::channel();
+        let handle = Watchdog::new()
+            .watch(
+                "flaky",
+                tmp.path().join("flaky.beat"),
+                Duration::from_secs(30),
+                CommandSpec::new("sh").arg("-c").arg(script),
+            )
+            .check_interval(Duration::from_millis(20))
+            .policy(RestartPolicy::Backoff {
+                initial: Duration::from_millis(50),
+                max: Duration::from_secs(1),
+            })
+            .max_restarts(2)
+            .events_to_channel(tx)
+            .start();
+
+        let mut delays = Vec::new();
+        let gave_up = loop {
+            match rx.recv_timeout(Duration::from_secs(10)).unwrap() {
+                WatchdogEvent::Restarting { delay_ms, .. } => delays.push(delay_ms),
+                WatchdogEvent::GaveUp { restarts, .. } => break restarts,
+                _ => {}
+            }
+        };
+        handle.stop();
+        assert_eq!(gave_up, 2);
+        assert_eq!(delays, [50, 100]);
+        thread::sleep(Duration::from_millis(100));
+        let orphans: Vec<u32> = fs::read_to_string(&pids)
+            .unwrap()
+            .lines()
+            .map(|l| l.parse().unwrap())
+            .collect();
+        assert_eq!(orphans.len(), 3);
+        assert!(orphans.iter().all(|&pid| !is_alive(pid)), "{orphans:?}");
+    }
+
+    #[test]
+    fn test_heartbeat_writes_pid() {
+        let tmp = TempDir::new("apiari-watchdog-test-beat").unwrap();
+        let path = tmp.path().join("run/self.beat");
+        heartbeat(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().trim(),
+            std::process::id().to_string()
+        );
+    }
+}
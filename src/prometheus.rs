@@ -0,0 +1,315 @@
+//! Prometheus textfile export for the [metrics registry](crate::metrics).
+//!
+//! [`render`] formats a [`Registry`] in the Prometheus text exposition
+//! format. A [`TextfileExporter`] writes that to a `.prom` file on an
+//! interval, for node_exporter's textfile collector to pick up. Each write
+//! goes to a temp file in the same directory that is renamed into place, so
+//! the collector never reads a half-written file.
+//!
+//! Names are sanitized rather than rejected: characters Prometheus doesn't
+//! allow become `_`, an optional namespace is prefixed (`apiari_`), and
+//! counters get the conventional `_total` suffix. Label values are escaped.
+
+use crate::guard;
+use crate::metrics::{Kind, Labels, MetricFamily, Registry, Value};
+use crate::shutdown::ShutdownToken;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Default interval between textfile writes.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Render every metric in `registry`, with names prefixed by `namespace_`
+/// if `namespace` is non-empty.
+pub fn render(registry: &Registry, namespace: &str) -> String {
+    let mut out = String::new();
+    for family in registry.snapshot() {
+        render_family(&mut out, &family, namespace);
+    }
+    out
+}
+
+/// Render `registry` to `path`, atomically replacing it.
+pub fn write_textfile(registry: &Registry, namespace: &str, path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    // The collector only reads `*.prom`, so the temp name must not end in it.
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(format!(".tmp-{}", process::id()));
+    let tmp = guard::remove_file_on_drop(tmp);
+    fs::write(&*tmp, render(registry, namespace))?;
+    fs::rename(&*tmp, path)?;
+    tmp.disarm();
+    Ok(())
+}
+
+/// Periodically writes a registry to a Prometheus textfile.
+#[derive(Debug, Clone)]
+pub struct TextfileExporter {
+    registry: Registry,
+    path: PathBuf,
+    namespace: String,
+    interval: Duration,
+}
+
+impl TextfileExporter {
+    /// Export `registry` to `path` (which should end in `.prom`).
+    pub fn new(registry: &Registry, path: impl Into<PathBuf>) -> Self {
+        Self {
+            registry: registry.clone(),
+            path: path.into(),
+            namespace: "apiari".to_string(),
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+
+    /// Prefix for metric names (default `apiari`; empty for none).
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Write the file now, then every interval on a background thread until
+    /// the handle is dropped (which writes once more).
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the first write; later write errors are
+    /// ignored and retried on the next interval.
+    pub fn start(self) -> io::Result<ExporterHandle> {
+        write_textfile(&self.registry, &self.namespace, &self.path)?;
+        let shutdown = ShutdownToken::new();
+        let token = shutdown.clone();
+        let thread = thread::Builder::new()
+            .name("apiari-prometheus".to_string())
+            .spawn(move || {
+                while !token.wait_timeout(self.interval) {
+                    let _ = write_textfile(&self.registry, &self.namespace, &self.path);
+                }
+                let _ = write_textfile(&self.registry, &self.namespace, &self.path);
+            })?;
+        Ok(ExporterHandle {
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Stops a [`TextfileExporter`] when dropped.
+#[derive(Debug)]
+pub struct ExporterHandle {
+    shutdown: ShutdownToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ExporterHandle {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A valid metric name: `[a-zA-Z_:][a-zA-Z0-9_:]*`.
+pub fn metric_name(namespace: &str, name: &str) -> String {
+    let full = if namespace.is_empty() {
+        name.to_string()
+    } else {
+        format!("{namespace}_{name}")
+    };
+    sanitize(&full, true)
+}
+
+/// A valid label name: `[a-zA-Z_][a-zA-Z0-9_]*`, not starting with the
+/// reserved `__`.
+pub fn label_name(name: &str) -> String {
+    let name = sanitize(name, false);
+    match name.strip_prefix("__") {
+        Some(rest) => format!("_{}", rest.trim_start_matches('_')),
+        None => name,
+    }
+}
+
+fn sanitize(name: &str, allow_colon: bool) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || (allow_colon && c == ':') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn render_family(out: &mut String, family: &MetricFamily, namespace: &str) {
+    let mut name = metric_name(namespace, &family.name);
+    if family.kind == Kind::Counter && !name.ends_with("_total") {
+        name.push_str("_total");
+    }
+    let kind = match family.kind {
+        Kind::Counter => "counter",
+        Kind::Gauge => "gauge",
+        Kind::Histogram => "histogram",
+    };
+    if !family.help.is_empty() {
+        let help = family.help.replace('\\', "\\\\").replace('\n', "\\n");
+        let _ = writeln!(out, "# HELP {name} {help}");
+    }
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in &family.samples {
+        match value {
+            Value::Counter(n) => sample(out, &name, labels, None, &n.to_string()),
+            Value::Gauge(v) => sample(out, &name, labels, None, &number(*v)),
+            Value::Histogram(h) => {
+                let bucket = format!("{name}_bucket");
+                let mut cumulative = 0;
+                for (bound, count) in h.bounds.iter().zip(&h.counts) {
+                    cumulative += count;
+                    let le = number(*bound);
+                    sample(out, &bucket, labels, Some(&le), &cumulative.to_string());
+                }
+                sample(out, &bucket, labels, Some("+Inf"), &h.count.to_string());
+                sample(out, &format!("{name}_sum"), labels, None, &number(h.sum));
+                sample(
+                    out,
+                    &format!("{name}_count"),
+                    labels,
+                    None,
+                    &h.count.to_string(),
+                );
+            }
+        }
+    }
+}
+
+fn sample(out: &mut String, name: &str, labels: &Labels, le: Option<&str>, value: &str) {
+    out.push_str(name);
+    let mut pairs: Vec<(String, &str)> = labels
+        .iter()
+        .map(|(k, v)| (label_name(k), v.as_str()))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(("le".to_string(), le));
+    }
+    if !pairs.is_empty() {
+        out.push('{');
+        for (i, (key, value)) in pairs.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            let _ = write!(out, "{key}=\"{value}\"");
+        }
+        out.push('}');
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+fn number(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v.is_infinite() {
+        if v > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_render_exposition_format() {
+        let registry = Registry::new();
+        registry
+            .counter_with(
+                "jobs.run",
+                "Jobs run\nby outcome",
+                &[("outcome", "fail \"x\""), ("__kind", "a")],
+            )
+            .add(3);
+        registry.gauge("9queue-depth", "").set(f64::INFINITY);
+        let latency = registry.histogram_with("latency_seconds", "Latency", &[0.1, 1.0], &[]);
+        latency.observe(0.05);
+        latency.observe(0.5);
+        latency.observe(3.0);
+
+        let text = render(&registry, "apiari");
+        let expected = "\
+# TYPE apiari_9queue_depth gauge
+apiari_9queue_depth +Inf
+# HELP apiari_jobs_run_total Jobs run\\nby outcome
+# TYPE apiari_jobs_run_total counter
+apiari_jobs_run_total{_kind=\"a\",outcome=\"fail \\\"x\\\"\"} 3
+# HELP apiari_latency_seconds Latency
+# TYPE apiari_latency_seconds histogram
+apiari_latency_seconds_bucket{le=\"0.1\"} 1
+apiari_latency_seconds_bucket{le=\"1\"} 2
+apiari_latency_seconds_bucket{le=\"+Inf\"} 3
+apiari_latency_seconds_sum 3.55
+apiari_latency_seconds_count 3
+";
+        assert_eq!(text, expected);
+        assert_eq!(metric_name("", "9lives"), "_9lives");
+        assert_eq!(label_name("a-b"), "a_b");
+    }
+
+    #[test]
+    fn test_textfile_exporter_rewrites_file() {
+        let tmp = TempDir::new("apiari-prometheus-test-textfile").unwrap();
+        let path = tmp.join("textfile/apiari.prom");
+        let registry = Registry::new();
+        let counter = registry.counter("requests", "");
+        let handle = TextfileExporter::new(&registry, &path)
+            .interval(Duration::from_millis(10))
+            .start()
+            .unwrap();
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("apiari_requests_total 0")
+        );
+        counter.add(5);
+        crate::poll::poll_until(Duration::from_millis(5), Duration::from_secs(5), || {
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("apiari_requests_total 5")
+                .then_some(())
+        })
+        .unwrap();
+        counter.inc();
+        drop(handle);
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("apiari_requests_total 6")
+        );
+        let leftovers = fs::read_dir(tmp.join("textfile")).unwrap().count();
+        assert_eq!(leftovers, 1);
+    }
+}
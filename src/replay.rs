@@ -0,0 +1,364 @@
+//! Deterministic record and replay of event-driven logic.
+//!
+//! To answer "why did the supervisor do X", record every record it polled
+//! and play the same sequence back through the same handler. A [`Recorder`]
+//! appends each record to a JSONL recording with a sequence number, the
+//! time from its [`Clock`], and the name of the source it came from (so
+//! several inputs interleave in the order they were seen).
+//!
+//! A [`Replay`] feeds the recording back in order. Before each record its
+//! [`ReplayClock`] is set to the recorded time, so logic that takes a
+//! `Clock` sees exactly the times it saw live. Playback runs as fast as
+//! possible or paced at a multiple of real time ([`Speed`]), and stops at
+//! breakpoints: predicates on the next record, checked before it is
+//! delivered. Calling [`Replay::run`] again resumes from there.
+
+use crate::clock::unix_ms;
+use crate::clock::{Clock, SystemClock};
+use crate::ipc::{JsonlReader, JsonlWriter};
+use crate::vfs::FileSystem;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// One recorded record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry<T> {
+    /// Position in the recording, from 0.
+    pub seq: u64,
+    /// When the record was seen, in milliseconds since the Unix epoch.
+    pub ts: u64,
+    /// Which input the record came from.
+    pub source: String,
+    pub record: T,
+}
+
+impl<T> Entry<T> {
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.ts)
+    }
+}
+
+/// Appends polled records to a recording.
+pub struct Recorder<C = SystemClock> {
+    writer: JsonlWriter<Entry<serde_json::Value>>,
+    clock: C,
+    next_seq: u64,
+}
+
+impl Recorder {
+    /// Record to `path`, continuing its sequence numbers if it exists.
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::with_clock(path, SystemClock)
+    }
+}
+
+impl<C: Clock> Recorder<C> {
+    /// Like [`new`](Recorder::new), timestamping records with `clock`.
+    pub fn with_clock(path: impl Into<PathBuf>, clock: C) -> io::Result<Self> {
+        let path = path.into();
+        let next_seq = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().count() as u64,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            writer: JsonlWriter::new(path),
+            clock,
+            next_seq,
+        })
+    }
+
+    /// Append `record` as seen from `source`.
+    pub fn record<T: Serialize>(&mut self, source: &str, record: &T) -> io::Result<()> {
+        let ts = unix_ms(self.clock.now());
+        let entry = Entry {
+            seq: self.next_seq,
+            ts,
+            source: source.to_string(),
+            record: serde_json::to_value(record).map_err(io::Error::other)?,
+        };
+        self.writer.append(&entry)?;
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Poll `reader` and record everything it returns under `source`. Use
+    /// in place of `reader.poll()`.
+    pub fn poll<T, F>(&mut self, source: &str, reader: &mut JsonlReader<T, F>) -> io::Result<Vec<T>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FileSystem,
+    {
+        let records = reader.poll()?;
+        for record in &records {
+            self.record(source, record)?;
+        }
+        Ok(records)
+    }
+}
+
+/// A [`Clock`] that replays recorded time. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ReplayClock(Arc<Mutex<SystemTime>>);
+
+impl ReplayClock {
+    fn set(&self, to: SystemTime) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = to;
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// How fast [`Replay::run`] delivers records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// No waiting between records.
+    Instant,
+    /// Wait the recorded gap between records divided by this factor
+    /// (`1.0` is real time, `10.0` ten times faster).
+    Realtime(f64),
+}
+
+/// Why [`Replay::run`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// Every record was delivered.
+    Finished,
+    /// The breakpoint with this index (in the order added) matched the next
+    /// record, with sequence number `seq`, which has not been delivered.
+    Breakpoint { breakpoint: usize, seq: u64 },
+}
+
+type Breakpoint<T> = Box<dyn Fn(&Entry<T>) -> bool + Send>;
+
+/// Plays a recording back through a handler.
+pub struct Replay<T> {
+    entries: Vec<Entry<T>>,
+    position: usize,
+    speed: Speed,
+    breakpoints: Vec<Breakpoint<T>>,
+    clock: ReplayClock,
+    /// Set after stopping at a breakpoint, so resuming delivers that record.
+    resume_past_break: bool,
+}
+
+impl<T: DeserializeOwned> Replay<T> {
+    /// Load the recording at `path`. The clock starts at the first record's
+    /// time.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::InvalidData` if a line isn't an [`Entry`] of
+    /// `T`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(
+                serde_json::from_str(&line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            );
+        }
+        Ok(Self::from_entries(entries))
+    }
+}
+
+impl<T> Replay<T> {
+    /// Replay entries already in memory.
+    pub fn from_entries(entries: Vec<Entry<T>>) -> Self {
+        let start = entries.first().map_or(UNIX_EPOCH, Entry::time);
+        Self {
+            entries,
+            position: 0,
+            speed: Speed::Instant,
+            breakpoints: Vec::new(),
+            clock: ReplayClock(Arc::new(Mutex::new(start))),
+            resume_past_break: false,
+        }
+    }
+
+    pub fn speed(mut self, speed: Speed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Stop before delivering any record matching `predicate`.
+    pub fn break_on(mut self, predicate: impl Fn(&Entry<T>) -> bool + Send + 'static) -> Self {
+        self.breakpoints.push(Box::new(predicate));
+        self
+    }
+
+    /// The clock to hand to the logic under replay.
+    pub fn clock(&self) -> ReplayClock {
+        self.clock.clone()
+    }
+
+    pub fn entries(&self) -> &[Entry<T>] {
+        &self.entries
+    }
+
+    /// The next record to be delivered, if any.
+    pub fn peek(&self) -> Option<&Entry<T>> {
+        self.entries.get(self.position)
+    }
+
+    /// Deliver records to `handler` until the end or a breakpoint.
+    ///
+    /// # Errors
+    ///
+    /// Stops at and returns the first handler error; the failing record
+    /// counts as delivered.
+    pub fn run(
+        &mut self,
+        mut handler: impl FnMut(&Entry<T>) -> io::Result<()>,
+    ) -> io::Result<Stop> {
+        while let Some(entry) = self.entries.get(self.position) {
+            if !std::mem::take(&mut self.resume_past_break)
+                && let Some(breakpoint) = self.breakpoints.iter().position(|b| b(entry))
+            {
+                self.resume_past_break = true;
+                return Ok(Stop::Breakpoint {
+                    breakpoint,
+                    seq: entry.seq,
+                });
+            }
+            self.deliver(&mut handler)?;
+        }
+        Ok(Stop::Finished)
+    }
+
+    /// Deliver just the next record, ignoring breakpoints. Returns `false`
+    /// at the end of the recording.
+    pub fn step(
+        &mut self,
+        mut handler: impl FnMut(&Entry<T>) -> io::Result<()>,
+    ) -> io::Result<bool> {
+        if self.position == self.entries.len() {
+            return Ok(false);
+        }
+        self.resume_past_break = false;
+        self.deliver(&mut handler)?;
+        Ok(true)
+    }
+
+    fn deliver(&mut self, handler: &mut impl FnMut(&Entry<T>) -> io::Result<()>) -> io::Result<()> {
+        let entry = &self.entries[self.position];
+        if let Speed::Realtime(factor) = self.speed
+            && factor > 0.0
+            && let Ok(gap) = entry.time().duration_since(self.clock.now())
+        {
+            thread::sleep(gap.div_f64(factor));
+        }
+        self.clock.set(entry.time());
+        self.position += 1;
+        handler(entry)
+    }
+}
+
+impl<T> fmt::Debug for Replay<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replay")
+            .field("entries", &self.entries.len())
+            .field("position", &self.position)
+            .field("speed", &self.speed)
+            .field("breakpoints", &self.breakpoints.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{MockClock, TempDir};
+    use serde_json::{Value, json};
+    use std::time::Instant;
+
+    #[test]
+    fn test_record_and_replay_with_breakpoint() {
+        let tmp = TempDir::new("apiari-replay-test").unwrap();
+        let inbox = tmp.path().join("inbox.jsonl");
+        let recording = tmp.path().join("recording.jsonl");
+        let writer = JsonlWriter::new(&inbox);
+        let mut reader = JsonlReader::<Value>::new(&inbox);
+
+        let clock = MockClock::at_unix_secs(1_000);
+        let mut recorder = Recorder::with_clock(&recording, clock.clone()).unwrap();
+        for n in 0..4 {
+            writer.append(&json!({"n": n})).unwrap();
+            assert_eq!(recorder.poll("inbox", &mut reader).unwrap().len(), 1);
+            clock.advance(Duration::from_secs(10));
+        }
+        recorder.record("timer", &json!("tick")).unwrap();
+
+        let mut replay = Replay::<Value>::load(&recording)
+            .unwrap()
+            .break_on(|e| e.record["n"] == 2);
+        let clock = replay.clock();
+        let mut seen = Vec::new();
+        let mut handler = |e: &Entry<Value>| {
+            let secs = clock.now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            seen.push((e.seq, e.source.clone(), secs));
+            Ok(())
+        };
+        assert_eq!(
+            replay.run(&mut handler).unwrap(),
+            Stop::Breakpoint {
+                breakpoint: 0,
+                seq: 2
+            }
+        );
+        assert_eq!(replay.peek().unwrap().record, json!({"n": 2}));
+        assert_eq!(replay.run(&mut handler).unwrap(), Stop::Finished);
+        assert_eq!(
+            seen,
+            [
+                (0, "inbox".to_string(), 1_000),
+                (1, "inbox".to_string(), 1_010),
+                (2, "inbox".to_string(), 1_020),
+                (3, "inbox".to_string(), 1_030),
+                (4, "timer".to_string(), 1_040),
+            ]
+        );
+
+        // Recording again continues the sequence.
+        let mut recorder = Recorder::new(&recording).unwrap();
+        recorder.record("timer", &json!("tick")).unwrap();
+        assert_eq!(
+            Replay::<Value>::load(&recording).unwrap().entries()[5].seq,
+            5
+        );
+    }
+
+    #[test]
+    fn test_realtime_speed_paces_delivery() {
+        let entries = (0..3)
+            .map(|i| Entry {
+                seq: i,
+                ts: 1_000 + i * 100,
+                source: "s".into(),
+                record: i,
+            })
+            .collect();
+        let mut replay = Replay::from_entries(entries).speed(Speed::Realtime(2.0));
+        let started = Instant::now();
+        assert!(replay.step(|_| Ok(())).unwrap());
+        assert_eq!(replay.run(|_| Ok(())).unwrap(), Stop::Finished);
+        // Two 100ms gaps at double speed.
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(!replay.step(|_| Ok(())).unwrap());
+    }
+}
@@ -0,0 +1,380 @@
+//! Dispatch JSONL messages to handlers by variant.
+//!
+//! Instead of one large `match` in every consumer, a [`Router`] maps message
+//! tags to handlers. The tag is read from the message's serialized form:
+//!
+//! - an internally tagged enum's tag field (`"type"` by default, see
+//!   [`Router::tag_field`]),
+//! - otherwise the variant name of an externally tagged enum (serde's
+//!   default: `"Stop"` or `{"Start": {...}}`).
+//!
+//! Every handler registered for a tag runs, in registration order; messages
+//! with no handler go to the [`otherwise`](Router::otherwise) handler, if
+//! any. Hooks added with [`inspect`](Router::inspect) see every message
+//! before dispatch (logging, metrics), and [`on_error`](Router::on_error)
+//! hooks see every handler or read error.
+//!
+//! Drive the router with [`poll`](Router::poll) from an existing loop, or
+//! hand it a reader with [`tail`](Router::tail) to poll on a background
+//! thread.
+
+use crate::ipc::JsonlReader;
+use crate::shutdown::ShutdownToken;
+use crate::vfs::FileSystem;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type Handler<Msg> = Box<dyn FnMut(&Msg) -> io::Result<()> + Send>;
+type Inspect<Msg> = Box<dyn FnMut(&str, &Msg) + Send>;
+type OnError<Msg> = Box<dyn FnMut(&io::Error, Option<&Msg>) + Send>;
+
+/// Counts from one [`Router::poll`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Routed {
+    /// Messages that reached at least one handler (including `otherwise`).
+    pub handled: usize,
+    /// Messages no handler was registered for.
+    pub unhandled: usize,
+    /// Messages for which a handler returned an error.
+    pub failed: usize,
+}
+
+/// Handler registry for messages of type `Msg`.
+///
+/// ```ignore
+/// let mut router = Router::<Event>::new()
+///     .on("SessionStarted", |e| track_start(e))
+///     .on("SessionEnded", |e| track_end(e))
+///     .otherwise(|_| Ok(()))
+///     .inspect(|tag, _| log::debug!("event {tag}"))
+///     .on_error(|err, _| eprintln!("event handler failed: {err}"));
+/// router.poll(&mut reader)?;
+/// ```
+pub struct Router<Msg> {
+    handlers: HashMap<String, Vec<Handler<Msg>>>,
+    otherwise: Option<Handler<Msg>>,
+    inspect: Vec<Inspect<Msg>>,
+    on_error: Vec<OnError<Msg>>,
+    tag_field: String,
+}
+
+impl<Msg: Serialize> Default for Router<Msg> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Msg: Serialize> Router<Msg> {
+    /// Create a router with no handlers.
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+            otherwise: None,
+            inspect: Vec::new(),
+            on_error: Vec::new(),
+            tag_field: "type".to_string(),
+        }
+    }
+
+    /// Field holding the tag of an internally tagged enum (default `type`).
+    pub fn tag_field(mut self, field: impl Into<String>) -> Self {
+        self.tag_field = field.into();
+        self
+    }
+
+    /// Register a handler for messages tagged `tag`.
+    pub fn on(
+        mut self,
+        tag: impl Into<String>,
+        handler: impl FnMut(&Msg) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        self.handlers
+            .entry(tag.into())
+            .or_default()
+            .push(Box::new(handler));
+        self
+    }
+
+    /// Register the catch-all for messages without a specific handler.
+    pub fn otherwise(
+        mut self,
+        handler: impl FnMut(&Msg) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        self.otherwise = Some(Box::new(handler));
+        self
+    }
+
+    /// Call `hook` with the tag of every message before it is dispatched.
+    pub fn inspect(mut self, hook: impl FnMut(&str, &Msg) + Send + 'static) -> Self {
+        self.inspect.push(Box::new(hook));
+        self
+    }
+
+    /// Call `hook` for every handler error (with the message) and read
+    /// error (without one).
+    pub fn on_error(mut self, hook: impl FnMut(&io::Error, Option<&Msg>) + Send + 'static) -> Self {
+        self.on_error.push(Box::new(hook));
+        self
+    }
+
+    /// The tag `msg` is routed by, or `""` if it has none.
+    pub fn tag_of(&self, msg: &Msg) -> String {
+        serde_json::to_value(msg)
+            .ok()
+            .and_then(|value| tag(&value, &self.tag_field))
+            .unwrap_or_default()
+    }
+
+    /// Route one message.
+    ///
+    /// Returns `Ok(false)` if no handler (not even `otherwise`) took it.
+    /// All matching handlers run even if one fails; the first error is
+    /// returned after being passed to the `on_error` hooks.
+    pub fn dispatch(&mut self, msg: &Msg) -> io::Result<bool> {
+        let tag = self.tag_of(msg);
+        for hook in &mut self.inspect {
+            hook(&tag, msg);
+        }
+        let handlers = match self.handlers.get_mut(&tag) {
+            Some(handlers) => handlers.as_mut_slice(),
+            None => match &mut self.otherwise {
+                Some(handler) => std::slice::from_mut(handler),
+                None => return Ok(false),
+            },
+        };
+        let mut first = None;
+        for handler in handlers {
+            if let Err(e) = handler(msg) {
+                for hook in &mut self.on_error {
+                    hook(&e, Some(msg));
+                }
+                first.get_or_insert(e);
+            }
+        }
+        first.map_or(Ok(true), Err)
+    }
+
+    /// Dispatch every message appended to `reader` since the last poll.
+    ///
+    /// Handler errors are counted (and passed to `on_error`) rather than
+    /// returned; only a failure to read is an error.
+    pub fn poll<F: FileSystem>(&mut self, reader: &mut JsonlReader<Msg, F>) -> io::Result<Routed>
+    where
+        Msg: DeserializeOwned,
+    {
+        let mut routed = Routed::default();
+        for msg in reader.poll()? {
+            match self.dispatch(&msg) {
+                Ok(true) => routed.handled += 1,
+                Ok(false) => routed.unhandled += 1,
+                Err(_) => {
+                    routed.handled += 1;
+                    routed.failed += 1;
+                }
+            }
+        }
+        Ok(routed)
+    }
+}
+
+impl<Msg> Router<Msg>
+where
+    Msg: Serialize + DeserializeOwned + Send + 'static,
+{
+    /// Poll `reader` every `interval` on a background thread until the
+    /// returned handle is dropped. Read errors go to the `on_error` hooks
+    /// and polling continues.
+    pub fn tail<F>(mut self, mut reader: JsonlReader<Msg, F>, interval: Duration) -> RouterThread
+    where
+        F: FileSystem + Send + 'static,
+    {
+        let shutdown = ShutdownToken::new();
+        let token = shutdown.clone();
+        let thread = thread::spawn(move || {
+            loop {
+                if let Err(e) = self.poll(&mut reader) {
+                    for hook in &mut self.on_error {
+                        hook(&e, None);
+                    }
+                }
+                if token.wait_timeout(interval) {
+                    break;
+                }
+            }
+        });
+        RouterThread {
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<Msg> fmt::Debug for Router<Msg> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tags: Vec<&String> = self.handlers.keys().collect();
+        tags.sort();
+        f.debug_struct("Router")
+            .field("tags", &tags)
+            .field("otherwise", &self.otherwise.is_some())
+            .field("tag_field", &self.tag_field)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stops a [`Router::tail`] thread when dropped.
+#[derive(Debug)]
+pub struct RouterThread {
+    shutdown: ShutdownToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for RouterThread {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn tag(value: &Value, field: &str) -> Option<String> {
+    match value {
+        Value::String(variant) => Some(variant.clone()),
+        Value::Object(map) => match map.get(field) {
+            Some(Value::String(tag)) => Some(tag.clone()),
+            _ if map.len() == 1 => map.keys().next().cloned(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::JsonlWriter;
+    use crate::testutil::TempDir;
+    use serde::Deserialize;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Event {
+        Ping,
+        Start { id: u32 },
+        Stop(u32),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "kind")]
+    enum Tagged {
+        Hello { name: String },
+    }
+
+    #[test]
+    fn test_tag_extraction() {
+        let router = Router::<Event>::new();
+        assert_eq!(router.tag_of(&Event::Ping), "Ping");
+        assert_eq!(router.tag_of(&Event::Start { id: 1 }), "Start");
+        assert_eq!(router.tag_of(&Event::Stop(2)), "Stop");
+        let tagged = Router::<Tagged>::new().tag_field("kind");
+        let hello = Tagged::Hello {
+            name: "x".to_string(),
+        };
+        assert_eq!(tagged.tag_of(&hello), "Hello");
+        assert_eq!(
+            tag(&serde_json::json!({"type": "a", "x": 1}), "type").as_deref(),
+            Some("a")
+        );
+    }
+
+    #[test]
+    fn test_dispatch_handlers_fallback_and_hooks() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = |label: &'static str| {
+            let seen = Arc::clone(&seen);
+            move |e: &Event| {
+                seen.lock().unwrap().push(format!("{label}:{e:?}"));
+                Ok(())
+            }
+        };
+        let inspected = Arc::new(Mutex::new(Vec::new()));
+        let errors = Arc::new(Mutex::new(0));
+        let mut router = Router::new()
+            .on("Start", log("a"))
+            .on("Start", log("b"))
+            .on("Stop", |_| Err(io::Error::other("stop failed")))
+            .inspect({
+                let inspected = Arc::clone(&inspected);
+                move |tag, _| inspected.lock().unwrap().push(tag.to_string())
+            })
+            .on_error({
+                let errors = Arc::clone(&errors);
+                move |e, msg| {
+                    assert_eq!(e.to_string(), "stop failed");
+                    assert_eq!(msg, Some(&Event::Stop(3)));
+                    *errors.lock().unwrap() += 1;
+                }
+            });
+
+        assert!(router.dispatch(&Event::Start { id: 1 }).unwrap());
+        assert!(!router.dispatch(&Event::Ping).unwrap());
+        assert!(router.dispatch(&Event::Stop(3)).is_err());
+        router = router.otherwise(log("other"));
+        assert!(router.dispatch(&Event::Ping).unwrap());
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["a:Start { id: 1 }", "b:Start { id: 1 }", "other:Ping"]
+        );
+        assert_eq!(
+            *inspected.lock().unwrap(),
+            ["Start", "Ping", "Stop", "Ping"]
+        );
+        assert_eq!(*errors.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_poll_and_tail() {
+        let tmp = TempDir::new("apiari-router-test").unwrap();
+        let path = tmp.join("events.jsonl");
+        let writer = JsonlWriter::new(&path);
+        for event in [Event::Ping, Event::Start { id: 1 }, Event::Stop(1)] {
+            writer.append(&event).unwrap();
+        }
+
+        let mut router = Router::new()
+            .on("Start", |_| Ok(()))
+            .on("Stop", |_| Err(io::Error::other("nope")));
+        let mut reader = JsonlReader::new(&path);
+        let routed = router.poll(&mut reader).unwrap();
+        assert_eq!(
+            routed,
+            Routed {
+                handled: 2,
+                unhandled: 1,
+                failed: 1
+            }
+        );
+
+        let (tx, rx) = mpsc::channel();
+        let tail = Router::new()
+            .on("Start", move |e: &Event| {
+                tx.send(e.clone()).unwrap();
+                Ok(())
+            })
+            .tail(reader, Duration::from_millis(10));
+        writer.append(&Event::Start { id: 7 }).unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::Start { id: 7 }
+        );
+        drop(tail);
+    }
+}
@@ -0,0 +1,620 @@
+//! Periodic jobs on a background thread.
+//!
+//! A [`Scheduler`] runs named [`Job`]s on fixed intervals or on cron
+//! expressions. Each run happens on its own thread, so a slow job never
+//! delays the others, but a job never overlaps itself: if it is still
+//! running when it comes due again, that occurrence is skipped and counted.
+//!
+//! Shutdown goes through a [`ShutdownToken`]: once it fires the scheduler
+//! stops dispatching, waits for in-flight runs, and exits.
+//!
+//! Cron expressions use the classic five fields (`minute hour day-of-month
+//! month day-of-week`) with `*`, lists, ranges, and `/` steps, evaluated in
+//! UTC. The `@hourly`, `@daily`, `@weekly`, `@monthly`, and `@yearly`
+//! shorthands are accepted too.
+
+use crate::clock::now_ns;
+use crate::parse::ParseError;
+use crate::shutdown::ShutdownToken;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// When a job runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every interval, starting one interval after the scheduler starts.
+    Every(Duration),
+    /// Whenever the cron expression matches (UTC, minute resolution).
+    Cron(CronExpr),
+}
+
+impl Schedule {
+    /// Parse a cron expression into a schedule.
+    pub fn cron(expr: &str) -> Result<Self, ParseError> {
+        expr.parse().map(Schedule::Cron)
+    }
+}
+
+/// A parsed five-field cron expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Both day fields were restricted, so either may match (cron semantics).
+    day_or: bool,
+}
+
+impl FromStr for CronExpr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let expanded = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(ParseError::new(s, "expected five fields"));
+        };
+        let field = |text: &str, min: u32, max: u32| {
+            parse_field(text, min, max).ok_or_else(|| ParseError::new(s, "invalid field"))
+        };
+
+        let mut weekdays = field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            day_or: day != "*" && weekday != "*",
+        })
+    }
+}
+
+impl CronExpr {
+    /// Return the first matching minute strictly after `time`, or `None`
+    /// if nothing matches within the next few years (e.g. `0 0 30 2 *`).
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let start = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let limit = start + 5 * 366 * 86_400;
+        let mut secs = (start / 60 + 1) * 60;
+        while secs < limit {
+            let days = (secs / 86_400) as i64;
+            let (year, month, day) = civil_from_days(days);
+            if !bit(self.months, month) {
+                let (y, m) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                secs = days_from_civil(y, m, 1) as u64 * 86_400;
+                continue;
+            }
+            let weekday = (days + 4).rem_euclid(7) as u32; // 1970-01-01 was a Thursday.
+            let dom = bit(self.days, day);
+            let dow = bit(self.weekdays, weekday);
+            let day_matches = if self.day_or { dom || dow } else { dom && dow };
+            if !day_matches {
+                secs = (days as u64 + 1) * 86_400;
+                continue;
+            }
+            if !bit(self.hours, (secs % 86_400 / 3_600) as u32) {
+                secs = (secs / 3_600 + 1) * 3_600;
+                continue;
+            }
+            if bit(self.minutes, (secs % 3_600 / 60) as u32) {
+                return Some(UNIX_EPOCH + Duration::from_secs(secs));
+            }
+            secs += 60;
+        }
+        None
+    }
+}
+
+fn bit(mask: u64, n: u32) -> bool {
+    mask & (1 << n) != 0
+}
+
+fn parse_field(text: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0u64;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((lo, hi)) = range.split_once('-') {
+            (lo.parse().ok()?, hi.parse().ok()?)
+        } else {
+            let n = range.parse().ok()?;
+            // `5/15` means "from 5 to the end, every 15".
+            (n, if part.contains('/') { max } else { n })
+        };
+        if lo < min || hi > max || lo > hi {
+            return None;
+        }
+        for n in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Some(mask)
+}
+
+/// Days since 1970-01-01 to (year, month, day), proleptic Gregorian.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// (year, month, day) to days since 1970-01-01, proleptic Gregorian.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let m = i64::from(month);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// A named task and its schedule.
+pub struct Job {
+    name: String,
+    schedule: Schedule,
+    jitter: Duration,
+    task: Box<dyn FnMut() + Send>,
+}
+
+impl Job {
+    /// Create a job that runs `task` on `schedule`.
+    pub fn new(
+        name: impl Into<String>,
+        schedule: Schedule,
+        task: impl FnMut() + Send + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            schedule,
+            jitter: Duration::ZERO,
+            task: Box::new(task),
+        }
+    }
+
+    /// Delay each run by a random amount up to `max`, so that many
+    /// processes on the same schedule don't fire in lockstep.
+    pub fn jitter(mut self, max: Duration) -> Self {
+        self.jitter = max;
+        self
+    }
+}
+
+/// Run counters for one job, as returned by [`SchedulerHandle::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobStats {
+    pub name: String,
+    /// Runs that have completed (including ones that panicked).
+    pub runs: u64,
+    /// Occurrences skipped because the previous run was still going.
+    pub skipped: u64,
+}
+
+/// Runs registered jobs until a [`ShutdownToken`] fires.
+///
+/// ```ignore
+/// let shutdown = ShutdownToken::new();
+/// let handle = Scheduler::new()
+///     .job(Job::new("heartbeat", Schedule::Every(5s), heartbeat))
+///     .job(Job::new("gc", Schedule::cron("0 3 * * *")?, gc).jitter(10min))
+///     .start(shutdown.clone());
+/// ```
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    /// Create an empty scheduler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a job.
+    pub fn job(mut self, job: Job) -> Self {
+        self.jobs.push(job);
+        self
+    }
+
+    /// Start dispatching on a background thread.
+    pub fn start(self, shutdown: ShutdownToken) -> SchedulerHandle {
+        let started = Instant::now();
+        let seed = now_ns();
+        let mut rng = Rng(seed | 1);
+
+        let entries: Vec<Entry> = self
+            .jobs
+            .into_iter()
+            .map(|job| {
+                let mut entry = Entry {
+                    stats: Arc::new(Counters::default()),
+                    running: Arc::new(AtomicBool::new(false)),
+                    task: Arc::new(Mutex::new(job.task)),
+                    in_flight: None,
+                    slot: None,
+                    wall: None,
+                    next: None,
+                    name: job.name,
+                    schedule: job.schedule,
+                    jitter: job.jitter,
+                };
+                let slot = entry.next_slot(started, SystemTime::now());
+                entry.arm(slot, &mut rng);
+                entry
+            })
+            .collect();
+        let stats = entries
+            .iter()
+            .map(|e| (e.name.clone(), Arc::clone(&e.stats)))
+            .collect();
+
+        let token = shutdown.clone();
+        let thread = thread::spawn(move || run(entries, token, rng));
+        SchedulerHandle {
+            shutdown,
+            stats,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    runs: AtomicU64,
+    skipped: AtomicU64,
+}
+
+struct Entry {
+    name: String,
+    schedule: Schedule,
+    jitter: Duration,
+    task: Arc<Mutex<Box<dyn FnMut() + Send>>>,
+    running: Arc<AtomicBool>,
+    in_flight: Option<JoinHandle<()>>,
+    stats: Arc<Counters>,
+    /// When the job is next due before jitter; interval cadence follows it.
+    slot: Option<Instant>,
+    /// The wall-clock time of `slot`, for cron entries.
+    wall: Option<SystemTime>,
+    /// `slot` plus this run's jitter.
+    next: Option<Instant>,
+}
+
+impl Entry {
+    /// The slot after the current one (or the first, before any), without
+    /// jitter. `wall` is the wall-clock reading at `now`.
+    fn next_slot(&mut self, now: Instant, wall: SystemTime) -> Option<Instant> {
+        match &self.schedule {
+            Schedule::Every(interval) => Some(match self.slot {
+                // Keep a steady cadence, but don't replay occurrences missed
+                // while the process was suspended.
+                Some(slot) if slot + *interval > now => slot + *interval,
+                _ => now + *interval,
+            }),
+            Schedule::Cron(expr) => {
+                // Strictly after the occurrence that just fired, even if the
+                // wall clock reads a little earlier than it on waking.
+                let after = self.wall.map_or(wall, |fired| fired.max(wall));
+                self.wall = expr.next_after(after);
+                self.wall
+                    .map(|next| now + next.duration_since(wall).unwrap_or_default())
+            }
+        }
+    }
+
+    /// Schedule the next run at `slot` plus fresh jitter.
+    fn arm(&mut self, slot: Option<Instant>, rng: &mut Rng) {
+        self.slot = slot;
+        self.next = slot.map(|slot| slot + rng.below(self.jitter));
+    }
+
+    /// Schedule the run after the one that just became due.
+    fn rearm(&mut self, now: Instant, wall: SystemTime, rng: &mut Rng) {
+        let slot = self.next_slot(now, wall);
+        self.arm(slot, rng);
+    }
+
+    fn dispatch(&mut self) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            self.stats.skipped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if let Some(prev) = self.in_flight.take() {
+            let _ = prev.join();
+        }
+        let task = Arc::clone(&self.task);
+        let running = Arc::clone(&self.running);
+        let stats = Arc::clone(&self.stats);
+        self.in_flight = Some(thread::spawn(move || {
+            let mut task = task.lock().unwrap_or_else(|e| e.into_inner());
+            // A panicking job is counted as a run and scheduled again.
+            let _ = panic::catch_unwind(AssertUnwindSafe(&mut **task));
+            stats.runs.fetch_add(1, Ordering::Relaxed);
+            running.store(false, Ordering::SeqCst);
+        }));
+    }
+}
+
+fn run(mut entries: Vec<Entry>, shutdown: ShutdownToken, mut rng: Rng) {
+    loop {
+        let due = entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.next.map(|t| (i, t)))
+            .min_by_key(|(_, t)| *t);
+        let Some((index, at)) = due else {
+            shutdown.wait();
+            break;
+        };
+        if shutdown.wait_timeout(at.saturating_duration_since(Instant::now())) {
+            break;
+        }
+        let entry = &mut entries[index];
+        entry.dispatch();
+        entry.rearm(Instant::now(), SystemTime::now(), &mut rng);
+    }
+    for entry in &mut entries {
+        if let Some(thread) = entry.in_flight.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Handle to a running [`Scheduler`].
+pub struct SchedulerHandle {
+    shutdown: ShutdownToken,
+    stats: Vec<(String, Arc<Counters>)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    /// Current run counters for every job, in registration order.
+    pub fn stats(&self) -> Vec<JobStats> {
+        self.stats
+            .iter()
+            .map(|(name, c)| JobStats {
+                name: name.clone(),
+                runs: c.runs.load(Ordering::Relaxed),
+                skipped: c.skipped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Trigger the shutdown token and wait for in-flight runs to finish.
+    pub fn stop(mut self) {
+        self.shutdown.shutdown();
+        self.join_thread();
+    }
+
+    /// Wait for the scheduler to exit after the token fires elsewhere.
+    pub fn join(mut self) {
+        self.join_thread();
+    }
+
+    fn join_thread(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Small xorshift generator; jitter only needs to be spread, not secure.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, max: Duration) -> Duration {
+        let nanos = max.as_nanos() as u64;
+        if nanos == 0 {
+            return Duration::ZERO;
+        }
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        Duration::from_nanos(self.0 % nanos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicUsize;
+
+    fn utc(year: i64, month: u32, day: u32, hour: u64, minute: u64) -> SystemTime {
+        let days = days_from_civil(year, month, day) as u64;
+        UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3_600 + minute * 60)
+    }
+
+    #[test]
+    fn test_cron_parse_errors() {
+        assert!("* * * *".parse::<CronExpr>().is_err());
+        assert!("60 * * * *".parse::<CronExpr>().is_err());
+        assert!("*/0 * * * *".parse::<CronExpr>().is_err());
+        assert!("5-1 * * * *".parse::<CronExpr>().is_err());
+        assert!("0,15,30-45/5 */2 1-7 * 1-5".parse::<CronExpr>().is_ok());
+        assert_eq!(
+            "@daily".parse::<CronExpr>().unwrap(),
+            "0 0 * * *".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let nightly: CronExpr = "0 3 * * *".parse().unwrap();
+        assert_eq!(
+            nightly.next_after(utc(2024, 1, 1, 12, 0)),
+            Some(utc(2024, 1, 2, 3, 0))
+        );
+
+        let quarter: CronExpr = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            quarter.next_after(utc(2024, 1, 1, 12, 0)),
+            Some(utc(2024, 1, 1, 12, 15))
+        );
+        assert_eq!(
+            quarter.next_after(utc(2024, 12, 31, 23, 50)),
+            Some(utc(2025, 1, 1, 0, 0))
+        );
+
+        let leap: CronExpr = "0 0 29 2 *".parse().unwrap();
+        assert_eq!(
+            leap.next_after(utc(2023, 3, 1, 0, 0)),
+            Some(utc(2024, 2, 29, 0, 0))
+        );
+        let never: CronExpr = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(never.next_after(utc(2024, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_cron_day_fields_or_when_both_restricted() {
+        // The 15th of the month or any Monday; 2024-01-01 is a Monday.
+        let expr: CronExpr = "0 0 15 * 1".parse().unwrap();
+        assert_eq!(
+            expr.next_after(utc(2023, 12, 31, 12, 0)),
+            Some(utc(2024, 1, 1, 0, 0))
+        );
+        assert_eq!(
+            expr.next_after(utc(2024, 1, 10, 0, 0)),
+            Some(utc(2024, 1, 15, 0, 0))
+        );
+        // Sunday as 7.
+        let sunday: CronExpr = "0 0 * * 7".parse().unwrap();
+        assert_eq!(
+            sunday.next_after(utc(2024, 1, 1, 0, 0)),
+            Some(utc(2024, 1, 7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_interval_job_runs_until_shutdown() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let inner = Arc::clone(&count);
+        let shutdown = ShutdownToken::new();
+        let handle = Scheduler::new()
+            .job(Job::new(
+                "tick",
+                Schedule::Every(Duration::from_millis(10)),
+                move || {
+                    inner.fetch_add(1, Ordering::SeqCst);
+                },
+            ))
+            .start(shutdown.clone());
+        thread::sleep(Duration::from_millis(120));
+        shutdown.shutdown();
+        handle.join();
+
+        let ran = count.load(Ordering::SeqCst);
+        assert!(ran >= 3, "ran {ran} times");
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(count.load(Ordering::SeqCst), ran);
+    }
+
+    #[test]
+    fn test_overlapping_runs_are_skipped() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let (a, m) = (Arc::clone(&active), Arc::clone(&max_active));
+        let handle = Scheduler::new()
+            .job(
+                Job::new(
+                    "slow",
+                    Schedule::Every(Duration::from_millis(5)),
+                    move || {
+                        let now = a.fetch_add(1, Ordering::SeqCst) + 1;
+                        m.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(40));
+                        a.fetch_sub(1, Ordering::SeqCst);
+                    },
+                )
+                .jitter(Duration::from_millis(2)),
+            )
+            .start(ShutdownToken::new());
+        thread::sleep(Duration::from_millis(150));
+        let stats = handle.stats();
+        handle.stop();
+
+        assert_eq!(max_active.load(Ordering::SeqCst), 1);
+        assert_eq!(stats[0].name, "slow");
+        assert!(stats[0].runs >= 1);
+        assert!(stats[0].skipped >= 1);
+        assert_eq!(active.load(Ordering::SeqCst), 0, "stop waits for runs");
+    }
+
+    fn entry(schedule: Schedule, jitter: Duration) -> Entry {
+        Entry {
+            name: "sync".into(),
+            schedule,
+            jitter,
+            task: Arc::new(Mutex::new(Box::new(|| {}))),
+            running: Arc::new(AtomicBool::new(false)),
+            in_flight: None,
+            stats: Arc::new(Counters::default()),
+            slot: None,
+            wall: None,
+            next: None,
+        }
+    }
+
+    #[test]
+    fn test_every_run_is_jittered_around_a_steady_cadence() {
+        let interval = Duration::from_secs(60);
+        let mut entry = entry(Schedule::Every(interval), Duration::from_secs(10));
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let start = Instant::now();
+        entry.arm(Some(start), &mut rng);
+        let mut offsets = HashSet::new();
+        for i in 1..=20 {
+            entry.rearm(entry.next.unwrap(), SystemTime::now(), &mut rng);
+            assert_eq!(entry.slot, Some(start + interval * i));
+            let offset = entry.next.unwrap() - entry.slot.unwrap();
+            assert!(offset < entry.jitter);
+            offsets.insert(offset);
+        }
+        assert!(offsets.len() > 1, "jitter was only applied once");
+    }
+
+    #[test]
+    fn test_cron_rearm_is_strictly_after_the_fired_slot() {
+        let mut entry = entry(Schedule::cron("* * * * *").unwrap(), Duration::ZERO);
+        let mut rng = Rng(1);
+        let fired = utc(2024, 5, 1, 12, 0);
+        let now = Instant::now();
+        entry.wall = Some(fired);
+        // Woken for the 12:00 slot while the wall clock still reads 11:59:59.995.
+        entry.rearm(now, fired - Duration::from_millis(5), &mut rng);
+        assert_eq!(entry.wall, Some(utc(2024, 5, 1, 12, 1)));
+        assert_eq!(entry.next, Some(now + Duration::from_millis(60_005)));
+    }
+}
@@ -0,0 +1,545 @@
+//! Runtime JSON Schema validation for inbound messages.
+//!
+//! Enabled with the `schema` feature. Messages from third-party or older
+//! tools can be checked against an embedded schema before deserialization,
+//! so a bad message is rejected with precise JSON Pointer paths
+//! (`/payload/items/2/id: expected integer, found string`) instead of an
+//! opaque serde error.
+//!
+//! The validator implements the commonly used subset of JSON Schema
+//! (draft 7 / 2020-12 keywords): `type`, `enum`, `const`, `properties`,
+//! `required`, `additionalProperties`, `items`, `minItems`, `maxItems`,
+//! `minLength`, `maxLength`, `minimum`, `maximum`, `exclusiveMinimum`,
+//! `exclusiveMaximum`, `allOf`, `anyOf`, `oneOf`, `not`, and local `$ref`s
+//! (`#/$defs/...`, `#/definitions/...`); a reference that loops back to
+//! itself without descending into the instance is reported as an error.
+//! Other keywords are ignored.
+//!
+//! ```ignore
+//! static SCHEMA: &str = include_str!("task_event.schema.json");
+//!
+//! let schema = Schema::from_str(SCHEMA)?;
+//! let event: TaskEvent = schema.deserialize(value)?;
+//! ```
+//!
+//! A [`Registry`] is the shared list of event names and versions with the
+//! payload schema of each. Producers [`stamp`](Registry::stamp) payloads into
+//! an [`Envelope`] (`{"event", "version", "payload"}`) and consumers
+//! [`open`](Registry::open) incoming envelopes, so both sides check against
+//! the same definitions.
+
+mod registry;
+
+pub use registry::{Envelope, Registry};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+use std::io;
+
+/// A single validation failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// JSON Pointer to the offending value in the instance (`""` is the root).
+    pub instance_path: String,
+    /// JSON Pointer to the schema keyword that failed.
+    pub schema_path: String,
+    /// Human-readable description.
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.instance_path.is_empty() {
+            "/"
+        } else {
+            &self.instance_path
+        };
+        write!(f, "{path}: {}", self.message)
+    }
+}
+
+/// All failures found while validating one instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{e}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl From<ValidationErrors> for io::Error {
+    fn from(e: ValidationErrors) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A compiled (parsed) JSON Schema.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    root: Value,
+}
+
+impl Schema {
+    /// Wrap an already-parsed schema document.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::Error` if the document is neither an object nor a
+    /// boolean schema.
+    pub fn new(root: Value) -> io::Result<Self> {
+        if !(root.is_object() || root.is_boolean()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "schema must be a JSON object or boolean",
+            ));
+        }
+        Ok(Self { root })
+    }
+
+    /// Parse a schema from JSON text (typically an `include_str!`).
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> io::Result<Self> {
+        let root = serde_json::from_str(text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Self::new(root)
+    }
+
+    /// Validate `instance`, collecting every failure.
+    pub fn validate(&self, instance: &Value) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        self.check(&self.root, instance, "", "", &mut errors, &mut Vec::new());
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    /// Return `true` if `instance` is valid.
+    pub fn is_valid(&self, instance: &Value) -> bool {
+        self.validate(instance).is_ok()
+    }
+
+    /// Validate `instance` and then deserialize it into `T`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::InvalidData` carrying the [`ValidationErrors`]
+    /// if validation fails, or the serde error if deserialization still does.
+    pub fn deserialize<T: DeserializeOwned>(&self, instance: Value) -> io::Result<T> {
+        self.validate(&instance)?;
+        serde_json::from_value(instance).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer)
+    }
+
+    fn check(
+        &self,
+        schema: &Value,
+        instance: &Value,
+        ipath: &str,
+        spath: &str,
+        errors: &mut Vec<ValidationError>,
+        refs: &mut Vec<(String, String)>,
+    ) {
+        let obj = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                errors.push(error(ipath, spath, "no value is allowed here".into()));
+                return;
+            }
+            Value::Object(obj) => obj,
+            _ => return,
+        };
+        if let Some(Value::String(reference)) = obj.get("$ref") {
+            // Following the same reference again for the same instance
+            // location can only loop.
+            let key = (reference.clone(), ipath.to_string());
+            match self.resolve(reference) {
+                Some(_) if refs.contains(&key) => errors.push(error(
+                    ipath,
+                    &format!("{spath}/$ref"),
+                    format!("reference cycle through {reference:?}"),
+                )),
+                Some(target) => {
+                    let target_spath = reference.trim_start_matches('#');
+                    refs.push(key);
+                    self.check(target, instance, ipath, target_spath, errors, refs);
+                    refs.pop();
+                }
+                None => errors.push(error(
+                    ipath,
+                    &format!("{spath}/$ref"),
+                    format!("unresolvable reference {reference:?}"),
+                )),
+            }
+        }
+        self.check_keywords(obj, instance, ipath, spath, errors, refs);
+    }
+
+    fn check_keywords(
+        &self,
+        obj: &serde_json::Map<String, Value>,
+        instance: &Value,
+        ipath: &str,
+        spath: &str,
+        errors: &mut Vec<ValidationError>,
+        refs: &mut Vec<(String, String)>,
+    ) {
+        macro_rules! fail {
+            ($kw:expr, $($arg:tt)*) => {
+                errors.push(error(ipath, &format!("{spath}/{}", $kw), format!($($arg)*)))
+            };
+        }
+
+        if let Some(expected) = obj.get("type") {
+            let allowed: Vec<&str> = match expected {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|t| matches_type(t, instance)) {
+                fail!(
+                    "type",
+                    "expected {}, found {}",
+                    allowed.join(" or "),
+                    type_name(instance)
+                );
+                // Further keywords would only produce noise.
+                return;
+            }
+        }
+
+        if let Some(Value::Array(options)) = obj.get("enum")
+            && !options.contains(instance)
+        {
+            fail!("enum", "value is not one of the allowed values");
+        }
+        if let Some(expected) = obj.get("const")
+            && expected != instance
+        {
+            fail!("const", "expected {expected}");
+        }
+
+        match instance {
+            Value::Object(map) => {
+                if let Some(Value::Array(required)) = obj.get("required") {
+                    for name in required.iter().filter_map(Value::as_str) {
+                        if !map.contains_key(name) {
+                            fail!("required", "missing required property {name:?}");
+                        }
+                    }
+                }
+                let properties = obj.get("properties").and_then(Value::as_object);
+                for (key, value) in map {
+                    let child_ipath = format!("{ipath}/{}", escape(key));
+                    if let Some(sub) = properties.and_then(|p| p.get(key)) {
+                        let child_spath = format!("{spath}/properties/{}", escape(key));
+                        self.check(sub, value, &child_ipath, &child_spath, errors, refs);
+                    } else if let Some(additional) = obj.get("additionalProperties") {
+                        if additional == &Value::Bool(false) {
+                            fail!("additionalProperties", "unexpected property {key:?}");
+                        } else {
+                            let child_spath = format!("{spath}/additionalProperties");
+                            self.check(additional, value, &child_ipath, &child_spath, errors, refs);
+                        }
+                    }
+                }
+            }
+            Value::Array(items) => {
+                if let Some(min) = obj.get("minItems").and_then(Value::as_u64)
+                    && (items.len() as u64) < min
+                {
+                    fail!(
+                        "minItems",
+                        "expected at least {min} items, found {}",
+                        items.len()
+                    );
+                }
+                if let Some(max) = obj.get("maxItems").and_then(Value::as_u64)
+                    && (items.len() as u64) > max
+                {
+                    fail!(
+                        "maxItems",
+                        "expected at most {max} items, found {}",
+                        items.len()
+                    );
+                }
+                if let Some(sub) = obj.get("items").filter(|s| !s.is_array()) {
+                    for (i, item) in items.iter().enumerate() {
+                        let child_ipath = format!("{ipath}/{i}");
+                        self.check(
+                            sub,
+                            item,
+                            &child_ipath,
+                            &format!("{spath}/items"),
+                            errors,
+                            refs,
+                        );
+                    }
+                }
+            }
+            Value::String(s) => {
+                let len = s.chars().count() as u64;
+                if let Some(min) = obj.get("minLength").and_then(Value::as_u64)
+                    && len < min
+                {
+                    fail!(
+                        "minLength",
+                        "expected at least {min} characters, found {len}"
+                    );
+                }
+                if let Some(max) = obj.get("maxLength").and_then(Value::as_u64)
+                    && len > max
+                {
+                    fail!(
+                        "maxLength",
+                        "expected at most {max} characters, found {len}"
+                    );
+                }
+            }
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or(f64::NAN);
+                if let Some(min) = obj.get("minimum").and_then(Value::as_f64)
+                    && n < min
+                {
+                    fail!("minimum", "{n} is less than the minimum {min}");
+                }
+                if let Some(max) = obj.get("maximum").and_then(Value::as_f64)
+                    && n > max
+                {
+                    fail!("maximum", "{n} is greater than the maximum {max}");
+                }
+                if let Some(min) = obj.get("exclusiveMinimum").and_then(Value::as_f64)
+                    && n <= min
+                {
+                    fail!("exclusiveMinimum", "{n} must be greater than {min}");
+                }
+                if let Some(max) = obj.get("exclusiveMaximum").and_then(Value::as_f64)
+                    && n >= max
+                {
+                    fail!("exclusiveMaximum", "{n} must be less than {max}");
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(Value::Array(subs)) = obj.get("allOf") {
+            for (i, sub) in subs.iter().enumerate() {
+                self.check(
+                    sub,
+                    instance,
+                    ipath,
+                    &format!("{spath}/allOf/{i}"),
+                    errors,
+                    refs,
+                );
+            }
+        }
+        if let Some(Value::Array(subs)) = obj.get("anyOf") {
+            let matched = subs.iter().any(|sub| {
+                let mut scratch = Vec::new();
+                self.check(sub, instance, ipath, spath, &mut scratch, refs);
+                scratch.is_empty()
+            });
+            if !matched {
+                fail!("anyOf", "value does not match any of the allowed schemas");
+            }
+        }
+        if let Some(Value::Array(subs)) = obj.get("oneOf") {
+            let matches = subs
+                .iter()
+                .filter(|sub| {
+                    let mut scratch = Vec::new();
+                    self.check(sub, instance, ipath, spath, &mut scratch, refs);
+                    scratch.is_empty()
+                })
+                .count();
+            if matches != 1 {
+                fail!(
+                    "oneOf",
+                    "value matches {matches} schemas, expected exactly one"
+                );
+            }
+        }
+        if let Some(sub) = obj.get("not") {
+            let mut scratch = Vec::new();
+            self.check(sub, instance, ipath, spath, &mut scratch, refs);
+            if scratch.is_empty() {
+                fail!("not", "value matches a disallowed schema");
+            }
+        }
+    }
+}
+
+fn error(ipath: &str, spath: &str, message: String) -> ValidationError {
+    ValidationError {
+        instance_path: ipath.to_string(),
+        schema_path: spath.to_string(),
+        message,
+    }
+}
+
+/// Escape a property name for use as a JSON Pointer segment (RFC 6901).
+fn escape(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+fn matches_type(expected: &str, value: &Value) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    fn task_schema() -> Schema {
+        Schema::new(json!({
+            "type": "object",
+            "required": ["id", "kind"],
+            "additionalProperties": false,
+            "properties": {
+                "id": { "type": "integer", "minimum": 1 },
+                "kind": { "enum": ["start", "stop"] },
+                "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" } }
+            },
+            "$defs": {
+                "tag": { "type": "string", "minLength": 1 }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_valid_instance() {
+        let schema = task_schema();
+        assert!(schema.is_valid(&json!({ "id": 3, "kind": "start", "tags": ["a"] })));
+    }
+
+    #[test]
+    fn test_errors_have_pointer_paths() {
+        let schema = task_schema();
+        let errs = schema
+            .validate(&json!({ "id": "3", "tags": ["ok", ""], "extra": true }))
+            .unwrap_err()
+            .0;
+        let paths: Vec<_> = errs.iter().map(|e| e.instance_path.as_str()).collect();
+        assert!(paths.contains(&"/id"), "{errs:?}");
+        assert!(paths.contains(&"/tags/1"), "{errs:?}");
+        assert!(paths.contains(&""), "{errs:?}"); // missing "kind", extra property
+
+        let id_err = errs.iter().find(|e| e.instance_path == "/id").unwrap();
+        assert_eq!(id_err.schema_path, "/properties/id/type");
+        assert_eq!(id_err.message, "expected integer, found string");
+        assert_eq!(id_err.to_string(), "/id: expected integer, found string");
+    }
+
+    #[test]
+    fn test_reference_cycles_are_errors() {
+        let itself = Schema::new(json!({ "$ref": "#" })).unwrap();
+        let errs = itself.validate(&json!(1)).unwrap_err().0;
+        assert!(errs[0].message.contains("reference cycle"), "{errs:?}");
+
+        let mutual = Schema::new(json!({
+            "$defs": { "a": { "$ref": "#/$defs/b" }, "b": { "$ref": "#/$defs/a" } },
+            "$ref": "#/$defs/a"
+        }))
+        .unwrap();
+        assert!(!mutual.is_valid(&json!({})));
+
+        // Recursion that descends into the instance is not a cycle.
+        let tree = Schema::new(json!({
+            "type": "object",
+            "properties": { "children": { "type": "array", "items": { "$ref": "#" } } }
+        }))
+        .unwrap();
+        assert!(tree.is_valid(&json!({ "children": [{ "children": [] }] })));
+        assert!(!tree.is_valid(&json!({ "children": [{ "children": 1 }] })));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let schema = Schema::new(json!({
+            "oneOf": [
+                { "type": "string" },
+                { "type": "integer", "exclusiveMaximum": 10 }
+            ],
+            "not": { "const": "forbidden" }
+        }))
+        .unwrap();
+        assert!(schema.is_valid(&json!("hello")));
+        assert!(schema.is_valid(&json!(3)));
+        assert!(!schema.is_valid(&json!(10)));
+        assert!(!schema.is_valid(&json!("forbidden")));
+        assert!(!schema.is_valid(&json!(null)));
+    }
+
+    #[test]
+    fn test_pointer_escaping() {
+        let schema = Schema::new(json!({
+            "additionalProperties": { "type": "number" }
+        }))
+        .unwrap();
+        let errs = schema.validate(&json!({ "a/b~c": "x" })).unwrap_err().0;
+        assert_eq!(errs[0].instance_path, "/a~1b~0c");
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Task {
+            id: u64,
+            kind: String,
+        }
+
+        let schema = task_schema();
+        let task: Task = schema
+            .deserialize(json!({ "id": 1, "kind": "stop" }))
+            .unwrap();
+        assert_eq!(task.kind, "stop");
+
+        let err = schema
+            .deserialize::<Task>(json!({ "id": 0, "kind": "stop" }))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("/id"), "{err}");
+    }
+}
@@ -0,0 +1,232 @@
+//! Versioned event definitions shared by every tool that emits or reads
+//! events.
+
+use super::{Schema, ValidationError, ValidationErrors};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+
+/// An event as it travels between tools: its registered name and version,
+/// and the payload that version's schema describes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub event: String,
+    pub version: u32,
+    pub payload: Value,
+}
+
+/// Event names, their versions, and the payload schema of each version.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    events: BTreeMap<String, BTreeMap<u32, Schema>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add version `version` of event `name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AlreadyExists` if that version is already registered; a
+    /// published version's shape never changes, so add a new version
+    /// instead.
+    pub fn register(&mut self, name: &str, version: u32, schema: Schema) -> io::Result<()> {
+        let versions = self.events.entry(name.to_string()).or_default();
+        if versions.contains_key(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("event {name} v{version} is already registered"),
+            ));
+        }
+        versions.insert(version, schema);
+        Ok(())
+    }
+
+    /// Like [`register`](Self::register), parsing the schema from JSON text
+    /// (typically an `include_str!`).
+    pub fn register_str(&mut self, name: &str, version: u32, schema: &str) -> io::Result<()> {
+        self.register(name, version, Schema::from_str(schema)?)
+    }
+
+    pub fn get(&self, name: &str, version: u32) -> Option<&Schema> {
+        self.events.get(name)?.get(&version)
+    }
+
+    /// The highest registered version of `name`.
+    pub fn latest(&self, name: &str) -> Option<u32> {
+        self.events.get(name)?.keys().next_back().copied()
+    }
+
+    /// Registered event names with their versions, both ascending.
+    pub fn events(&self) -> impl Iterator<Item = (&str, Vec<u32>)> {
+        self.events
+            .iter()
+            .map(|(name, versions)| (name.as_str(), versions.keys().copied().collect()))
+    }
+
+    /// Wrap `payload` as the latest version of `name`, checking it against
+    /// that version's schema so a producer can't emit what consumers would
+    /// reject.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` for an unregistered event, or `InvalidData`
+    /// carrying the [`ValidationErrors`] (with `/payload/...` paths).
+    pub fn stamp<T: Serialize>(&self, name: &str, payload: &T) -> io::Result<Envelope> {
+        let version = self.latest(name).ok_or_else(|| unknown(name, None))?;
+        self.stamp_version(name, version, payload)
+    }
+
+    /// Like [`stamp`](Self::stamp), for a specific (e.g. older) version.
+    pub fn stamp_version<T: Serialize>(
+        &self,
+        name: &str,
+        version: u32,
+        payload: &T,
+    ) -> io::Result<Envelope> {
+        let envelope = Envelope {
+            event: name.to_string(),
+            version,
+            payload: serde_json::to_value(payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        };
+        self.verify(&envelope)?;
+        Ok(envelope)
+    }
+
+    /// Check `envelope`'s payload against the schema of its event and
+    /// version.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` for an unregistered event or version, or
+    /// `InvalidData` carrying the [`ValidationErrors`].
+    pub fn verify(&self, envelope: &Envelope) -> io::Result<()> {
+        let schema = self
+            .get(&envelope.event, envelope.version)
+            .ok_or_else(|| unknown(&envelope.event, Some(envelope.version)))?;
+        schema.validate(&envelope.payload).map_err(|errors| {
+            ValidationErrors(
+                errors
+                    .0
+                    .into_iter()
+                    .map(|e| ValidationError {
+                        instance_path: format!("/payload{}", e.instance_path),
+                        ..e
+                    })
+                    .collect(),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Parse an incoming message as an [`Envelope`] and verify it.
+    pub fn open(&self, message: Value) -> io::Result<Envelope> {
+        let envelope: Envelope = serde_json::from_value(message)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.verify(&envelope)?;
+        Ok(envelope)
+    }
+
+    /// Verify `envelope` and deserialize its payload.
+    pub fn decode<T: DeserializeOwned>(&self, envelope: &Envelope) -> io::Result<T> {
+        self.verify(envelope)?;
+        T::deserialize(&envelope.payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+fn unknown(name: &str, version: Option<u32>) -> io::Error {
+    let what = match version {
+        Some(v) => format!("event {name} v{v} is not registered"),
+        None => format!("event {name} is not registered"),
+    };
+    io::Error::new(io::ErrorKind::NotFound, what)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn registry() -> Registry {
+        let mut registry = Registry::new();
+        registry
+            .register_str(
+                "task.started",
+                1,
+                r#"{"type": "object", "required": ["id"], "properties": {"id": {"type": "integer"}}}"#,
+            )
+            .unwrap();
+        registry
+            .register_str(
+                "task.started",
+                2,
+                r#"{"type": "object", "required": ["id", "agent"],
+                    "properties": {"id": {"type": "string"}, "agent": {"type": "string"}}}"#,
+            )
+            .unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_stamp_uses_latest_and_validates() {
+        let mut registry = registry();
+        assert_eq!(registry.latest("task.started"), Some(2));
+        assert_eq!(
+            registry.events().collect::<Vec<_>>(),
+            [("task.started", vec![1, 2])]
+        );
+
+        let envelope = registry
+            .stamp("task.started", &json!({"id": "t-1", "agent": "claude"}))
+            .unwrap();
+        assert_eq!(envelope.version, 2);
+        assert_eq!(
+            serde_json::to_value(&envelope).unwrap(),
+            json!({"event": "task.started", "version": 2, "payload": {"id": "t-1", "agent": "claude"}})
+        );
+        let old = registry
+            .stamp_version("task.started", 1, &json!({"id": 7}))
+            .unwrap();
+        assert_eq!(old.version, 1);
+
+        let err = registry
+            .stamp("task.started", &json!({"id": 7}))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("/payload/id: expected string"));
+        let err = registry.stamp("task.done", &json!({})).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = registry.register_str("task.started", 1, "{}").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_open_and_decode_incoming() {
+        #[derive(Deserialize)]
+        struct StartedV1 {
+            id: u64,
+        }
+        let registry = registry();
+        let envelope = registry
+            .open(json!({"event": "task.started", "version": 1, "payload": {"id": 3}}))
+            .unwrap();
+        assert_eq!(registry.decode::<StartedV1>(&envelope).unwrap().id, 3);
+
+        let unknown_version = json!({"event": "task.started", "version": 9, "payload": {}});
+        assert_eq!(
+            registry.open(unknown_version).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        let not_envelope = json!({"id": 3});
+        assert_eq!(
+            registry.open(not_envelope).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,576 @@
+//! Secret storage behind a backend-agnostic API.
+//!
+//! Callers depend on the [`SecretStore`] trait (`get`/`set`/`delete` by
+//! name) rather than a concrete backend. [`EncryptedFileStore`] keeps secrets
+//! in a single encrypted file, for CI runners and headless servers where no
+//! OS keyring is available.
+//!
+//! The file is JSON holding a ChaCha20-Poly1305 ciphertext of the secrets
+//! map. The key is either stretched from a passphrase with scrypt (the salt
+//! and cost parameters are stored in the file) or read from a key file made
+//! by [`generate_key_file`]. The header is authenticated along with the
+//! ciphertext, so tampering with either is detected. Every write uses a fresh
+//! nonce, goes through a temp file renamed into place with mode `0600`, and
+//! holds a [`LockFile`] so concurrent `set`s don't lose updates.
+//!
+//! Salts, nonces, and generated keys come from `/dev/urandom` on Unix and
+//! from .NET's `RandomNumberGenerator` (through PowerShell) on Windows. On
+//! other targets anything that needs randomness fails with `Unsupported`.
+
+mod crypto;
+
+use crate::hash::to_hex;
+use crate::lock::LockFile;
+use crate::{env, guard};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Environment variable naming a key file, read by
+/// [`EncryptedFileStore::from_env`].
+pub const KEY_FILE_ENV: &str = "APIARI_SECRETS_KEY_FILE";
+
+/// Environment variable holding a passphrase, read by
+/// [`EncryptedFileStore::from_env`] when no key file is set.
+pub const PASSPHRASE_ENV: &str = "APIARI_SECRETS_PASSPHRASE";
+
+const FORMAT_VERSION: u32 = 1;
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Named secrets, independent of where they are kept.
+pub trait SecretStore {
+    /// The secret called `name`, or `None` if it isn't set.
+    fn get(&self, name: &str) -> io::Result<Option<String>>;
+
+    /// Set `name` to `value`, replacing any previous value.
+    fn set(&self, name: &str, value: &str) -> io::Result<()>;
+
+    /// Remove `name`. Returns `false` if it wasn't set.
+    fn delete(&self, name: &str) -> io::Result<bool>;
+}
+
+/// scrypt cost parameters for new passphrase-encrypted files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScryptParams {
+    /// `N = 2^log_n`.
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// `N = 2^15, r = 8, p = 1`: 32 MiB and well under a second per
+    /// derivation.
+    fn default() -> Self {
+        Self {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl ScryptParams {
+    /// Reject parameters that are invalid or would need more than 1 GiB, so
+    /// a crafted file can't exhaust memory.
+    fn validate(&self) -> io::Result<()> {
+        if self.log_n == 0 || self.log_n >= 32 || self.r == 0 || self.p == 0 || self.p > 16 {
+            return Err(invalid("invalid scrypt parameters"));
+        }
+        // scrypt needs `128 * r * N` bytes for V and `128 * r * p` for B.
+        let block = 128 * u64::from(self.r);
+        let memory = block
+            .checked_mul(1 << self.log_n)
+            .and_then(|v| v.checked_add(block * u64::from(self.p)));
+        if memory.is_none_or(|bytes| bytes > 1 << 30) {
+            return Err(invalid("scrypt parameters need more than 1 GiB"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum KeySource {
+    Passphrase(String),
+    KeyFile([u8; crypto::KEY_LEN]),
+}
+
+/// How the file's key was obtained; stored in the header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "kebab-case")]
+enum Kdf {
+    Scrypt {
+        salt: String,
+        #[serde(flatten)]
+        params: ScryptParams,
+    },
+    KeyFile,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    kdf: Kdf,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Secrets in an encrypted file.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    source: KeySource,
+    params: ScryptParams,
+    /// The last key derived from the passphrase, since scrypt is slow on
+    /// purpose.
+    derived: Mutex<Option<(Kdf, [u8; crypto::KEY_LEN])>>,
+}
+
+impl EncryptedFileStore {
+    /// A store at `path` encrypted with a key stretched from `passphrase`.
+    pub fn with_passphrase(path: impl Into<PathBuf>, passphrase: impl Into<String>) -> Self {
+        Self::new(path.into(), KeySource::Passphrase(passphrase.into()))
+    }
+
+    /// A store at `path` encrypted with the key in `key_file` (see
+    /// [`generate_key_file`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidData` if the key file doesn't hold 64 hex digits.
+    pub fn with_key_file(path: impl Into<PathBuf>, key_file: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(key_file)?;
+        let key = from_hex(text.trim())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a secrets key file", key_file.display()),
+                )
+            })?;
+        Ok(Self::new(path.into(), KeySource::KeyFile(key)))
+    }
+
+    /// A store at `path` keyed from the environment: the key file named by
+    /// [`KEY_FILE_ENV`] if set, otherwise the passphrase in
+    /// [`PASSPHRASE_ENV`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if neither variable is set.
+    pub fn from_env(path: impl Into<PathBuf>) -> io::Result<Self> {
+        if let Some(key_file) = env::get::<PathBuf>(KEY_FILE_ENV)? {
+            return Self::with_key_file(path, &key_file);
+        }
+        match env::get::<String>(PASSPHRASE_ENV)? {
+            Some(passphrase) => Ok(Self::with_passphrase(path, passphrase)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("neither {KEY_FILE_ENV} nor {PASSPHRASE_ENV} is set"),
+            )),
+        }
+    }
+
+    fn new(path: PathBuf, source: KeySource) -> Self {
+        Self {
+            path,
+            source,
+            params: ScryptParams::default(),
+            derived: Mutex::new(None),
+        }
+    }
+
+    /// scrypt cost for a file this store creates. Existing files keep the
+    /// parameters they were written with.
+    pub fn scrypt_params(mut self, params: ScryptParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Names of all stored secrets, sorted.
+    pub fn names(&self) -> io::Result<Vec<String>> {
+        Ok(self
+            .load()?
+            .map(|(_, map)| map.into_keys().collect())
+            .unwrap_or_default())
+    }
+
+    /// Read and decrypt the file; `None` if it doesn't exist yet.
+    fn load(&self) -> io::Result<Option<(Kdf, BTreeMap<String, String>)>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let envelope: Envelope = serde_json::from_str(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if envelope.version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported secrets file version {}", envelope.version),
+            ));
+        }
+        let nonce = from_hex(&envelope.nonce)
+            .and_then(|n| n.try_into().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed nonce"))?;
+        let ciphertext = from_hex(&envelope.ciphertext)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed ciphertext"))?;
+        let key = self.key(&envelope.kdf)?;
+        let plaintext =
+            crypto::open(&key, &nonce, &aad(&envelope.kdf)?, &ciphertext).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cannot decrypt {}: wrong passphrase or key, or the file was modified",
+                        self.path.display()
+                    ),
+                )
+            })?;
+        let map = serde_json::from_slice(&plaintext)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some((envelope.kdf, map)))
+    }
+
+    /// Encrypt `map` under `kdf` and atomically replace the file.
+    fn store(&self, kdf: Kdf, map: &BTreeMap<String, String>) -> io::Result<()> {
+        let key = self.key(&kdf)?;
+        let nonce = random::<{ crypto::NONCE_LEN }>()?;
+        let plaintext = serde_json::to_vec(map).map_err(io::Error::other)?;
+        let ciphertext = crypto::seal(&key, &nonce, &aad(&kdf)?, &plaintext);
+        let envelope = Envelope {
+            version: FORMAT_VERSION,
+            kdf,
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+        };
+        let json = serde_json::to_string_pretty(&envelope).map_err(io::Error::other)?;
+        write_private(&self.path, json.as_bytes())
+    }
+
+    /// The key for a file with this header.
+    fn key(&self, kdf: &Kdf) -> io::Result<[u8; crypto::KEY_LEN]> {
+        match (&self.source, kdf) {
+            (KeySource::KeyFile(key), Kdf::KeyFile) => Ok(*key),
+            (KeySource::Passphrase(passphrase), Kdf::Scrypt { salt, params }) => {
+                let mut derived = self.derived.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some((cached, key)) = &*derived
+                    && cached == kdf
+                {
+                    return Ok(*key);
+                }
+                params.validate()?;
+                let salt = from_hex(salt)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed salt"))?;
+                let mut key = [0u8; crypto::KEY_LEN];
+                crypto::scrypt(
+                    passphrase.as_bytes(),
+                    &salt,
+                    params.log_n,
+                    params.r as usize,
+                    params.p as usize,
+                    &mut key,
+                );
+                *derived = Some((kdf.clone(), key));
+                Ok(key)
+            }
+            (KeySource::KeyFile(_), Kdf::Scrypt { .. }) => Err(invalid(
+                "secrets file is passphrase-encrypted but a key file was given",
+            )),
+            (KeySource::Passphrase(_), Kdf::KeyFile) => Err(invalid(
+                "secrets file is key-file-encrypted but a passphrase was given",
+            )),
+        }
+    }
+
+    /// Load, apply `edit`, and write back if it returns `true`, holding the
+    /// store's lock file throughout.
+    fn update(&self, edit: impl FnOnce(&mut BTreeMap<String, String>) -> bool) -> io::Result<bool> {
+        let mut lock_path = self.path.as_os_str().to_os_string();
+        lock_path.push(".lock");
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let _lock = LockFile::acquire(PathBuf::from(lock_path), LOCK_TIMEOUT)?;
+        let (kdf, mut map) = match self.load()? {
+            Some(loaded) => loaded,
+            None => (self.new_kdf()?, BTreeMap::new()),
+        };
+        if !edit(&mut map) {
+            return Ok(false);
+        }
+        self.store(kdf, &map)?;
+        Ok(true)
+    }
+
+    fn new_kdf(&self) -> io::Result<Kdf> {
+        match self.source {
+            KeySource::KeyFile(_) => Ok(Kdf::KeyFile),
+            KeySource::Passphrase(_) => {
+                self.params.validate()?;
+                Ok(Kdf::Scrypt {
+                    salt: to_hex(&random::<16>()?),
+                    params: self.params,
+                })
+            }
+        }
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get(&self, name: &str) -> io::Result<Option<String>> {
+        Ok(self.load()?.and_then(|(_, mut map)| map.remove(name)))
+    }
+
+    fn set(&self, name: &str, value: &str) -> io::Result<()> {
+        self.update(|map| {
+            map.insert(name.to_string(), value.to_string());
+            true
+        })
+        .map(drop)
+    }
+
+    fn delete(&self, name: &str) -> io::Result<bool> {
+        if !self.path.exists() {
+            return Ok(false);
+        }
+        self.update(|map| map.remove(name).is_some())
+    }
+}
+
+impl fmt::Debug for EncryptedFileStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self.source {
+            KeySource::Passphrase(_) => "passphrase",
+            KeySource::KeyFile(_) => "key-file",
+        };
+        f.debug_struct("EncryptedFileStore")
+            .field("path", &self.path)
+            .field("source", &source)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Create a new random key file at `path`, readable only by its owner.
+///
+/// # Errors
+///
+/// Returns `AlreadyExists` rather than replacing an existing key, which
+/// would make files encrypted with it unreadable.
+pub fn generate_key_file(path: &Path) -> io::Result<()> {
+    let key = random::<{ crypto::KEY_LEN }>()?;
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = private_options().create_new(true).open(path)?;
+    writeln!(file, "{}", to_hex(&key))?;
+    file.sync_all()
+}
+
+/// The header fields bound to the ciphertext.
+fn aad(kdf: &Kdf) -> io::Result<Vec<u8>> {
+    let kdf = serde_json::to_string(kdf).map_err(io::Error::other)?;
+    Ok(format!("apiari-secrets/{FORMAT_VERSION}/{kdf}").into_bytes())
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(format!(".tmp-{}", process::id()));
+    let tmp = guard::remove_file_on_drop(tmp);
+    let mut file = private_options().create(true).truncate(true).open(&*tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&*tmp, path)?;
+    tmp.disarm();
+    Ok(())
+}
+
+fn private_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+/// Bytes from the OS random source. Unlike [`crate::id`], there is no
+/// fallback: a predictable key or nonce is worse than an error.
+#[cfg(unix)]
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    use std::io::Read;
+
+    let mut bytes = [0u8; N];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Bytes from the OS random source, via .NET's CSPRNG in PowerShell.
+#[cfg(windows)]
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let script = format!(
+        "$b = New-Object byte[] {N}; \
+         [Security.Cryptography.RandomNumberGenerator]::Create().GetBytes($b); \
+         [BitConverter]::ToString($b)"
+    );
+    let output = process::Command::new("powershell")
+        .args(["-NoProfile", "-Command", &script])
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .output()?;
+    let hex: String = String::from_utf8_lossy(&output.stdout)
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .collect();
+    from_hex(&hex)
+        .and_then(|bytes| bytes.try_into().ok())
+        .filter(|_| output.status.success())
+        .ok_or_else(|| io::Error::other("could not read random bytes from PowerShell"))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no OS random source on this platform; encrypted secrets are unavailable",
+    ))
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    // from_str_radix alone would accept a leading '+', so "+f" would decode.
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// Cheap parameters so tests don't spend seconds in scrypt.
+    const FAST: ScryptParams = ScryptParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+
+    #[test]
+    fn test_passphrase_store_roundtrip() {
+        let tmp = TempDir::new("apiari-secrets-test-passphrase").unwrap();
+        let path = tmp.join("state/secrets.json");
+        let store = EncryptedFileStore::with_passphrase(&path, "hunter2").scrypt_params(FAST);
+        assert_eq!(store.get("token").unwrap(), None);
+        assert!(!store.delete("token").unwrap());
+
+        store.set("token", "s3cr3t-value").unwrap();
+        store.set("other", "x").unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("s3cr3t"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A fresh store with the same passphrase reads it back.
+        let reopened = EncryptedFileStore::with_passphrase(&path, "hunter2");
+        assert_eq!(
+            reopened.get("token").unwrap().as_deref(),
+            Some("s3cr3t-value")
+        );
+        assert!(reopened.delete("token").unwrap());
+        assert_eq!(store.names().unwrap(), ["other"]);
+
+        let wrong = EncryptedFileStore::with_passphrase(&path, "hunter3");
+        let err = wrong.get("other").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_hostile_scrypt_params_rejected() {
+        let tmp = TempDir::new("apiari-secrets-test-hostile").unwrap();
+        let path = tmp.join("secrets.json");
+        let store = EncryptedFileStore::with_passphrase(&path, "pw").scrypt_params(FAST);
+        store.set("a", "b").unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+
+        // `128 * r << log_n` wraps to 0 for this pair; the B buffer alone is
+        // too big for the last one.
+        for (log_n, r, p) in [(31, 1 << 26, 1), (31, u32::MAX, 16), (1, 1 << 22, 16)] {
+            let mut envelope: serde_json::Value = serde_json::from_str(&text).unwrap();
+            envelope["kdf"]["log_n"] = log_n.into();
+            envelope["kdf"]["r"] = r.into();
+            envelope["kdf"]["p"] = p.into();
+            fs::write(&path, envelope.to_string()).unwrap();
+            let err = store.get("a").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{log_n} {r} {p}");
+        }
+        assert!(FAST.validate().is_ok() && ScryptParams::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_key_file_store_and_tampering() {
+        let tmp = TempDir::new("apiari-secrets-test-key-file").unwrap();
+        let key = tmp.join("secrets.key");
+        let path = tmp.join("secrets.json");
+        generate_key_file(&key).unwrap();
+        let err = generate_key_file(&key).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        let store: Box<dyn SecretStore> =
+            Box::new(EncryptedFileStore::with_key_file(&path, &key).unwrap());
+        store.set("api", "k-123").unwrap();
+        assert_eq!(store.get("api").unwrap().as_deref(), Some("k-123"));
+
+        let passphrase = EncryptedFileStore::with_passphrase(&path, "x");
+        let err = passphrase.get("api").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let text = fs::read_to_string(&path).unwrap();
+        let mut envelope: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let ciphertext = envelope["ciphertext"].as_str().unwrap();
+        let flipped = if ciphertext.starts_with('0') {
+            "1"
+        } else {
+            "0"
+        };
+        envelope["ciphertext"] = format!("{flipped}{}", &ciphertext[1..]).into();
+        fs::write(&path, envelope.to_string()).unwrap();
+        let err = store.get("api").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::write(&path, &text).unwrap();
+        assert!(store.get("api").is_ok());
+    }
+
+    #[test]
+    fn test_from_hex_rejects_non_hex_digits() {
+        assert_eq!(from_hex("0aFf"), Some(vec![0x0a, 0xff]));
+        assert_eq!(from_hex("+f"), None);
+        assert_eq!(from_hex("-1"), None);
+        assert_eq!(from_hex("é"), None);
+        assert_eq!(from_hex("abc"), None);
+    }
+}
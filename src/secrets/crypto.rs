@@ -0,0 +1,360 @@
+//! The primitives behind the encrypted secrets file: scrypt (RFC 7914) to
+//! stretch a passphrase into a key, and ChaCha20-Poly1305 (RFC 8439) to seal
+//! the contents. Both are checked against the RFCs' test vectors.
+
+use crate::hash::Sha256;
+use std::io;
+
+/// Key length for [`seal`] and [`open`].
+pub const KEY_LEN: usize = 32;
+/// Nonce length for [`seal`] and [`open`].
+pub const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Derive `out.len()` bytes from `password` and `salt` with scrypt, using
+/// `N = 2^log_n`. Parameters must already be validated.
+pub fn scrypt(password: &[u8], salt: &[u8], log_n: u8, r: usize, p: usize, out: &mut [u8]) {
+    let n = 1usize << log_n;
+    let words = 32 * r;
+    let mut b = vec![0u8; p * 128 * r];
+    pbkdf2_sha256(password, salt, &mut b);
+
+    let mut x = vec![0u32; words];
+    let mut y = vec![0u32; words];
+    let mut v = vec![0u32; words * n];
+    for chunk in b.chunks_mut(128 * r) {
+        for (w, bytes) in x.iter_mut().zip(chunk.chunks_exact(4)) {
+            *w = u32::from_le_bytes(bytes.try_into().expect("4-byte chunk"));
+        }
+        for i in 0..n {
+            v[i * words..(i + 1) * words].copy_from_slice(&x);
+            block_mix(&x, &mut y, r);
+            std::mem::swap(&mut x, &mut y);
+        }
+        for _ in 0..n {
+            let j = x[(2 * r - 1) * 16] as usize & (n - 1);
+            for (a, b) in x.iter_mut().zip(&v[j * words..(j + 1) * words]) {
+                *a ^= b;
+            }
+            block_mix(&x, &mut y, r);
+            std::mem::swap(&mut x, &mut y);
+        }
+        for (bytes, w) in chunk.chunks_exact_mut(4).zip(&x) {
+            bytes.copy_from_slice(&w.to_le_bytes());
+        }
+    }
+    pbkdf2_sha256(password, &b, out);
+}
+
+/// Encrypt `plaintext` and append the 16-byte authentication tag.
+pub fn seal(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut out = plaintext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut out);
+    let tag = tag(key, nonce, aad, &out);
+    out.extend_from_slice(&tag);
+    out
+}
+
+/// Verify and decrypt the output of [`seal`].
+///
+/// # Errors
+///
+/// Returns `InvalidData` if the tag doesn't match: the key is wrong, or the
+/// ciphertext or associated data was modified.
+pub fn open(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    sealed: &[u8],
+) -> io::Result<Vec<u8>> {
+    let failed = || io::Error::new(io::ErrorKind::InvalidData, "authentication failed");
+    let split = sealed.len().checked_sub(TAG_LEN).ok_or_else(failed)?;
+    let (ciphertext, expected) = sealed.split_at(split);
+    let actual = tag(key, nonce, aad, ciphertext);
+    // Compare without an early exit, so timing doesn't reveal the prefix.
+    let diff = actual
+        .iter()
+        .zip(expected)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    if diff != 0 {
+        return Err(failed());
+    }
+    let mut out = ciphertext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut out);
+    Ok(out)
+}
+
+fn tag(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let block = chacha20_block(key, 0, nonce);
+    let otk: [u8; 32] = block[..32].try_into().expect("32 bytes");
+    let pad = |len: usize| vec![0u8; (16 - len % 16) % 16];
+    let mut data = Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+    data.extend_from_slice(aad);
+    data.extend(pad(aad.len()));
+    data.extend_from_slice(ciphertext);
+    data.extend(pad(ciphertext.len()));
+    data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly1305(&otk, &data)
+}
+
+struct Hmac {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl Hmac {
+    fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; 64];
+        if key.len() > 64 {
+            let mut hasher = Sha256::new();
+            hasher.update(key);
+            block[..32].copy_from_slice(&hasher.finalize());
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut inner = Sha256::new();
+        inner.update(&block.map(|b| b ^ 0x36));
+        let mut outer = Sha256::new();
+        outer.update(&block.map(|b| b ^ 0x5c));
+        Self { inner, outer }
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> [u8; 32] {
+        let mut inner = self.inner.clone();
+        for part in parts {
+            inner.update(part);
+        }
+        let mut outer = self.outer.clone();
+        outer.update(&inner.finalize());
+        outer.finalize()
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 with one iteration, which is all scrypt uses.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], out: &mut [u8]) {
+    let hmac = Hmac::new(password);
+    for (i, chunk) in out.chunks_mut(32).enumerate() {
+        let block = (i as u32 + 1).to_be_bytes();
+        let t = hmac.mac(&[salt, &block]);
+        chunk.copy_from_slice(&t[..chunk.len()]);
+    }
+}
+
+fn block_mix(input: &[u32], out: &mut [u32], r: usize) {
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&input[(2 * r - 1) * 16..2 * r * 16]);
+    for i in 0..2 * r {
+        for (a, b) in x.iter_mut().zip(&input[i * 16..(i + 1) * 16]) {
+            *a ^= b;
+        }
+        salsa20_8(&mut x);
+        // Even blocks go to the first half of the output, odd to the second.
+        let dst = if i % 2 == 0 { i / 2 } else { r + i / 2 };
+        out[dst * 16..(dst + 1) * 16].copy_from_slice(&x);
+    }
+}
+
+fn salsa20_8(block: &mut [u32; 16]) {
+    fn quarter(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    }
+    let mut x = *block;
+    for _ in 0..4 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 5, 9, 13, 1);
+        quarter(&mut x, 10, 14, 2, 6);
+        quarter(&mut x, 15, 3, 7, 11);
+        quarter(&mut x, 0, 1, 2, 3);
+        quarter(&mut x, 5, 6, 7, 4);
+        quarter(&mut x, 10, 11, 8, 9);
+        quarter(&mut x, 15, 12, 13, 14);
+    }
+    for (b, x) in block.iter_mut().zip(x) {
+        *b = b.wrapping_add(x);
+    }
+}
+
+fn chacha20_block(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u8; 64] {
+    fn quarter(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[a] = x[a].wrapping_add(x[b]);
+        x[d] = (x[d] ^ x[a]).rotate_left(16);
+        x[c] = x[c].wrapping_add(x[d]);
+        x[b] = (x[b] ^ x[c]).rotate_left(12);
+        x[a] = x[a].wrapping_add(x[b]);
+        x[d] = (x[d] ^ x[a]).rotate_left(8);
+        x[c] = x[c].wrapping_add(x[d]);
+        x[b] = (x[b] ^ x[c]).rotate_left(7);
+    }
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for (w, bytes) in state[4..12].iter_mut().zip(key.chunks_exact(4)) {
+        *w = le32(bytes);
+    }
+    state[12] = counter;
+    for (w, bytes) in state[13..].iter_mut().zip(nonce.chunks_exact(4)) {
+        *w = le32(bytes);
+    }
+    let mut x = state;
+    for _ in 0..10 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 1, 5, 9, 13);
+        quarter(&mut x, 2, 6, 10, 14);
+        quarter(&mut x, 3, 7, 11, 15);
+        quarter(&mut x, 0, 5, 10, 15);
+        quarter(&mut x, 1, 6, 11, 12);
+        quarter(&mut x, 2, 7, 8, 13);
+        quarter(&mut x, 3, 4, 9, 14);
+    }
+    let mut out = [0u8; 64];
+    for (i, bytes) in out.chunks_exact_mut(4).enumerate() {
+        bytes.copy_from_slice(&x[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    out
+}
+
+fn chacha20_xor(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        for (b, s) in chunk.iter_mut().zip(stream) {
+            *b ^= s;
+        }
+    }
+}
+
+/// Poly1305 over 26-bit limbs.
+fn poly1305(key: &[u8; 32], msg: &[u8]) -> [u8; 16] {
+    const MASK: u64 = 0x3ff_ffff;
+    let r0 = u64::from(le32(&key[0..4])) & 0x3ff_ffff;
+    let r1 = u64::from(le32(&key[3..7]) >> 2) & 0x3ff_ff03;
+    let r2 = u64::from(le32(&key[6..10]) >> 4) & 0x3ff_c0ff;
+    let r3 = u64::from(le32(&key[9..13]) >> 6) & 0x3f0_3fff;
+    let r4 = u64::from(le32(&key[12..16]) >> 8) & 0x00f_ffff;
+    let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+    let (mut h0, mut h1, mut h2, mut h3, mut h4) = (0u64, 0u64, 0u64, 0u64, 0u64);
+
+    for chunk in msg.chunks(16) {
+        let mut block = [0u8; 17];
+        block[..chunk.len()].copy_from_slice(chunk);
+        block[chunk.len()] = 1;
+        let hibit = u64::from(block[16]) << 24;
+        h0 += u64::from(le32(&block[0..4])) & MASK;
+        h1 += u64::from(le32(&block[3..7]) >> 2) & MASK;
+        h2 += u64::from(le32(&block[6..10]) >> 4) & MASK;
+        h3 += u64::from(le32(&block[9..13]) >> 6) & MASK;
+        h4 += u64::from(le32(&block[12..16]) >> 8) | hibit;
+
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+        h0 = d0 & MASK;
+        d1 += d0 >> 26;
+        h1 = d1 & MASK;
+        d2 += d1 >> 26;
+        h2 = d2 & MASK;
+        d3 += d2 >> 26;
+        h3 = d3 & MASK;
+        d4 += d3 >> 26;
+        h4 = d4 & MASK;
+        h0 += (d4 >> 26) * 5;
+        h1 += h0 >> 26;
+        h0 &= MASK;
+    }
+
+    // Fully carry h, then compute h - p and keep it if it didn't underflow.
+    h2 += h1 >> 26;
+    h1 &= MASK;
+    h3 += h2 >> 26;
+    h2 &= MASK;
+    h4 += h3 >> 26;
+    h3 &= MASK;
+    h0 += (h4 >> 26) * 5;
+    h4 &= MASK;
+    h1 += h0 >> 26;
+    h0 &= MASK;
+
+    let mut g0 = h0 + 5;
+    let mut g1 = h1 + (g0 >> 26);
+    g0 &= MASK;
+    let mut g2 = h2 + (g1 >> 26);
+    g1 &= MASK;
+    let mut g3 = h3 + (g2 >> 26);
+    g2 &= MASK;
+    let g4 = h4 + (g3 >> 26);
+    g3 &= MASK;
+    let select = 0u64.wrapping_sub(g4 >> 26);
+    h0 = (h0 & !select) | (g0 & select);
+    h1 = (h1 & !select) | (g1 & select);
+    h2 = (h2 & !select) | (g2 & select);
+    h3 = (h3 & !select) | (g3 & select);
+    h4 = (h4 & !select) | (g4 & MASK & select);
+
+    let h = u128::from(h0)
+        | (u128::from(h1) << 26)
+        | (u128::from(h2) << 52)
+        | (u128::from(h3) << 78)
+        | (u128::from(h4) << 104);
+    let s = u128::from_le_bytes(key[16..].try_into().expect("16 bytes"));
+    h.wrapping_add(s).to_le_bytes()
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().expect("4 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::to_hex;
+
+    #[test]
+    fn test_scrypt_vectors() {
+        let mut out = [0u8; 64];
+        scrypt(b"", b"", 4, 1, 1, &mut out);
+        assert_eq!(
+            to_hex(&out),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+        scrypt(b"password", b"NaCl", 10, 8, 16, &mut out);
+        assert_eq!(
+            to_hex(&out),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+             2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+        );
+    }
+
+    #[test]
+    fn test_chacha20_poly1305_vector() {
+        let key: [u8; 32] = std::array::from_fn(|i| 0x80 + i as u8);
+        let nonce = [
+            0x07, 0, 0, 0, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+        ];
+        let aad = [
+            0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+        ];
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+            only one tip for the future, sunscreen would be it.";
+        let sealed = seal(&key, &nonce, &aad, plaintext);
+        assert_eq!(
+            to_hex(&sealed),
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+             3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+             92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+             3ff4def08e4b7a9de576d26586cec64b6116\
+             1ae10b594f09e26a7e902ecbd0600691"
+        );
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), plaintext);
+
+        let mut tampered = sealed.clone();
+        tampered[3] ^= 1;
+        assert!(open(&key, &nonce, &aad, &tampered).is_err());
+        assert!(open(&key, &nonce, b"other", &sealed).is_err());
+        assert!(open(&key, &nonce, &aad, &sealed[..10]).is_err());
+    }
+}
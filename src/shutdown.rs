@@ -0,0 +1,113 @@
+//! Cooperative shutdown signalling.
+//!
+//! A [`ShutdownToken`] is a cloneable flag that background threads poll or
+//! sleep on. Calling [`shutdown`](ShutdownToken::shutdown) on any clone wakes
+//! every thread blocked in [`wait`](ShutdownToken::wait) or
+//! [`wait_timeout`](ShutdownToken::wait_timeout), so loops can use it in
+//! place of `thread::sleep` and exit promptly.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Cloneable, one-way shutdown flag.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    stopped: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl ShutdownToken {
+    /// Create a token that has not been shut down.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request shutdown and wake all waiters. Idempotent.
+    pub fn shutdown(&self) {
+        *self.lock() = true;
+        self.inner.cond.notify_all();
+    }
+
+    /// Return `true` once shutdown has been requested.
+    pub fn is_shutdown(&self) -> bool {
+        *self.lock()
+    }
+
+    /// Block until shutdown is requested.
+    pub fn wait(&self) {
+        let mut stopped = self.lock();
+        while !*stopped {
+            stopped = self
+                .inner
+                .cond
+                .wait(stopped)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Sleep for up to `timeout`, returning early if shutdown is requested.
+    ///
+    /// Returns `true` if shutdown was requested.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut stopped = self.lock();
+        while !*stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            stopped = self
+                .inner
+                .cond
+                .wait_timeout(stopped, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        true
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, bool> {
+        self.inner.stopped.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_starts_running() {
+        let token = ShutdownToken::new();
+        assert!(!token.is_shutdown());
+        assert!(!token.wait_timeout(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn test_shutdown_visible_to_clones() {
+        let token = ShutdownToken::new();
+        let clone = token.clone();
+        clone.shutdown();
+        clone.shutdown();
+        assert!(token.is_shutdown());
+        assert!(token.wait_timeout(Duration::from_secs(60)));
+        token.wait();
+    }
+
+    #[test]
+    fn test_shutdown_wakes_sleeper() {
+        let token = ShutdownToken::new();
+        let sleeper = token.clone();
+        let start = Instant::now();
+        let handle = thread::spawn(move || sleeper.wait_timeout(Duration::from_secs(30)));
+        thread::sleep(Duration::from_millis(20));
+        token.shutdown();
+        assert!(handle.join().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,346 @@
+//! Directory snapshot and restore.
+//!
+//! [`capture`] records a cheap restore point of a workspace before an agent
+//! modifies it; [`restore`] puts the workspace back. A snapshot directory
+//! holds the file contents under `files/` and a `manifest.json` listing every
+//! file with its size and SHA-256, which [`verify`] and [`restore`] check.
+//!
+//! [`CaptureMode::HardLink`] makes capture nearly free, but a hard link
+//! shares the file's data: if the workspace later modifies a file *in place*
+//! (rather than writing a new file and renaming it over the old one), the
+//! snapshot changes too. The manifest hashes catch this — restore refuses a
+//! snapshot that no longer matches — so use [`CaptureMode::Copy`] when
+//! in-place writes are likely.
+//!
+//! Only regular files are captured; symlinks and empty directories are
+//! skipped.
+
+use crate::clock::now_ms;
+use crate::globs::Matcher;
+use crate::guard;
+use crate::hash::file_sha256_hex;
+use crate::state::{load_state, save_state};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const FILES_DIR: &str = "files";
+
+/// How file contents are stored in the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureMode {
+    /// Hard-link files into the snapshot, falling back to a copy when
+    /// linking fails (e.g. across filesystems).
+    #[default]
+    HardLink,
+    /// Always copy.
+    Copy,
+}
+
+/// Options for [`capture`].
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotOptions {
+    pub mode: CaptureMode,
+    /// Ignore patterns with `.gitignore` semantics (see [`crate::globs`]):
+    /// a pattern without `/` matches at any depth (`target`, `*.log`); one
+    /// with `/` is anchored to the root (`build/cache`). Ignored paths are
+    /// neither captured nor touched by [`restore`].
+    pub ignore: Vec<String>,
+}
+
+/// A file recorded in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// The snapshot manifest, stored as `manifest.json` in the snapshot dir.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Milliseconds since the Unix epoch when the snapshot was taken.
+    pub created_ms: u64,
+    pub mode: CaptureMode,
+    pub ignore: Vec<String>,
+    /// Files keyed by `/`-separated path relative to the snapshot root.
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+/// Snapshot `dir` into `dest`, which must not already exist.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::AlreadyExists` if `dest` exists, or any error
+/// from walking, linking/copying, or hashing.
+pub fn capture(dir: &Path, dest: &Path, options: &SnapshotOptions) -> io::Result<Manifest> {
+    if dest.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("snapshot destination {} already exists", dest.display()),
+        ));
+    }
+    let files_root = dest.join(FILES_DIR);
+    fs::create_dir_all(&files_root)?;
+
+    let mut manifest = Manifest {
+        created_ms: now_ms(),
+        mode: options.mode,
+        ignore: options.ignore.clone(),
+        files: BTreeMap::new(),
+    };
+
+    for rel in walk(dir, &options.ignore)? {
+        let src = dir.join(&rel);
+        let stored = files_root.join(&rel);
+        if let Some(parent) = stored.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let linked = options.mode == CaptureMode::HardLink && fs::hard_link(&src, &stored).is_ok();
+        if !linked {
+            fs::copy(&src, &stored)?;
+        }
+        let entry = FileEntry {
+            size: fs::metadata(&stored)?.len(),
+            sha256: file_sha256_hex(&stored)?,
+        };
+        manifest.files.insert(rel_key(&rel), entry);
+    }
+
+    save_state(&dest.join(MANIFEST_FILE), &manifest)?;
+    Ok(manifest)
+}
+
+/// Load a snapshot's manifest.
+pub fn manifest(dest: &Path) -> io::Result<Manifest> {
+    let path = dest.join(MANIFEST_FILE);
+    if !path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a snapshot (no manifest)", dest.display()),
+        ));
+    }
+    load_state(&path)
+}
+
+/// Check every file in a snapshot against its manifest entry.
+///
+/// Returns the relative paths of files that are missing or whose contents
+/// changed; an empty list means the snapshot is intact.
+pub fn verify(dest: &Path) -> io::Result<Vec<String>> {
+    let manifest = manifest(dest)?;
+    let files_root = dest.join(FILES_DIR);
+    let mut damaged = Vec::new();
+    for (key, entry) in &manifest.files {
+        let ok = match file_sha256_hex(&files_root.join(key)) {
+            Ok(hash) => hash == entry.sha256,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        if !ok {
+            damaged.push(key.clone());
+        }
+    }
+    Ok(damaged)
+}
+
+/// Restore the snapshot at `dest` into `dir`.
+///
+/// The snapshot is verified first; nothing in `dir` is touched if any file
+/// is damaged. Files are copied (never linked) back, so the snapshot stays
+/// usable. Files in `dir` that are not in the snapshot are deleted, except
+/// those matching the snapshot's ignore patterns.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::InvalidData` if verification fails.
+pub fn restore(dest: &Path, dir: &Path) -> io::Result<()> {
+    let damaged = verify(dest)?;
+    if !damaged.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "snapshot {} is damaged ({} file(s) changed: {})",
+                dest.display(),
+                damaged.len(),
+                damaged.join(", ")
+            ),
+        ));
+    }
+    let manifest = manifest(dest)?;
+    let files_root = dest.join(FILES_DIR);
+
+    fs::create_dir_all(dir)?;
+    for rel in walk(dir, &manifest.ignore)? {
+        if !manifest.files.contains_key(&rel_key(&rel)) {
+            fs::remove_file(dir.join(&rel))?;
+        }
+    }
+
+    for key in manifest.files.keys() {
+        let target = dir.join(key);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Copy beside the target, then rename over it: an interrupted
+        // restore never leaves a half-written file, and a target that is
+        // still hard-linked into the snapshot is replaced, not written
+        // through.
+        let tmp = guard::remove_file_on_drop(target.with_file_name(format!(
+            ".{}.restore.tmp",
+            target.file_name().unwrap_or_default().to_string_lossy()
+        )));
+        fs::copy(files_root.join(key), &*tmp)?;
+        fs::rename(&*tmp, &target)?;
+        tmp.disarm();
+    }
+    Ok(())
+}
+
+/// List regular files under `root` (relative paths, sorted), skipping
+/// ignored paths.
+fn walk(root: &Path, ignore: &[String]) -> io::Result<Vec<PathBuf>> {
+    let ignore = Matcher::new(ignore);
+    let mut out = Vec::new();
+    let mut stack = vec![PathBuf::new()];
+    while let Some(rel_dir) = stack.pop() {
+        let entries = match fs::read_dir(root.join(&rel_dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let rel = rel_dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if ignore.matches_dir(&rel) {
+                    continue;
+                }
+                stack.push(rel);
+            } else if file_type.is_file() && !ignore.matches(&rel) {
+                out.push(rel);
+            }
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+fn rel_key(rel: &Path) -> String {
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn workspace(tmp: &TempDir) -> PathBuf {
+        let ws = tmp.join("ws");
+        fs::create_dir_all(ws.join("src")).unwrap();
+        fs::create_dir_all(ws.join("target/debug")).unwrap();
+        fs::write(ws.join("Cargo.toml"), "[package]").unwrap();
+        fs::write(ws.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(ws.join("target/debug/app"), "binary").unwrap();
+        fs::write(ws.join("debug.log"), "noise").unwrap();
+        ws
+    }
+
+    fn options(mode: CaptureMode) -> SnapshotOptions {
+        SnapshotOptions {
+            mode,
+            ignore: vec!["target".into(), "*.log".into()],
+        }
+    }
+
+    #[test]
+    fn test_capture_honors_ignore_and_writes_manifest() {
+        let tmp = TempDir::new("apiari-snapshot-test-capture").unwrap();
+        let ws = workspace(&tmp);
+        let snap = tmp.join("snap");
+
+        let manifest = capture(&ws, &snap, &options(CaptureMode::Copy)).unwrap();
+        let keys: Vec<_> = manifest.files.keys().cloned().collect();
+        assert_eq!(keys, ["Cargo.toml", "src/main.rs"]);
+        assert_eq!(manifest.files["Cargo.toml"].size, 9);
+        assert_eq!(super::manifest(&snap).unwrap(), manifest);
+        assert!(verify(&snap).unwrap().is_empty());
+        assert!(capture(&ws, &snap, &options(CaptureMode::Copy)).is_err());
+    }
+
+    #[test]
+    fn test_restore_round_trip() {
+        let tmp = TempDir::new("apiari-snapshot-test-restore").unwrap();
+        let ws = workspace(&tmp);
+        let snap = tmp.join("snap");
+        capture(&ws, &snap, &options(CaptureMode::HardLink)).unwrap();
+
+        // Agent edits: replace one file, delete one, add one.
+        fs::remove_file(ws.join("src/main.rs")).unwrap();
+        fs::write(ws.join("src/main.rs"), "fn main() { panic!() }").unwrap();
+        fs::remove_file(ws.join("Cargo.toml")).unwrap();
+        fs::write(ws.join("src/new.rs"), "// new").unwrap();
+
+        restore(&snap, &ws).unwrap();
+        assert_eq!(
+            fs::read_to_string(ws.join("src/main.rs")).unwrap(),
+            "fn main() {}"
+        );
+        assert_eq!(
+            fs::read_to_string(ws.join("Cargo.toml")).unwrap(),
+            "[package]"
+        );
+        assert!(!ws.join("src/new.rs").exists());
+        // Ignored paths are left alone.
+        assert!(ws.join("target/debug/app").exists());
+        assert!(ws.join("debug.log").exists());
+    }
+
+    #[test]
+    fn test_in_place_edit_of_hard_link_is_detected() {
+        let tmp = TempDir::new("apiari-snapshot-test-inplace").unwrap();
+        let ws = workspace(&tmp);
+        let snap = tmp.join("snap");
+        capture(&ws, &snap, &options(CaptureMode::HardLink)).unwrap();
+
+        // Truncating in place writes through the hard link.
+        fs::write(ws.join("Cargo.toml"), "changed").unwrap();
+
+        assert_eq!(verify(&snap).unwrap(), ["Cargo.toml"]);
+        let err = restore(&snap, &ws).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // Nothing was touched.
+        assert!(ws.join("debug.log").exists());
+    }
+
+    #[test]
+    fn test_restore_into_empty_dir() {
+        let tmp = TempDir::new("apiari-snapshot-test-empty").unwrap();
+        let ws = workspace(&tmp);
+        let snap = tmp.join("snap");
+        capture(&ws, &snap, &SnapshotOptions::default()).unwrap();
+
+        let target = tmp.join("fresh");
+        restore(&snap, &target).unwrap();
+        assert!(target.join("target/debug/app").exists());
+        assert!(target.join("src/main.rs").exists());
+    }
+
+    #[test]
+    fn test_ignore_patterns() {
+        let patterns = Matcher::new(["node_modules", "build/cache", "*.tmp"]);
+        assert!(patterns.matches("node_modules/x/index.js"));
+        assert!(patterns.matches("pkg/node_modules"));
+        assert!(patterns.matches("build/cache/obj.o"));
+        assert!(!patterns.matches("src/build/cache"));
+        assert!(patterns.matches("a/b/file.tmp"));
+        assert!(!patterns.matches("src/lib.rs"));
+    }
+}
@@ -4,11 +4,22 @@
 //! serde's `Serialize` / `DeserializeOwned`. State files are written atomically
 //! (write to a temp file, then rename) so a crash mid-write never corrupts the
 //! on-disk state.
-
-use serde::Serialize;
+//!
+//! [`load_state_in`] and [`save_state_in`] do the same against any
+//! [`FileSystem`].
+//!
+//! State materialized from a JSONL log can be saved with [`save_with_log`],
+//! which stores the log reader's offset alongside it. [`load_with_log`]
+//! returns the state and a reader positioned at that offset, so a consumer
+//! resumes with exactly the records the snapshot hasn't seen: no gap, no
+//! overlap.
+
+use crate::ipc::JsonlReader;
+use crate::vfs::{FileSystem, StdFs};
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Load state from a JSON file.
 ///
@@ -19,9 +30,19 @@ use std::path::Path;
 ///
 /// Returns `io::Error` if the file exists but cannot be read or parsed.
 pub fn load_state<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
-    match std::fs::read_to_string(path) {
+    load_state_in(&StdFs, path)
+}
+
+/// Load state from a JSON file on the given filesystem.
+///
+/// Same semantics as [`load_state`].
+pub fn load_state_in<T: DeserializeOwned + Default>(
+    fs: &impl FileSystem,
+    path: &Path,
+) -> io::Result<T> {
+    match fs.read(path) {
         Ok(data) => {
-            serde_json::from_str(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
         }
         Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
         Err(e) => Err(e),
@@ -30,9 +51,10 @@ pub fn load_state<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
 
 /// Save state to a JSON file atomically.
 ///
-/// Writes to a temporary file in the same directory, then renames it into
-/// place. This guarantees that the state file is always either the old
-/// version or the new version, never a partially-written mix.
+/// Writes to a temporary file next to it (`<path>.tmp`, e.g.
+/// `state.json.tmp`), then renames it into place. This guarantees that the
+/// state file is always either the old version or the new version, never a
+/// partially-written mix.
 ///
 /// Parent directories are created automatically if they don't exist.
 ///
@@ -41,23 +63,114 @@ pub fn load_state<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
 /// Returns `io::Error` if serialization, directory creation, writing,
 /// or renaming fails.
 pub fn save_state<T: Serialize>(path: &Path, state: &T) -> io::Result<()> {
+    save_state_in(&StdFs, path, state)
+}
+
+/// Save state to a JSON file on the given filesystem.
+///
+/// Same semantics as [`save_state`]; atomicity is only as strong as the
+/// backend's [`FileSystem::replace`].
+pub fn save_state_in<T: Serialize>(fs: &impl FileSystem, path: &Path, state: &T) -> io::Result<()> {
     if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
+        fs.create_dir_all(parent)?;
     }
 
     let data = serde_json::to_string_pretty(state).map_err(io::Error::other)?;
+    fs.replace(path, data.as_bytes())
+}
 
-    // Write to a sibling temp file, then atomically rename.
-    let tmp_path = path.with_extension("json.tmp");
-    std::fs::write(&tmp_path, &data)?;
-    std::fs::rename(&tmp_path, path)?;
+/// On-disk form of a state saved with [`save_with_log`].
+#[derive(Serialize, Deserialize)]
+struct WithLog<T> {
+    log_offset: u64,
+    state: T,
+}
+
+/// Save `state` along with `log`'s current offset, atomically.
+///
+/// Call this right after applying the records returned by `log.poll()`,
+/// so the offset marks exactly the records `state` includes.
+pub fn save_with_log<T: Serialize, M: DeserializeOwned, F: FileSystem>(
+    path: &Path,
+    state: &T,
+    log: &JsonlReader<M, F>,
+) -> io::Result<()> {
+    save_with_log_in(&StdFs, path, state, log)
+}
+
+/// Same as [`save_with_log`], on the given filesystem.
+pub fn save_with_log_in<T: Serialize, M: DeserializeOwned, F: FileSystem>(
+    fs: &impl FileSystem,
+    path: &Path,
+    state: &T,
+    log: &JsonlReader<M, F>,
+) -> io::Result<()> {
+    let snapshot = WithLog {
+        log_offset: log.offset(),
+        state,
+    };
+    save_state_in(fs, path, &snapshot)
+}
+
+/// Load a state saved with [`save_with_log`] and a reader for `log`
+/// positioned where the state left off.
+///
+/// A missing state file gives the default state and a reader at offset 0.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::InvalidData` if the state can't be parsed, or if
+/// `log` is now shorter than the saved offset (it was truncated or replaced,
+/// so the offset no longer points between the same records).
+pub fn load_with_log<T: DeserializeOwned + Default, M: DeserializeOwned>(
+    path: &Path,
+    log: impl Into<PathBuf>,
+) -> io::Result<(T, JsonlReader<M>)> {
+    load_with_log_in(&StdFs, path, log)
+}
 
-    Ok(())
+/// Same as [`load_with_log`], on the given filesystem.
+pub fn load_with_log_in<T, M, F>(
+    fs: &F,
+    path: &Path,
+    log: impl Into<PathBuf>,
+) -> io::Result<(T, JsonlReader<M, F>)>
+where
+    T: DeserializeOwned + Default,
+    M: DeserializeOwned,
+    F: FileSystem + Clone,
+{
+    let log = log.into();
+    let (state, offset) = match fs.read(path) {
+        Ok(data) => {
+            let snapshot: WithLog<T> = serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            (snapshot.state, snapshot.log_offset)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (T::default(), 0),
+        Err(e) => return Err(e),
+    };
+    let len = match fs.metadata(&log) {
+        Ok(meta) => meta.len,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+    if len < offset {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is {len} bytes, shorter than the snapshot's offset {offset}",
+                log.display()
+            ),
+        ));
+    }
+    Ok((state, JsonlReader::with_offset_in(fs.clone(), log, offset)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vfs::MemoryFs;
     use serde::{Deserialize, Serialize};
     use std::fs;
 
@@ -176,4 +289,51 @@ mod tests {
 
         let _ = fs::remove_dir_all(&dir);
     }
+
+    #[test]
+    fn test_memory_fs_save_and_load() {
+        let mem = MemoryFs::new();
+        let path = Path::new("/state/app/state.json");
+
+        let missing: TestState = load_state_in(&mem, path).unwrap();
+        assert_eq!(missing, TestState::default());
+
+        let state = TestState {
+            counter: 5,
+            name: "memory".into(),
+        };
+        save_state_in(&mem, path, &state).unwrap();
+        let loaded: TestState = load_state_in(&mem, path).unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(mem.files(), vec![path.to_path_buf()]);
+    }
+
+    #[test]
+    fn test_load_with_log_resumes_at_snapshot_offset() {
+        use crate::ipc::JsonlWriter;
+
+        let mem = MemoryFs::new();
+        let (path, log) = (Path::new("/state.json"), Path::new("/events.jsonl"));
+        let writer = JsonlWriter::<u64, _>::new_in(mem.clone(), log);
+        for n in [1, 2, 3] {
+            writer.append(&n).unwrap();
+        }
+
+        let (mut sum, mut reader): (u64, JsonlReader<u64, _>) =
+            load_with_log_in(&mem, path, log).unwrap();
+        assert_eq!((sum, reader.offset()), (0, 0));
+        sum += reader.poll().unwrap().iter().sum::<u64>();
+        save_with_log_in(&mem, path, &sum, &reader).unwrap();
+
+        writer.append(&10).unwrap();
+        let (sum, mut reader): (u64, JsonlReader<u64, _>) =
+            load_with_log_in(&mem, path, log).unwrap();
+        assert_eq!(sum, 6);
+        assert_eq!(reader.poll().unwrap(), [10]);
+
+        // A log replaced by a shorter one no longer lines up.
+        mem.write(log, b"1\n").unwrap();
+        let err = load_with_log_in::<u64, u64, _>(&mem, path, log).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
 }
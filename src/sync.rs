@@ -0,0 +1,322 @@
+//! Cross-process synchronization primitives.
+//!
+//! [`Barrier`] lets a known number of processes rendezvous ("wait until all
+//! four workers are ready") through a shared directory. Each arriving
+//! participant claims the lowest free numbered slot file with an exclusive
+//! create, which is atomic on every platform; the barrier releases once
+//! slot `expected - 1` exists, so every participant observes the release
+//! regardless of arrival order.
+//!
+//! A barrier is single-use: once full, later joins fail with
+//! `AlreadyExists` until [`Barrier::reset`] clears it.
+//!
+//! [`Semaphore`] caps how many processes do something at once ("at most two
+//! builds machine-wide"). Each of its permits is a [`LockFile`] in the
+//! semaphore directory, so a permit held by a process that died is
+//! reclaimed the same way a stale lock is.
+
+use crate::guard;
+use crate::lock::{self, LockFile};
+use crate::poll::poll_until;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A participant's place in a released barrier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Barrier {
+    dir: PathBuf,
+    index: usize,
+    expected: usize,
+}
+
+impl Barrier {
+    /// Arrive at barrier `name` in `dir` and wait until `expected`
+    /// participants (including this one) have arrived.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TimedOut` if the quorum isn't reached in time (this
+    /// participant's slot is withdrawn), `AlreadyExists` if the barrier is
+    /// already full, and `InvalidInput` if `expected` is 0.
+    pub fn join(dir: &Path, name: &str, expected: usize, timeout: Duration) -> io::Result<Self> {
+        if expected == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a barrier needs at least one participant",
+            ));
+        }
+        let dir = barrier_dir(dir, name);
+        fs::create_dir_all(&dir)?;
+        let index = claim(&dir, expected)?;
+        // Withdraw the slot if we time out (or panic) before the release.
+        let claimed = guard::remove_file_on_drop(slot(&dir, index));
+        let last = slot(&dir, expected - 1);
+        if let Err(e) = poll_until(POLL_INTERVAL, timeout, || last.exists().then_some(())) {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("barrier `{name}` not reached: {e}"),
+            ));
+        }
+        claimed.disarm();
+        Ok(Self {
+            dir,
+            index,
+            expected,
+        })
+    }
+
+    /// Remove barrier `name` in `dir` so it can be used again.
+    pub fn reset(dir: &Path, name: &str) -> io::Result<()> {
+        match fs::remove_dir_all(barrier_dir(dir, name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// This participant's arrival order, from 0.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// `true` for the first participant to arrive, a convenient choice for
+    /// one-time setup after the rendezvous.
+    pub fn is_leader(&self) -> bool {
+        self.index == 0
+    }
+
+    pub fn parties(&self) -> usize {
+        self.expected
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// A counting semaphore shared by every process using the same directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Semaphore {
+    dir: PathBuf,
+    max_permits: usize,
+}
+
+impl Semaphore {
+    /// A semaphore in `dir` allowing `max_permits` concurrent holders. All
+    /// users of a directory should agree on `max_permits`.
+    pub fn new(dir: impl Into<PathBuf>, max_permits: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            max_permits,
+        }
+    }
+
+    /// Take a permit if one is free (or held by a dead process).
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` if the semaphore has no permits.
+    pub fn try_acquire(&self) -> io::Result<Option<Permit>> {
+        if self.max_permits == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a semaphore needs at least one permit",
+            ));
+        }
+        for index in 0..self.max_permits {
+            if let Some(lock) = LockFile::try_acquire(self.permit_path(index))? {
+                return Ok(Some(Permit { index, lock }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Wait up to `timeout` for a permit.
+    ///
+    /// # Errors
+    ///
+    /// Returns `io::ErrorKind::WouldBlock` if every permit is still held
+    /// when the timeout expires.
+    pub fn acquire(&self, timeout: Duration) -> io::Result<Permit> {
+        let mut result = None;
+        let waited = poll_until(POLL_INTERVAL, timeout, || match self.try_acquire() {
+            Ok(None) => None,
+            other => {
+                result = Some(other);
+                Some(())
+            }
+        });
+        match (waited, result) {
+            (Ok(()), Some(Ok(Some(permit)))) => Ok(permit),
+            (_, Some(Err(e))) => Err(e),
+            _ => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!(
+                    "all {} permits in {} are held",
+                    self.max_permits,
+                    self.dir.display()
+                ),
+            )),
+        }
+    }
+
+    /// Number of permits currently held by live processes.
+    pub fn in_use(&self) -> io::Result<usize> {
+        let mut held = 0;
+        for index in 0..self.max_permits {
+            if !lock::is_stale(&self.permit_path(index))? {
+                held += 1;
+            }
+        }
+        Ok(held)
+    }
+
+    pub fn max_permits(&self) -> usize {
+        self.max_permits
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn permit_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("permit-{index}.lock"))
+    }
+}
+
+/// A held [`Semaphore`] permit, released on drop.
+#[derive(Debug)]
+pub struct Permit {
+    index: usize,
+    lock: LockFile,
+}
+
+impl Permit {
+    /// Which permit slot this is, from 0.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn path(&self) -> &Path {
+        self.lock.path()
+    }
+}
+
+fn barrier_dir(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.barrier"))
+}
+
+fn slot(dir: &Path, index: usize) -> PathBuf {
+    dir.join(index.to_string())
+}
+
+/// Exclusively create the lowest free slot below `expected`.
+fn claim(dir: &Path, expected: usize) -> io::Result<usize> {
+    for index in 0..expected {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(slot(dir, index))
+        {
+            Ok(mut file) => {
+                writeln!(file, "{}", process::id())?;
+                return Ok(index);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("barrier {} is already full", dir.display()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::thread;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_barrier_releases_all_parties() {
+        let tmp = TempDir::new("apiari-sync-test-barrier").unwrap();
+        let dir = tmp.path().to_path_buf();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let dir = dir.clone();
+                thread::spawn(move || Barrier::join(&dir, "ready", 4, WAIT).unwrap())
+            })
+            .collect();
+        let mut indices: Vec<usize> = workers
+            .into_iter()
+            .map(|w| w.join().unwrap().index())
+            .collect();
+        indices.sort();
+        assert_eq!(indices, [0, 1, 2, 3]);
+
+        let err = Barrier::join(&dir, "ready", 4, WAIT).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        Barrier::reset(&dir, "ready").unwrap();
+        Barrier::reset(&dir, "ready").unwrap();
+        let solo = Barrier::join(&dir, "ready", 1, WAIT).unwrap();
+        assert!(solo.is_leader());
+        assert_eq!(solo.parties(), 1);
+    }
+
+    #[test]
+    fn test_barrier_timeout_withdraws() {
+        let tmp = TempDir::new("apiari-sync-test-barrier-timeout").unwrap();
+        let err = Barrier::join(tmp.path(), "ready", 2, Duration::from_millis(30)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // The withdrawn slot is free again, so two new parties still meet.
+        let dir = tmp.path().to_path_buf();
+        let other = thread::spawn(move || Barrier::join(&dir, "ready", 2, WAIT).unwrap());
+        let here = Barrier::join(tmp.path(), "ready", 2, WAIT).unwrap();
+        let there = other.join().unwrap();
+        assert_ne!(here.index(), there.index());
+
+        let err = Barrier::join(tmp.path(), "x", 0, WAIT).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_semaphore_caps_holders() {
+        let tmp = TempDir::new("apiari-sync-test-semaphore").unwrap();
+        let sem = Semaphore::new(tmp.join("builds"), 2);
+        let a = sem.try_acquire().unwrap().unwrap();
+        let b = sem.acquire(WAIT).unwrap();
+        assert_ne!(a.index(), b.index());
+        assert_eq!(sem.in_use().unwrap(), 2);
+        assert!(sem.try_acquire().unwrap().is_none());
+        let err = sem.acquire(Duration::from_millis(30)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        let waiter = {
+            let sem = sem.clone();
+            thread::spawn(move || sem.acquire(WAIT).map(|p| p.index()))
+        };
+        thread::sleep(Duration::from_millis(30));
+        let freed = a.index();
+        drop(a);
+        assert_eq!(waiter.join().unwrap().unwrap(), freed);
+        assert!(Semaphore::new(tmp.path(), 0).try_acquire().is_err());
+    }
+
+    #[test]
+    fn test_semaphore_reclaims_dead_holder() {
+        let tmp = TempDir::new("apiari-sync-test-semaphore-stale").unwrap();
+        let sem = Semaphore::new(tmp.path(), 1);
+        fs::write(tmp.join("permit-0.lock"), "999999999\n").unwrap();
+        assert_eq!(sem.in_use().unwrap(), 0);
+        let permit = sem.try_acquire().unwrap().unwrap();
+        assert_eq!(lock::owner(permit.path()).unwrap(), Some(process::id()));
+    }
+}
@@ -0,0 +1,238 @@
+//! Host environment detection for bug reports and capability gating.
+//!
+//! [`collect`] gathers a [`SystemInfo`] once per process and returns the
+//! cached value afterwards. Each probe is also available on its own
+//! ([`default_shell`], [`git_version`], [`container`], ...) and is likewise
+//! cached, so callers that need one fact don't pay for the rest (running
+//! `git --version` takes a few milliseconds).
+//!
+//! Probes never fail: anything that can't be determined is `None`.
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// A snapshot of the host environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemInfo {
+    /// `linux`, `macos`, `windows`, ... (as in `std::env::consts::OS`).
+    pub os: String,
+    /// Distribution or release, e.g. `Ubuntu 24.04 LTS` or `14.5`.
+    pub os_version: Option<String>,
+    /// `x86_64`, `aarch64`, ...
+    pub arch: String,
+    pub cpus: usize,
+    pub shell: Option<String>,
+    /// e.g. `2.43.0`; `None` if git is not installed.
+    pub git_version: Option<String>,
+    /// Container runtime, if running inside one.
+    pub container: Option<String>,
+    /// Running under Windows Subsystem for Linux.
+    pub wsl: bool,
+    /// Running in a CI environment (`CI` is set).
+    pub ci: bool,
+}
+
+/// Detect (once) and return the host environment.
+pub fn collect() -> &'static SystemInfo {
+    static INFO: OnceLock<SystemInfo> = OnceLock::new();
+    INFO.get_or_init(|| SystemInfo {
+        os: env::consts::OS.to_string(),
+        os_version: os_version().map(str::to_string),
+        arch: env::consts::ARCH.to_string(),
+        cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        shell: default_shell().map(str::to_string),
+        git_version: git_version().map(str::to_string),
+        container: container().map(str::to_string),
+        wsl: is_wsl(),
+        ci: env::var_os("CI").is_some_and(|v| !v.is_empty() && v != "false" && v != "0"),
+    })
+}
+
+/// The user's login shell: `$SHELL` on Unix (falling back to `/bin/sh`),
+/// `%COMSPEC%` on Windows.
+pub fn default_shell() -> Option<&'static str> {
+    static SHELL: OnceLock<Option<String>> = OnceLock::new();
+    SHELL
+        .get_or_init(|| {
+            let var = if cfg!(windows) { "COMSPEC" } else { "SHELL" };
+            env::var(var)
+                .ok()
+                .filter(|s| !s.is_empty())
+                .or_else(|| cfg!(unix).then(|| "/bin/sh".to_string()))
+        })
+        .as_deref()
+}
+
+/// The installed git version, from `git --version`.
+pub fn git_version() -> Option<&'static str> {
+    static GIT: OnceLock<Option<String>> = OnceLock::new();
+    GIT.get_or_init(|| run("git", &["--version"]).and_then(|out| parse_git_version(&out)))
+        .as_deref()
+}
+
+/// The OS release name or version.
+pub fn os_version() -> Option<&'static str> {
+    static VERSION: OnceLock<Option<String>> = OnceLock::new();
+    VERSION
+        .get_or_init(|| {
+            if cfg!(target_os = "linux") {
+                fs::read_to_string("/etc/os-release")
+                    .ok()
+                    .and_then(|text| parse_os_release(&text))
+            } else if cfg!(target_os = "macos") {
+                run("sw_vers", &["-productVersion"])
+            } else if cfg!(windows) {
+                run("cmd", &["/C", "ver"])
+            } else {
+                run("uname", &["-r"])
+            }
+        })
+        .as_deref()
+}
+
+/// The container runtime this process runs in (`docker`, `podman`,
+/// `kubernetes`, `containerd`, `lxc`, or the value of `$container`).
+pub fn container() -> Option<&'static str> {
+    static CONTAINER: OnceLock<Option<String>> = OnceLock::new();
+    CONTAINER
+        .get_or_init(|| {
+            if env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+                return Some("kubernetes".to_string());
+            }
+            if Path::new("/.dockerenv").exists() {
+                return Some("docker".to_string());
+            }
+            if Path::new("/run/.containerenv").exists() {
+                return Some("podman".to_string());
+            }
+            if let Ok(name) = env::var("container")
+                && !name.is_empty()
+            {
+                return Some(name);
+            }
+            fs::read_to_string("/proc/1/cgroup")
+                .ok()
+                .and_then(|text| parse_cgroup(&text))
+                .map(str::to_string)
+        })
+        .as_deref()
+}
+
+/// Return `true` under Windows Subsystem for Linux.
+pub fn is_wsl() -> bool {
+    static WSL: OnceLock<bool> = OnceLock::new();
+    *WSL.get_or_init(|| {
+        cfg!(target_os = "linux")
+            && fs::read_to_string("/proc/sys/kernel/osrelease")
+                .is_ok_and(|r| r.to_ascii_lowercase().contains("microsoft"))
+    })
+}
+
+/// Run a command and return its trimmed stdout if it succeeds.
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    (out.status.success() && !text.is_empty()).then_some(text)
+}
+
+/// `git version 2.39.3 (Apple Git-146)` → `2.39.3`.
+fn parse_git_version(output: &str) -> Option<String> {
+    output
+        .strip_prefix("git version ")?
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+}
+
+/// `PRETTY_NAME` from os-release, falling back to `NAME VERSION_ID`.
+fn parse_os_release(text: &str) -> Option<String> {
+    let field = |key: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(|v| v.trim().trim_matches('"').to_string())
+            .filter(|v| !v.is_empty())
+    };
+    field("PRETTY_NAME").or_else(|| match (field("NAME"), field("VERSION_ID")) {
+        (Some(name), Some(version)) => Some(format!("{name} {version}")),
+        (name, _) => name,
+    })
+}
+
+/// Infer a container runtime from `/proc/1/cgroup`.
+fn parse_cgroup(text: &str) -> Option<&'static str> {
+    [
+        ("kubepods", "kubernetes"),
+        ("docker", "docker"),
+        ("libpod", "podman"),
+        ("containerd", "containerd"),
+        ("lxc", "lxc"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| text.contains(marker))
+    .map(|(_, name)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_is_cached_and_serializable() {
+        let info = collect();
+        assert!(std::ptr::eq(info, collect()));
+        assert_eq!(info.os, env::consts::OS);
+        assert!(info.cpus >= 1);
+        let json = serde_json::to_string(info).unwrap();
+        let back: SystemInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(&back, info);
+    }
+
+    #[test]
+    fn test_parse_git_version() {
+        assert_eq!(
+            parse_git_version("git version 2.43.0").as_deref(),
+            Some("2.43.0")
+        );
+        assert_eq!(
+            parse_git_version("git version 2.39.3 (Apple Git-146)").as_deref(),
+            Some("2.39.3")
+        );
+        assert_eq!(parse_git_version("command not found"), None);
+    }
+
+    #[test]
+    fn test_parse_os_release() {
+        let ubuntu = "NAME=\"Ubuntu\"\nVERSION_ID=\"24.04\"\nPRETTY_NAME=\"Ubuntu 24.04 LTS\"\n";
+        assert_eq!(
+            parse_os_release(ubuntu).as_deref(),
+            Some("Ubuntu 24.04 LTS")
+        );
+        assert_eq!(
+            parse_os_release("NAME=Alpine\nVERSION_ID=3.20\n").as_deref(),
+            Some("Alpine 3.20")
+        );
+        assert_eq!(parse_os_release(""), None);
+    }
+
+    #[test]
+    fn test_parse_cgroup() {
+        assert_eq!(
+            parse_cgroup("0::/system.slice/docker-abc.scope"),
+            Some("docker")
+        );
+        assert_eq!(
+            parse_cgroup("12:pids:/kubepods/burstable/pod1"),
+            Some("kubernetes")
+        );
+        assert_eq!(parse_cgroup("0::/init.scope"), None);
+    }
+}
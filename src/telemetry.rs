@@ -0,0 +1,439 @@
+//! Opt-in, anonymous usage telemetry.
+//!
+//! Nothing is recorded unless the user has opted in: consent lives in a
+//! `consent.json` state file in the telemetry directory and defaults to off.
+//! Recorded events are queued to a background thread and appended to a
+//! local `spool.jsonl`, capped in size; [`record`](Telemetry::record) never
+//! blocks and silently drops events when the queue or spool is full.
+//!
+//! An uploader (possibly another process) empties the spool with [`drain`].
+//! Revoking consent deletes the spool. The writer re-reads `consent.json`
+//! around every append, so an opt-out made by another process (through the
+//! free [`set_consent`]) stops a running recorder at its next event; an
+//! opt-in elsewhere only takes effect when the recorder is reopened.
+
+use crate::clock::now_ms;
+use crate::ipc::JsonlWriter;
+use crate::state::{load_state, save_state};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const CONSENT_FILE: &str = "consent.json";
+const SPOOL_FILE: &str = "spool.jsonl";
+const DRAINING_FILE: &str = "spool.draining.jsonl";
+
+/// The user's telemetry decision, as stored in `consent.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Consent {
+    pub enabled: bool,
+    /// When the decision was made (ms since the Unix epoch); `None` if the
+    /// user was never asked.
+    #[serde(default)]
+    pub decided_at_ms: Option<u64>,
+}
+
+/// How a command ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+    Cancelled,
+}
+
+/// One usage event. Keep `props` free of anything identifying.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub ts_ms: u64,
+    pub command: String,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub props: BTreeMap<String, Value>,
+}
+
+impl Event {
+    /// Create an event stamped with the current time.
+    pub fn new(command: impl Into<String>, duration: Duration, outcome: Outcome) -> Self {
+        Self {
+            ts_ms: now_ms(),
+            command: command.into(),
+            duration_ms: duration.as_millis() as u64,
+            outcome,
+            props: BTreeMap::new(),
+        }
+    }
+
+    /// Attach a property.
+    pub fn prop(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.props.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Limits for a [`Telemetry`] recorder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetryOptions {
+    /// Events beyond this spool size are dropped until the next drain.
+    pub max_spool_bytes: u64,
+    /// Events queued for the writer thread before `record` starts dropping.
+    pub queue_len: usize,
+}
+
+impl Default for TelemetryOptions {
+    fn default() -> Self {
+        Self {
+            max_spool_bytes: 1024 * 1024,
+            queue_len: 1024,
+        }
+    }
+}
+
+enum Msg {
+    Event(Event),
+    Flush(Sender<()>),
+}
+
+/// Records events to the spool in a telemetry directory.
+#[derive(Debug)]
+pub struct Telemetry {
+    dir: PathBuf,
+    enabled: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    tx: Option<SyncSender<Msg>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Telemetry {
+    /// Open the telemetry directory with default limits.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::with_options(dir, TelemetryOptions::default())
+    }
+
+    /// Open the telemetry directory with explicit limits.
+    pub fn with_options(dir: impl Into<PathBuf>, options: TelemetryOptions) -> io::Result<Self> {
+        let dir = dir.into();
+        let consent = consent(&dir)?;
+        let enabled = Arc::new(AtomicBool::new(consent.enabled));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (tx, rx) = mpsc::sync_channel(options.queue_len);
+
+        let writer = Writer {
+            dir: dir.clone(),
+            spool: JsonlWriter::new(dir.join(SPOOL_FILE)),
+            enabled: Arc::clone(&enabled),
+            dropped: Arc::clone(&dropped),
+            max_spool_bytes: options.max_spool_bytes,
+        };
+        let thread = thread::spawn(move || writer.run(rx));
+        Ok(Self {
+            dir,
+            enabled,
+            dropped,
+            tx: Some(tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Return `true` if the user has opted in.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Record the user's decision. Opting out also deletes the spool.
+    pub fn set_consent(&self, enabled: bool) -> io::Result<()> {
+        if enabled {
+            set_consent(&self.dir, true)?;
+            self.enabled.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
+        // Stop recording and let the writer finish any event it is in the
+        // middle of, so nothing recreates the spool after it is deleted.
+        self.enabled.store(false, Ordering::Relaxed);
+        self.flush();
+        set_consent(&self.dir, false)
+    }
+
+    /// Queue an event. Never blocks; a no-op without consent.
+    pub fn record(&self, event: Event) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(tx) = &self.tx
+            && let Err(TrySendError::Full(_)) = tx.try_send(Msg::Event(event))
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Events dropped because the queue or spool was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Wait until every event queued so far has been written.
+    pub fn flush(&self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        if let Some(tx) = &self.tx
+            && tx.send(Msg::Flush(done_tx)).is_ok()
+        {
+            let _ = done_rx.recv();
+        }
+    }
+
+    /// The telemetry directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        // Closing the channel lets the writer finish the queue and exit.
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Writer {
+    dir: PathBuf,
+    spool: JsonlWriter<Event>,
+    enabled: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    max_spool_bytes: u64,
+}
+
+impl Writer {
+    fn run(self, rx: Receiver<Msg>) {
+        for msg in rx {
+            match msg {
+                Msg::Event(event) => {
+                    if !self.write(&event) {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Msg::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write(&self, event: &Event) -> bool {
+        // Consent may have been revoked while the event sat in the queue,
+        // here or by another process.
+        if !self.enabled.load(Ordering::Relaxed) || !self.consented() {
+            return true;
+        }
+        let Ok(line) = serde_json::to_string(event) else {
+            return false;
+        };
+        let size = fs::metadata(self.spool.path()).map_or(0, |m| m.len());
+        if size + line.len() as u64 + 1 > self.max_spool_bytes {
+            return false;
+        }
+        let written = self.spool.append(event).is_ok();
+        // Another process may have opted out (and deleted the spool) while
+        // we appended; don't leave the event behind.
+        if !self.consented() {
+            let _ = fs::remove_file(self.spool.path());
+        }
+        written
+    }
+
+    /// Whether consent on disk is still given. A revocation found here
+    /// also stops `record` from queueing.
+    fn consented(&self) -> bool {
+        let enabled = consent(&self.dir).is_ok_and(|c| c.enabled);
+        if !enabled {
+            self.enabled.store(false, Ordering::Relaxed);
+        }
+        enabled
+    }
+}
+
+/// Read the consent state in `dir` (off if never decided).
+pub fn consent(dir: impl AsRef<Path>) -> io::Result<Consent> {
+    load_state(&dir.as_ref().join(CONSENT_FILE))
+}
+
+/// Record the user's decision in `dir`. Opting out also deletes any
+/// spooled events.
+pub fn set_consent(dir: impl AsRef<Path>, enabled: bool) -> io::Result<()> {
+    let dir = dir.as_ref();
+    save_state(
+        &dir.join(CONSENT_FILE),
+        &Consent {
+            enabled,
+            decided_at_ms: Some(now_ms()),
+        },
+    )?;
+    if !enabled {
+        for file in [SPOOL_FILE, DRAINING_FILE] {
+            match fs::remove_file(dir.join(file)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hand spooled events in `dir` to `upload` and delete them once it
+/// succeeds. Returns the number of events uploaded.
+///
+/// The spool is moved aside before reading, so recorders can keep appending
+/// while an upload is in progress. If `upload` fails the batch is kept and
+/// offered again (ahead of newer events) on the next drain.
+pub fn drain(
+    dir: impl AsRef<Path>,
+    upload: impl FnOnce(&[Event]) -> io::Result<()>,
+) -> io::Result<usize> {
+    let dir = dir.as_ref();
+    let draining = dir.join(DRAINING_FILE);
+    if !draining.exists() {
+        match fs::rename(dir.join(SPOOL_FILE), &draining) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            other => other?,
+        }
+    }
+
+    let mut events = Vec::new();
+    for line in BufReader::new(fs::File::open(&draining)?).lines() {
+        // A torn final line from a crashed writer is not worth failing on.
+        if let Ok(event) = serde_json::from_str(&line?) {
+            events.push(event);
+        }
+    }
+    if !events.is_empty() {
+        upload(&events)?;
+    }
+    fs::remove_file(&draining)?;
+    Ok(events.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn event(command: &str) -> Event {
+        Event::new(command, Duration::from_millis(12), Outcome::Success)
+    }
+
+    fn drained(dir: &Path) -> Vec<String> {
+        let mut commands = Vec::new();
+        drain(dir, |events| {
+            commands.extend(events.iter().map(|e| e.command.clone()));
+            Ok(())
+        })
+        .unwrap();
+        commands
+    }
+
+    #[test]
+    fn test_nothing_recorded_without_consent() {
+        let tmp = TempDir::new("apiari-telemetry-test-off").unwrap();
+        let telemetry = Telemetry::open(tmp.path()).unwrap();
+        assert!(!telemetry.is_enabled());
+        assert_eq!(consent(tmp.path()).unwrap().decided_at_ms, None);
+
+        telemetry.record(event("swarm start"));
+        telemetry.flush();
+        assert!(!tmp.join(SPOOL_FILE).exists());
+        assert!(drained(tmp.path()).is_empty());
+    }
+
+    #[test]
+    fn test_record_and_drain() {
+        let tmp = TempDir::new("apiari-telemetry-test-drain").unwrap();
+        set_consent(tmp.path(), true).unwrap();
+        let telemetry = Telemetry::open(tmp.path()).unwrap();
+        assert!(telemetry.is_enabled());
+
+        telemetry.record(event("swarm start").prop("agents", 3));
+        telemetry.record(Event::new("swarm stop", Duration::ZERO, Outcome::Cancelled));
+        telemetry.flush();
+
+        assert_eq!(drained(tmp.path()), ["swarm start", "swarm stop"]);
+        assert!(drained(tmp.path()).is_empty(), "drain empties the spool");
+    }
+
+    #[test]
+    fn test_spool_cap_drops_events() {
+        let tmp = TempDir::new("apiari-telemetry-test-cap").unwrap();
+        set_consent(tmp.path(), true).unwrap();
+        let line_len = serde_json::to_string(&event("cmd")).unwrap().len() as u64 + 1;
+        let telemetry = Telemetry::with_options(
+            tmp.path(),
+            TelemetryOptions {
+                max_spool_bytes: line_len * 2 + line_len / 2,
+                ..TelemetryOptions::default()
+            },
+        )
+        .unwrap();
+
+        for _ in 0..5 {
+            telemetry.record(event("cmd"));
+        }
+        telemetry.flush();
+        assert_eq!(telemetry.dropped(), 3);
+        assert_eq!(drained(tmp.path()).len(), 2);
+    }
+
+    #[test]
+    fn test_failed_upload_is_retried() {
+        let tmp = TempDir::new("apiari-telemetry-test-retry").unwrap();
+        set_consent(tmp.path(), true).unwrap();
+        let telemetry = Telemetry::open(tmp.path()).unwrap();
+        telemetry.record(event("first"));
+        telemetry.flush();
+
+        let err = drain(tmp.path(), |_| Err(io::Error::other("offline")));
+        assert!(err.is_err());
+
+        telemetry.record(event("second"));
+        telemetry.flush();
+        assert_eq!(drained(tmp.path()), ["first"]);
+        assert_eq!(drained(tmp.path()), ["second"]);
+    }
+
+    #[test]
+    fn test_opt_out_deletes_spool() {
+        let tmp = TempDir::new("apiari-telemetry-test-optout").unwrap();
+        set_consent(tmp.path(), true).unwrap();
+        let telemetry = Telemetry::open(tmp.path()).unwrap();
+        telemetry.record(event("cmd"));
+        telemetry.flush();
+        assert!(tmp.join(SPOOL_FILE).exists());
+
+        telemetry.set_consent(false).unwrap();
+        assert!(!telemetry.is_enabled());
+        assert!(!tmp.join(SPOOL_FILE).exists());
+        assert!(!consent(tmp.path()).unwrap().enabled);
+    }
+
+    #[test]
+    fn test_opt_out_from_another_process_stops_recorder() {
+        let tmp = TempDir::new("apiari-telemetry-test-optout-elsewhere").unwrap();
+        set_consent(tmp.path(), true).unwrap();
+        let telemetry = Telemetry::open(tmp.path()).unwrap();
+
+        set_consent(tmp.path(), false).unwrap();
+        telemetry.record(event("cmd"));
+        telemetry.flush();
+        assert!(!tmp.join(SPOOL_FILE).exists());
+        assert!(!telemetry.is_enabled());
+    }
+}
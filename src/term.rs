@@ -0,0 +1,238 @@
+//! Terminal capability detection: TTYs, color, and width.
+//!
+//! Every Apiari CLI should make the same call about color and interactivity
+//! so output behaves consistently when piped. [`ColorChoice::Auto`] resolves
+//! in this order:
+//!
+//! 1. `CLICOLOR_FORCE` set to anything but `0` → color, even when piped.
+//! 2. `NO_COLOR` set to a non-empty value → no color (<https://no-color.org>).
+//! 3. `TERM=dumb` or `CLICOLOR=0` → no color.
+//! 4. Otherwise color only if the stream is a terminal.
+//!
+//! [`width`] prefers `$COLUMNS`, then asks the terminal (`stty size` on Unix,
+//! `mode con` on Windows), and falls back to [`DEFAULT_WIDTH`].
+//!
+//! [`Markdown`] renders agent responses for the terminal, wrapped to the
+//! width and styled only when color is enabled.
+
+mod markdown;
+
+pub use markdown::Markdown;
+
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Width assumed when the terminal can't be queried.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// A standard stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+/// Return `true` if `stream` is attached to a terminal.
+pub fn is_tty(stream: Stream) -> bool {
+    match stream {
+        Stream::Stdin => io::stdin().is_terminal(),
+        Stream::Stdout => io::stdout().is_terminal(),
+        Stream::Stderr => io::stderr().is_terminal(),
+    }
+}
+
+/// Return `true` if it is reasonable to prompt the user: stdin and stderr
+/// are terminals and the terminal isn't `dumb`.
+pub fn is_interactive() -> bool {
+    is_tty(Stream::Stdin) && is_tty(Stream::Stderr) && !is_dumb()
+}
+
+/// Whether to emit ANSI color, typically from a `--color` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Decide from the environment and the stream (see module docs).
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Resolve to a yes/no for `stream`.
+    pub fn enabled(self, stream: Stream) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => auto_color(&|name| env::var(name).ok(), is_tty(stream)),
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    /// Parse `auto`, `always`, or `never` (as accepted by `--color`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "always" | "yes" | "force" => Ok(Self::Always),
+            "never" | "no" | "none" => Ok(Self::Never),
+            other => Err(format!(
+                "invalid color choice `{other}` (expected auto, always, or never)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Always => "always",
+            Self::Never => "never",
+        })
+    }
+}
+
+/// Resolve [`ColorChoice::Auto`] for stdout to `Always` or `Never`.
+pub fn color_choice() -> ColorChoice {
+    color_choice_for(Stream::Stdout)
+}
+
+/// Resolve [`ColorChoice::Auto`] for `stream` to `Always` or `Never`.
+pub fn color_choice_for(stream: Stream) -> ColorChoice {
+    if ColorChoice::Auto.enabled(stream) {
+        ColorChoice::Always
+    } else {
+        ColorChoice::Never
+    }
+}
+
+/// The terminal width in columns.
+pub fn width() -> usize {
+    if let Some(columns) = env::var("COLUMNS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|c| *c > 0)
+    {
+        return columns;
+    }
+    if is_tty(Stream::Stdout) || is_tty(Stream::Stderr) {
+        query_width().unwrap_or(DEFAULT_WIDTH)
+    } else {
+        DEFAULT_WIDTH
+    }
+}
+
+fn is_dumb() -> bool {
+    env::var("TERM").is_ok_and(|t| t == "dumb")
+}
+
+fn auto_color(var: &dyn Fn(&str) -> Option<String>, tty: bool) -> bool {
+    if var("CLICOLOR_FORCE").is_some_and(|v| !v.is_empty() && v != "0") {
+        return true;
+    }
+    if var("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if var("TERM").as_deref() == Some("dumb") || var("CLICOLOR").as_deref() == Some("0") {
+        return false;
+    }
+    tty
+}
+
+#[cfg(unix)]
+fn query_width() -> Option<usize> {
+    let tty = std::fs::File::open("/dev/tty").ok()?;
+    let out = Command::new("stty")
+        .arg("size")
+        .stdin(tty)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    parse_stty_size(&String::from_utf8_lossy(&out.stdout))
+}
+
+#[cfg(not(unix))]
+fn query_width() -> Option<usize> {
+    let out = Command::new("cmd")
+        .args(["/C", "mode con"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    parse_mode_con(&String::from_utf8_lossy(&out.stdout))
+}
+
+/// `"<rows> <cols>"` → cols.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn parse_stty_size(output: &str) -> Option<usize> {
+    output
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+        .filter(|c| *c > 0)
+}
+
+/// The `Columns:` line of `mode con`.
+#[cfg_attr(unix, allow(dead_code))]
+fn parse_mode_con(output: &str) -> Option<usize> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "Columns")
+            .then(|| value.trim().parse().ok())
+            .flatten()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn auto(vars: &[(&str, &str)], tty: bool) -> bool {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        auto_color(&|name| vars.get(name).cloned(), tty)
+    }
+
+    #[test]
+    fn test_auto_color_precedence() {
+        assert!(auto(&[], true));
+        assert!(!auto(&[], false));
+        assert!(!auto(&[("NO_COLOR", "1")], true));
+        assert!(auto(&[("NO_COLOR", "")], true));
+        assert!(auto(&[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")], false));
+        assert!(!auto(&[("CLICOLOR_FORCE", "0")], false));
+        assert!(!auto(&[("TERM", "dumb")], true));
+        assert!(!auto(&[("CLICOLOR", "0")], true));
+    }
+
+    #[test]
+    fn test_color_choice_parse() {
+        assert_eq!("always".parse::<ColorChoice>(), Ok(ColorChoice::Always));
+        assert_eq!("Never".parse::<ColorChoice>(), Ok(ColorChoice::Never));
+        assert_eq!("auto".parse::<ColorChoice>(), Ok(ColorChoice::Auto));
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+        assert!(ColorChoice::Always.enabled(Stream::Stdout));
+        assert!(!ColorChoice::Never.enabled(Stream::Stdout));
+        assert_eq!(ColorChoice::Never.to_string(), "never");
+    }
+
+    #[test]
+    fn test_width_parsers() {
+        assert_eq!(parse_stty_size("48 160\n"), Some(160));
+        assert_eq!(parse_stty_size("0 0"), None);
+        assert_eq!(parse_stty_size(""), None);
+        let mode = "Status for device CON:\n----------------------\n    Lines:          9001\n    Columns:        120\n";
+        assert_eq!(parse_mode_con(mode), Some(120));
+        assert!(width() > 0);
+    }
+}
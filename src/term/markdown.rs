@@ -0,0 +1,695 @@
+//! Markdown rendering for terminals.
+//!
+//! Covers what agent responses use: ATX headings, paragraphs, `**bold**`,
+//! `*italic*`, `` `code` ``, links, bullet and numbered lists (nested by
+//! indentation), block quotes, rules, and fenced code blocks. Prose is
+//! wrapped to the width; code blocks are not. Anything else passes through
+//! as text.
+//!
+//! With color off, the same layout is produced without escape codes: markup
+//! characters are dropped, headings are underlined with `=` or `-`, and
+//! links print their URL in parentheses. With feature `highlight`, code
+//! blocks get keyword, string, number, and comment colors.
+
+use super::{ColorChoice, Stream, width};
+
+const RESET: &str = "\x1b[0m";
+
+/// Renders Markdown to a width, with or without ANSI styling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Markdown {
+    width: usize,
+    color: bool,
+}
+
+impl Markdown {
+    /// Render for `width` columns, styled if `color`.
+    pub fn new(width: usize, color: bool) -> Self {
+        Self {
+            width: width.max(20),
+            color,
+        }
+    }
+
+    /// Render for `stream`: the terminal width, styled only if
+    /// [`ColorChoice::Auto`] allows color there (so piped output is plain).
+    pub fn for_stream(stream: Stream) -> Self {
+        Self::new(width(), ColorChoice::Auto.enabled(stream))
+    }
+
+    pub fn render(&self, markdown: &str) -> String {
+        let mut out = String::new();
+        let mut previous: Option<Block> = None;
+        for block in parse(markdown) {
+            // Consecutive list items stay together; everything else gets a
+            // blank line between blocks.
+            if let Some(prev) = &previous
+                && !(prev.is_item() && block.is_item())
+            {
+                out.push('\n');
+            }
+            self.render_block(&mut out, &block);
+            previous = Some(block);
+        }
+        out
+    }
+
+    fn render_block(&self, out: &mut String, block: &Block) {
+        match block {
+            Block::Heading(level, text) => {
+                let mut style = Style {
+                    bold: true,
+                    ..Style::default()
+                };
+                style.underline = *level == 1 && self.color;
+                let lines = self.wrap(&inline(text, style), self.width);
+                for line in &lines {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+                if !self.color && *level <= 2 {
+                    let len = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+                    let rule = if *level == 1 { "=" } else { "-" };
+                    out.push_str(&rule.repeat(len));
+                    out.push('\n');
+                }
+            }
+            Block::Paragraph(text) => {
+                for line in self.wrap(&inline(text, Style::default()), self.width) {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+            }
+            Block::Item {
+                depth,
+                marker,
+                text,
+            } => {
+                let bullet = match marker {
+                    None if self.color => "•".to_string(),
+                    None => "-".to_string(),
+                    Some(number) => format!("{number}."),
+                };
+                let indent = " ".repeat(depth * 2);
+                let hang = " ".repeat(depth * 2 + bullet.chars().count() + 1);
+                let avail = self.width.saturating_sub(hang.len()).max(10);
+                let lines = self.wrap(&inline(text, Style::default()), avail);
+                for (i, line) in lines.iter().enumerate() {
+                    if i == 0 {
+                        out.push_str(&indent);
+                        out.push_str(&bullet);
+                        out.push(' ');
+                    } else {
+                        out.push_str(&hang);
+                    }
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+            Block::Quote(text) => {
+                let bar = if self.color {
+                    "\x1b[2m│\x1b[0m "
+                } else {
+                    "> "
+                };
+                let style = Style {
+                    italic: self.color,
+                    ..Style::default()
+                };
+                for line in self.wrap(&inline(text, style), self.width - 2) {
+                    out.push_str(bar);
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+            }
+            Block::Code { lang, lines } => {
+                for line in lines {
+                    out.push_str("    ");
+                    if self.color {
+                        out.push_str(&code_line(lang, line));
+                    } else {
+                        out.push_str(line);
+                    }
+                    out.push('\n');
+                }
+            }
+            Block::Rule => {
+                let rule = if self.color { "─" } else { "-" };
+                out.push_str(&rule.repeat(self.width));
+                out.push('\n');
+            }
+        }
+    }
+
+    /// Greedily fill lines of at most `width` visible columns; a word
+    /// longer than the width gets a line of its own.
+    fn wrap(&self, spans: &[Span], width: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        let mut line_len = 0;
+        for word in words(spans) {
+            let len: usize = word.iter().map(|s| s.text.chars().count()).sum();
+            if line_len > 0 && line_len + 1 + len > width {
+                lines.push(std::mem::take(&mut line));
+                line_len = 0;
+            }
+            if line_len > 0 {
+                line.push(' ');
+                line_len += 1;
+            }
+            for span in &word {
+                self.push_span(&mut line, span);
+            }
+            line_len += len;
+        }
+        if line_len > 0 || lines.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn push_span(&self, out: &mut String, span: &Span) {
+        let codes = span.style.codes();
+        if self.color && !codes.is_empty() {
+            out.push_str(&format!("\x1b[{codes}m{}{RESET}", span.text));
+        } else {
+            out.push_str(&span.text);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Block {
+    Heading(usize, String),
+    Paragraph(String),
+    /// `marker` is the number of an ordered item.
+    Item {
+        depth: usize,
+        marker: Option<String>,
+        text: String,
+    },
+    Quote(String),
+    Code {
+        lang: String,
+        lines: Vec<String>,
+    },
+    Rule,
+}
+
+impl Block {
+    fn is_item(&self) -> bool {
+        matches!(self, Self::Item { .. })
+    }
+}
+
+fn parse(markdown: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut lines = markdown.lines().peekable();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Some(fence) = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f)) {
+            let lang = trimmed[3..].trim().to_string();
+            let mut code = Vec::new();
+            for line in lines.by_ref() {
+                if line.trim_start().starts_with(fence) {
+                    break;
+                }
+                code.push(line.to_string());
+            }
+            blocks.push(Block::Code { lang, lines: code });
+        } else if let Some((level, text)) = heading(trimmed) {
+            blocks.push(Block::Heading(level, text.to_string()));
+        } else if is_rule(trimmed) {
+            blocks.push(Block::Rule);
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            let mut text = quote.trim().to_string();
+            while let Some(next) = lines.peek().and_then(|l| l.trim_start().strip_prefix('>')) {
+                append(&mut text, next.trim());
+                lines.next();
+            }
+            blocks.push(Block::Quote(text));
+        } else if let Some((marker, rest)) = list_item(trimmed) {
+            let depth = (line.len() - trimmed.len()) / 2;
+            let mut text = rest.to_string();
+            // Indented lines that don't start a new block continue the item.
+            while let Some(next) = lines.peek() {
+                let next_trimmed = next.trim_start();
+                if next_trimmed.is_empty()
+                    || next.len() == next_trimmed.len()
+                    || list_item(next_trimmed).is_some()
+                    || starts_block(next_trimmed)
+                {
+                    break;
+                }
+                append(&mut text, next_trimmed);
+                lines.next();
+            }
+            blocks.push(Block::Item {
+                depth,
+                marker,
+                text,
+            });
+        } else {
+            let mut text = trimmed.to_string();
+            while let Some(next) = lines.peek() {
+                let next_trimmed = next.trim_start();
+                if next_trimmed.is_empty()
+                    || list_item(next_trimmed).is_some()
+                    || starts_block(next_trimmed)
+                {
+                    break;
+                }
+                append(&mut text, next_trimmed);
+                lines.next();
+            }
+            blocks.push(Block::Paragraph(text));
+        }
+    }
+    blocks
+}
+
+fn append(text: &mut String, line: &str) {
+    if !text.is_empty() {
+        text.push(' ');
+    }
+    text.push_str(line);
+}
+
+fn starts_block(line: &str) -> bool {
+    line.starts_with("```")
+        || line.starts_with("~~~")
+        || line.starts_with('>')
+        || heading(line).is_some()
+        || is_rule(line)
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let rest = &line[level..];
+    ((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' ')))
+        .then(|| (level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+fn is_rule(line: &str) -> bool {
+    let line: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    line.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|m| line.chars().all(|c| c == *m))
+}
+
+/// `- text`, `* text`, `+ text` → `(None, text)`; `12. text` or `12) text`
+/// → `(Some("12"), text)`.
+fn list_item(line: &str) -> Option<(Option<String>, &str)> {
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(bullet) {
+            return Some((None, rest.trim_start()));
+        }
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if (1..=9).contains(&digits) {
+        let rest = &line[digits..];
+        if let Some(text) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            return Some((Some(line[..digits].to_string()), text.trim_start()));
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    code: bool,
+    dim: bool,
+}
+
+impl Style {
+    /// SGR parameters, e.g. `1;4`.
+    fn codes(self) -> String {
+        let mut codes = Vec::new();
+        for (on, code) in [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.code, "36"),
+        ] {
+            if on {
+                codes.push(code);
+            }
+        }
+        codes.join(";")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Span {
+    text: String,
+    style: Style,
+}
+
+/// Split inline Markdown into styled spans. A delimiter only opens if it is
+/// closed later on, so a stray `*` in `2 * 3` stays literal.
+fn inline(text: &str, base: Style) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut current = String::new();
+    let mut style = base;
+    let flush = |spans: &mut Vec<Span>, current: &mut String, style: Style| {
+        if !current.is_empty() {
+            spans.push(Span {
+                text: std::mem::take(current),
+                style,
+            });
+        }
+    };
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let prev = text[..i].chars().next_back();
+        if let Some(escaped) = rest
+            .strip_prefix('\\')
+            .and_then(|r| r.chars().next())
+            .filter(char::is_ascii_punctuation)
+        {
+            current.push(escaped);
+            i += 2;
+        } else if let Some(code) = rest.strip_prefix('`')
+            && let Some(end) = code.find('`')
+        {
+            flush(&mut spans, &mut current, style);
+            spans.push(Span {
+                text: code[..end].to_string(),
+                style: Style {
+                    code: true,
+                    ..style
+                },
+            });
+            i += end + 2;
+        } else if rest.starts_with('[')
+            && let Some((label, url, len)) = link(rest)
+        {
+            flush(&mut spans, &mut current, style);
+            let link_style = Style {
+                underline: true,
+                ..style
+            };
+            spans.extend(inline(label, link_style));
+            if url != label {
+                spans.push(Span {
+                    text: format!(" ({url})"),
+                    style: Style { dim: true, ..style },
+                });
+            }
+            i += len;
+        } else if let Some(delim) = ["**", "__", "*", "_"]
+            .into_iter()
+            .find(|d| rest.starts_with(d))
+        {
+            let strong = delim.len() == 2;
+            let open = if strong { style.bold } else { style.italic };
+            // `_` inside a word (snake_case) is not emphasis.
+            let intraword = delim.starts_with('_')
+                && prev.is_some_and(char::is_alphanumeric)
+                && rest[delim.len()..]
+                    .chars()
+                    .next()
+                    .is_some_and(char::is_alphanumeric);
+            if !intraword && (open || rest[delim.len()..].contains(delim)) {
+                flush(&mut spans, &mut current, style);
+                if strong {
+                    style.bold = !style.bold;
+                } else {
+                    style.italic = !style.italic;
+                }
+            } else {
+                current.push_str(delim);
+            }
+            i += delim.len();
+        } else {
+            let c = rest.chars().next().expect("not at end");
+            current.push(c);
+            i += c.len_utf8();
+        }
+    }
+    flush(&mut spans, &mut current, style);
+    spans
+}
+
+/// `[label](url)` at the start of `text` → `(label, url, bytes consumed)`.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let close = text.find("](")?;
+    let end = close + 2 + text[close + 2..].find(')')?;
+    Some((&text[1..close], &text[close + 2..end], end + 1))
+}
+
+/// Break spans into words at whitespace; a word may span several styles.
+fn words(spans: &[Span]) -> Vec<Vec<Span>> {
+    let mut words = Vec::new();
+    let mut word: Vec<Span> = Vec::new();
+    for span in spans {
+        let mut first = true;
+        for part in span.text.split(char::is_whitespace) {
+            if !first && !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            first = false;
+            if !part.is_empty() {
+                word.push(Span {
+                    text: part.to_string(),
+                    style: span.style,
+                });
+            }
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(not(feature = "highlight"))]
+fn code_line(_lang: &str, line: &str) -> String {
+    format!("\x1b[36m{line}{RESET}")
+}
+
+/// Color one line of code. Works line by line, so a block comment or string
+/// spanning lines is only colored on its first line.
+#[cfg(feature = "highlight")]
+fn code_line(lang: &str, line: &str) -> String {
+    const KEYWORD: &str = "\x1b[35m";
+    const STRING: &str = "\x1b[32m";
+    const NUMBER: &str = "\x1b[33m";
+    const COMMENT: &str = "\x1b[2;3m";
+
+    let lang = lang.to_ascii_lowercase();
+    let keywords: &[&str] = match lang.as_str() {
+        "rust" | "rs" => &[
+            "as", "break", "const", "continue", "crate", "else", "enum", "false", "fn", "for",
+            "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+            "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "use",
+            "where", "while", "async", "await", "dyn", "unsafe",
+        ],
+        "python" | "py" => &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "if", "import", "in",
+            "is", "lambda", "None", "not", "or", "pass", "raise", "return", "True", "try", "while",
+            "with", "yield",
+        ],
+        "js" | "javascript" | "ts" | "typescript" => &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "for",
+            "function",
+            "if",
+            "import",
+            "in",
+            "interface",
+            "let",
+            "new",
+            "null",
+            "return",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "type",
+            "undefined",
+            "var",
+            "while",
+        ],
+        "sh" | "bash" | "shell" | "zsh" => &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "then", "while",
+        ],
+        "go" => &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "false",
+            "for",
+            "func",
+            "go",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "nil",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "true",
+            "type",
+            "var",
+        ],
+        "json" | "toml" | "yaml" | "yml" => &["true", "false", "null"],
+        _ => return format!("\x1b[36m{line}{RESET}"),
+    };
+    let hash_comments = matches!(
+        lang.as_str(),
+        "python" | "py" | "sh" | "bash" | "shell" | "zsh" | "toml" | "yaml" | "yml"
+    );
+
+    let mut out = String::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..].iter().collect();
+        if (c == '#' && hash_comments) || rest.starts_with("//") {
+            out.push_str(&format!("{COMMENT}{rest}{RESET}"));
+            break;
+        } else if c == '"' || c == '\'' || c == '`' {
+            let mut end = i + 1;
+            while end < chars.len() && chars[end] != c {
+                end += if chars[end] == '\\' { 2 } else { 1 };
+            }
+            let end = (end + 1).min(chars.len());
+            let s: String = chars[i..end].iter().collect();
+            out.push_str(&format!("{STRING}{s}{RESET}"));
+            i = end;
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut end = i;
+            while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            let word: String = chars[i..end].iter().collect();
+            if c.is_ascii_digit() {
+                out.push_str(&format!("{NUMBER}{word}{RESET}"));
+            } else if keywords.contains(&word.as_str()) {
+                out.push_str(&format!("{KEYWORD}{word}{RESET}"));
+            } else {
+                out.push_str(&word);
+            }
+            i = end;
+        } else {
+            out.push(c);
+            i += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOC: &str = "\
+# Release notes
+
+The **new** scheduler is *much* faster; see [the docs](https://example.com/docs) \
+and `jobs::run`. Values like 2 * 3 and snake_case_names stay literal.
+
+- first item that is long enough to wrap onto a second line here
+  - nested
+3. numbered
+
+> quoted text
+
+```rust
+fn main() {}
+```
+---
+";
+
+    #[test]
+    fn test_plain_rendering() {
+        let out = Markdown::new(40, false).render(DOC);
+        let expected = "\
+Release notes
+=============
+
+The new scheduler is much faster; see
+the docs (https://example.com/docs) and
+jobs::run. Values like 2 * 3 and
+snake_case_names stay literal.
+
+- first item that is long enough to wrap
+  onto a second line here
+  - nested
+3. numbered
+
+> quoted text
+
+    fn main() {}
+
+----------------------------------------
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_color_rendering() {
+        let out = Markdown::new(80, true).render(DOC);
+        assert!(out.starts_with("\x1b[1;4mRelease\x1b[0m \x1b[1;4mnotes\x1b[0m\n"));
+        assert!(out.contains("\x1b[1mnew\x1b[0m"));
+        assert!(out.contains("\x1b[3mmuch\x1b[0m"));
+        assert!(out.contains("\x1b[4mdocs\x1b[0m \x1b[2m(https://example.com/docs)\x1b[0m"));
+        assert!(out.contains("\x1b[36mjobs::run\x1b[0m."));
+        assert!(out.contains("• first item"));
+        assert!(out.contains("\x1b[2m│\x1b[0m \x1b[3mquoted\x1b[0m"));
+        assert!(out.contains(&"─".repeat(80)));
+        #[cfg(feature = "highlight")]
+        assert!(out.contains("    \x1b[35mfn\x1b[0m main() {}"));
+        #[cfg(not(feature = "highlight"))]
+        assert!(out.contains("    \x1b[36mfn main() {}\x1b[0m"));
+        // Visible text never exceeds the width.
+        for line in out.lines() {
+            let mut visible = String::new();
+            let mut in_escape = false;
+            for c in line.chars() {
+                match c {
+                    '\x1b' => in_escape = true,
+                    'm' if in_escape => in_escape = false,
+                    _ if !in_escape => visible.push(c),
+                    _ => {}
+                }
+            }
+            assert!(visible.chars().count() <= 80, "{visible:?}");
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Test fixtures for crates built on `apiari-common`.
+//!
+//! Available in this crate's own tests and, for downstream crates, behind the
+//! `testutil` feature (typically enabled from `[dev-dependencies]`).
+//!
+//! - [`TempDir`] — a uniquely named temporary directory removed on drop.
+//! - [`MockClock`] — a [`Clock`] that only moves when told to.
+//! - [`JsonlFileBuilder`] / [`TempDir::state_file`] — prepopulated JSONL and
+//!   state files.
+//! - [`block_on`] — drive a future to completion on the current thread.
+
+use serde::Serialize;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::clock::Clock;
+use crate::state::save_state;
+
+static TEMP_DIR_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary directory that is removed (recursively) when dropped.
+///
+/// Names combine a caller-supplied prefix, the process ID, and a counter so
+/// parallel tests and concurrent test binaries never collide.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a fresh, empty directory under the system temp dir.
+    pub fn new(prefix: &str) -> io::Result<Self> {
+        let n = TEMP_DIR_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("{prefix}-{}-{n}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    /// Return the directory path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Join a relative path onto the directory.
+    pub fn join(&self, rel: impl AsRef<Path>) -> PathBuf {
+        self.path.join(rel)
+    }
+
+    /// Start building a JSONL file at `rel` inside this directory.
+    pub fn jsonl_file(&self, rel: impl AsRef<Path>) -> JsonlFileBuilder {
+        JsonlFileBuilder::new(self.join(rel))
+    }
+
+    /// Write `state` as a state file at `rel` (via `save_state`) and return
+    /// its full path.
+    pub fn state_file<T: Serialize>(
+        &self,
+        rel: impl AsRef<Path>,
+        state: &T,
+    ) -> io::Result<PathBuf> {
+        let path = self.join(rel);
+        save_state(&path, state)?;
+        Ok(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Builds a JSONL file from typed records and raw lines.
+///
+/// Raw lines make it easy to inject malformed or torn content between
+/// well-formed records.
+#[derive(Debug)]
+pub struct JsonlFileBuilder {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+impl JsonlFileBuilder {
+    /// Create a builder for the given path.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lines: Vec::new(),
+        }
+    }
+
+    /// Append a serialized record.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `record` cannot be serialized — this is a test fixture.
+    pub fn record<T: Serialize>(mut self, record: &T) -> Self {
+        let json = serde_json::to_string(record).expect("fixture record must serialize");
+        self.lines.push(json);
+        self
+    }
+
+    /// Append a raw line verbatim (no trailing newline needed).
+    pub fn raw_line(mut self, line: impl Into<String>) -> Self {
+        self.lines.push(line.into());
+        self
+    }
+
+    /// Write the file, replacing any existing content, and return its path.
+    ///
+    /// Creates parent directories if they don't exist.
+    pub fn write(self) -> io::Result<PathBuf> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::File::create(&self.path)?;
+        for line in &self.lines {
+            writeln!(file, "{line}")?;
+        }
+        Ok(self.path)
+    }
+}
+
+/// A manually driven [`Clock`].
+///
+/// Clones share the same underlying time, so a test can hand one clone to
+/// the code under test and advance another.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl MockClock {
+    /// Create a clock frozen at `start`.
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    /// Create a clock frozen at the Unix epoch plus `secs` seconds.
+    pub fn at_unix_secs(secs: u64) -> Self {
+        Self::new(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += by;
+    }
+
+    /// Set the clock to an absolute time.
+    pub fn set(&self, to: SystemTime) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) = to;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Run `future` to completion on the current thread, parking between
+/// polls until its waker fires. Enough for testing this crate's futures
+/// without an async runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::JsonlReader;
+    use crate::state::load_state;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+    struct Rec {
+        id: u32,
+    }
+
+    #[test]
+    fn test_temp_dir_removed_on_drop() {
+        let path = {
+            let tmp = TempDir::new("apiari-testutil-test-drop").unwrap();
+            fs::write(tmp.join("file.txt"), "x").unwrap();
+            assert!(tmp.path().is_dir());
+            tmp.path().to_path_buf()
+        };
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_temp_dirs_are_unique() {
+        let a = TempDir::new("apiari-testutil-test-unique").unwrap();
+        let b = TempDir::new("apiari-testutil-test-unique").unwrap();
+        assert_ne!(a.path(), b.path());
+    }
+
+    #[test]
+    fn test_jsonl_builder() {
+        let tmp = TempDir::new("apiari-testutil-test-jsonl").unwrap();
+        let path = tmp
+            .jsonl_file("nested/log.jsonl")
+            .record(&Rec { id: 1 })
+            .raw_line("garbage")
+            .record(&Rec { id: 2 })
+            .write()
+            .unwrap();
+
+        let records = JsonlReader::<Rec>::new(&path).poll().unwrap();
+        assert_eq!(records, vec![Rec { id: 1 }, Rec { id: 2 }]);
+    }
+
+    #[test]
+    fn test_state_file() {
+        let tmp = TempDir::new("apiari-testutil-test-state").unwrap();
+        let path = tmp.state_file("state.json", &Rec { id: 7 }).unwrap();
+        let loaded: Rec = load_state(&path).unwrap();
+        assert_eq!(loaded, Rec { id: 7 });
+    }
+
+    #[test]
+    fn test_mock_clock_shared_between_clones() {
+        let clock = MockClock::at_unix_secs(100);
+        let handle = clock.clone();
+        handle.advance(Duration::from_secs(5));
+        assert_eq!(
+            clock.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(105)
+        );
+
+        clock.set(SystemTime::UNIX_EPOCH);
+        assert_eq!(handle.now(), SystemTime::UNIX_EPOCH);
+    }
+}
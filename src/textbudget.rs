@@ -0,0 +1,301 @@
+//! Fitting text into a bounded prompt.
+//!
+//! [`truncate`] cuts text to a [`Limit`] in bytes or approximate tokens,
+//! keeping the start ([`Strategy::Head`]), the end ([`Strategy::Tail`]), or
+//! both ([`Strategy::Middle`]). The cut is replaced by a marker line saying
+//! how much was elided, and the marker counts against the limit. Cuts land on
+//! line boundaries when that loses little, and always on `char` boundaries.
+//!
+//! [`fit`] shares one limit between several [`Section`]s by weight. A section
+//! that needs less than its share gives the rest back to the others, so a
+//! short diff leaves more room for a long log.
+//!
+//! Tokens are estimated at [`BYTES_PER_TOKEN`] bytes each, which is close for
+//! English and code with common tokenizers. Budgets should keep some slack.
+
+/// Bytes per token assumed by [`estimate_tokens`] and [`Limit::Tokens`].
+pub const BYTES_PER_TOKEN: usize = 4;
+
+/// Approximate token count of `text`.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(BYTES_PER_TOKEN)
+}
+
+/// A size limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Bytes(usize),
+    /// Approximate tokens; see [`estimate_tokens`].
+    Tokens(usize),
+}
+
+impl Limit {
+    /// The limit in bytes.
+    pub fn bytes(self) -> usize {
+        match self {
+            Self::Bytes(n) => n,
+            Self::Tokens(n) => n.saturating_mul(BYTES_PER_TOKEN),
+        }
+    }
+}
+
+/// Which part of the text to keep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Keep the start (e.g. a file's imports and signatures).
+    #[default]
+    Head,
+    /// Keep the end (e.g. the latest log lines).
+    Tail,
+    /// Keep the start and end, eliding the middle (e.g. a long diff).
+    Middle,
+}
+
+/// Cut `text` to fit `limit`, marking what was elided. Text that already
+/// fits is returned unchanged.
+pub fn truncate(text: &str, limit: Limit, strategy: Strategy) -> String {
+    truncate_counted(text, limit.bytes(), strategy).0
+}
+
+/// Truncate to `max` bytes; also returns the number of bytes elided.
+fn truncate_counted(text: &str, max: usize, strategy: Strategy) -> (String, usize) {
+    if text.len() <= max {
+        return (text.to_string(), 0);
+    }
+    // The marker's width depends on the count it reports; reserving room for
+    // the full length's digits is always enough.
+    let reserve = marker(text.len()).len();
+    let Some(room) = max.checked_sub(reserve) else {
+        // Too small for a marker: keep what fits.
+        let kept = match strategy {
+            Strategy::Tail => &text[ceil_boundary(text, text.len() - max)..],
+            _ => &text[..floor_boundary(text, max)],
+        };
+        return (kept.to_string(), text.len() - kept.len());
+    };
+    let (head, tail) = match strategy {
+        Strategy::Head => (head_cut(text, room), text.len()),
+        Strategy::Tail => (0, tail_cut(text, room)),
+        Strategy::Middle => {
+            let head = head_cut(text, room.div_ceil(2));
+            // The newline after the head is one more byte of overhead.
+            (head, tail_cut(text, room.saturating_sub(head + 1)))
+        }
+    };
+    let elided = tail - head;
+    let mut out = String::with_capacity(max);
+    out.push_str(&text[..head]);
+    if head > 0 && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&marker(elided));
+    if tail < text.len() {
+        out.push('\n');
+        out.push_str(&text[tail..]);
+    }
+    (out, elided)
+}
+
+/// The line that replaces elided text.
+fn marker(elided: usize) -> String {
+    format!("[... {elided} bytes elided ...]")
+}
+
+/// End of a prefix of at most `room` bytes (leaving a byte for the newline
+/// before the marker), moved back to a line end if that keeps at least half.
+fn head_cut(text: &str, room: usize) -> usize {
+    let end = floor_boundary(text, room.saturating_sub(1));
+    match text[..end].rfind('\n') {
+        Some(nl) if nl + 1 >= end / 2 => nl + 1,
+        _ => end,
+    }
+}
+
+/// Start of a suffix of at most `room` bytes (leaving a byte for the newline
+/// after the marker), moved forward to a line start if that keeps at least
+/// half.
+fn tail_cut(text: &str, room: usize) -> usize {
+    let keep = room.saturating_sub(1);
+    let start = ceil_boundary(text, text.len() - keep.min(text.len()));
+    let len = text.len() - start;
+    match text[start..].find('\n') {
+        Some(nl) if len - (nl + 1) >= len / 2 => start + nl + 1,
+        _ => start,
+    }
+}
+
+fn floor_boundary(text: &str, mut i: usize) -> usize {
+    while !text.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
+
+fn ceil_boundary(text: &str, mut i: usize) -> usize {
+    while !text.is_char_boundary(i) {
+        i += 1;
+    }
+    i
+}
+
+/// One part of a prompt competing for a shared limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub text: String,
+    strategy: Strategy,
+    weight: u32,
+}
+
+impl Section {
+    /// A section kept with [`Strategy::Head`] and weight 1.
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            text: text.into(),
+            strategy: Strategy::Head,
+            weight: 1,
+        }
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Relative share of the limit (at least 1).
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight.max(1);
+        self
+    }
+}
+
+/// A section after [`fit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fitted {
+    pub name: String,
+    pub text: String,
+    /// Bytes allotted to this section.
+    pub budget: usize,
+    /// Bytes cut from the original text (0 if it fit).
+    pub elided: usize,
+}
+
+/// Truncate `sections` so that together they fit `limit`, in order.
+///
+/// Space is divided by weight. Sections that need less than their share are
+/// given exactly what they need, and the rest is shared again among the
+/// others until every remaining section is over its share.
+pub fn fit(sections: &[Section], limit: Limit) -> Vec<Fitted> {
+    let mut budgets: Vec<Option<usize>> = vec![None; sections.len()];
+    let mut remaining = limit.bytes();
+    loop {
+        let open: Vec<usize> = (0..sections.len())
+            .filter(|&i| budgets[i].is_none())
+            .collect();
+        if open.is_empty() {
+            break;
+        }
+        let total_weight: u64 = open.iter().map(|&i| u64::from(sections[i].weight)).sum();
+        let share = |i: usize| {
+            (remaining as u128 * u128::from(sections[i].weight) / u128::from(total_weight)) as usize
+        };
+        let satisfied: Vec<usize> = open
+            .iter()
+            .copied()
+            .filter(|&i| sections[i].text.len() <= share(i))
+            .collect();
+        if satisfied.is_empty() {
+            for &i in &open {
+                budgets[i] = Some(share(i));
+            }
+            break;
+        }
+        for i in satisfied {
+            budgets[i] = Some(sections[i].text.len());
+            remaining -= sections[i].text.len();
+        }
+    }
+    sections
+        .iter()
+        .zip(budgets)
+        .map(|(section, budget)| {
+            let budget = budget.unwrap_or(0);
+            let (text, elided) = truncate_counted(&section.text, budget, section.strategy);
+            Fitted {
+                name: section.name.clone(),
+                text,
+                budget,
+                elided,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(lines: usize) -> String {
+        (0..lines).map(|i| format!("line {i:03}\n")).collect()
+    }
+
+    #[test]
+    fn test_truncate_strategies() {
+        let text = numbered(100); // 900 bytes
+        assert_eq!(
+            truncate("short", Limit::Bytes(10), Strategy::Middle),
+            "short"
+        );
+
+        let head = truncate(&text, Limit::Bytes(200), Strategy::Head);
+        assert!(head.len() <= 200);
+        assert!(head.starts_with("line 000\n"));
+        assert!(head.ends_with(" bytes elided ...]"));
+
+        let tail = truncate(&text, Limit::Tokens(50), Strategy::Tail);
+        assert!(tail.len() <= 200);
+        assert!(tail.starts_with("[... "));
+        assert!(tail.ends_with("line 099\n"));
+
+        let middle = truncate(&text, Limit::Bytes(200), Strategy::Middle);
+        assert!(middle.len() <= 200);
+        assert!(middle.starts_with("line 000\n") && middle.ends_with("line 099\n"));
+        // Cuts fall on line boundaries, and the marker counts the rest.
+        let lines: Vec<&str> = middle.lines().collect();
+        assert!(lines.iter().all(|l| l.len() == 8 || l.contains("elided")));
+        let kept = lines.iter().filter(|l| l.starts_with("line")).count();
+        assert!(middle.contains(&format!("[... {} bytes elided ...]", 900 - kept * 9)));
+
+        // Multi-byte text is cut on char boundaries, even without a marker.
+        let wide = "é".repeat(50);
+        for strategy in [Strategy::Head, Strategy::Tail, Strategy::Middle] {
+            assert!(truncate(&wide, Limit::Bytes(41), strategy).len() <= 41);
+            assert!(truncate(&wide, Limit::Bytes(7), strategy).len() <= 7);
+        }
+        assert_eq!(estimate_tokens("abcdefghi"), 3);
+    }
+
+    #[test]
+    fn test_fit_rebalances_unused_space() {
+        let sections = [
+            Section::new("task", "fix the flaky test"),
+            Section::new("diff", numbered(50)).strategy(Strategy::Middle),
+            Section::new("log", numbered(200))
+                .strategy(Strategy::Tail)
+                .weight(3),
+        ];
+        let fitted = fit(&sections, Limit::Bytes(1000));
+        assert_eq!(fitted[0].text, "fix the flaky test");
+        assert_eq!(fitted[0].elided, 0);
+        // The task's unused share went to the others, 1:3.
+        assert_eq!(fitted[1].budget, (1000 - 18) / 4);
+        assert_eq!(fitted[2].budget, (1000 - 18) * 3 / 4);
+        assert!(fitted[2].text.ends_with("line 199\n"));
+        let total: usize = fitted.iter().map(|f| f.text.len()).sum();
+        assert!(total <= 1000);
+
+        let roomy = fit(&sections, Limit::Tokens(10_000));
+        assert!(roomy.iter().all(|f| f.elided == 0));
+    }
+}
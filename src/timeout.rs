@@ -0,0 +1,186 @@
+//! Time limits for blocking operations.
+//!
+//! Filesystem and lock calls on network mounts can hang indefinitely, and
+//! std offers no way to cancel them. [`with_timeout`] runs the operation on a
+//! helper thread and stops waiting after the deadline.
+//!
+//! **The helper thread is abandoned, not killed**: on timeout it keeps
+//! running until the operation returns (possibly never), and its result is
+//! discarded. Only wrap operations that are safe to leave running, and don't
+//! use this for work that must not happen twice if the caller retries.
+//!
+//! [`with_timeout_async`] is the same for async callers: it returns a future
+//! that does not block the executor, and works with any runtime.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+/// Run `op` on a helper thread, waiting at most `timeout` for it.
+///
+/// # Errors
+///
+/// Returns `io::ErrorKind::TimedOut` if `op` did not finish in time (the
+/// thread is left running; see the module docs), or an error if the helper
+/// thread could not be spawned or `op` panicked. Errors returned by `op`
+/// itself are passed through.
+pub fn with_timeout<T, F>(timeout: Duration, op: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    spawn_op(op, move |result| {
+        let _ = tx.send(result);
+    })?;
+    wait(&rx, timeout)
+}
+
+/// Async counterpart of [`with_timeout`].
+///
+/// `op` is still a blocking closure; it runs on a helper thread and the
+/// returned future resolves when it finishes or `timeout` passes. No
+/// particular async runtime is required.
+pub fn with_timeout_async<T, F>(timeout: Duration, op: F) -> TimeoutFuture<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Slot {
+        result: None,
+        waker: None,
+    }));
+    let (tx, rx) = mpsc::channel();
+    let started = spawn_op(op, move |result| {
+        let _ = tx.send(result);
+    })
+    .and_then(|()| {
+        let shared = Arc::clone(&shared);
+        thread::Builder::new()
+            .name("apiari-timeout".to_string())
+            .spawn(move || {
+                let result = wait(&rx, timeout);
+                let mut slot = shared.lock().unwrap_or_else(|e| e.into_inner());
+                slot.result = Some(result);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            })
+            .map(drop)
+    });
+    if let Err(e) = started {
+        shared.lock().unwrap_or_else(|e| e.into_inner()).result = Some(Err(e));
+    }
+    TimeoutFuture { shared }
+}
+
+/// Future returned by [`with_timeout_async`].
+#[derive(Debug)]
+pub struct TimeoutFuture<T> {
+    shared: Arc<Mutex<Slot<T>>>,
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    result: Option<io::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for TimeoutFuture<T> {
+    type Output = io::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn spawn_op<T, F>(op: F, done: impl FnOnce(io::Result<T>) + Send + 'static) -> io::Result<()>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    thread::Builder::new()
+        .name("apiari-timeout-op".to_string())
+        .spawn(move || done(op()))
+        .map(drop)
+}
+
+fn wait<T>(rx: &mpsc::Receiver<io::Result<T>>, timeout: Duration) -> io::Result<T> {
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("operation did not finish within {timeout:?}"),
+        )),
+        // The sender is dropped without sending only if `op` panicked.
+        Err(RecvTimeoutError::Disconnected) => Err(io::Error::other("operation panicked")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::block_on;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    #[test]
+    fn test_with_timeout_returns_result() {
+        assert_eq!(with_timeout(Duration::from_secs(5), || Ok(42)).unwrap(), 42);
+        let err = with_timeout(Duration::from_secs(5), || -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::NotFound, "gone"))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = with_timeout(Duration::from_secs(5), || -> io::Result<()> {
+            panic!("boom")
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "operation panicked");
+    }
+
+    #[test]
+    fn test_with_timeout_abandons_slow_operation() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&finished);
+        let start = Instant::now();
+        let err = with_timeout(Duration::from_millis(20), move || {
+            thread::sleep(Duration::from_millis(200));
+            flag.store(true, Ordering::SeqCst);
+            Ok(())
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_millis(150));
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_with_timeout_async() {
+        let value = block_on(with_timeout_async(Duration::from_secs(5), || {
+            thread::sleep(Duration::from_millis(10));
+            Ok("done")
+        }))
+        .unwrap();
+        assert_eq!(value, "done");
+
+        let err = block_on(with_timeout_async(Duration::from_millis(20), || {
+            thread::sleep(Duration::from_millis(200));
+            Ok(())
+        }))
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
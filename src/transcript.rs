@@ -0,0 +1,372 @@
+//! Canonical transcripts of agent conversations.
+//!
+//! Every exchange between a user, an agent, and its tools is a
+//! [`TranscriptEvent`]: who spoke ([`Role`]), what they said, the tool calls
+//! they made, and any files attached. A [`Recorder`] appends events for one
+//! conversation to a JSONL file through a [`JsonlWriter`], numbering them so
+//! order survives clock skew. Several conversations may share a file.
+//!
+//! Attachments are copied next to the transcript, into `attachments/` named
+//! by their SHA-256, and events refer to them by that relative path. This
+//! keeps the JSONL small and lets identical files be stored once.
+//!
+//! [`load`] reads a transcript back as [`Conversation`]s with their events
+//! in recorded order and attachment paths resolvable against the file.
+
+use crate::clock::now_ms;
+use crate::hash::file_sha256_hex;
+use crate::ipc::{JsonlReader, JsonlWriter};
+use crate::{fsutil, id};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory next to the transcript that holds attachment copies.
+pub const ATTACHMENTS_DIR: &str = "attachments";
+
+/// Who produced an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    /// The result of a tool call.
+    Tool,
+}
+
+/// A tool invocation requested by the assistant.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Matches the `tool_call_id` of the [`Role::Tool`] event with the result.
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// A file referenced by an event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// Path relative to the transcript's directory.
+    pub path: String,
+    /// The original file name.
+    pub name: String,
+    pub sha256: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+}
+
+/// One line of a transcript.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptEvent {
+    /// Unique event id.
+    pub id: String,
+    /// The conversation this event belongs to.
+    pub conversation: String,
+    /// Position within the conversation, starting at 0.
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch.
+    pub ts: u64,
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// For [`Role::Tool`] events, the call this is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+impl TranscriptEvent {
+    /// An event with `content`; the recorder fills in the id, conversation,
+    /// sequence number, and timestamp.
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            id: String::new(),
+            conversation: String::new(),
+            seq: 0,
+            ts: 0,
+            role,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// A [`Role::Tool`] event holding the result of call `call_id`.
+    pub fn tool_result(call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(call_id.into()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+
+    pub fn tool_call(
+        mut self,
+        id: impl Into<String>,
+        name: impl Into<String>,
+        arguments: Value,
+    ) -> Self {
+        self.tool_calls.push(ToolCall {
+            id: id.into(),
+            name: name.into(),
+            arguments,
+        });
+        self
+    }
+
+    /// Attach a file stored with [`Recorder::attach`].
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+}
+
+/// Appends one conversation's events to a transcript file.
+///
+/// A recorder assumes it is the only writer for its conversation.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: JsonlWriter<TranscriptEvent>,
+    conversation: String,
+    next_seq: u64,
+}
+
+impl Recorder {
+    /// Record a new conversation with a fresh id.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            writer: JsonlWriter::new(path),
+            conversation: id::new_uuid(),
+            next_seq: 0,
+        }
+    }
+
+    /// Continue recording `conversation`, numbering after its last event in
+    /// the file.
+    pub fn resume(path: impl Into<PathBuf>, conversation: impl Into<String>) -> io::Result<Self> {
+        let path = path.into();
+        let conversation = conversation.into();
+        let next_seq = JsonlReader::<TranscriptEvent>::new(&path)
+            .poll()?
+            .iter()
+            .filter(|e| e.conversation == conversation)
+            .map(|e| e.seq + 1)
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            writer: JsonlWriter::new(path),
+            conversation,
+            next_seq,
+        })
+    }
+
+    pub fn conversation(&self) -> &str {
+        &self.conversation
+    }
+
+    pub fn path(&self) -> &Path {
+        self.writer.path()
+    }
+
+    /// Append `event`, filling in its id, conversation, sequence number, and
+    /// (if zero) timestamp. Returns the event as written.
+    pub fn record(&mut self, mut event: TranscriptEvent) -> io::Result<TranscriptEvent> {
+        if event.id.is_empty() {
+            event.id = id::new_uuid();
+        }
+        if event.ts == 0 {
+            event.ts = now_ms();
+        }
+        event.conversation = self.conversation.clone();
+        event.seq = self.next_seq;
+        self.writer.append(&event)?;
+        self.next_seq += 1;
+        Ok(event)
+    }
+
+    /// Record a plain message.
+    pub fn message(
+        &mut self,
+        role: Role,
+        content: impl Into<String>,
+    ) -> io::Result<TranscriptEvent> {
+        self.record(TranscriptEvent::new(role, content))
+    }
+
+    /// Copy `file` into the transcript's attachment directory (once per
+    /// distinct content) and return a reference to put on an event.
+    pub fn attach(&self, file: &Path, media_type: Option<&str>) -> io::Result<Attachment> {
+        let sha256 = file_sha256_hex(file)?;
+        let relative = format!("{ATTACHMENTS_DIR}/{sha256}");
+        let dest = base_dir(self.path()).join(&relative);
+        let size = match fs::metadata(&dest) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(dest.parent().expect("attachment has a parent"))?;
+                fsutil::copy_atomic(file, &dest, |_| {})?
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Attachment {
+            path: relative,
+            name: file
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            sha256,
+            size,
+            media_type: media_type.map(str::to_string),
+        })
+    }
+}
+
+/// A conversation read back from a transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversation {
+    pub id: String,
+    /// Events in recorded order.
+    pub events: Vec<TranscriptEvent>,
+    base_dir: PathBuf,
+}
+
+impl Conversation {
+    /// The absolute location of an attachment's stored copy.
+    pub fn resolve(&self, attachment: &Attachment) -> PathBuf {
+        self.base_dir.join(&attachment.path)
+    }
+
+    /// Every attachment with the event that references it, in order.
+    pub fn attachments(&self) -> impl Iterator<Item = (&TranscriptEvent, &Attachment)> {
+        self.events
+            .iter()
+            .flat_map(|e| e.attachments.iter().map(move |a| (e, a)))
+    }
+
+    /// The [`Role::Tool`] event answering tool call `call_id`.
+    pub fn tool_result(&self, call_id: &str) -> Option<&TranscriptEvent> {
+        self.events
+            .iter()
+            .find(|e| e.role == Role::Tool && e.tool_call_id.as_deref() == Some(call_id))
+    }
+}
+
+/// Read every conversation in a transcript, in order of first appearance.
+///
+/// Events are ordered by sequence number; a duplicated event id (an append
+/// retried after a partial failure) is kept once. Malformed lines are
+/// skipped. A missing file has no conversations.
+pub fn load(path: &Path) -> io::Result<Vec<Conversation>> {
+    let events = JsonlReader::<TranscriptEvent>::new(path).poll()?;
+    let base_dir = base_dir(path).to_path_buf();
+    let mut conversations: Vec<Conversation> = Vec::new();
+    let mut seen = HashSet::new();
+    for event in events {
+        if !seen.insert(event.id.clone()) {
+            continue;
+        }
+        match conversations
+            .iter_mut()
+            .find(|c| c.id == event.conversation)
+        {
+            Some(conversation) => conversation.events.push(event),
+            None => conversations.push(Conversation {
+                id: event.conversation.clone(),
+                events: vec![event],
+                base_dir: base_dir.clone(),
+            }),
+        }
+    }
+    for conversation in &mut conversations {
+        conversation.events.sort_by_key(|e| e.seq);
+    }
+    Ok(conversations)
+}
+
+fn base_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use serde_json::json;
+
+    #[test]
+    fn test_record_and_load_conversations() {
+        let tmp = TempDir::new("apiari-transcript-test-load").unwrap();
+        let path = tmp.join("transcripts/session.jsonl");
+        let mut first = Recorder::new(&path);
+        let mut second = Recorder::new(&path);
+        first.message(Role::System, "be brief").unwrap();
+        second.message(Role::User, "other chat").unwrap();
+        first.message(Role::User, "list files").unwrap();
+        first
+            .record(TranscriptEvent::new(Role::Assistant, "").tool_call(
+                "call-1",
+                "ls",
+                json!({ "dir": "." }),
+            ))
+            .unwrap();
+        first
+            .record(TranscriptEvent::tool_result("call-1", "a.txt\nb.txt"))
+            .unwrap();
+
+        let mut resumed = Recorder::resume(&path, first.conversation()).unwrap();
+        resumed.message(Role::Assistant, "two files").unwrap();
+
+        let conversations = load(&path).unwrap();
+        assert_eq!(conversations.len(), 2);
+        let chat = &conversations[0];
+        assert_eq!(chat.id, first.conversation());
+        let seqs: Vec<u64> = chat.events.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, [0, 1, 2, 3, 4]);
+        assert_eq!(chat.events[2].tool_calls[0].arguments["dir"], ".");
+        assert_eq!(chat.tool_result("call-1").unwrap().content, "a.txt\nb.txt");
+        assert_eq!(chat.events[4].content, "two files");
+        assert_eq!(conversations[1].events[0].content, "other chat");
+        assert!(load(&tmp.join("missing.jsonl")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_attachments_are_stored_once_and_resolve() {
+        let tmp = TempDir::new("apiari-transcript-test-attach").unwrap();
+        let path = tmp.join("session.jsonl");
+        let screenshot = tmp.join("shot.png");
+        fs::write(&screenshot, b"not really a png").unwrap();
+        let copy = tmp.join("copy.png");
+        fs::write(&copy, b"not really a png").unwrap();
+
+        let mut recorder = Recorder::new(&path);
+        let a = recorder.attach(&screenshot, Some("image/png")).unwrap();
+        let b = recorder.attach(&copy, None).unwrap();
+        assert_eq!(a.path, b.path);
+        assert_eq!(a.size, 16);
+        recorder
+            .record(TranscriptEvent::new(Role::User, "see attached").attachment(a))
+            .unwrap();
+        fs::remove_file(&screenshot).unwrap();
+
+        let conversation = &load(&path).unwrap()[0];
+        let (event, attachment) = conversation.attachments().next().unwrap();
+        assert_eq!(event.content, "see attached");
+        assert_eq!(attachment.name, "shot.png");
+        assert_eq!(
+            fs::read(conversation.resolve(attachment)).unwrap(),
+            b"not really a png"
+        );
+        assert_eq!(fs::read_dir(tmp.join(ATTACHMENTS_DIR)).unwrap().count(), 1);
+    }
+}
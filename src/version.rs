@@ -0,0 +1,371 @@
+//! Semantic version compatibility checks for tool interop.
+//!
+//! Tools exchange versions in handshakes and manifests. Rather than comparing
+//! strings, parse them into [`Version`] and check them with
+//! [`is_compatible`] or a [`RequiredVersion`] such as `^1.4` or `~2.1.0`.
+//!
+//! Caret and tilde follow Cargo's rules: `^1.2.3` accepts `>=1.2.3, <2.0.0`
+//! (`^0.2.3` accepts `<0.3.0`), and `~1.2.3` accepts `>=1.2.3, <1.3.0`.
+//! Pre-release versions order before their release (`1.0.0-rc.1 <
+//! 1.0.0`); build metadata is ignored.
+
+use crate::parse::ParseError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// A semantic version, `MAJOR.MINOR.PATCH[-PRE][+BUILD]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Dot-separated pre-release identifiers (empty for a release).
+    pub pre: Vec<String>,
+}
+
+impl Version {
+    /// Create a release version.
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            pre: Vec::new(),
+        }
+    }
+
+    /// Return `true` if this is a pre-release.
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseError;
+
+    /// Parse a version. A leading `v` is accepted; build metadata is dropped.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let (parts, pre) = parse_parts(s, s, false)?;
+        Ok(Self {
+            major: parts[0],
+            minor: parts[1],
+            patch: parts[2],
+            pre,
+        })
+    }
+}
+
+/// Parse `[v]X[.Y[.Z]][-pre][+build]` from `text`, returning the numbers
+/// (missing ones as 0), how many were present, and the pre-release
+/// identifiers. Errors quote `s`, the full user input.
+fn parse_numbers(s: &str, text: &str) -> Result<([u64; 3], usize, Vec<String>), ParseError> {
+    let trimmed = text.trim();
+    let trimmed = trimmed.strip_prefix('v').unwrap_or(trimmed);
+    let without_build = trimmed.split_once('+').map_or(trimmed, |(v, _)| v);
+    let (core, pre) = match without_build.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (without_build, None),
+    };
+
+    let mut numbers = [0u64; 3];
+    let mut count = 0;
+    for part in core.split('.') {
+        if count == 3 {
+            return Err(ParseError::new(s, "too many version components"));
+        }
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::new(s, "version components must be numbers"));
+        }
+        numbers[count] = part
+            .parse()
+            .map_err(|_| ParseError::new(s, "version component too large"))?;
+        count += 1;
+    }
+
+    let pre = if let Some(pre) = pre {
+        let ids: Vec<String> = pre.split('.').map(str::to_string).collect();
+        let valid = |id: &String| {
+            !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        };
+        if !ids.iter().all(valid) {
+            return Err(ParseError::new(s, "invalid pre-release identifier"));
+        }
+        ids
+    } else {
+        Vec::new()
+    };
+    Ok((numbers, count, pre))
+}
+
+fn parse_parts(s: &str, text: &str, partial: bool) -> Result<([u64; 3], Vec<String>), ParseError> {
+    let (numbers, count, pre) = parse_numbers(s, text)?;
+    if count < 3 && !partial {
+        return Err(ParseError::new(s, "expected MAJOR.MINOR.PATCH"));
+    }
+    Ok((numbers, pre))
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            write!(f, "-{}", self.pre.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => cmp_pre(&self.pre, &other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Semver precedence for pre-release identifiers: numeric identifiers
+/// compare numerically and sort before alphanumeric ones.
+fn cmp_pre(a: &[String], b: &[String]) -> Ordering {
+    for (x, y) in a.iter().zip(b) {
+        let ord = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => x.cmp(y),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// How strictly two versions must agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// Exactly the same version.
+    Exact,
+    /// Same minor line, not older (`~`).
+    Tilde,
+    /// No breaking changes, not older (`^`).
+    Caret,
+    /// Not older.
+    AtLeast,
+    /// Anything.
+    Any,
+}
+
+/// Return `true` if `theirs` satisfies `policy` relative to `mine`, i.e.
+/// `theirs` matches the requirement `{policy}{mine}`.
+pub fn is_compatible(mine: &Version, theirs: &Version, policy: Policy) -> bool {
+    RequiredVersion {
+        policy,
+        version: mine.clone(),
+    }
+    .matches(theirs)
+}
+
+/// A version requirement such as `^1.4`, `~2.1.0`, `>=1.0.0`, `=1.2.3`, or
+/// `*`. Serializes as that string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequiredVersion {
+    pub policy: Policy,
+    pub version: Version,
+}
+
+impl RequiredVersion {
+    /// Return `true` if `version` satisfies the requirement.
+    pub fn matches(&self, version: &Version) -> bool {
+        let req = &self.version;
+        if version < req {
+            return self.policy == Policy::Any;
+        }
+        match self.policy {
+            Policy::Exact => version == req,
+            Policy::Tilde => version.major == req.major && version.minor == req.minor,
+            Policy::Caret => match (req.major, req.minor) {
+                (0, 0) => version.major == 0 && version.minor == 0 && version.patch == req.patch,
+                (0, _) => version.major == 0 && version.minor == req.minor,
+                _ => version.major == req.major,
+            },
+            Policy::AtLeast | Policy::Any => true,
+        }
+    }
+}
+
+impl FromStr for RequiredVersion {
+    type Err = ParseError;
+
+    /// Parse a requirement. A bare version means `^version`; missing minor
+    /// or patch components default to 0.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let trimmed = s.trim();
+        if trimmed == "*" {
+            return Ok(Self {
+                policy: Policy::Any,
+                version: Version::new(0, 0, 0),
+            });
+        }
+        let (policy, rest) = if let Some(rest) = trimmed.strip_prefix(">=") {
+            (Policy::AtLeast, rest)
+        } else if let Some(rest) = trimmed.strip_prefix('=') {
+            (Policy::Exact, rest)
+        } else if let Some(rest) = trimmed.strip_prefix('~') {
+            (Policy::Tilde, rest)
+        } else if let Some(rest) = trimmed.strip_prefix('^') {
+            (Policy::Caret, rest)
+        } else {
+            (Policy::Caret, trimmed)
+        };
+        let (parts, pre) = parse_parts(s, rest, true)?;
+        Ok(Self {
+            policy,
+            version: Version {
+                major: parts[0],
+                minor: parts[1],
+                patch: parts[2],
+                pre,
+            },
+        })
+    }
+}
+
+impl fmt::Display for RequiredVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.policy {
+            Policy::Exact => "=",
+            Policy::Tilde => "~",
+            Policy::Caret => "^",
+            Policy::AtLeast => ">=",
+            Policy::Any => return f.write_str("*"),
+        };
+        write!(f, "{op}{}", self.version)
+    }
+}
+
+impl Serialize for RequiredVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RequiredVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    fn req(s: &str) -> RequiredVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(v("1.2.3"), Version::new(1, 2, 3));
+        assert_eq!(v("v1.2.3+build.5"), Version::new(1, 2, 3));
+        assert_eq!(v("1.0.0-rc.1").pre, ["rc", "1"]);
+        assert_eq!(v("1.0.0-rc.1+abc").to_string(), "1.0.0-rc.1");
+        for bad in ["", "1.2", "1.2.3.4", "1.x.3", "1.2.3-", "1.2.3-a..b"] {
+            assert!(bad.parse::<Version>().is_err(), "{bad:?} should fail");
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_policies() {
+        let mine = v("1.4.2");
+        let check = |theirs: &str, policy| is_compatible(&mine, &v(theirs), policy);
+        assert!(check("1.4.2", Policy::Exact));
+        assert!(!check("1.4.3", Policy::Exact));
+        assert!(check("1.4.9", Policy::Tilde));
+        assert!(!check("1.5.0", Policy::Tilde));
+        assert!(check("1.9.0", Policy::Caret));
+        assert!(!check("2.0.0", Policy::Caret));
+        assert!(!check("1.4.1", Policy::Caret), "older is incompatible");
+        assert!(check("3.0.0", Policy::AtLeast));
+        assert!(check("0.1.0", Policy::Any));
+    }
+
+    #[test]
+    fn test_caret_on_zero_versions() {
+        assert!(req("^0.2.3").matches(&v("0.2.9")));
+        assert!(!req("^0.2.3").matches(&v("0.3.0")));
+        assert!(req("^0.0.3").matches(&v("0.0.3")));
+        assert!(!req("^0.0.3").matches(&v("0.0.4")));
+        assert!(req("1.4").matches(&v("1.7.0")), "bare means caret");
+        assert!(req("~2.1").matches(&v("2.1.5")));
+    }
+
+    #[test]
+    fn test_required_version_serde() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Handshake {
+            version: Version,
+            requires: RequiredVersion,
+        }
+        let hs = Handshake {
+            version: v("1.4.2"),
+            requires: req(">=1.2"),
+        };
+        let json = serde_json::to_string(&hs).unwrap();
+        assert_eq!(json, r#"{"version":"1.4.2","requires":">=1.2.0"}"#);
+        assert_eq!(serde_json::from_str::<Handshake>(&json).unwrap(), hs);
+        assert_eq!(req("*").to_string(), "*");
+        assert!(serde_json::from_str::<RequiredVersion>(r#""^x""#).is_err());
+    }
+}
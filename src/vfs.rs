@@ -0,0 +1,531 @@
+//! Filesystem abstraction.
+//!
+//! [`FileSystem`] covers the handful of operations the rest of the crate
+//! needs. [`StdFs`] forwards to `std::fs` and is the default everywhere;
+//! [`MemoryFs`] keeps everything in memory, which makes tests fast and
+//! hermetic and leaves room for other backends.
+//!
+//! `JsonlReader`, `JsonlWriter`, `load_state`, and `save_state` all have
+//! `*_in` variants that take a `FileSystem`.
+//!
+//! The trait is shaped so that a backend needn't be a filesystem at all:
+//! readers only ever ask for byte ranges ([`FileSystem::read_range`]),
+//! writers only append, and state is written with a whole-file
+//! [`FileSystem::replace`]. A key-value store such as IndexedDB (for a
+//! wasm build) can implement the required methods directly and inherit the
+//! rest; the defaults are written in terms of `read`, `write`, and `rename`.
+
+use crate::guard;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// A readable, seekable file handle returned by [`FileSystem::open`].
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<R: Read + Seek + Send> ReadSeek for R {}
+
+/// File metadata as seen through a [`FileSystem`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Size in bytes (0 for directories).
+    pub len: u64,
+    /// Whether the path is a directory.
+    pub is_dir: bool,
+    /// Last modification time, if the backend tracks it.
+    pub modified: Option<SystemTime>,
+}
+
+/// The filesystem operations used by this crate.
+///
+/// Errors follow `std::fs` conventions, in particular
+/// `io::ErrorKind::NotFound` for missing paths.
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    /// Open an existing file for reading.
+    ///
+    /// The default reads the whole file into memory.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        Ok(Box::new(Cursor::new(self.read(path)?)))
+    }
+
+    /// Read the whole file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Read up to `len` bytes starting at `offset`. Returns fewer bytes (or
+    /// none) if the file ends first.
+    fn read_range(&self, path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut file = self.open(path)?;
+        file.seek(io::SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        file.take(len as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Create or truncate a file and write `data` to it.
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Append `data` to a file, creating it if it doesn't exist.
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Replace a file's contents atomically: readers see either the old
+    /// contents or `data`, never a mix.
+    ///
+    /// The default writes a sibling `<path>.tmp` and renames it into place,
+    /// so it is only as atomic as the backend's `rename`.
+    fn replace(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let tmp = tmp_path(path);
+        self.write(&tmp, data)?;
+        self.rename(&tmp, path)
+    }
+
+    /// Rename a file, replacing the destination if it exists.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Query metadata for a path.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Create a directory and all missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+}
+
+impl<F: FileSystem + ?Sized> FileSystem for &F {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        (**self).open(path)
+    }
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        (**self).read(path)
+    }
+    fn read_range(&self, path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).read_range(path, offset, len)
+    }
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        (**self).write(path, data)
+    }
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        (**self).append(path, data)
+    }
+    fn replace(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        (**self).replace(path, data)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        (**self).rename(from, to)
+    }
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        (**self).metadata(path)
+    }
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        (**self).create_dir_all(path)
+    }
+}
+
+/// The sibling temp file used to replace `path`: the full file name plus
+/// `.tmp`, so `a.json` and `a.toml` in one directory never share one.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// The real filesystem, via `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_range(&self, path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut file = fs::File::open(path)?;
+        file.seek(io::SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        file.take(len as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::write(path, data)
+    }
+
+    fn replace(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let tmp = guard::remove_file_on_drop(tmp_path(path));
+        let mut file = fs::File::create(&*tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&*tmp, path)?;
+        tmp.disarm();
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.write_all(data)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let meta = fs::metadata(path)?;
+        Ok(Metadata {
+            len: if meta.is_dir() { 0 } else { meta.len() },
+            is_dir: meta.is_dir(),
+            modified: meta.modified().ok(),
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Dir,
+    File { data: Vec<u8>, modified: SystemTime },
+}
+
+/// An in-memory filesystem.
+///
+/// Clones share the same contents. Paths are normalized lexically (`.` and
+/// `..` components are resolved without touching any real filesystem), and
+/// the root and the empty relative path always exist as directories.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    nodes: Arc<Mutex<BTreeMap<PathBuf, Node>>>,
+}
+
+impl MemoryFs {
+    /// Create an empty in-memory filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, Node>> {
+        self.nodes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Return every file path currently stored, in sorted order.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.lock()
+            .iter()
+            .filter(|(_, n)| matches!(n, Node::File { .. }))
+            .map(|(p, _)| p.clone())
+            .collect()
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{}: no such file or directory", path.display()),
+    )
+}
+
+fn is_dir(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> bool {
+    path.parent().is_none()
+        || path.as_os_str().is_empty()
+        || matches!(nodes.get(path), Some(Node::Dir))
+}
+
+fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !is_dir(nodes, parent) => Err(not_found(parent)),
+        _ => Ok(()),
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = normalize(path);
+        match self.lock().get(&path) {
+            Some(Node::File { data, .. }) => Ok(data.clone()),
+            Some(Node::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{}: is a directory", path.display()),
+            )),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn read_range(&self, path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let path = normalize(path);
+        match self.lock().get(&path) {
+            Some(Node::File { data, .. }) => {
+                let start = usize::try_from(offset).map_or(data.len(), |o| o.min(data.len()));
+                let end = start.saturating_add(len).min(data.len());
+                Ok(data[start..end].to_vec())
+            }
+            Some(Node::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{}: is a directory", path.display()),
+            )),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    /// Writes are already atomic here: the whole file is swapped under the
+    /// lock.
+    fn replace(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.write(path, data)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let path = normalize(path);
+        let mut nodes = self.lock();
+        check_parent(&nodes, &path)?;
+        if is_dir(&nodes, &path) {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{}: is a directory", path.display()),
+            ));
+        }
+        nodes.insert(
+            path,
+            Node::File {
+                data: data.to_vec(),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let path = normalize(path);
+        let mut nodes = self.lock();
+        check_parent(&nodes, &path)?;
+        match nodes.get_mut(&path) {
+            Some(Node::File {
+                data: existing,
+                modified,
+            }) => {
+                existing.extend_from_slice(data);
+                *modified = SystemTime::now();
+                Ok(())
+            }
+            Some(Node::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{}: is a directory", path.display()),
+            )),
+            None => {
+                nodes.insert(
+                    path,
+                    Node::File {
+                        data: data.to_vec(),
+                        modified: SystemTime::now(),
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut nodes = self.lock();
+        check_parent(&nodes, &to)?;
+        match nodes.get(&from) {
+            Some(Node::File { .. }) => {}
+            Some(Node::Dir) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "MemoryFs only supports renaming files",
+                ));
+            }
+            None => return Err(not_found(&from)),
+        }
+        if matches!(nodes.get(&to), Some(Node::Dir)) {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("{}: is a directory", to.display()),
+            ));
+        }
+        let node = nodes.remove(&from).expect("checked above");
+        nodes.insert(to, node);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = normalize(path);
+        let nodes = self.lock();
+        match nodes.get(&path) {
+            Some(Node::File { data, modified }) => Ok(Metadata {
+                len: data.len() as u64,
+                is_dir: false,
+                modified: Some(*modified),
+            }),
+            _ if is_dir(&nodes, &path) => Ok(Metadata {
+                len: 0,
+                is_dir: true,
+                modified: None,
+            }),
+            _ => Err(not_found(&path)),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = normalize(path);
+        let mut nodes = self.lock();
+        for ancestor in path.ancestors() {
+            if ancestor.as_os_str().is_empty() || ancestor.parent().is_none() {
+                continue;
+            }
+            match nodes.get(ancestor) {
+                Some(Node::Dir) => {}
+                Some(Node::File { .. }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{}: is a file", ancestor.display()),
+                    ));
+                }
+                None => {
+                    nodes.insert(ancestor.to_path_buf(), Node::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_fs_write_read_append() {
+        let fs = MemoryFs::new();
+        let path = Path::new("/data/log.txt");
+
+        assert_eq!(
+            fs.write(path, b"x").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        fs.create_dir_all(Path::new("/data")).unwrap();
+        fs.write(path, b"hello").unwrap();
+        fs.append(path, b" world").unwrap();
+        assert_eq!(fs.read(path).unwrap(), b"hello world");
+        assert_eq!(fs.metadata(path).unwrap().len, 11);
+        assert!(fs.metadata(Path::new("/data")).unwrap().is_dir);
+
+        let mut contents = String::new();
+        let mut file = fs.open(Path::new("/data/./log.txt")).unwrap();
+        file.seek(io::SeekFrom::Start(6)).unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "world");
+    }
+
+    #[test]
+    fn test_memory_fs_rename_replaces() {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/d")).unwrap();
+        fs.write(Path::new("/d/a"), b"new").unwrap();
+        fs.write(Path::new("/d/b"), b"old").unwrap();
+        fs.rename(Path::new("/d/a"), Path::new("/d/b")).unwrap();
+
+        assert_eq!(fs.read(Path::new("/d/b")).unwrap(), b"new");
+        assert_eq!(
+            fs.metadata(Path::new("/d/a")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(fs.files(), vec![PathBuf::from("/d/b")]);
+    }
+
+    /// A backend implementing only the required methods, as a key-value
+    /// store would.
+    #[derive(Debug, Default)]
+    struct KvFs(MemoryFs);
+
+    impl FileSystem for KvFs {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.0.read(path)
+        }
+        fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+            self.0.write(path, data)
+        }
+        fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+            self.0.append(path, data)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.0.rename(from, to)
+        }
+        fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+            self.0.metadata(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.0.create_dir_all(path)
+        }
+    }
+
+    #[test]
+    fn test_ranges_and_replace_on_every_backend() {
+        use crate::ipc::{JsonlReader, JsonlWriter};
+        use crate::state::{load_state_in, save_state_in};
+        use crate::testutil::TempDir;
+
+        fn exercise(fs: &impl FileSystem, dir: &Path) {
+            let log = dir.join("log.jsonl");
+            let writer = JsonlWriter::new_in(fs, &log);
+            let mut reader = JsonlReader::<u32, _>::new_in(fs, &log);
+            writer.append(&1).unwrap();
+            assert_eq!(reader.poll().unwrap(), [1]);
+            writer.append(&2).unwrap();
+            fs.append(&log, b"\n{bad\n3\n").unwrap();
+            assert_eq!(reader.poll().unwrap(), [2, 3]);
+            assert_eq!(fs.read_range(&log, 2, 2).unwrap(), b"2\n");
+            assert_eq!(fs.read_range(&log, 100, 2).unwrap(), b"");
+
+            let state = dir.join("state.json");
+            save_state_in(fs, &state, &vec![1, 2]).unwrap();
+            save_state_in(fs, &state, &vec![3]).unwrap();
+            assert_eq!(load_state_in::<Vec<u32>>(fs, &state).unwrap(), [3]);
+            assert!(fs.metadata(&dir.join("state.json.tmp")).is_err());
+        }
+
+        let tmp = TempDir::new("apiari-vfs-test").unwrap();
+        exercise(&StdFs, tmp.path());
+        exercise(&MemoryFs::new(), Path::new("/d"));
+        let kv = KvFs::default();
+        exercise(&kv, Path::new("/d"));
+        assert_eq!(
+            kv.0.files(),
+            [
+                PathBuf::from("/d/log.jsonl"),
+                PathBuf::from("/d/state.json")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memory_fs_clones_share_state() {
+        let fs = MemoryFs::new();
+        let other = fs.clone();
+        fs.write(Path::new("file"), b"1").unwrap();
+        assert_eq!(other.read(Path::new("file")).unwrap(), b"1");
+    }
+}
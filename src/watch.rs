@@ -0,0 +1,153 @@
+//! Polling file watcher.
+//!
+//! [`watch`] checks a path's modification time and size on an interval and
+//! runs a callback whenever either changes, or the file appears or
+//! disappears. Polling keeps this dependency-free and behaves the same on
+//! every platform and filesystem (including network mounts, where native
+//! notifications are unreliable).
+
+use crate::shutdown::ShutdownToken;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// Stops the watcher thread when dropped.
+///
+/// Dropping it from inside its own callback (say, when the callback held
+/// the last reference to its owner) stops the thread once the callback
+/// returns, rather than waiting for it.
+#[derive(Debug)]
+pub struct FileWatcher {
+    path: PathBuf,
+    shutdown: ShutdownToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileWatcher {
+    /// The watched path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take()
+            && thread.thread().id() != thread::current().id()
+        {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// What the watcher compares between polls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let meta = fs::metadata(path).ok()?;
+    Some(Stamp {
+        modified: meta.modified().ok(),
+        len: meta.len(),
+    })
+}
+
+/// Call `on_change` whenever the file at `path` changes, checking every
+/// `interval`. The state at the time of the call is the baseline, so the
+/// callback does not fire for it.
+pub fn watch(
+    path: impl Into<PathBuf>,
+    interval: Duration,
+    mut on_change: impl FnMut(&Path) + Send + 'static,
+) -> FileWatcher {
+    let path = path.into();
+    let shutdown = ShutdownToken::new();
+    let token = shutdown.clone();
+    let watched = path.clone();
+    let mut last = stamp(&watched);
+    let thread = thread::spawn(move || {
+        while !token.wait_timeout(interval) {
+            let current = stamp(&watched);
+            if current != last {
+                last = current;
+                on_change(&watched);
+            }
+        }
+    });
+    FileWatcher {
+        path,
+        shutdown,
+        thread: Some(thread),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::sync::{Arc, Mutex, mpsc};
+
+    const TICK: Duration = Duration::from_millis(10);
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_reports_create_modify_delete() {
+        let tmp = TempDir::new("apiari-watch-test-events").unwrap();
+        let path = tmp.join("flags.json");
+        let (tx, rx) = mpsc::channel();
+        let _watcher = watch(&path, TICK, move |p| tx.send(p.to_path_buf()).unwrap());
+
+        fs::write(&path, "{}").unwrap();
+        assert_eq!(rx.recv_timeout(WAIT).unwrap(), path);
+        fs::write(&path, "{\"a\": true}").unwrap();
+        assert_eq!(rx.recv_timeout(WAIT).unwrap(), path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(rx.recv_timeout(WAIT).unwrap(), path);
+    }
+
+    #[test]
+    fn test_unchanged_file_is_quiet() {
+        let tmp = TempDir::new("apiari-watch-test-quiet").unwrap();
+        let path = tmp.join("flags.json");
+        fs::write(&path, "{}").unwrap();
+        let (tx, rx) = mpsc::channel();
+        let _watcher = watch(&path, TICK, move |_| tx.send(()).unwrap());
+        assert!(rx.recv_timeout(TICK * 10).is_err());
+    }
+
+    #[test]
+    fn test_drop_stops_watching() {
+        let tmp = TempDir::new("apiari-watch-test-drop").unwrap();
+        let path = tmp.join("flags.json");
+        let (tx, rx) = mpsc::channel();
+        let watcher = watch(&path, TICK, move |_| {
+            let _ = tx.send(());
+        });
+        assert_eq!(watcher.path(), path);
+        drop(watcher);
+        fs::write(&path, "{}").unwrap();
+        assert!(rx.recv_timeout(TICK * 10).is_err());
+    }
+
+    #[test]
+    fn test_drop_from_own_callback() {
+        let tmp = TempDir::new("apiari-watch-test-self-drop").unwrap();
+        let path = tmp.join("flags.json");
+        let slot = Arc::new(Mutex::new(None));
+        let (tx, rx) = mpsc::channel();
+        let owner = Arc::clone(&slot);
+        let watcher = watch(&path, TICK, move |_| {
+            drop(owner.lock().unwrap().take());
+            let _ = tx.send(());
+        });
+        *slot.lock().unwrap() = Some(watcher);
+        fs::write(&path, "{}").unwrap();
+        rx.recv_timeout(WAIT).unwrap();
+        assert!(slot.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,200 @@
+//! Per-session working directories.
+//!
+//! [`allocate`] creates a fresh directory under a root for one session, named
+//! from a caller's hint and a friendly two-word name (`fix-login-brisk-otter`).
+//! The directory is created with a single `create_dir`, which fails if the
+//! name is taken, so two tools allocating at once can never be handed the
+//! same directory; on a collision another name is tried, up to a limit.
+//!
+//! Each workspace gets a `<id>.pid` sidecar holding the owner's PID, so
+//! [`crate::gc::sweep`] leaves it alone while the owner runs and collects it
+//! after a crash.
+
+use crate::guard;
+use crate::id::random_bytes;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Longest slug [`sanitize`] returns.
+pub const MAX_SLUG: usize = 32;
+
+/// Friendly names to try before adding a random suffix.
+const FRIENDLY_ATTEMPTS: usize = 8;
+
+/// Names to try in all before giving up.
+const MAX_ATTEMPTS: usize = 64;
+
+const ADJECTIVES: [&str; 16] = [
+    "amber", "brisk", "calm", "clever", "dapper", "eager", "gentle", "golden", "humble", "jolly",
+    "lucky", "mellow", "nimble", "quiet", "sunny", "witty",
+];
+
+const NOUNS: [&str; 16] = [
+    "badger", "beetle", "falcon", "ferret", "heron", "koala", "lynx", "marten", "otter", "panda",
+    "quail", "raven", "robin", "stoat", "walrus", "wren",
+];
+
+/// Reduce `hint` to a directory-name slug: lowercase ASCII letters, digits,
+/// and single hyphens, at most [`MAX_SLUG`] bytes. May be empty.
+pub fn sanitize(hint: &str) -> String {
+    let mut slug = String::new();
+    for c in hint.chars() {
+        if c.is_ascii_alphanumeric() {
+            if slug.len() == MAX_SLUG {
+                break;
+            }
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// A random `adjective-noun` name.
+pub fn friendly_name() -> String {
+    let bytes = random_bytes();
+    let adjective = ADJECTIVES[usize::from(bytes[0]) % ADJECTIVES.len()];
+    let noun = NOUNS[usize::from(bytes[1]) % NOUNS.len()];
+    format!("{adjective}-{noun}")
+}
+
+/// A session directory created by [`allocate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    id: String,
+    path: PathBuf,
+}
+
+impl Workspace {
+    /// The directory name, unique under its root.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The canonicalized directory path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Delete the directory and its PID sidecar.
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_dir_all(&self.path)?;
+        match fs::remove_file(pid_file(&self.path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Create a new, uniquely named session directory under `root` (created if
+/// missing), named from `hint` (see [`sanitize`]) and a [`friendly_name`].
+///
+/// # Errors
+///
+/// Returns `io::Error` if `root` or the directory can't be created, or
+/// `io::ErrorKind::AlreadyExists` if every name tried was taken.
+pub fn allocate(root: &Path, hint: &str) -> io::Result<Workspace> {
+    let slug = sanitize(hint);
+    allocate_with(root, |attempt| {
+        let mut id = friendly_name();
+        if !slug.is_empty() {
+            id = format!("{slug}-{id}");
+        }
+        if attempt >= FRIENDLY_ATTEMPTS {
+            // The friendly names for this hint are crowded.
+            let bytes = random_bytes();
+            id = format!("{id}-{:02x}{:02x}{:02x}", bytes[0], bytes[1], bytes[2]);
+        }
+        id
+    })
+}
+
+fn allocate_with(root: &Path, mut name: impl FnMut(usize) -> String) -> io::Result<Workspace> {
+    fs::create_dir_all(root)?;
+    let root = fs::canonicalize(root)?;
+    for attempt in 0..MAX_ATTEMPTS {
+        let id = name(attempt);
+        let path = root.join(&id);
+        match fs::create_dir(&path) {
+            Ok(()) => {
+                // Without its sidecar the directory looks ownerless to gc,
+                // so neither outlives a failed write.
+                let dir = guard::remove_dir_on_drop(path);
+                let sidecar = guard::remove_file_on_drop(pid_file(&dir));
+                fs::write(&*sidecar, format!("{}\n", std::process::id()))?;
+                sidecar.disarm();
+                return Ok(Workspace {
+                    id,
+                    path: dir.disarm(),
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(
+            "no free workspace name under {} after {MAX_ATTEMPTS} tries",
+            root.display()
+        ),
+    ))
+}
+
+fn pid_file(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".pid");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("Fix login bug #42!"), "fix-login-bug-42");
+        assert_eq!(sanitize("  --Ünïcode__ok--"), "n-code-ok");
+        assert_eq!(sanitize("../../etc"), "etc");
+        assert_eq!(sanitize("?!"), "");
+        assert_eq!(sanitize(&"a".repeat(100)).len(), MAX_SLUG);
+    }
+
+    #[test]
+    fn test_allocate_unique_dirs() {
+        let tmp = TempDir::new("apiari-workspace-test").unwrap();
+        let root = tmp.path().join("sessions");
+        let mut ids = HashSet::new();
+        // More sessions than friendly names, so suffixes kick in.
+        for _ in 0..300 {
+            let ws = allocate(&root, "Fix login").unwrap();
+            assert!(ws.id().starts_with("fix-login-"));
+            assert!(ws.path().is_dir());
+            assert!(ids.insert(ws.id().to_string()));
+        }
+        let ws = allocate(&root, "").unwrap();
+        assert_eq!(ws.id().matches('-').count(), 1);
+        let pid = fs::read_to_string(pid_file(ws.path())).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+        let path = ws.path().to_path_buf();
+        ws.remove().unwrap();
+        assert!(!path.exists() && !pid_file(&path).exists());
+    }
+
+    #[test]
+    fn test_allocate_gives_up_and_cleans_up() {
+        let tmp = TempDir::new("apiari-workspace-test-fail").unwrap();
+        fs::create_dir(tmp.join("taken")).unwrap();
+        let err = allocate_with(tmp.path(), |_| "taken".to_string()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        // A directory in the sidecar's place makes its write fail.
+        fs::create_dir(tmp.join("orphan.pid")).unwrap();
+        assert!(allocate_with(tmp.path(), |_| "orphan".to_string()).is_err());
+        assert!(!tmp.join("orphan").exists());
+    }
+}